@@ -15,6 +15,23 @@ use internal as rtsync;
 mod cli;
 mod build;
 
+// write_pidfile将当前进程的pid写入pid文件
+fn write_pidfile(path: &str) {
+    let pidfile = std::path::Path::new(path);
+    if let Some(dir) = pidfile.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Err(e) = std::fs::write(pidfile, format!("{}\n", std::process::id())) {
+        error!("写入pid文件 {} 失败: {}", path, e);
+    }
+}
+
+fn remove_pidfile(path: &str) {
+    if let Err(e) = std::fs::remove_file(path) {
+        error!("删除pid文件 {} 失败: {}", path, e);
+    }
+}
+
 async fn start_manager(c: &ArgMatches) -> Result<()> {
     // rtsync::logger::init_logger(true, true, false);
     rtsync::logger::init_logger(c.get_flag("verbose"), 
@@ -27,16 +44,55 @@ async fn start_manager(c: &ArgMatches) -> Result<()> {
             exit(1);
         },
         Ok(config) => {
+            rtsync::logger::set_log_level(c.get_flag("verbose"), c.get_flag("debug") || config.debug());
             match manager::server::get_rtsync_manager(&config){
                 Err(_e) => {
                     error!("初始化 RT sync manager 失败.");
                     exit(1);
                 },
                 Ok(manager) => {
-                    info!("启动 rtsync manager 服务器.");
+                    let pidfile = c.get_one::<String>("pidfile").cloned();
+                    if let Some(pidfile) = &pidfile {
+                        write_pidfile(pidfile);
+                    }
+
+                    let handle = manager.handle();
+                    let c = c.clone();
                     tokio::spawn(async move {
-                        manager.run().await;
-                    }).await?;
+                        let mut sighup = signal(SignalKind::hangup()).unwrap();
+                        let mut sigint = signal(SignalKind::interrupt()).unwrap();
+                        let mut sigterm = signal(SignalKind::terminate()).unwrap();
+                        loop{
+                            tokio::select! {
+                                _ = sighup.recv() => {
+                                    info!("收到重载信号");
+                                    match manager::config::load_config(c.get_one::<String>("config").cloned(), &c){
+                                        Err(e) => {
+                                            error!("导入config失败: {}", e);
+                                        },
+                                        Ok(new_cfg) => {
+                                            rtsync::logger::set_log_level(c.get_flag("verbose"),
+                                                                          c.get_flag("debug") || new_cfg.debug());
+                                            handle.reload(new_cfg).await;
+                                        }
+                                    }
+                                },
+                                _ = sigint.recv() => {
+                                    handle.shutdown().await;
+                                },
+                                _ = sigterm.recv() => {
+                                    handle.shutdown().await;
+                                },
+                            }
+                        }
+                    });
+
+                    info!("启动 rtsync manager 服务器.");
+                    manager.run().await;
+                    if let Some(pidfile) = &pidfile {
+                        remove_pidfile(pidfile);
+                    }
+                    info!("rtsync manager 已退出.");
                 }
             }
         }
@@ -80,7 +136,12 @@ async fn start_worker(c: &ArgMatches) -> Result<()> {
                             exit(1);
                         }
                     }
-                    let mut w_clone = w.clone();
+                    let pidfile = c.get_one::<String>("pidfile").cloned();
+                    if let Some(pidfile) = &pidfile {
+                        write_pidfile(pidfile);
+                    }
+
+                    let w_clone = w.clone();
                     tokio::spawn(async move {
                         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                         let mut sighup = signal(SignalKind::hangup()).unwrap();
@@ -111,6 +172,9 @@ async fn start_worker(c: &ArgMatches) -> Result<()> {
 
                     info!("运行 rtsync worker.");
                     w.run().await;
+                    if let Some(pidfile) = &pidfile {
+                        remove_pidfile(pidfile);
+                    }
                 }
             }
         }
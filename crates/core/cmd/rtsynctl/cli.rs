@@ -10,12 +10,35 @@ pub(crate) fn build_cli() -> Command{
         Arg::new("ca-cert").long("ca-cert")
             .value_name("CERT").action(ArgAction::Set)
             .help("指定`CERT`为信任根CA证书文件路径"),
+        Arg::new("client-cert").long("client-cert")
+            .value_name("CERT").action(ArgAction::Set)
+            .requires("client-key")
+            .help("双向TLS时使用`CERT`作为客户端证书"),
+        Arg::new("client-key").long("client-key")
+            .value_name("KEY").action(ArgAction::Set)
+            .requires("client-cert")
+            .help("双向TLS时使用`KEY`作为客户端私钥"),
+        Arg::new("api-key").long("api-key")
+            .value_name("KEY").action(ArgAction::Set)
+            .help("使用`KEY`向 manager 表明身份（记录在审计日志中）"),
         arg!(-v --verbose "启用详细日志记录"),
         arg!(--debug "开启调试日志")];
 
     // 向manager服务器发送start命令时使用
     let force_start_flag = [
-        arg!(-f --force "忽略并发限制"),
+        arg!(-f --force "忽略并发限制和允许同步的时间段"),
+    ];
+
+    // start/stop/disable/restart的目标，WORKER和MIRROR都可以是glob模式（例如 'debian*'），
+    // 可以同时指定多个MIRROR，并用状态和标签进一步筛选
+    let job_target_args = [
+        arg!(<WORKER> "指定 `WORKER`，可以是glob模式"),
+        Arg::new("MIRROR")
+            .help("指定一个或多个 `MIRROR`，可以是glob模式")
+            .num_args(1..)
+            .required_unless_present_any(["status", "tag"]),
+        arg!(-s --status <STATUS> "只选择状态为 `STATUS` 的镜像，多个状态用逗号分隔"),
+        arg!(-t --tag <TAG> "只选择带有标签 `TAG` 的镜像，多个标签用逗号分隔"),
     ];
     
     Command::new("rtsynctl")
@@ -32,6 +55,7 @@ pub(crate) fn build_cli() -> Command{
                 .args(&[
                     arg!(-a --all "列出 workers 的所有同步任务"),
                     arg!(-s --status <STATUS> "根据提供的 `STATUS` 过滤输出"),
+                    arg!(-t --tag <TAG> "只列出带有标签 `TAG` 的同步任务，多个标签用逗号分隔"),
                     arg!(-f --format <FORMAT> "使用 Tera 的 `FORMAT` 模版格式化输出"),
                 ])
                 .arg_required_else_help(true)
@@ -60,40 +84,57 @@ pub(crate) fn build_cli() -> Command{
                 .about("设置镜像大小")
                 .arg(arg!(<WORKER> "指定 `WORKER`"))  // 必须要求被获得的参数，不提供会报错
                 .arg(arg!(<MIRROR> "指定任务镜像为 `MIRROR`"))
-                .arg(arg!(<SIZE> "指定 `SIZE`"))
+                .arg(arg!(<SIZE> "指定 `SIZE`，例如 1.5T、800GiB 或字节数"))
                 .args(&common_flags)
                 .arg_required_else_help(true)
         )
         .subcommand(
-            Command::new("start")
-                .about("开始一个同步任务")
+            Command::new("size-stats")
+                .about("统计每个 worker 以及所有 worker 上镜像的总大小（字节）")
+                .args(&common_flags)
+                .arg_required_else_help(false)
+        )
+        .subcommand(
+            Command::new("disks")
+                .about("列出 workers 上镜像所在文件系统的空间使用情况")
+                .args(&common_flags)
+                .arg_required_else_help(false)
+        )
+        .subcommand(
+            Command::new("history")
+                .about("列出镜像的同步历史和每次同步消耗的资源")
                 .arg(arg!(<WORKER> "指定 `WORKER`"))
-                .arg(arg!(<MIRROR> "指定 `MIRROR`"))
+                .arg(arg!(<MIRROR> "指定任务镜像为 `MIRROR`"))
+                .arg(arg!(-n --limit <N> "只显示最近的 `N` 条记录"))
+                .args(&common_flags)
+                .arg_required_else_help(true)
+        )
+        .subcommand(
+            Command::new("start")
+                .about("开始一个或多个同步任务")
+                .args(&job_target_args)
                 .args(&common_flags)
                 .args(&force_start_flag)
                 .arg_required_else_help(true)
         )
         .subcommand(
             Command::new("stop")
-                .about("暂停一个同步任务")
-                .arg(arg!(<WORKER> "指定 `WORKER`"))
-                .arg(arg!(<MIRROR> "指定 `MIRROR`"))
+                .about("暂停一个或多个同步任务")
+                .args(&job_target_args)
                 .args(&common_flags)
                 .arg_required_else_help(true)
         )
         .subcommand(
             Command::new("disable")
-                .about("禁用一个同步任务")
-                .arg(arg!(<WORKER> "指定 `WORKER`"))
-                .arg(arg!(<MIRROR> "指定 `MIRROR`"))
+                .about("禁用一个或多个同步任务")
+                .args(&job_target_args)
                 .args(&common_flags)
                 .arg_required_else_help(true)
         )
         .subcommand(
             Command::new("restart")
-                .about("重新开始一个同步任务")
-                .arg(arg!(<WORKER> "指定 `WORKER`"))
-                .arg(arg!(<MIRROR> "指定 `MIRROR`"))
+                .about("重新开始一个或多个同步任务")
+                .args(&job_target_args)
                 .args(&common_flags)
                 .arg_required_else_help(true)
         )
@@ -104,6 +145,19 @@ pub(crate) fn build_cli() -> Command{
                 .args(&common_flags)
                 .arg_required_else_help(true)
         )
+        .subcommand(
+            Command::new("audit")
+                .about("查看审计日志")
+                .args(&common_flags)
+                .args(&[
+                    arg!(-w --worker <WORKER> "只显示与 `WORKER` 有关的记录"),
+                    arg!(--mirror <MIRROR> "只显示与 `MIRROR` 有关的记录"),
+                    arg!(--verb <VERB> "只显示操作为 `VERB` 的记录"),
+                    arg!(--since <TIME> "只显示 `TIME`（RFC 3339 格式，如 2026-10-01T00:00:00Z）之后的记录，默认为最近7天"),
+                    arg!(-n --limit <N> "只显示最近的 `N` 条记录"),
+                ])
+                .arg_required_else_help(false)
+        )
         .subcommand(
             Command::new("ping")
                 .arg(arg!(<WORKER> "指定 `WORKER`"))
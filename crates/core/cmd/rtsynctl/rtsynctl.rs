@@ -21,6 +21,9 @@ const LIST_JOBS_PATH: &str = "/jobs";
 const LIST_WORKERS_PATH: &str = "/workers";
 const FLUSH_DISABLED_PATH: &str = "/jobs/disabled";
 const CMD_PATH: &str = "/cmd";
+const AUDIT_PATH: &str = "/audit";
+const SIZE_STATS_PATH: &str = "/stats/size";
+const DISKS_PATH: &str = "/disks";
 const SYSTEM_CFG_FILE: &str = "/etc/rtsync/ctl.conf";   // 系统级的配置文件地址
 const  USER_CFG_FILE: &str = "$HOME/.config/rtsync/ctl.conf";   // 用户级别的配置文件地址
 
@@ -36,6 +39,9 @@ struct Config {
     manager_addr: String,
     manager_port: u32,
     ca_cert: String,
+    client_cert: String,
+    client_key: String,
+    api_key: String,
 }
 
 fn load_config(cfg_file: &str, cfg: &mut Config) -> Result<()>{
@@ -87,6 +93,13 @@ async fn initialize(c: &ArgMatches) -> Result<()>{
     if let Some(ca_cert) = c.get_one::<String>("ca-cert"){
         cfg.ca_cert = ca_cert.clone();
     }
+    if let (Some(cert), Some(key)) = (c.get_one::<String>("client-cert"), c.get_one::<String>("client-key")){
+        cfg.client_cert = cert.clone();
+        cfg.client_key = key.clone();
+    }
+    if let Some(api_key) = c.get_one::<String>("api-key"){
+        cfg.api_key = api_key.clone();
+    }
 
     // 解析 manager server 的 base url 
     let mut url_lock = BASE_URL.write().await;
@@ -104,7 +117,21 @@ async fn initialize(c: &ArgMatches) -> Result<()>{
         ca_cert if !ca_cert.is_empty() => Some(ca_cert.clone()),
         _ => None,
     };
-    match rtsync::util::create_http_client(ca_cert.as_deref()){
+    let mut headers = reqwest::header::HeaderMap::new();
+    if !cfg.api_key.is_empty(){
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", cfg.api_key))?;
+        value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+    }
+    let identity = if !cfg.client_cert.is_empty() && !cfg.client_key.is_empty(){
+        Some(rtsync::util::load_client_identity(&cfg.client_cert, &cfg.client_key)
+            .map_err(|e| anyhow!("加载客户端证书失败: {e}"))?)
+    }else {
+        None
+    };
+    match rtsync::util::http_client_builder(ca_cert.as_deref(), identity)
+        .and_then(|builder| builder.default_headers(headers).build())
+    {
         Ok(client) => {
             *client_lock = client;
         }
@@ -163,19 +190,13 @@ async fn list_jobs(c: &ArgMatches) -> Result<()>{
         if let Some(status_str) = c.get_one::<String>("status"){
             let mut filtered_jobs: Vec<rtsync::status_web::WebMirrorStatus> =
                 Vec::with_capacity(10);
-            let mut statuses: Vec<rtsync::status::SyncStatus> = vec![];
-            for s in status_str.split(","){
-                match serde_json::from_str(format!("\"{}\"", s.trim()).as_str()) {
-                    Ok(s) => {
-                        let status: rtsync::status::SyncStatus = s;
-                        statuses.push(status);
-                    },
-                    Err(e) => {
-                        eprintln!("解析状态失败: {}", e);
-                        exit(1);
-                    }
+            let statuses = match parse_statuses(status_str){
+                Ok(statuses) => statuses,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1);
                 }
-            }
+            };
             for job in jobs.iter(){
                 for s in statuses.iter(){
                     if job.status == *s{
@@ -228,6 +249,12 @@ async fn list_jobs(c: &ArgMatches) -> Result<()>{
         generic_jobs_ms.extend(jobs);
     }
 
+    if let Some(tag) = c.get_one::<String>("tag"){
+        let tags: Vec<String> = tag.split(",").map(|t| t.trim().to_string()).collect();
+        generic_jobs_wms.retain(|w| w.metadata.has_any_tag(&tags));
+        generic_jobs_ms.retain(|m| m.metadata.has_any_tag(&tags));
+    }
+
     if let Some(format) = c.get_one::<String>("format"){
         let mut tera = Tera::default();
         if !generic_jobs_wms.is_empty(){
@@ -242,6 +269,7 @@ async fn list_jobs(c: &ArgMatches) -> Result<()>{
                 context.insert("last_ended", &w.last_ended);
                 context.insert("last_started", &w.last_started);
                 context.insert("scheduled", &w.scheduled);
+                context.insert("metadata", &w.metadata);
                 match tera.render_str(format, &context){
                     Ok(output) => {
                         println!("{}", output);
@@ -266,6 +294,7 @@ async fn list_jobs(c: &ArgMatches) -> Result<()>{
                 context.insert("status", &m.upstream);
                 context.insert("status", &m.size);
                 context.insert("status", &m.error_msg);
+                context.insert("metadata", &m.metadata);
                 match tera.render_str(format, &context){
                     Ok(output) => {
                         println!("{}", output);
@@ -301,11 +330,16 @@ async fn update_mirror_size(c: &ArgMatches) -> Result<()>{
     #[derive(Serialize, Deserialize)]
     struct Msg<'a>{
         name: &'a str,
-        size: usize,
+        size: &'a str,
+    }
+    let size = rtsync::size::MirrorSize::parse(mirror_size);
+    if size.bytes.is_none(){
+        eprintln!("无法解析镜像大小: {}", mirror_size);
+        exit(1);
     }
     let msg = Msg{
         name: mirror_id,
-        size: mirror_size.parse::<usize>()?,
+        size: mirror_size,
     };
     let url = format!("{}/workers/{}/jobs/{}/size",
                       *BASE_URL.read().await,
@@ -323,7 +357,7 @@ async fn update_mirror_size(c: &ArgMatches) -> Result<()>{
                 exit(1);
             }
             let status: rtsync::msg::MirrorStatus = resp.json().await.expect("无法解析成MirrorStatus");
-            if status.size != *mirror_size {
+            if status.size != size {
                 eprintln!("镜像大小错误, 应该为 {}, 但manager返回 {}", mirror_size, status.size);
                 exit(1);
             }
@@ -334,6 +368,84 @@ async fn update_mirror_size(c: &ArgMatches) -> Result<()>{
     Ok(())
 }
 
+async fn size_stats(_c: &ArgMatches) -> Result<()>{
+    let url = format!("{}{}", *BASE_URL.read().await, SIZE_STATS_PATH);
+    let client = CLIENT.read().await.clone();
+    let stats: rtsync::msg::SizeStats = match rtsync::util::get_json(&url, Some(client)).await{
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("不能正确地从manager服务器获得镜像大小: {}", e);
+            exit(1);
+        }
+    };
+    let pretty_json = to_string_pretty(&json!(stats))?;
+    println!("{}", pretty_json);
+    Ok(())
+}
+
+async fn list_disks(_c: &ArgMatches) -> Result<()>{
+    let url = format!("{}{}", *BASE_URL.read().await, DISKS_PATH);
+    let client = CLIENT.read().await.clone();
+    let disks: Vec<rtsync::msg::WorkerDiskUsage> = match rtsync::util::get_json(&url, Some(client)).await{
+        Ok(disks) => disks,
+        Err(e) => {
+            eprintln!("不能正确地从manager服务器获得磁盘空间信息: {}", e);
+            exit(1);
+        }
+    };
+
+    let worker_width = disks.iter().map(|d| d.worker.len()).max().unwrap_or(0).max("WORKER".len());
+    let path_width = disks.iter().map(|d| d.usage.path.len()).max().unwrap_or(0).max("PATH".len());
+    println!("{:<worker_width$}  {:<path_width$}  {:>10}  {:>10}  {:>6}", "WORKER", "PATH", "FREE", "TOTAL", "FREE%");
+    for d in disks.iter(){
+        let percent = if d.usage.total_bytes > 0 {
+            d.usage.free_bytes as f64 / d.usage.total_bytes as f64 * 100.0
+        } else {
+            0.0
+        };
+        println!("{:<worker_width$}  {:<path_width$}  {:>10}  {:>10}  {:>5.1}%{}",
+                 d.worker, d.usage.path,
+                 rtsync::size::format_bytes(d.usage.free_bytes),
+                 rtsync::size::format_bytes(d.usage.total_bytes),
+                 percent, if d.low {"  (空间不足)"} else {""});
+    }
+    Ok(())
+}
+
+async fn list_history(c: &ArgMatches) -> Result<()>{
+    let worker_id = c.get_one::<String>("WORKER").unwrap();
+    let mirror_id = c.get_one::<String>("MIRROR").unwrap();
+    let mut query: Vec<(&str, String)> = vec![];
+    if let Some(limit) = c.get_one::<String>("limit"){
+        query.push(("limit", limit.clone()));
+    }
+    let url = format!("{}/workers/{}/jobs/{}/history", *BASE_URL.read().await, worker_id, mirror_id);
+    let client = CLIENT.read().await.clone();
+    let resp = client.get(&url).query(&query).send().await?;
+    if resp.status() != StatusCode::OK{
+        eprintln!("获取同步历史失败，HTTP状态码不是200: {:?}", resp);
+        exit(1);
+    }
+    let entries: Vec<rtsync::msg::SyncHistoryEntry> = resp.json().await?;
+
+    let secs = |ms: u64| format!("{:.1}s", ms as f64 / 1000.0);
+    println!("{:<19}  {:<8}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}",
+             "ENDED (UTC)", "STATUS", "ELAPSED", "USER", "SYSTEM", "MAX RSS", "READ", "WRITE");
+    for e in entries.iter(){
+        let ended = e.ended.format("%Y-%m-%d %H:%M:%S");
+        match e.usage {
+            Some(u) => println!("{:<19}  {:<8}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}",
+                                ended, e.status.to_string(),
+                                secs(u.elapsed_ms), secs(u.user_time_ms), secs(u.system_time_ms),
+                                rtsync::size::format_bytes(u.max_rss_bytes),
+                                rtsync::size::format_bytes(u.read_bytes),
+                                rtsync::size::format_bytes(u.write_bytes)),
+            None => println!("{:<19}  {:<8}  {:>10}", ended, e.status.to_string(), "-"),
+        }
+    }
+    Ok(())
+}
+
 async fn remove_worker(c: &ArgMatches) -> Result<()>{
     let worker_id = c.get_one::<String>("worker").unwrap();
     let url = format!("{}/workers/{}", *BASE_URL.read().await, worker_id);
@@ -370,9 +482,45 @@ async fn flush_disabled_jobs(_c: &ArgMatches) -> Result<()>{
     Ok(())
 }
 
+async fn list_audit_entries(c: &ArgMatches) -> Result<()>{
+    let mut query: Vec<(&str, String)> = vec![];
+    for (key, arg) in [("worker", "worker"), ("mirror", "mirror"), ("verb", "verb"), ("since", "since"), ("limit", "limit")]{
+        if let Some(v) = c.get_one::<String>(arg){
+            query.push((key, v.clone()));
+        }
+    }
+    let url = format!("{}{}", *BASE_URL.read().await, AUDIT_PATH);
+    let client = CLIENT.read().await.clone();
+    let resp = client.get(&url).query(&query).send().await?;
+    if resp.status() != StatusCode::OK{
+        eprintln!("获取审计日志失败，HTTP状态码不是200: {:?}", resp);
+        exit(1);
+    }
+    let entries: Vec<rtsync::msg::AuditEntry> = resp.json().await?;
+    let pretty_json = to_string_pretty(&json!(entries))?;
+    println!("{}", pretty_json);
+    Ok(())
+}
+
+// parse_statuses把逗号分隔的状态列表解析为SyncStatus
+fn parse_statuses(status_str: &str) -> Result<Vec<rtsync::status::SyncStatus>>{
+    let mut statuses = vec![];
+    for s in status_str.split(","){
+        let status: rtsync::status::SyncStatus = serde_json::from_str(format!("\"{}\"", s.trim()).as_str())
+            .map_err(|e| anyhow!("解析状态失败: {}", e))?;
+        statuses.push(status);
+    }
+    Ok(statuses)
+}
+
 async fn cmd_job(cmd: rtsync::msg::CmdVerb, c: &ArgMatches, is_start: bool){
     let worker_id = c.get_one::<String>("WORKER").unwrap().clone();
-    let mirror_id = c.get_one::<String>("MIRROR").unwrap().clone();
+    let mirror_ids: Vec<String> = c.get_many::<String>("MIRROR")
+        .map(|m| m.cloned().collect())
+        .unwrap_or_default();
+    // ping子命令没有status和tag参数
+    let status = c.try_get_one::<String>("status").ok().flatten();
+    let tag = c.try_get_one::<String>("tag").ok().flatten();
 
     let mut options: HashMap<String, bool> = HashMap::new();
     // force针对start
@@ -383,17 +531,62 @@ async fn cmd_job(cmd: rtsync::msg::CmdVerb, c: &ArgMatches, is_start: bool){
             options.insert("force".to_string(), true);
         }
     }
-    
+
+    let is_pattern = |s: &str| s.contains(['*', '?', '[']);
+    let is_bulk = mirror_ids.len() != 1 || status.is_some() || tag.is_some()
+        || is_pattern(&worker_id) || mirror_ids.iter().any(|m| is_pattern(m));
+    if !is_bulk{
+        let client_cmd = rtsync::msg::ClientCmd{
+            cmd,
+            mirror_id: mirror_ids[0].clone(),
+            worker_id,
+            options,
+            ..Default::default()
+        };
+        let url = format!("{}{}", *BASE_URL.read().await, CMD_PATH);
+        let client = CLIENT.read().await.clone();
+        match rtsync::util::post_json(&url, &client_cmd, Some(client)).await{
+            Err(e) => {
+                eprintln!("发送命令失败: {}", e);
+                exit(1);
+            }
+            Ok(resp) => {
+                if resp.status() != StatusCode::OK{
+                    eprintln!("发送命令失败，HTTP状态码不是200: {:?}", resp);
+                    exit(1);
+                }
+                println!("成功发送命令");
+            }
+        }
+        return
+    }
+
+    let mut selector = rtsync::msg::CmdSelector{
+        mirrors: mirror_ids,
+        workers: vec![worker_id],
+        ..Default::default()
+    };
+    if let Some(status) = status{
+        match parse_statuses(status){
+            Ok(statuses) => selector.statuses = statuses,
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+    }
+    if let Some(tag) = tag{
+        selector.tags = tag.split(",").map(|t| t.trim().to_string()).collect();
+    }
     let client_cmd = rtsync::msg::ClientCmd{
         cmd,
-        mirror_id,
-        worker_id,
         options,
+        selector: Some(selector),
         ..Default::default()
     };
     let url = format!("{}{}", *BASE_URL.read().await, CMD_PATH);
     let client = CLIENT.read().await.clone();
-    match rtsync::util::post_json(&url, &client_cmd, Some(client)).await{
+    let results: Vec<rtsync::msg::CmdResult> = match rtsync::util::post_json(&url, &client_cmd, Some(client)).await{
         Err(e) => {
             eprintln!("发送命令失败: {}", e);
             exit(1);
@@ -403,9 +596,33 @@ async fn cmd_job(cmd: rtsync::msg::CmdVerb, c: &ArgMatches, is_start: bool){
                 eprintln!("发送命令失败，HTTP状态码不是200: {:?}", resp);
                 exit(1);
             }
-            println!("成功发送命令");
+            match resp.json().await{
+                Ok(results) => results,
+                Err(e) => {
+                    eprintln!("解析响应失败: {}", e);
+                    exit(1);
+                }
+            }
         }
+    };
+    if results.is_empty(){
+        println!("没有匹配的同步任务");
+        return
     }
+
+    let worker_width = results.iter().map(|r| r.worker_id.len()).max().unwrap_or(0).max("WORKER".len());
+    let mirror_width = results.iter().map(|r| r.mirror_id.len()).max().unwrap_or(0).max("MIRROR".len());
+    println!("{:<worker_width$}  {:<mirror_width$}  {:<6}  REPLY", "WORKER", "MIRROR", "RESULT");
+    for r in results.iter(){
+        println!("{:<worker_width$}  {:<mirror_width$}  {:<6}  {}",
+                 r.worker_id, r.mirror_id, if r.success {"ok"} else {"failed"}, r.reply.trim());
+    }
+    let failed = results.iter().filter(|r| !r.success).count();
+    if failed > 0{
+        eprintln!("{}个同步任务中有{}个发送失败", results.len(), failed);
+        exit(1);
+    }
+    println!("成功向{}个同步任务发送命令", results.len());
 }
 
 async fn cmd_worker(cmd: rtsync::msg::CmdVerb, c: &ArgMatches){
@@ -483,6 +700,29 @@ async fn main() -> Result<()> {
                 update_mirror_size(&sub_matches).await?;
             }
         },
+        Some(("size-stats", sub_matches)) => {
+            if let Err(e) = initialize(sub_matches).await {
+                eprintln!("{}", e);
+                exit(1);
+            }
+            size_stats(sub_matches).await?;
+        },
+        Some(("disks", sub_matches)) => {
+            if let Err(e) = initialize(sub_matches).await {
+                eprintln!("{}", e);
+                exit(1);
+            }
+            list_disks(sub_matches).await?;
+        },
+        Some(("history", sub_matches)) => {
+            if sub_matches.args_present() {
+                if let Err(e) = initialize(sub_matches).await {
+                    eprintln!("{}", e);
+                    exit(1);
+                }
+                list_history(&sub_matches).await?;
+            }
+        },
         Some(("start", sub_matches)) => {
             if sub_matches.args_present() {
                 if let Err(e) = initialize(sub_matches).await {
@@ -528,6 +768,13 @@ async fn main() -> Result<()> {
                 cmd_worker(rtsync::msg::CmdVerb::Reload, &sub_matches).await;
             }
         },
+        Some(("audit", sub_matches)) => {
+            if let Err(e) = initialize(sub_matches).await {
+                eprintln!("{}", e);
+                exit(1);
+            }
+            list_audit_entries(sub_matches).await?;
+        },
         Some(("ping", sub_matches)) => {
             if sub_matches.args_present() {
                 if let Err(e) = initialize(sub_matches).await {
@@ -2,5 +2,6 @@ pub mod logger;
 pub mod status;
 pub mod status_web;
 pub mod msg;
+pub mod size;
 pub mod util;
 pub mod version;
@@ -44,20 +44,28 @@ pub fn init_logger(verbose: bool, debug: bool, with_systemd: bool) {
         }
     });
 
+    // fern本身不过滤，实际的日志级别由log::set_max_level控制，以便运行时调整
+    base_config = base_config.level(LevelFilter::Trace);
+
+    // 将配置应用于标准输出
+    base_config = base_config.chain(std::io::stdout());
+
+    // 初始化日志系统
+    base_config.apply().expect("Failed to initialize logger");
+
     // 设置日志级别
-    base_config = base_config.level(if debug {
+    set_log_level(verbose, debug);
+}
+
+// set_log_level 在运行时调整日志级别，例如在重载配置时
+pub fn set_log_level(verbose: bool, debug: bool) {
+    log::set_max_level(if debug {
         LevelFilter::Debug
     } else if verbose {
         LevelFilter::Info
     } else {
         LevelFilter::Warn
     });
-
-    // 将配置应用于标准输出
-    base_config = base_config.chain(std::io::stdout());
-
-    // 初始化日志系统
-    base_config.apply().expect("Failed to initialize logger");
 }
 
 
@@ -4,8 +4,9 @@ use serde::{Deserialize, Serialize};
 use serde::de::{self, Deserializer};
 use serde::ser::Serializer;
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use crate::status::SyncStatus;
+use crate::size::MirrorSize;
 
 // 当一个worker完成同步时，MirrorStatus表示一个msg
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -20,9 +21,99 @@ pub struct MirrorStatus {
     #[serde(rename = "next_schedule")]
     pub scheduled: DateTime<Utc>,
     pub upstream: String,
-    pub size: String,
+    #[serde(flatten, with = "crate::size::wire")]
+    pub size: MirrorSize,
     pub error_msg: String,
+    #[serde(flatten)]
+    pub metadata: MirrorMetadata,
+    // 镜像所在的文件系统的空间使用情况
+    #[serde(default)]
+    pub disks: Vec<DiskUsage>,
+    // 最近一次同步结束时统计的资源消耗
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ResourceUsage>,
 }
+
+// ResourceUsage是一次同步消耗的资源，包括同步进程创建的所有子进程
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct ResourceUsage {
+    pub user_time_ms: u64,
+    pub system_time_ms: u64,
+    pub max_rss_bytes: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub elapsed_ms: u64,
+}
+
+impl ResourceUsage {
+    // add累加依次运行的另一个进程消耗的资源，例如two-stage-rsync的第二阶段
+    pub fn add(&mut self, other: &ResourceUsage) {
+        self.user_time_ms += other.user_time_ms;
+        self.system_time_ms += other.system_time_ms;
+        self.max_rss_bytes = self.max_rss_bytes.max(other.max_rss_bytes);
+        self.read_bytes += other.read_bytes;
+        self.write_bytes += other.write_bytes;
+        self.elapsed_ms += other.elapsed_ms;
+    }
+
+    // merge用对同一次运行的另一份更准确的统计覆盖这一份，other中为0（没有统计到）的项保留原值
+    pub fn merge(&mut self, other: &ResourceUsage) {
+        let pick = |mine: &mut u64, theirs: u64| if theirs > 0 { *mine = theirs };
+        pick(&mut self.user_time_ms, other.user_time_ms);
+        pick(&mut self.system_time_ms, other.system_time_ms);
+        pick(&mut self.max_rss_bytes, other.max_rss_bytes);
+        pick(&mut self.read_bytes, other.read_bytes);
+        pick(&mut self.write_bytes, other.write_bytes);
+        pick(&mut self.elapsed_ms, other.elapsed_ms);
+    }
+}
+
+// SyncHistoryEntry是一次同步的结果，manager在收到Success或Failed状态时记录
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct SyncHistoryEntry {
+    pub name: String,
+    pub worker: String,
+    pub status: SyncStatus,
+    pub started: DateTime<Utc>,
+    pub ended: DateTime<Utc>,
+    #[serde(flatten, with = "crate::size::wire")]
+    pub size: MirrorSize,
+    pub error_msg: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ResourceUsage>,
+}
+
+// DiskUsage是worker上一个目录所在的文件系统的空间使用情况
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub path: String,
+    pub total_bytes: u64,
+    pub free_bytes: u64,    // 非特权用户可用的空间
+    pub updated: DateTime<Utc>,
+}
+
+// WorkerDiskUsage是manager汇总的worker上各个文件系统的空间使用情况，low表示可用空间低于告警阈值
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct WorkerDiskUsage {
+    pub worker: String,
+    #[serde(flatten)]
+    pub usage: DiskUsage,
+    pub low: bool,
+}
+
+// MirrorMetadata是镜像的描述信息，由worker根据镜像配置上报
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct MirrorMetadata {
+    pub tags: Vec<String>,  // 用于对镜像分组，例如 distro、lang-pkg
+    pub description: String,
+    pub homepage: String,
+    pub help_url: String,
+    pub maintainers: Vec<String>,
+    pub after: Vec<String>,  // 依赖的镜像，这些镜像同步成功后才开始同步
+}
+
 impl Ord for MirrorStatus{
     fn cmp(&self, other: &MirrorStatus) -> Ordering {
         self.name.cmp(&other.name)
@@ -65,6 +156,14 @@ pub struct MirrorSchedule {
     pub next_schedule: DateTime<Utc>,
 }
 
+// SizeStats是manager统计的镜像大小，单位为字节
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct SizeStats {
+    pub total_bytes: u64,
+    pub workers: BTreeMap<String, u64>,     // 每个worker上所有镜像的大小之和
+    pub unknown: Vec<String>,   // 大小未知的镜像，格式为 worker/mirror
+}
+
 // CmdVerb是对worker或其job的操作
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum CmdVerb {
@@ -153,6 +252,9 @@ pub struct ClientCmd {
     pub worker_id: String,
     pub args: Vec<String>,
     pub options: HashMap<String, bool>,
+    // 设置了selector时，命令会被发送给所有匹配的镜像，mirror_id和worker_id作为额外的匹配条件
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<CmdSelector>,
 }
 impl Default for ClientCmd {
     fn default() -> Self {
@@ -162,8 +264,213 @@ impl Default for ClientCmd {
             worker_id: "".to_string(),
             args: Vec::new(),
             options: HashMap::new(),
+            selector: None,
         }
     }
 }
 
+// CmdSelector描述批量命令的目标，不同字段之间是"与"的关系，同一字段内的多个值是"或"的关系，
+// 空字段表示不做限制。mirrors和workers支持glob模式，例如 debian*
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct CmdSelector {
+    pub mirrors: Vec<String>,
+    pub workers: Vec<String>,
+    pub statuses: Vec<SyncStatus>,
+    pub tags: Vec<String>,
+}
+
+impl CmdSelector {
+    // validate检查所有glob模式是否合法
+    pub fn validate(&self) -> Result<(), String> {
+        for p in self.mirrors.iter().chain(self.workers.iter()) {
+            if let Err(e) = glob::Pattern::new(p) {
+                return Err(format!("无效的模式 {}: {}", p, e))
+            }
+        }
+        Ok(())
+    }
+
+    // matches判断一个镜像是否被选中
+    pub fn matches(&self, m: &MirrorStatus) -> bool {
+        fn match_any(patterns: &[String], name: &str) -> bool {
+            patterns.is_empty() || patterns.iter().any(|p| {
+                glob::Pattern::new(p).map(|p| p.matches(name)).unwrap_or(false)
+            })
+        }
+        match_any(&self.mirrors, &m.name)
+            && match_any(&self.workers, &m.worker)
+            && (self.statuses.is_empty() || self.statuses.contains(&m.status))
+            && (self.tags.is_empty() || m.metadata.has_any_tag(&self.tags))
+    }
+}
+
+impl MirrorMetadata {
+    // has_any_tag判断镜像是否带有tags中的任意一个标签
+    pub fn has_any_tag(&self, tags: &[String]) -> bool {
+        tags.iter().any(|t| self.tags.contains(t))
+    }
+}
+
+// CmdResult是批量命令中发给一个镜像的命令的结果
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct CmdResult {
+    pub worker_id: String,
+    pub mirror_id: String,
+    pub success: bool,
+    pub reply: String,
+}
+
+
+
+// AuditEntry是一条审计记录，记录了谁在什么时候对哪个镜像/worker执行了什么操作，以及得到的响应
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub source: String,     // 请求的来源地址
+    pub identity: String,   // 发起请求的身份（API key 对应的名字）
+    pub verb: String,       // start/stop/disable/restart/reload/rm-worker/flush/set-size 等
+    pub mirror_id: String,
+    pub worker_id: String,
+    pub args: Vec<String>,
+    pub success: bool,
+    pub reply: String,      // worker（或manager）给出的响应
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::SyncStatus::{Failed, Success};
+
+    #[test]
+    fn test_cmd_selector() {
+        let status = |name: &str, worker: &str, status: SyncStatus, tags: &[&str]| MirrorStatus {
+            name: name.to_string(),
+            worker: worker.to_string(),
+            status,
+            metadata: MirrorMetadata {
+                tags: tags.iter().map(|t| t.to_string()).collect(),
+                ..MirrorMetadata::default()
+            },
+            ..MirrorStatus::default()
+        };
+        let debian = status("debian", "w1", Failed, &["distro"]);
+        let debian_cd = status("debian-cd", "w2", Success, &["distro", "iso"]);
+        let pypi = status("pypi", "w1", Failed, &["lang-pkg"]);
+
+        // 空的selector选中所有镜像
+        let selector = CmdSelector::default();
+        assert!(selector.matches(&debian) && selector.matches(&debian_cd) && selector.matches(&pypi));
+
+        let selector = CmdSelector {
+            mirrors: vec!["debian*".to_string()],
+            ..CmdSelector::default()
+        };
+        assert!(selector.matches(&debian) && selector.matches(&debian_cd));
+        assert!(!selector.matches(&pypi));
+
+        let selector = CmdSelector {
+            mirrors: vec!["debian".to_string(), "pypi".to_string()],
+            statuses: vec![Failed],
+            ..CmdSelector::default()
+        };
+        assert!(selector.matches(&debian) && selector.matches(&pypi));
+        assert!(!selector.matches(&debian_cd));
+
+        let selector = CmdSelector {
+            workers: vec!["w?".to_string()],
+            tags: vec!["iso".to_string(), "lang-pkg".to_string()],
+            ..CmdSelector::default()
+        };
+        assert!(!selector.matches(&debian));
+        assert!(selector.matches(&debian_cd) && selector.matches(&pypi));
+
+        let selector = CmdSelector {
+            mirrors: vec!["debian[".to_string()],
+            ..CmdSelector::default()
+        };
+        assert!(selector.validate().is_err());
+        assert!(CmdSelector::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_mirror_status_size_wire() {
+        let m = MirrorStatus {
+            name: "debian".to_string(),
+            size: crate::size::MirrorSize::parse_rsync("1.33T"),
+            ..MirrorStatus::default()
+        };
+        let value = serde_json::to_value(&m).unwrap();
+        // size和旧版本一样是字符串，字节数在size_bytes中
+        assert_eq!(value["size"], "1.33T");
+        assert_eq!(value["size_bytes"], 1_330_000_000_000u64);
+        let m2: MirrorStatus = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(m2.size, m.size);
+
+        // 旧版本只有字符串
+        let mut old = value;
+        old.as_object_mut().unwrap().remove("size_bytes");
+        old["size"] = "4GB".into();
+        let m3: MirrorStatus = serde_json::from_value(old.clone()).unwrap();
+        assert_eq!(m3.size.bytes, Some(4_000_000_000));
 
+        // 上一版本保存的 {bytes, raw}
+        old["size"] = serde_json::json!({"bytes": 2048, "raw": "2K"});
+        let m4: MirrorStatus = serde_json::from_value(old).unwrap();
+        assert_eq!(m4.size.bytes, Some(2048));
+        assert_eq!(m4.size.raw, "2K");
+    }
+
+    #[test]
+    fn test_resource_usage() {
+        let stage1 = ResourceUsage {
+            user_time_ms: 100,
+            system_time_ms: 20,
+            max_rss_bytes: 4096,
+            read_bytes: 1000,
+            write_bytes: 0,
+            elapsed_ms: 500,
+        };
+        let stage2 = ResourceUsage {
+            user_time_ms: 300,
+            system_time_ms: 40,
+            max_rss_bytes: 1024,
+            read_bytes: 2000,
+            write_bytes: 5000,
+            elapsed_ms: 1500,
+        };
+        let mut total = stage1;
+        total.add(&stage2);
+        assert_eq!(total, ResourceUsage {
+            user_time_ms: 400,
+            system_time_ms: 60,
+            max_rss_bytes: 4096,
+            read_bytes: 3000,
+            write_bytes: 5000,
+            elapsed_ms: 2000,
+        });
+
+        // cgroup中读取不到的项（为0）保留原来的统计
+        let cgroup = ResourceUsage {
+            user_time_ms: 90,
+            system_time_ms: 10,
+            max_rss_bytes: 8192,
+            ..ResourceUsage::default()
+        };
+        let mut merged = stage1;
+        merged.merge(&cgroup);
+        assert_eq!(merged, ResourceUsage {
+            user_time_ms: 90,
+            system_time_ms: 10,
+            max_rss_bytes: 8192,
+            read_bytes: 1000,
+            write_bytes: 0,
+            elapsed_ms: 500,
+        });
+
+        // 旧版本的worker不会上报资源消耗
+        let status: MirrorStatus = serde_json::from_str(&serde_json::to_string(&MirrorStatus::default()).unwrap()).unwrap();
+        assert_eq!(status.usage, None);
+        assert!(!serde_json::to_string(&status).unwrap().contains("usage"));
+    }
+}
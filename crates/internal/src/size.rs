@@ -0,0 +1,262 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// MirrorSize是镜像的大小，bytes是解析得到的字节数，raw保留了原始的字符串用于显示
+// 无法解析的大小（例如 "unknown"）bytes为None
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MirrorSize {
+    pub bytes: Option<u64>,
+    pub raw: String,
+}
+
+impl MirrorSize {
+    // parse解析 du -h、ls -h 等输出的大小，不带B的单位（如 1.5G）按1024进制计算
+    pub fn parse(s: &str) -> MirrorSize {
+        MirrorSize {
+            bytes: parse_bytes(s, 1024),
+            raw: s.trim().to_string(),
+        }
+    }
+
+    // parse_rsync解析 rsync -h 输出的大小，rsync在这个级别下按1000进制计算（如 1.33T）
+    pub fn parse_rsync(s: &str) -> MirrorSize {
+        MirrorSize {
+            bytes: parse_bytes(s, 1000),
+            raw: s.trim().to_string(),
+        }
+    }
+
+    pub fn from_bytes(bytes: u64) -> MirrorSize {
+        MirrorSize {
+            bytes: Some(bytes),
+            raw: format_bytes(bytes),
+        }
+    }
+
+    pub fn unknown() -> MirrorSize {
+        MirrorSize {
+            bytes: None,
+            raw: "unknown".to_string(),
+        }
+    }
+
+    // is_unknown判断这个大小是否没有意义，没有意义的大小不应该覆盖已知的大小
+    pub fn is_unknown(&self) -> bool {
+        self.bytes.is_none() && (self.raw.is_empty() || self.raw == "unknown")
+    }
+}
+
+impl fmt::Display for MirrorSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.raw.is_empty() {
+            write!(f, "{}", self.raw)
+        } else if let Some(bytes) = self.bytes {
+            write!(f, "{}", format_bytes(bytes))
+        } else {
+            write!(f, "unknown")
+        }
+    }
+}
+
+impl FromStr for MirrorSize {
+    type Err = Infallible;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(MirrorSize::parse(s))
+    }
+}
+
+// 和旧版本一样序列化为字符串，字节数由wire另外以size_bytes字段给出
+impl Serialize for MirrorSize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+// 兼容旧版本中以字符串保存的大小，以及直接给出的字节数
+impl<'de> Deserialize<'de> for MirrorSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Raw(String),
+            Bytes(u64),
+            Full {
+                #[serde(default)]
+                bytes: Option<u64>,
+                #[serde(default)]
+                raw: String,
+            },
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Raw(s) => MirrorSize::parse(&s),
+            Repr::Bytes(bytes) => MirrorSize::from_bytes(bytes),
+            Repr::Full { bytes: None, raw } => MirrorSize::parse(&raw),
+            Repr::Full { bytes, raw } => MirrorSize { bytes, raw },
+        })
+    }
+}
+
+// wire用于消息中的size字段（配合 #[serde(flatten, with = "crate::size::wire")]），
+// size仍然是字符串，解析出的字节数放在新增的size_bytes字段中，旧版本会忽略这个字段
+pub mod wire {
+    use super::MirrorSize;
+    use serde::ser::SerializeMap;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(size: &MirrorSize, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("size", size)?;
+        if let Some(bytes) = size.bytes {
+            map.serialize_entry("size_bytes", &bytes)?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<MirrorSize, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Wire {
+            #[serde(default)]
+            size: MirrorSize,
+            #[serde(default)]
+            size_bytes: Option<u64>,
+        }
+        let Wire { mut size, size_bytes } = Wire::deserialize(deserializer)?;
+        if size_bytes.is_some() {
+            size.bytes = size_bytes;
+        }
+        Ok(size)
+    }
+}
+
+// parse_bytes把 "1.33T"、"512MiB"、"4 GB"、"1,234 bytes" 这样的大小解析为字节数
+// 带有iB的单位按1024进制，带有B的单位（如 GB）按1000进制，单独的字母按base进制
+fn parse_bytes(s: &str, base: u64) -> Option<u64> {
+    let s = s.trim();
+    let s = s.strip_suffix("bytes").or_else(|| s.strip_suffix("byte")).unwrap_or(s).trim_end();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ',')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: String = number.chars().filter(|c| *c != ',').collect();
+    if number.is_empty() {
+        return None
+    }
+
+    let unit = unit.trim().to_ascii_uppercase();
+    let (prefix, base) = if let Some(prefix) = unit.strip_suffix("IB") {
+        (prefix, 1024)
+    } else if let Some(prefix) = unit.strip_suffix('B') {
+        (prefix, if prefix.is_empty() { base } else { 1000 })
+    } else {
+        (unit.as_str(), base)
+    };
+    let exp = match prefix {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        "E" => 6,
+        _ => return None,
+    };
+
+    // 用整数计算小数部分，避免浮点误差
+    let (int_part, frac_part) = number.split_once('.').unwrap_or((&number, ""));
+    if frac_part.contains('.') || frac_part.len() > 18 {
+        return None
+    }
+    let mantissa: u128 = format!("{}{}", int_part, frac_part).parse().ok()?;
+    let scale = 10u128.pow(frac_part.len() as u32);
+    let mult = (base as u128).checked_pow(exp)?;
+    let bytes = mantissa.checked_mul(mult)?.checked_add(scale / 2)? / scale;
+    u64::try_from(bytes).ok()
+}
+
+// format_bytes把字节数格式化为 du -h 风格的字符串，如 1.5G
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if bytes < 1024 {
+        return bytes.to_string()
+    }
+    let mut value = bytes as f64;
+    let mut unit = "";
+    for u in UNITS {
+        if value < 1024.0 {
+            break
+        }
+        value /= 1024.0;
+        unit = u;
+    }
+    format!("{:.2}{}", value, unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(MirrorSize::parse_rsync("1.33T").bytes, Some(1_330_000_000_000));
+        assert_eq!(MirrorSize::parse_rsync("1.33T").raw, "1.33T");
+        assert_eq!(MirrorSize::parse_rsync("37.55M").bytes, Some(37_550_000));
+        assert_eq!(MirrorSize::parse_rsync("1,234,567").bytes, Some(1_234_567));
+        assert_eq!(MirrorSize::parse("1.5G").bytes, Some(1_610_612_736));
+        assert_eq!(MirrorSize::parse("512MiB").bytes, Some(512 * 1024 * 1024));
+        assert_eq!(MirrorSize::parse("4GB").bytes, Some(4_000_000_000));
+        assert_eq!(MirrorSize::parse("4 gb").bytes, Some(4_000_000_000));
+        assert_eq!(MirrorSize::parse("2048 bytes").bytes, Some(2048));
+        assert_eq!(MirrorSize::parse("100B").bytes, Some(100));
+        assert_eq!(MirrorSize::parse("12345").bytes, Some(12345));
+
+        assert!(MirrorSize::parse("unknown").is_unknown());
+        assert!(MirrorSize::parse("").is_unknown());
+        assert!(MirrorSize::default().is_unknown());
+        assert_eq!(MirrorSize::parse("1.2.3G").bytes, None);
+        assert_eq!(MirrorSize::parse("3X").bytes, None);
+        assert!(!MirrorSize::parse("3X").is_unknown());
+        assert_eq!(MirrorSize::parse("99999999E").bytes, None);
+    }
+
+    #[test]
+    fn test_size_display() {
+        assert_eq!(MirrorSize::parse_rsync("1.33T").to_string(), "1.33T");
+        assert_eq!(MirrorSize::from_bytes(1_610_612_736).to_string(), "1.50G");
+        assert_eq!(MirrorSize::from_bytes(1000).to_string(), "1000");
+        assert_eq!(MirrorSize::default().to_string(), "unknown");
+    }
+
+    #[test]
+    fn test_size_ser_de() {
+        let size = MirrorSize::parse_rsync("1.33T");
+        let serialized = serde_json::to_string(&size).unwrap();
+        assert_eq!(serialized, r#""1.33T""#);
+
+        // 由上一版本保存的 {bytes, raw}
+        let size2: MirrorSize = serde_json::from_str(r#"{"bytes":1330000000000,"raw":"1.33T"}"#).unwrap();
+        assert_eq!(size2, size);
+
+        // 旧版本中大小以字符串保存
+        let size: MirrorSize = serde_json::from_str(r#""4GB""#).unwrap();
+        assert_eq!(size.bytes, Some(4_000_000_000));
+        assert_eq!(size.raw, "4GB");
+
+        let size: MirrorSize = serde_json::from_str("2048").unwrap();
+        assert_eq!(size.bytes, Some(2048));
+
+        let size: MirrorSize = serde_json::from_str(r#"{"raw":"1K"}"#).unwrap();
+        assert_eq!(size.bytes, Some(1024));
+    }
+}
@@ -1,6 +1,6 @@
 use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use crate::msg::MirrorStatus;
+use crate::msg::{DiskUsage, MirrorMetadata, MirrorStatus, ResourceUsage};
 use crate::status::SyncStatus;
 
 #[derive(Debug, Clone, Default)]
@@ -70,6 +70,14 @@ pub struct WebMirrorStatus {
     pub scheduled_ts: StampTime,
     pub upstream: String,
     pub size: String,
+    #[serde(default)]
+    pub size_bytes: Option<u64>,
+    #[serde(flatten)]
+    pub metadata: MirrorMetadata,
+    #[serde(default)]
+    pub disks: Vec<DiskUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ResourceUsage>,
 }
 
 
@@ -88,7 +96,11 @@ pub fn build_web_mirror_status(m: MirrorStatus) -> WebMirrorStatus {
         scheduled: TextTime(m.scheduled),
         scheduled_ts: StampTime(m.scheduled),
         upstream: m.upstream,
-        size: m.size,
+        size: m.size.to_string(),
+        size_bytes: m.size.bytes,
+        metadata: m.metadata,
+        disks: m.disks,
+        usage: m.usage,
     }
 }
 
@@ -116,7 +128,15 @@ mod tests{
             scheduled: TextTime(DateTime::from(t)),
             scheduled_ts: StampTime(t.with_timezone(&Utc)),
             size: String::from("5GB"),
+            size_bytes: Some(5_000_000_000),
             upstream: String::from("rsync://mirrors.tuna.tsinghua.edu.cn/tunalinux/"),
+            metadata: MirrorMetadata {
+                tags: vec![String::from("distro")],
+                description: String::from("TUNA Linux"),
+                homepage: String::from("https://tuna.moe/"),
+                maintainers: vec![String::from("tuna")],
+                ..MirrorMetadata::default()
+            },
             ..WebMirrorStatus::default()
         };
         // 序列化 WebMirrorStatus 结构体
@@ -145,7 +165,9 @@ mod tests{
         assert_eq!(m2.scheduled.0.timestamp_nanos_opt().unwrap(), m.scheduled.0.timestamp_nanos_opt().unwrap());
         assert_eq!(m2.scheduled_ts.0.timestamp_nanos_opt().unwrap(), m.scheduled_ts.0.timestamp_nanos_opt().unwrap());
         assert_eq!(m2.size, m.size);
+        assert_eq!(m2.size_bytes, m.size_bytes);
         assert_eq!(m2.upstream, m.upstream);
+        assert_eq!(m2.metadata, m.metadata);
     }
 
     #[test]
@@ -160,7 +182,17 @@ mod tests{
             last_ended: Utc::now(),
             scheduled: Utc::now() + Duration::minutes(5),
             upstream: String::from("mirrors.tuna.tsinghua.edu.cn"),
-            size: String::from("4GB"),
+            size: "4GB".parse().unwrap(),
+            metadata: MirrorMetadata {
+                tags: vec![String::from("distro")],
+                help_url: String::from("https://mirrors.tuna.tsinghua.edu.cn/help/archlinux/"),
+                ..MirrorMetadata::default()
+            },
+            usage: Some(ResourceUsage {
+                user_time_ms: 1500,
+                max_rss_bytes: 64 * 1024 * 1024,
+                ..ResourceUsage::default()
+            }),
             ..Default::default()
         };
 
@@ -183,9 +215,11 @@ mod tests{
         assert_eq!(m2.scheduled_ts.0.timestamp(), m.scheduled.timestamp());
         assert_eq!(m2.scheduled.0.timestamp_nanos_opt().unwrap(), m.scheduled.timestamp_nanos_opt().unwrap());
         assert_eq!(m2.scheduled_ts.0.timestamp_nanos_opt().unwrap(), m.scheduled.timestamp_nanos_opt().unwrap());
-        assert_eq!(m2.size, m.size);
+        assert_eq!(m2.size, m.size.raw);
+        assert_eq!(m2.size_bytes, Some(4_000_000_000));
         assert_eq!(m2.upstream, m.upstream);
-        
+        assert_eq!(m2.metadata, m.metadata);
+        assert_eq!(m2.usage, m.usage);
     }
 }
 
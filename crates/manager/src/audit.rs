@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use log::{error, info};
+use internal::msg::AuditEntry;
+use crate::db::DbAdapter;
+
+// AuditLog记录客户端命令和管理操作，
+// 每条记录都会写入数据库，如果配置了audit_file，还会以JSONL格式追加到该文件中
+#[derive(Clone)]
+pub(crate) struct AuditLog {
+    // 所有克隆共享同一个文件，重载配置时重新打开
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(audit_file: Option<&str>) -> Result<AuditLog, Box<dyn Error>> {
+        Ok(AuditLog { file: Arc::new(Mutex::new(open_audit_file(audit_file)?)) })
+    }
+
+    // reopen重新打开审计日志文件，文件被轮转或者配置中的路径改变后使用新的文件
+    pub(crate) fn reopen(&self, audit_file: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let file = open_audit_file(audit_file)?;
+        *self.file.lock().unwrap() = file;
+        Ok(())
+    }
+
+    // record保存一条审计记录，写入失败只记录错误日志，不影响命令本身的执行结果
+    pub(crate) fn record(&self, adapter: &dyn DbAdapter, entry: AuditEntry) {
+        info!("审计: {}@{} {} worker<{}> 镜像[{}] {:?} => {}",
+            entry.identity, entry.source, entry.verb,
+            entry.worker_id, entry.mirror_id, entry.args, entry.reply);
+
+        if let Some(f) = self.file.lock().unwrap().as_mut() {
+            match serde_json::to_string(&entry) {
+                Ok(line) => {
+                    if let Err(e) = writeln!(f, "{}", line) {
+                        error!("写入审计日志文件失败: {}", e);
+                    }
+                }
+                Err(e) => {
+                    error!("序列化审计记录失败: {}", e);
+                }
+            }
+        }
+
+        if let Err(e) = adapter.add_audit_entry(entry) {
+            error!("保存审计记录到数据库失败: {}", e);
+        }
+    }
+}
+
+fn open_audit_file(audit_file: Option<&str>) -> Result<Option<File>, Box<dyn Error>> {
+    match audit_file.filter(|f| !f.is_empty()) {
+        Some(path) => {
+            let f = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("打开审计日志文件 {} 失败: {}", path, e))?;
+            Ok(Some(f))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use chrono::Utc;
+    use tempfile::Builder;
+    use crate::db::make_db_adapter;
+    use super::*;
+
+    #[test]
+    fn test_audit_log() {
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let db_dir_path = tmp_dir.path().join("leveldb.db");
+        fs::create_dir_all(&db_dir_path).expect("failed to create db directory");
+        let adapter = make_db_adapter("leveldb", db_dir_path.to_str().unwrap()).unwrap();
+
+        let audit_file = tmp_dir.path().join("audit.jsonl");
+        let audit = AuditLog::new(audit_file.to_str()).unwrap();
+        for verb in ["disable", "rm-worker"] {
+            audit.record(&adapter, AuditEntry{
+                timestamp: Utc::now(),
+                source: "127.0.0.1".to_string(),
+                identity: "alice".to_string(),
+                verb: verb.to_string(),
+                worker_id: "test_worker".to_string(),
+                success: true,
+                ..AuditEntry::default()
+            });
+        }
+
+        let content = fs::read_to_string(&audit_file).unwrap();
+        let lines: Vec<AuditEntry> = content.lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].verb, "disable");
+        assert_eq!(lines[1].identity, "alice");
+
+        let entries = adapter.list_audit_entries(Utc::now() - chrono::Duration::minutes(1)).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].verb, "rm-worker");
+
+        // 文件被轮转后重新打开，之后的记录写入新的文件
+        fs::rename(&audit_file, tmp_dir.path().join("audit.jsonl.1")).unwrap();
+        audit.clone().reopen(audit_file.to_str()).unwrap();
+        audit.record(&adapter, AuditEntry{ verb: "reload".to_string(), ..AuditEntry::default() });
+        let content = fs::read_to_string(&audit_file).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.contains("reload"));
+    }
+}
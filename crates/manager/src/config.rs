@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs;
 use std::str::FromStr;
 use clap::ArgMatches;
@@ -13,6 +14,17 @@ pub struct Config {
     pub(crate) debug: bool,
     pub(crate) server: ServerConfig,
     pub(crate) files: FileConfig,
+    #[serde(default)]
+    pub(crate) auth: AuthConfig,
+    #[serde(default)]
+    pub(crate) disk: DiskConfig,
+}
+
+impl Config {
+    // debug返回配置文件中是否开启了调试日志
+    pub fn debug(&self) -> bool {
+        self.debug
+    }
 }
 
 // ServerConfig表示HTTP服务器的配置
@@ -26,6 +38,12 @@ pub(crate) struct ServerConfig {
     pub(crate) ssl_cert: Option<String>,
     #[serde(default)]
     pub(crate) ssl_key: Option<String>,
+    // 用于校验客户端证书的CA，设置后启用双向TLS
+    #[serde(default)]
+    pub(crate) ssl_client_ca: Option<String>,
+    // 为true时拒绝没有提供客户端证书的连接
+    #[serde(default)]
+    pub(crate) ssl_client_required: Option<bool>,
 }
 
 // FileConfig包含特殊文件的路径
@@ -39,6 +57,36 @@ pub(crate) struct  FileConfig {
     pub(crate) db_type: Option<String>,
     #[serde(default)]
     pub(crate) ca_cert: Option<String>,
+    // 向worker发送命令时使用的客户端证书和私钥
+    #[serde(default)]
+    pub(crate) client_cert: Option<String>,
+    #[serde(default)]
+    pub(crate) client_key: Option<String>,
+    // 审计日志文件，每行一条JSON格式的记录
+    #[serde(default)]
+    pub(crate) audit_file: Option<String>,
+}
+
+// AuthConfig包含客户端身份相关的配置
+#[derive(Debug, Default, Deserialize, Clone)]
+pub(crate) struct AuthConfig {
+    // 身份名 -> API key，客户端通过 `Authorization: Bearer <key>` 请求头表明身份
+    #[serde(default)]
+    pub(crate) api_keys: HashMap<String, String>,
+    // 启用双向TLS时可以管理任意worker的客户端证书CN，如rtsynctl使用的证书
+    #[serde(default)]
+    pub(crate) admin_cns: Vec<String>,
+}
+
+// DiskConfig是worker磁盘空间告警的配置，两个阈值任意一个被突破都会告警
+#[derive(Debug, Default, Deserialize, Clone)]
+pub(crate) struct DiskConfig {
+    // 可用空间低于该值时告警，例如 "100G"
+    #[serde(default)]
+    pub(crate) alert_free_space: Option<String>,
+    // 可用空间占总空间的百分比低于该值时告警
+    #[serde(default)]
+    pub(crate) alert_free_percent: Option<f64>,
 }
 
 // LoadConfig从指定文件加载配置
@@ -89,10 +137,23 @@ mod tests {
 	[server]
 	addr = "0.0.0.0"
 	port = 5000
+	ssl_client_ca = "/etc/rtsync/client-ca.pem"
+	ssl_client_required = true
 
 	[files]
 	status_file = "/tmp/rtsync.json"
 	db_file = "/var/lib/rtsync/rtsync.db"
+	audit_file = "/var/log/rtsync/audit.jsonl"
+
+	[auth]
+	admin_cns = ["rtsynctl"]
+
+	[auth.api_keys]
+	alice = "alice-secret"
+
+	[disk]
+	alert_free_space = "100G"
+	alert_free_percent = 5.0
 	"#;
     
     // 解码toml文件，和用toml文件初始化Config对象的测试
@@ -103,8 +164,15 @@ mod tests {
         _conf = toml::from_str(CFG_BLOB).expect("toml decode error");
         assert_eq!(_conf.server.addr.unwrap(), "0.0.0.0".to_string());
         assert_eq!(_conf.server.port.unwrap(), 5000);
+        assert_eq!(_conf.server.ssl_client_ca.unwrap(), "/etc/rtsync/client-ca.pem".to_string());
+        assert!(_conf.server.ssl_client_required.unwrap());
         assert_eq!(_conf.files.status_file.unwrap(), "/tmp/rtsync.json".to_string());
         assert_eq!(_conf.files.db_file.unwrap(), "/var/lib/rtsync/rtsync.db".to_string());
+        assert_eq!(_conf.files.audit_file.unwrap(), "/var/log/rtsync/audit.jsonl".to_string());
+        assert_eq!(_conf.auth.api_keys.get("alice").unwrap(), "alice-secret");
+        assert_eq!(_conf.auth.admin_cns, vec!["rtsynctl".to_string()]);
+        assert_eq!(_conf.disk.alert_free_space.unwrap(), "100G".to_string());
+        assert_eq!(_conf.disk.alert_free_percent.unwrap(), 5.0);
     }
 
 
@@ -1,10 +1,10 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::error::Error;
-use chrono::Utc;
-use internal::msg::{WorkerStatus, MirrorStatus};
+use chrono::{DateTime, Utc};
+use internal::msg::{AuditEntry, WorkerStatus, MirrorStatus, SyncHistoryEntry};
 use serde_json;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use internal::status::SyncStatus;
 use std::path::Path;
 use crate::db_rocksdb::RocksDbAdapter;
@@ -28,6 +28,11 @@ pub(crate) trait DbAdapter: Send + Sync {
     fn list_mirror_states(&self, worker_id: &str) -> Result<Vec<MirrorStatus>, Box<dyn Error>>;
     fn list_all_mirror_states(&self) -> Result<Vec<MirrorStatus>, Box<dyn Error>>;
     fn flush_disabled_jobs(&self) -> Result<(), Box<dyn Error>>;
+    fn add_audit_entry(&self, entry: AuditEntry) -> Result<AuditEntry, Box<dyn Error>>;
+    // list_audit_entries按时间顺序返回since之后的审计记录
+    fn list_audit_entries(&self, since: DateTime<Utc>) -> Result<Vec<AuditEntry>, Box<dyn Error>>;
+    fn add_history_entry(&self, entry: SyncHistoryEntry) -> Result<SyncHistoryEntry, Box<dyn Error>>;
+    fn list_history_entries(&self, worker_id: &str, mirror_id: &str) -> Result<Vec<SyncHistoryEntry>, Box<dyn Error>>;
     fn close(&self) -> Result<(), Box<dyn Error>>;
 }
 
@@ -35,6 +40,15 @@ pub(crate) trait KvAdapter {
     fn init_bucket(&self, bucket: &str) -> Result<(), Box<dyn Error>>;
     fn get(&self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
     fn get_all(&self, bucket: &str) -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>>;
+    // scan_from按键的顺序返回bucket中键不小于start的记录。默认实现读取整个bucket再过滤，
+    // 键有序的数据库应当直接从start开始遍历
+    fn scan_from(&self, bucket: &str, start: &str) -> Result<Vec<(String, Vec<u8>)>, Box<dyn Error>> {
+        let mut results: Vec<(String, Vec<u8>)> = self.get_all(bucket)?.into_iter()
+            .filter(|(key, _)| key.as_str() >= start)
+            .collect();
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(results)
+    }
     fn put(&self, bucket: &str, key: &str, value: Vec<u8>) -> Result<(), Box<dyn Error>>;
     fn delete(&self, bucket: &str, key: &str) -> Result<(), Box<dyn Error>>;
     fn close(&self) -> Result<(), Box<dyn Error>>;
@@ -42,6 +56,17 @@ pub(crate) trait KvAdapter {
 
 const _WORKER_BUCKET_KEY: &str = "worker";
 const _STATUS_BUCKET_KEY: &str = "mirror_status";
+const _AUDIT_BUCKET_KEY: &str = "audit";
+const _HISTORY_BUCKET_KEY: &str = "history";
+// 每个worker上的每个镜像最多保留的同步历史条数
+const HISTORY_LIMIT: usize = 100;
+// 每个镜像新增这么多条同步历史之后才清理一次旧记录，避免每次写入都扫描整个bucket
+const HISTORY_PRUNE_BATCH: usize = 20;
+
+// audit_key返回审计记录的键，即补零到20位的纳秒时间戳
+fn audit_key(timestamp: &DateTime<Utc>) -> String {
+    format!("{:020}", timestamp.timestamp_nanos_opt().unwrap_or_default())
+}
 
 pub(crate) fn make_db_adapter(db_type: &str, db_file: &str) -> Result<impl DbAdapter, Box<dyn Error>> {
     if db_type.eq("leveldb"){
@@ -52,7 +77,7 @@ pub(crate) fn make_db_adapter(db_type: &str, db_file: &str) -> Result<impl DbAda
         return match rusty_leveldb::DB::open(path, options) {
             Ok(inner_db) => { 
                 let db = LeveldbAdapter{db: RefCell::new(inner_db)}; 
-                let kv = KvDbAdapter{db: Arc::new(RwLock::new(db))};
+                let kv = KvDbAdapter::new(db);
                 kv.init()?;
                 Ok(kv)
             },
@@ -67,7 +92,7 @@ pub(crate) fn make_db_adapter(db_type: &str, db_file: &str) -> Result<impl DbAda
         return match redis::Client::open(format!("redis://{}/0", db_file)) {
             Ok(inner_db) => {
                 let db = RedisAdapter { db: inner_db };
-                let kv = KvDbAdapter { db: Arc::new(RwLock::new(db)) };
+                let kv = KvDbAdapter::new(db);
                 kv.init()?;
                 Ok(kv)
             }
@@ -84,7 +109,7 @@ pub(crate) fn make_db_adapter(db_type: &str, db_file: &str) -> Result<impl DbAda
         return match rocksdb::DB::open_default(db_file) {
             Ok(inner_db) => {
                 let db = RocksDbAdapter { db: inner_db };
-                let kv = KvDbAdapter{db: Arc::new(RwLock::new(db))};
+                let kv = KvDbAdapter::new(db);
                 kv.init()?; // init()创建bucket，在go版本中只有boltdb有这个操作，所以实际上重写版本无需这个函数
                 Ok(kv)
             }
@@ -106,6 +131,14 @@ pub(crate) fn make_db_adapter(db_type: &str, db_file: &str) -> Result<impl DbAda
 
 struct KvDbAdapter {
     db: Arc<RwLock<dyn KvAdapter>>,
+    // 每个 mirror/worker 上一次清理之后新增的同步历史条数
+    history_added: Mutex<HashMap<String, usize>>,
+}
+
+impl KvDbAdapter {
+    fn new(db: impl KvAdapter + 'static) -> KvDbAdapter {
+        KvDbAdapter { db: Arc::new(RwLock::new(db)), history_added: Mutex::new(HashMap::new()) }
+    }
 }
 // 静态分析工具：可以使用像 Clippy、Miri 等工具来检测潜在的线程安全问题。
 // Miri 是一个执行 Rust 程序的工具，它可以帮助发现内存和线程安全问题。
@@ -145,7 +178,13 @@ impl DbAdapter for KvDbAdapter {
         create_bucket(_WORKER_BUCKET_KEY)?;
 
         // 尝试创建 _STATUS_BUCKET_KEY
-        create_bucket(_STATUS_BUCKET_KEY)
+        create_bucket(_STATUS_BUCKET_KEY)?;
+
+        // 尝试创建 _AUDIT_BUCKET_KEY
+        create_bucket(_AUDIT_BUCKET_KEY)?;
+
+        // 尝试创建 _HISTORY_BUCKET_KEY
+        create_bucket(_HISTORY_BUCKET_KEY)
         
     }
 
@@ -414,6 +453,122 @@ impl DbAdapter for KvDbAdapter {
         
     }
 
+    fn add_audit_entry(&self, entry: AuditEntry) -> Result<AuditEntry, Box<dyn Error>> {
+        match self.db.write().map_err(|e| {
+            Box::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("获取锁失败: {}", e),
+            )) as Box<dyn Error>}) {
+            Ok(db) => {
+                // 以纳秒时间戳作为键，补零后字典序即时间顺序；同一纳秒内的记录追加补零的序号避免覆盖
+                let base = audit_key(&entry.timestamp);
+                let mut key = base.clone();
+                let mut seq = 0;
+                while db.get(_AUDIT_BUCKET_KEY, &key)?.is_some() {
+                    seq += 1;
+                    key = format!("{}-{:06}", base, seq);
+                }
+                let value = serde_json::to_vec(&entry)?;
+                db.put(_AUDIT_BUCKET_KEY, &key, value)?;
+                Ok(entry)
+            }
+            Err(e) => {
+                Err(e)
+            }
+        }
+    }
+
+    fn list_audit_entries(&self, since: DateTime<Utc>) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+        match self.db.read().map_err(|e| {
+            Box::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("获取锁失败: {}", e),
+            )) as Box<dyn Error>}) {
+            Ok(db) => {
+                let values = db.scan_from(_AUDIT_BUCKET_KEY, &audit_key(&since))?;
+                let mut entries = Vec::with_capacity(values.len());
+                for (_key, value) in values {
+                    match serde_json::from_slice::<AuditEntry>(&value) {
+                        Ok(entry) => entries.push(entry),
+                        Err(e) => eprintln!("反序列化 AuditEntry 失败: {}", e),
+                    }
+                }
+                Ok(entries)
+            }
+            Err(e) => {
+                Err(e)
+            }
+        }
+    }
+
+    fn add_history_entry(&self, entry: SyncHistoryEntry) -> Result<SyncHistoryEntry, Box<dyn Error>> {
+        match self.db.write().map_err(|e| {
+            Box::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("获取锁失败: {}", e),
+            )) as Box<dyn Error>}) {
+            Ok(db) => {
+                // 键的格式为 mirror/worker/结束时间的纳秒时间戳，补零后字典序即时间顺序
+                let prefix = format!("{}/{}/", entry.name, entry.worker);
+                let key = format!("{}{:020}", prefix, entry.ended.timestamp_nanos_opt().unwrap_or_default());
+                let value = serde_json::to_vec(&entry)?;
+                db.put(_HISTORY_BUCKET_KEY, &key, value)?;
+
+                // 每新增HISTORY_PRUNE_BATCH条记录删除一次超出条数限制的旧记录，
+                // manager启动后第一次写入时也会清理
+                let mut history_added = self.history_added.lock().unwrap();
+                let added = history_added.entry(prefix.clone()).or_insert(HISTORY_PRUNE_BATCH - 1);
+                *added += 1;
+                if *added < HISTORY_PRUNE_BATCH {
+                    return Ok(entry)
+                }
+                *added = 0;
+                drop(history_added);
+                let all_vals = db.get_all(_HISTORY_BUCKET_KEY)?;
+                let mut keys: Vec<&String> = all_vals.keys().filter(|k| k.starts_with(&prefix)).collect();
+                if keys.len() > HISTORY_LIMIT {
+                    keys.sort();
+                    for key in &keys[..keys.len() - HISTORY_LIMIT] {
+                        db.delete(_HISTORY_BUCKET_KEY, key)?;
+                    }
+                }
+                Ok(entry)
+            }
+            Err(e) => {
+                Err(e)
+            }
+        }
+    }
+
+    fn list_history_entries(&self, worker_id: &str, mirror_id: &str) -> Result<Vec<SyncHistoryEntry>, Box<dyn Error>> {
+        match self.db.read().map_err(|e| {
+            Box::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("获取锁失败: {}", e),
+            )) as Box<dyn Error>}) {
+            Ok(db) => {
+                let prefix = format!("{}/{}/", mirror_id, worker_id);
+                let all_vals = db.get_all(_HISTORY_BUCKET_KEY)?;
+                let mut keys: Vec<&String> = all_vals.keys().filter(|k| k.starts_with(&prefix)).collect();
+                keys.sort();
+                // 旧记录是分批清理的，这里只返回最近的HISTORY_LIMIT条
+                let keys = &keys[keys.len().saturating_sub(HISTORY_LIMIT)..];
+
+                let mut entries = Vec::with_capacity(keys.len());
+                for &key in keys {
+                    match serde_json::from_slice::<SyncHistoryEntry>(&all_vals[key]) {
+                        Ok(entry) => entries.push(entry),
+                        Err(e) => eprintln!("反序列化 SyncHistoryEntry 失败: {}", e),
+                    }
+                }
+                Ok(entries)
+            }
+            Err(e) => {
+                Err(e)
+            }
+        }
+    }
+
     fn close(&self) -> Result<(), Box<dyn Error>> {
         match self.db.read().map_err(|e| {
             Box::new(std::io::Error::new(
@@ -441,6 +596,7 @@ mod tests {
     use std::fs::File;
     use chrono::Duration;
     use tempfile::Builder;
+    use internal::msg::ResourceUsage;
     use super::*;
     
     fn sort_mirror_status(status: &mut Vec<MirrorStatus>) {
@@ -558,6 +714,56 @@ mod tests {
         db.flush_disabled_jobs().unwrap();
         let ms = db.list_all_mirror_states().unwrap();
         assert_eq!(ms.len(), 2);
+
+        // 测试审计记录，同一时刻的多条记录都应被保留，且按时间顺序返回
+        let now = Utc::now();
+        for (i, verb) in ["start", "stop", "rm-worker"].iter().enumerate() {
+            let entry = AuditEntry{
+                timestamp: if i == 2 { now + Duration::seconds(1) } else { now },
+                verb: verb.to_string(),
+                worker_id: test_worker_ids[0].to_string(),
+                ..Default::default()
+            };
+            db.add_audit_entry(entry).unwrap();
+        }
+        let entries = db.list_audit_entries(now - Duration::seconds(1)).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].verb, "rm-worker");
+        // 只读取since之后的记录
+        let entries = db.list_audit_entries(now + Duration::milliseconds(500)).unwrap();
+        assert_eq!(entries.iter().map(|e| e.verb.as_str()).collect::<Vec<_>>(), vec!["rm-worker"]);
+        // 同一纳秒内超过9条记录时仍然按照写入的顺序返回
+        let later = now + Duration::seconds(2);
+        for i in 0..12 {
+            db.add_audit_entry(AuditEntry{ timestamp: later, args: vec![i.to_string()], ..Default::default() }).unwrap();
+        }
+        let entries = db.list_audit_entries(later).unwrap();
+        assert_eq!(entries.iter().map(|e| e.args[0].clone()).collect::<Vec<_>>(),
+                   (0..12).map(|i| i.to_string()).collect::<Vec<_>>());
+
+        // 测试同步历史，按结束时间排序，超出条数限制的旧记录被删除
+        for i in 0..HISTORY_LIMIT + 2 {
+            let entry = SyncHistoryEntry{
+                name: "arch-sync1".to_string(),
+                worker: test_worker_ids[0].to_string(),
+                status: if i % 2 == 0 { SyncStatus::Success } else { SyncStatus::Failed },
+                ended: now + Duration::seconds(i as i64),
+                usage: Some(ResourceUsage{ user_time_ms: i as u64, ..ResourceUsage::default() }),
+                ..SyncHistoryEntry::default()
+            };
+            db.add_history_entry(entry).unwrap();
+        }
+        db.add_history_entry(SyncHistoryEntry{
+            name: "arch-sync1".to_string(),
+            worker: test_worker_ids[1].to_string(),
+            ..SyncHistoryEntry::default()
+        }).unwrap();
+        let entries = db.list_history_entries(test_worker_ids[0], "arch-sync1").unwrap();
+        assert_eq!(entries.len(), HISTORY_LIMIT);
+        assert_eq!(entries[0].usage.unwrap().user_time_ms, 2);
+        assert_eq!(entries[HISTORY_LIMIT - 1].usage.unwrap().user_time_ms, HISTORY_LIMIT as u64 + 1);
+        assert_eq!(db.list_history_entries(test_worker_ids[1], "arch-sync1").unwrap().len(), 1);
+        assert!(db.list_history_entries(test_worker_ids[0], "arch-sync2").unwrap().is_empty());
     }
     #[test]
     fn test_leveldb_adapter(){
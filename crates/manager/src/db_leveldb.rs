@@ -46,6 +46,20 @@ impl KvAdapter for LeveldbAdapter {
         Ok(results)
     }
 
+    fn scan_from(&self, bucket: &str, start: &str) -> Result<Vec<(String, Vec<u8>)>, Box<dyn Error>> {
+        let mut results = Vec::new();
+        let prefix = bucket.as_bytes();
+        // leveldb中的键是有序的，直接定位到start，遍历到bucket结束为止
+        let mut iterator = self.db.borrow_mut().new_iter()?;
+        iterator.seek(format!("{}{}", bucket, start).as_bytes());
+        let (mut key, mut value) = (Vec::new(), Vec::new());
+        while iterator.valid() && iterator.current(&mut key, &mut value) && key.starts_with(prefix) {
+            results.push((String::from_utf8_lossy(&key[prefix.len()..]).to_string(), value.clone()));
+            iterator.advance();
+        }
+        Ok(results)
+    }
+
     fn put(&self, bucket: &str, key: &str, value: Vec<u8>) -> Result<(), Box<dyn Error>> {
         // 拼接 bucket 和 key 作为数据库的存储键
         let full_key = format!("{}{}", bucket, key);
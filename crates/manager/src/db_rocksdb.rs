@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use rocksdb::DB;
+use rocksdb::{Direction, IteratorMode, DB};
 use std::error::Error;
 use crate::db::KvAdapter;
 
@@ -39,6 +39,19 @@ impl KvAdapter for RocksDbAdapter {
         Ok(results)
     }
 
+    fn scan_from(&self, bucket: &str, start: &str) -> Result<Vec<(String, Vec<u8>)>, Box<dyn Error>> {
+        let mut results = Vec::new();
+        let full_key = format!("{}{}", bucket, start);
+
+        // 从start开始正向遍历，遇到其他bucket的键时停止
+        for item in self.db.iterator(IteratorMode::From(full_key.as_bytes(), Direction::Forward)) {
+            let (key, value) = item?;
+            let Some(actual_key) = key.strip_prefix(bucket.as_bytes()) else { break };
+            results.push((String::from_utf8(actual_key.to_vec())?, value.to_vec()));
+        }
+        Ok(results)
+    }
+
     fn put(&self, bucket: &str, key: &str, value: Vec<u8>) -> Result<(), Box<dyn Error>> {
         // 拼接 bucket 和 key
         let full_key = format!("{}{}", bucket, key);
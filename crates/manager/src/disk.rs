@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use log::{error, warn};
+use internal::msg::{DiskUsage, MirrorStatus, WorkerDiskUsage};
+use internal::size::{format_bytes, MirrorSize};
+use crate::config::DiskConfig;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Threshold {
+    free_bytes: Option<u64>,
+    free_percent: Option<f64>,
+}
+
+impl Threshold {
+    fn from_config(cfg: &DiskConfig) -> Threshold {
+        let free_bytes = cfg.alert_free_space.as_deref()
+            .filter(|s| !s.is_empty())
+            .and_then(|s| {
+                let bytes = MirrorSize::parse(s).bytes;
+                if bytes.is_none() {
+                    error!("磁盘告警阈值 alert_free_space 无效: {}", s);
+                }
+                bytes
+            });
+        Threshold {
+            free_bytes,
+            free_percent: cfg.alert_free_percent,
+        }
+    }
+}
+
+// DiskAlert根据配置的阈值判断worker上的文件系统可用空间是否过低，重载配置时可以替换阈值
+#[derive(Clone, Default)]
+pub(crate) struct DiskAlert(Arc<RwLock<Threshold>>);
+
+impl DiskAlert {
+    pub(crate) fn new(cfg: &DiskConfig) -> DiskAlert {
+        DiskAlert(Arc::new(RwLock::new(Threshold::from_config(cfg))))
+    }
+
+    pub(crate) fn replace(&self, cfg: &DiskConfig) {
+        *self.0.write().unwrap() = Threshold::from_config(cfg);
+    }
+
+    pub(crate) fn is_low(&self, usage: &DiskUsage) -> bool {
+        let threshold = *self.0.read().unwrap();
+        threshold.free_bytes.is_some_and(|min| usage.free_bytes < min)
+            || threshold.free_percent.is_some_and(|min| {
+                usage.total_bytes > 0 && (usage.free_bytes as f64 / usage.total_bytes as f64) * 100.0 < min
+            })
+    }
+
+    // check对可用空间低于阈值的文件系统发出告警
+    pub(crate) fn check(&self, worker: &str, disks: &[DiskUsage]) {
+        for usage in disks.iter().filter(|u| self.is_low(u)) {
+            warn!("worker <{}> 上 {} 所在的文件系统可用空间不足: 剩余 {} / 共 {}",
+                worker, usage.path, format_bytes(usage.free_bytes), format_bytes(usage.total_bytes));
+        }
+    }
+
+    // summarize从镜像状态中汇总每个worker上各个文件系统最新的空间使用情况
+    pub(crate) fn summarize(&self, states: Vec<MirrorStatus>) -> Vec<WorkerDiskUsage> {
+        let mut latest: BTreeMap<(String, String), DiskUsage> = BTreeMap::new();
+        for m in states {
+            for usage in m.disks {
+                let key = (m.worker.clone(), usage.path.clone());
+                if latest.get(&key).is_none_or(|u| u.updated < usage.updated) {
+                    latest.insert(key, usage);
+                }
+            }
+        }
+        latest.into_iter()
+            .map(|((worker, _), usage)| WorkerDiskUsage {
+                worker,
+                low: self.is_low(&usage),
+                usage,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use super::*;
+
+    #[test]
+    fn test_disk_alert() {
+        let usage = |path: &str, free_bytes: u64, ago: i64| DiskUsage {
+            path: path.to_string(),
+            total_bytes: 1000 * 1024 * 1024 * 1024,
+            free_bytes,
+            updated: Utc::now() - Duration::minutes(ago),
+        };
+        let gib = 1024 * 1024 * 1024;
+
+        // 没有配置阈值时不会告警
+        let alert = DiskAlert::default();
+        assert!(!alert.is_low(&usage("/data", 0, 0)));
+
+        let alert = DiskAlert::new(&DiskConfig {
+            alert_free_space: Some("100G".to_string()),
+            alert_free_percent: None,
+        });
+        assert!(alert.is_low(&usage("/data", 99 * gib, 0)));
+        assert!(!alert.is_low(&usage("/data", 101 * gib, 0)));
+
+        // 重载后使用新的阈值
+        alert.replace(&DiskConfig {
+            alert_free_space: None,
+            alert_free_percent: Some(5.0),
+        });
+        assert!(!alert.is_low(&usage("/data", 99 * gib, 0)));
+        assert!(alert.is_low(&usage("/data", 49 * gib, 0)));
+
+        let states = vec![
+            MirrorStatus {
+                name: "debian".to_string(),
+                worker: "w1".to_string(),
+                disks: vec![usage("/data", 40 * gib, 10), usage("/data/debian", 500 * gib, 10)],
+                ..MirrorStatus::default()
+            },
+            MirrorStatus {
+                name: "ubuntu".to_string(),
+                worker: "w1".to_string(),
+                disks: vec![usage("/data", 60 * gib, 1)],
+                ..MirrorStatus::default()
+            },
+            MirrorStatus {
+                name: "debian".to_string(),
+                worker: "w2".to_string(),
+                disks: vec![usage("/data", 10 * gib, 5)],
+                ..MirrorStatus::default()
+            },
+        ];
+        let summary = alert.summarize(states);
+        assert_eq!(summary.len(), 3);
+        assert_eq!((summary[0].worker.as_str(), summary[0].usage.path.as_str()), ("w1", "/data"));
+        assert_eq!(summary[0].usage.free_bytes, 60 * gib);
+        assert!(!summary[0].low);
+        assert_eq!(summary[1].usage.path, "/data/debian");
+        assert_eq!(summary[2].worker, "w2");
+        assert!(summary[2].low);
+    }
+}
@@ -1,4 +1,6 @@
 pub mod config;
+mod audit;
+mod disk;
 mod db;
 mod db_leveldb;
 mod db_redis;
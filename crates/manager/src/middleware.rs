@@ -1,4 +1,10 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use chrono::Utc;
+use internal::msg::AuditEntry;
 use rocket::{Request, Response};
+use rocket::mtls::Certificate;
 use rocket::fairing::{Fairing, Info, Kind};
 use rocket::http::Status;
 use log::error;
@@ -41,7 +47,7 @@ impl<'r> FromRequest<'r> for CheckWorkerId {
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let id = request.param::<&str>(1).unwrap().unwrap();
-        match request.rocket().state::<Box<dyn DbAdapter>>(){
+        match request.rocket().state::<Arc<dyn DbAdapter>>(){
             Some(adapter) => {
                 if let Err(_) = adapter.get_worker(id) {
                     // 这个worker不存在
@@ -61,3 +67,222 @@ impl<'r> FromRequest<'r> for CheckWorkerId {
 }
 
 
+
+// ApiKeys保存 身份名 -> API key 的映射，用于识别客户端身份，重载配置时会被替换
+#[derive(Clone, Default)]
+pub(crate) struct ApiKeys(Arc<RwLock<HashMap<String, String>>>);
+
+impl ApiKeys {
+    pub(crate) fn new(keys: HashMap<String, String>) -> ApiKeys {
+        ApiKeys(Arc::new(RwLock::new(keys)))
+    }
+
+    pub(crate) fn replace(&self, keys: HashMap<String, String>) {
+        *self.0.write().unwrap() = keys;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.read().unwrap().is_empty()
+    }
+
+    fn identity_of(&self, key: &str) -> Option<String> {
+        self.0.read().unwrap().iter()
+            .find(|(_, k)| k.as_str() == key)
+            .map(|(name, _)| name.clone())
+    }
+}
+
+// AdminCns保存可以管理任意worker的客户端证书CN，重载配置时会被替换
+#[derive(Clone, Default)]
+pub(crate) struct AdminCns(Arc<RwLock<Vec<String>>>);
+
+impl AdminCns {
+    pub(crate) fn new(cns: Vec<String>) -> AdminCns {
+        AdminCns(Arc::new(RwLock::new(cns)))
+    }
+
+    pub(crate) fn replace(&self, cns: Vec<String>) {
+        *self.0.write().unwrap() = cns;
+    }
+
+    fn contains(&self, cn: &str) -> bool {
+        self.0.read().unwrap().iter().any(|c| c == cn)
+    }
+}
+
+// SharedClient是manager向worker发送命令时使用的http客户端，重载配置时会被替换
+#[derive(Clone)]
+pub(crate) struct SharedClient(Arc<RwLock<reqwest::Client>>);
+
+impl SharedClient {
+    pub(crate) fn new(client: reqwest::Client) -> SharedClient {
+        SharedClient(Arc::new(RwLock::new(client)))
+    }
+
+    pub(crate) fn replace(&self, client: reqwest::Client) {
+        *self.0.write().unwrap() = client;
+    }
+
+    pub(crate) fn get(&self) -> reqwest::Client {
+        self.0.read().unwrap().clone()
+    }
+}
+
+// ClientInfo包含请求的来源地址和调用者的身份，用于记录审计日志
+#[derive(Debug)]
+pub(crate) struct ClientInfo {
+    pub(crate) source: String,
+    pub(crate) identity: String,
+    // 客户端证书，用于检查调用者能否访问某个worker
+    pub(crate) cert: PeerCert,
+    // 调用者能否访问审计日志等管理接口
+    pub(crate) admin: bool,
+}
+
+impl ClientInfo {
+    pub(crate) fn audit_entry(&self, verb: &str, worker_id: &str, mirror_id: &str, args: Vec<String>) -> AuditEntry {
+        AuditEntry {
+            timestamp: Utc::now(),
+            source: self.source.clone(),
+            identity: self.identity.clone(),
+            verb: verb.to_string(),
+            mirror_id: mirror_id.to_string(),
+            worker_id: worker_id.to_string(),
+            args,
+            ..AuditEntry::default()
+        }
+    }
+
+    // check_admin检查调用者能否访问管理接口：启用双向TLS时必须使用admin_cns中的证书，
+    // 否则配置了api_keys时必须携带有效的API key，两者都没有配置时不做检查
+    pub(crate) fn check_admin(&self) -> Result<(), (Status, Json<server::Response>)> {
+        if self.admin {
+            return Ok(())
+        }
+        let error = format!("{}@{} 没有访问管理接口的权限", self.identity, self.source);
+        error!("{}", error);
+        Err((Status::Forbidden, Json(server::Response::Error(error))))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let source = request.client_ip()
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        // 优先使用客户端证书的CN作为身份，
+        // 否则根据API key识别：没有携带API key的请求记为anonymous，携带了但无法识别的记为unknown
+        let cert = request.guard::<PeerCert>().await.succeeded().unwrap_or_default();
+        let api_keys = request.rocket().state::<ApiKeys>();
+        let key_identity = request.headers().get_one("Authorization")
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|key| api_keys.and_then(|keys| keys.identity_of(key.trim())));
+        let admin = if cert.verify {
+            cert.admin
+        } else {
+            api_keys.is_none_or(|keys| keys.is_empty()) || matches!(key_identity, Some(Some(_)))
+        };
+        let identity = match cert.cn.clone() {
+            Some(cn) => cn,
+            None => match key_identity {
+                None => "anonymous".to_string(),
+                Some(identity) => identity.unwrap_or_else(|| "unknown".to_string()),
+            },
+        };
+        Outcome::Success(ClientInfo { source, identity, cert, admin })
+    }
+}
+
+// PeerCert包含双向TLS中客户端证书的CN，客户端没有提供证书时为None
+#[derive(Debug, Default)]
+pub(crate) struct PeerCert {
+    pub(crate) cn: Option<String>,
+    // 服务器是否配置了校验客户端证书的CA
+    pub(crate) verify: bool,
+    // 证书的CN是否是admin_cns中的管理员
+    pub(crate) admin: bool,
+}
+
+impl PeerCert {
+    // check_worker检查证书的CN是否与worker_id一致，管理员的证书可以管理任意worker；
+    // 配置了客户端CA时必须提供证书，未启用双向TLS时不做检查
+    pub(crate) fn check_worker(&self, worker_id: &str) -> Result<(), (Status, Json<server::Response>)> {
+        if !self.verify {
+            return Ok(())
+        }
+        let error = match &self.cn {
+            Some(cn) if cn == worker_id || self.admin => return Ok(()),
+            Some(cn) => format!("客户端证书的CN {} 与worker_id {} 不一致", cn, worker_id),
+            None => format!("访问worker {} 需要提供客户端证书", worker_id),
+        };
+        error!("{}", error);
+        Err((Status::Forbidden, Json(server::Response::Error(error))))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PeerCert {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let cn = request.guard::<Certificate<'_>>().await
+            .succeeded()
+            .and_then(|cert| cert.subject().common_name().map(String::from));
+        let verify = request.rocket().config().tls.as_ref().and_then(|tls| tls.mutual()).is_some();
+        let admin = match (&cn, request.rocket().state::<AdminCns>()) {
+            (Some(cn), Some(admins)) => admins.contains(cn),
+            _ => false,
+        };
+        Outcome::Success(PeerCert { cn, verify, admin })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_cert_check_worker() {
+        let cert = |cn: Option<&str>, verify: bool, admin: bool| PeerCert { cn: cn.map(String::from), verify, admin };
+        // 未启用双向TLS时不做检查
+        assert!(cert(None, false, false).check_worker("worker1").is_ok());
+        assert!(cert(Some("worker1"), true, false).check_worker("worker1").is_ok());
+        let err = cert(Some("worker2"), true, false).check_worker("worker1").unwrap_err();
+        assert_eq!(err.0, Status::Forbidden);
+        // 配置了客户端CA时没有证书的请求不能冒充worker
+        let err = cert(None, true, false).check_worker("worker1").unwrap_err();
+        assert_eq!(err.0, Status::Forbidden);
+        assert!(cert(Some("rtsynctl"), true, true).check_worker("worker1").is_ok());
+    }
+
+    #[get("/admin")]
+    fn admin(info: ClientInfo) -> Result<String, (Status, Json<server::Response>)> {
+        info.check_admin().map(|_| info.identity)
+    }
+
+    #[rocket::async_test]
+    async fn test_client_info_check_admin() {
+        use rocket::http::Header;
+        use rocket::local::asynchronous::Client;
+        let client = |keys: HashMap<String, String>| async move {
+            Client::tracked(rocket::build().manage(ApiKeys::new(keys)).mount("/", routes![admin])).await.unwrap()
+        };
+
+        // 没有配置API key和双向TLS时不做检查
+        let open = client(HashMap::new()).await;
+        assert_eq!(open.get("/admin").dispatch().await.status(), Status::Ok);
+
+        // 配置了API key时必须携带有效的API key
+        let guarded = client(HashMap::from([("alice".to_string(), "alice-secret".to_string())])).await;
+        assert_eq!(guarded.get("/admin").dispatch().await.status(), Status::Forbidden);
+        let resp = guarded.get("/admin").header(Header::new("Authorization", "Bearer wrong")).dispatch().await;
+        assert_eq!(resp.status(), Status::Forbidden);
+        let resp = guarded.get("/admin").header(Header::new("Authorization", "Bearer alice-secret")).dispatch().await;
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(resp.into_string().await.unwrap(), "alice");
+    }
+}
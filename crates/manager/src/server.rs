@@ -1,8 +1,12 @@
-use chrono::Utc;
+use std::sync::Arc;
+use chrono::{DateTime, Duration, Utc};
 use reqwest::Client;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::task::JoinSet;
 use crate::config::Config;
-use rocket::{Build, Rocket, State};
-use internal::util::{create_http_client, post_json};
+use rocket::{Build, Ignite, Rocket, State};
+use rocket::fairing::AdHoc;
+use internal::util::{http_client_builder, load_client_identity, post_json};
 use crate::db::{make_db_adapter, DbAdapter};
 use rocket::http::Status;
 use rocket::serde::json::Json;
@@ -11,10 +15,13 @@ use internal::{
     msg::MirrorStatus,
     status_web::WebMirrorStatus,
 };
-use internal::msg::{ClientCmd, CmdVerb, MirrorSchedules, WorkerCmd, WorkerStatus};
+use internal::msg::{AuditEntry, ClientCmd, CmdResult, CmdSelector, CmdVerb, MirrorSchedules, SizeStats, SyncHistoryEntry, WorkerCmd, WorkerDiskUsage, WorkerStatus};
+use internal::size::MirrorSize;
 use internal::status::SyncStatus::{Disabled, Failed, Paused, PreSyncing, Success, Syncing};
 use internal::status_web::build_web_mirror_status;
-use crate::middleware::{CheckWorkerId, ContextErrorLogger};
+use crate::audit::AuditLog;
+use crate::disk::DiskAlert;
+use crate::middleware::{AdminCns, ApiKeys, CheckWorkerId, ClientInfo, ContextErrorLogger, PeerCert, SharedClient};
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -22,65 +29,321 @@ pub(crate) enum Response{
     Message(String),
     Error(String),
 }
+impl Response {
+    pub(crate) fn text(&self) -> &str {
+        match self {
+            Response::Message(s) | Response::Error(s) => s,
+        }
+    }
+}
+
+
+// ManagerCtrl是发送给运行中的manager的控制命令
+enum ManagerCtrl {
+    Reload(Box<Config>),
+    Shutdown,
+}
 
+// ManagerHandle用于在manager运行时通知其重载配置或者退出
+#[derive(Clone)]
+pub struct ManagerHandle {
+    tx: mpsc::Sender<ManagerCtrl>,
+}
+impl ManagerHandle {
+    // reload使manager应用新的配置，正在处理的请求不会被中断
+    pub async fn reload(&self, cfg: Config) {
+        let _ = self.tx.send(ManagerCtrl::Reload(Box::new(cfg))).await;
+    }
+
+    // shutdown使manager处理完正在进行的请求后关闭服务器和数据库
+    pub async fn shutdown(&self) {
+        let _ = self.tx.send(ManagerCtrl::Shutdown).await;
+    }
+}
 
 // 一个Manager代表一个manager服务器
 pub struct Manager{
     pub(crate) cfg: Config,
     pub(crate) engine: Rocket<Build>,
+    adapter: Option<Arc<dyn DbAdapter>>,
+    audit: Option<AuditLog>,
+    api_keys: ApiKeys,
+    admin_cns: AdminCns,
+    disk_alert: DiskAlert,
+    // 向worker发送命令时使用的http客户端
+    client: SharedClient,
+    // 服务器正在使用的证书、私钥和CA的内容，用于判断重载时是否需要重启
+    tls: Vec<Vec<u8>>,
+    ctrl: (mpsc::Sender<ManagerCtrl>, mpsc::Receiver<ManagerCtrl>),
 }
 impl Manager {
+    pub fn handle(&self) -> ManagerHandle {
+        ManagerHandle { tx: self.ctrl.0.clone() }
+    }
+
     pub async fn run(mut self){
-        let mut figment = self.engine.figment().clone();
-        if let (Some(addr), Some(port)) = (self.cfg.server.addr, self.cfg.server.port){
+        let engine = std::mem::replace(&mut self.engine, Rocket::build());
+        let (mut rocket, mut liftoff) = match self.ignite(engine).await {
+            Ok(ignited) => ignited,
+            Err(e) => {
+                error!("{}", e);
+                self.close();
+                return;
+            }
+        };
+        // 重启前正常运行的配置，新的服务器没能启动时恢复
+        let mut previous: Option<Config> = None;
+        loop {
+            let shutdown = rocket.shutdown();
+            let mut server = tokio::spawn(rocket.launch());
+            let mut lifted = false;
+
+            let next = loop {
+                tokio::select! {
+                    result = &mut server => {
+                        if let Ok(Err(e)) = result {
+                            error!("manager 服务器异常退出: {}", e);
+                        }
+                        let Some(cfg) = previous.take() else {
+                            break None;
+                        };
+                        warn!("新的配置无法启动服务器，恢复原有的配置");
+                        self.cfg = cfg;
+                        match self.rebuild().await {
+                            Ok(ignited) => break Some(ignited),
+                            Err(e) => {
+                                error!("{}", e);
+                                break None;
+                            }
+                        }
+                    }
+                    Ok(_) = &mut liftoff, if !lifted => {
+                        lifted = true;
+                        previous = None;
+                    }
+                    Some(ctrl) = self.ctrl.1.recv() => {
+                        match ctrl {
+                            ManagerCtrl::Shutdown => {
+                                info!("正在关闭 manager 服务器");
+                                shutdown.notify();
+                                let _ = (&mut server).await;
+                                break None;
+                            }
+                            ManagerCtrl::Reload(cfg) => {
+                                let old = self.cfg.clone();
+                                if !self.apply_config(*cfg) {
+                                    continue;
+                                }
+                                // 先用新的配置创建服务器，失败时旧的服务器继续运行
+                                match self.rebuild().await {
+                                    Ok(ignited) => {
+                                        // 监听地址或证书发生了变化，平滑地重启服务器，
+                                        // Rocket会等待正在处理的请求结束后再关闭
+                                        info!("重启 manager 服务器以应用新的监听地址和证书");
+                                        shutdown.notify();
+                                        let _ = (&mut server).await;
+                                        previous = Some(old);
+                                        break Some(ignited);
+                                    }
+                                    Err(e) => {
+                                        error!("{}，继续使用原有的监听地址和证书", e);
+                                        self.tls = tls_material(&old).unwrap_or_default();
+                                        self.cfg = old;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            };
+            match next {
+                Some((next_rocket, next_liftoff)) => {
+                    rocket = next_rocket;
+                    liftoff = next_liftoff;
+                }
+                None => break,
+            }
+        }
+        self.close();
+    }
+
+    // close关闭数据库
+    fn close(&self) {
+        if let Some(adapter) = &self.adapter {
+            match adapter.close() {
+                Ok(_) => info!("数据库已关闭"),
+                Err(e) => error!("关闭数据库失败: {}", e),
+            }
+        }
+    }
+
+    // ignite检查配置并创建服务器，返回的通道在服务器开始监听后收到消息
+    async fn ignite(&self, engine: Rocket<Build>) -> Result<(Rocket<Ignite>, oneshot::Receiver<()>), String> {
+        let (tx, rx) = oneshot::channel();
+        let rocket = self.configure(engine)
+            .attach(AdHoc::on_liftoff("Liftoff Notifier", move |_| Box::pin(async move {
+                let _ = tx.send(());
+            })))
+            .ignite().await
+            .map_err(|e| format!("启动 manager 服务器失败: {}", e))?;
+        Ok((rocket, rx))
+    }
+
+    // rebuild用当前的配置重新创建服务器
+    async fn rebuild(&self) -> Result<(Rocket<Ignite>, oneshot::Receiver<()>), String> {
+        self.ignite(self.build_engine()).await
+    }
+
+    // configure根据配置设置监听地址和TLS
+    fn configure(&self, engine: Rocket<Build>) -> Rocket<Build> {
+        let cfg = &self.cfg;
+        // 信号由调用者处理，再通过ManagerHandle通知manager
+        let mut figment = engine.figment().clone()
+            .merge(("shutdown.ctrlc", false))
+            .merge(("shutdown.signals", Vec::<String>::new()));
+        if let (Some(addr), Some(port)) = (cfg.server.addr.clone(), cfg.server.port){
             if !addr.is_empty() {
                 figment = figment
                     .merge((rocket::Config::PORT, port))
                     .merge((rocket::Config::ADDRESS, addr))
             }
         }
-        if let (Some(cert), Some(key)) = (self.cfg.server.ssl_cert, self.cfg.server.ssl_key){
+        if let (Some(cert), Some(key)) = (cfg.server.ssl_cert.clone(), cfg.server.ssl_key.clone()){
             if !cert.is_empty() && !key.is_empty() {
                 figment = figment
                     .merge(("tls.certs", cert))
                     .merge(("tls.key", key));
+                // 双向TLS：使用指定的CA校验客户端证书
+                if let Some(ca) = cfg.server.ssl_client_ca.clone().filter(|ca| !ca.is_empty()) {
+                    figment = figment
+                        .merge(("tls.mutual.ca_certs", ca))
+                        .merge(("tls.mutual.mandatory", cfg.server.ssl_client_required.unwrap_or(false)));
+                }
+            }
+        }
+        engine.configure(figment)
+    }
+
+    // apply_config应用重载后的配置，只有监听地址或者服务器的证书发生变化时才返回true，表示需要重启服务器
+    pub(crate) fn apply_config(&mut self, cfg: Config) -> bool {
+        info!("重载 manager 配置");
+        self.api_keys.replace(cfg.auth.api_keys.clone());
+        self.admin_cns.replace(cfg.auth.admin_cns.clone());
+        self.disk_alert.replace(&cfg.disk);
+        if let Some(audit) = &self.audit {
+            // 重新打开审计日志文件，配合logrotate等工具使用
+            if let Err(e) = audit.reopen(cfg.files.audit_file.as_deref()) {
+                error!("重新打开审计日志文件失败: {}，继续使用原有的文件", e);
             }
         }
-        self.engine = self.engine.configure(figment);
-        
-        self.engine.launch().await.unwrap();
+        // 向worker发送命令使用的客户端证书不需要重启服务器
+        match make_http_client(&cfg) {
+            Ok(client) => self.client.replace(client),
+            Err(e) => error!("{}，继续使用原有的客户端证书", e),
+        }
+
+        // 新的证书无效时继续使用旧的配置，避免服务器无法启动
+        let tls = match tls_material(&cfg) {
+            Ok(tls) => tls,
+            Err(e) => {
+                error!("{}，继续使用原有的证书配置", e);
+                return false;
+            }
+        };
+        let (old, new) = (&self.cfg.server, &cfg.server);
+        let restart = tls != self.tls
+            || old.addr != new.addr
+            || old.port != new.port
+            || old.ssl_client_required != new.ssl_client_required;
+        self.cfg = cfg;
+        self.tls = tls;
+        restart
     }
 
+    fn build_engine(&self) -> Rocket<Build> {
+        let mut engine = Rocket::build().manage(self.client.clone());
+        if let Some(adapter) = &self.adapter {
+            engine = engine.manage(adapter.clone());
+        }
+        if let Some(audit) = &self.audit {
+            engine = engine.manage(audit.clone());
+        }
+        engine
+            .manage(self.api_keys.clone())
+            .manage(self.admin_cns.clone())
+            .manage(self.disk_alert.clone())
+            .attach(ContextErrorLogger)
+            .mount("/", routes![
+                ping,
+                list_all_jobs,
+                size_stats,
+                list_disks,
+                flush_disabled_jobs,
+                list_workers,
+                register_worker,
+                delete_worker,
+                list_jobs_of_worker,
+                update_job_of_worker,
+                list_job_history,
+                update_mirror_size,
+                update_schedules_of_worker,
+                handle_client_cmd,
+                list_audit_entries,
+            ])
+    }
 }
 
-pub fn get_rtsync_manager(cfg: &Config) -> Result<Manager, String>{
-    let mut s = Manager{
-        cfg: cfg.clone(),
-        engine: Rocket::build(),
+fn make_http_client(cfg: &Config) -> Result<Client, String> {
+    let ca_cert = cfg.files.ca_cert.as_deref().filter(|s| !s.is_empty());
+    let identity = match (cfg.files.client_cert.as_deref(), cfg.files.client_key.as_deref()) {
+        (Some(cert), Some(key)) if !cert.is_empty() && !key.is_empty() => {
+            match load_client_identity(cert, key) {
+                Ok(identity) => Some(identity),
+                Err(e) => {
+                    return Err(format!("加载客户端证书失败: {}", e));
+                }
+            }
+        }
+        _ => None,
     };
+    http_client_builder(ca_cert, identity)
+        .and_then(|builder| builder.build())
+        .map_err(|e| format!("初始化http 客户端失败: {}", e))
+}
 
-    let ca_cert = cfg.files.ca_cert.as_deref().filter(|s| !s.is_empty());
-    let client_result = match ca_cert {
-        Some(cert) => create_http_client(Some(cert)),
-        None => create_http_client(None),
+// tls_material读取并检查服务器的证书、私钥和校验客户端证书的CA，没有启用TLS时返回空
+fn tls_material(cfg: &Config) -> Result<Vec<Vec<u8>>, String> {
+    let (cert, key) = match (cfg.server.ssl_cert.as_deref(), cfg.server.ssl_key.as_deref()) {
+        (Some(cert), Some(key)) if !cert.is_empty() && !key.is_empty() => (cert, key),
+        _ => return Ok(vec![]),
     };
-    match client_result {
-        Ok(client) => {
-            s.engine = s.engine.manage(client);
-        }
-        Err(e) => {
-            let err = format!("初始化http 客户端失败: {}", e);
-            log::error!("{}", err);
-            return Err(err);
+    let read = |f: &str| std::fs::read(f).map_err(|e| format!("无法读取证书文件 {}: {}", f, e));
+    let (cert_pem, key_pem) = (read(cert)?, read(key)?);
+    let mut identity = cert_pem.clone();
+    identity.push(b'\n');
+    identity.extend_from_slice(&key_pem);
+    reqwest::Identity::from_pem(&identity)
+        .map_err(|e| format!("证书 {} 或私钥 {} 无效: {}", cert, key, e))?;
+    let mut material = vec![cert_pem, key_pem];
+    if let Some(ca) = cfg.server.ssl_client_ca.as_deref().filter(|ca| !ca.is_empty()) {
+        let ca_pem = read(ca)?;
+        match reqwest::Certificate::from_pem_bundle(&ca_pem) {
+            Ok(certs) if !certs.is_empty() => {}
+            Ok(_) => return Err(format!("CA证书 {} 中没有证书", ca)),
+            Err(e) => return Err(format!("CA证书 {} 无效: {}", ca, e)),
         }
+        material.push(ca_pem);
     }
-    
-    if let (Some(db_type), Some(db_file)) = (&cfg.files.db_type, &cfg.files.db_file){
-        if !db_file.is_empty() && !db_type.is_empty(){
+    Ok(material)
+}
+
+pub fn get_rtsync_manager(cfg: &Config) -> Result<Manager, String>{
+    let client = make_http_client(cfg).inspect_err(|e| log::error!("{}", e))?;
+
+    let adapter: Arc<dyn DbAdapter> = match (&cfg.files.db_type, &cfg.files.db_file) {
+        (Some(db_type), Some(db_file)) if !db_file.is_empty() && !db_type.is_empty() => {
             match make_db_adapter(db_type, db_file) {
-                Ok(adapter) => {
-                    s.engine = s.engine.manage(Box::new(adapter) as Box<dyn DbAdapter>);
-                }
+                Ok(adapter) => Arc::new(adapter),
                 Err(e) => {
                     let err = format!("初始化数据库适配器(db adapter)失败: {}", e);
                     log::error!("{}", err);
@@ -88,25 +351,33 @@ pub fn get_rtsync_manager(cfg: &Config) -> Result<Manager, String>{
                 }
             }
         }
-    }else { 
-        return Err("数据库类型和数据库文件需要指定".into())
-    }
+        _ => {
+            return Err("数据库类型和数据库文件需要指定".into())
+        }
+    };
 
-    s.engine = s.engine.attach(ContextErrorLogger);
+    let audit = match AuditLog::new(cfg.files.audit_file.as_deref()) {
+        Ok(audit) => audit,
+        Err(e) => {
+            let err = format!("初始化审计日志失败: {}", e);
+            log::error!("{}", err);
+            return Err(err);
+        }
+    };
 
-    s.engine = s.engine.mount("/", routes![
-        ping,
-        list_all_jobs,
-        flush_disabled_jobs,
-        list_workers,
-        register_worker,
-        delete_worker,
-        list_jobs_of_worker,
-        update_job_of_worker,
-        update_mirror_size,
-        update_schedules_of_worker,
-        handle_client_cmd,
-    ]);
+    let mut s = Manager{
+        cfg: cfg.clone(),
+        engine: Rocket::build(),
+        adapter: Some(adapter),
+        audit: Some(audit),
+        api_keys: ApiKeys::new(cfg.auth.api_keys.clone()),
+        admin_cns: AdminCns::new(cfg.auth.admin_cns.clone()),
+        disk_alert: DiskAlert::new(&cfg.disk),
+        client: SharedClient::new(client),
+        tls: tls_material(cfg).inspect_err(|e| log::error!("{}", e))?,
+        ctrl: mpsc::channel(1),
+    };
+    s.engine = s.build_engine();
 
     Ok(s)
 }
@@ -116,16 +387,19 @@ pub(crate) async fn ping() -> Json<Response> {
     Json(Response::Message("pong".into()))
 }
 
-// list_all_jobs返回指定worker的所有job
-#[get("/jobs")]
-async fn list_all_jobs(engine: &State<Box<dyn DbAdapter>>)
+// list_all_jobs返回所有worker的所有job，指定tag时只返回带有该标签的job
+#[get("/jobs?<tag>")]
+async fn list_all_jobs(tag: Option<&str>,
+                       engine: &State<Arc<dyn DbAdapter>>)
     -> Result<Json<Vec<WebMirrorStatus>>, (Status, Json<Response>)>
 {
     match engine.list_all_mirror_states(){
         Ok(mirror_status_list) => {
             let mut web_mir_status_list: Vec<WebMirrorStatus> = vec![];
             for m in mirror_status_list{
-                web_mir_status_list.push(build_web_mirror_status(m))
+                if tag.is_none_or(|tag| m.metadata.tags.iter().any(|t| t == tag)){
+                    web_mir_status_list.push(build_web_mirror_status(m))
+                }
             }
             Ok(Json(web_mir_status_list))
         },
@@ -137,22 +411,74 @@ async fn list_all_jobs(engine: &State<Box<dyn DbAdapter>>)
     }
 }
 
+// size_stats返回每个worker以及所有worker上镜像的总大小
+#[get("/stats/size")]
+async fn size_stats(adapter: &State<Arc<dyn DbAdapter>>)
+    -> Result<Json<SizeStats>, (Status, Json<Response>)>
+{
+    match adapter.list_all_mirror_states(){
+        Ok(mirror_status_list) => {
+            let mut stats = SizeStats::default();
+            for m in mirror_status_list{
+                let worker_total = stats.workers.entry(m.worker.clone()).or_default();
+                match m.size.bytes {
+                    Some(bytes) => {
+                        *worker_total += bytes;
+                        stats.total_bytes += bytes;
+                    }
+                    None => stats.unknown.push(format!("{}/{}", m.worker, m.name)),
+                }
+            }
+            Ok(Json(stats))
+        },
+        Err(e) => {
+            let error = format!("在列出所有的镜像的过程中失败：{}", e);
+            error!("{}", error);
+            Err((Status::InternalServerError, Json(Response::Error(error))))
+        }
+    }
+}
+
+// list_disks返回每个worker上各个文件系统最新上报的空间使用情况
+#[get("/disks")]
+async fn list_disks(adapter: &State<Arc<dyn DbAdapter>>,
+                    disk_alert: &State<DiskAlert>)
+    -> Result<Json<Vec<WorkerDiskUsage>>, (Status, Json<Response>)>
+{
+    match adapter.list_all_mirror_states(){
+        Ok(mirror_status_list) => Ok(Json(disk_alert.summarize(mirror_status_list))),
+        Err(e) => {
+            let error = format!("在列出所有的镜像的过程中失败：{}", e);
+            error!("{}", error);
+            Err((Status::InternalServerError, Json(Response::Error(error))))
+        }
+    }
+}
+
 // flush_disabled_jobs删除所有被标记为deleted的job
 #[delete("/jobs/disabled")]
-async fn flush_disabled_jobs(adapter: &State<Box<dyn DbAdapter>>)
+async fn flush_disabled_jobs(info: ClientInfo,
+                             adapter: &State<Arc<dyn DbAdapter>>,
+                             audit: &State<AuditLog>)
     -> Result<Json<Response>, (Status, Json<Response>)>
 {
+    let mut entry = info.audit_entry("flush", "", "", vec![]);
     if let Err(e) = adapter.flush_disabled_jobs(){
         let error = format!("未能刷新已禁用的jobs：{}", e);
         error!("{}", error);
+        entry.reply = error.clone();
+        audit.record(adapter.inner().as_ref(), entry);
         return Err((Status::InternalServerError, Json(Response::Error(error))))
     }
+    entry.success = true;
+    entry.reply = "flushed".into();
+    audit.record(adapter.inner().as_ref(), entry);
     Ok(Json(Response::Message ("flushed".into())))
 }
 
 // list_workers使用所有worker的信息进行响应
 #[get("/workers")]
-async fn list_workers(adapter: &State<Box<dyn DbAdapter>>)
+async fn list_workers(adapter: &State<Arc<dyn DbAdapter>>)
     -> Result<Json<Vec<WorkerStatus>>, (Status, Json<Response>)>
 {
     let mut worker_infos: Vec<WorkerStatus> = vec![];
@@ -179,9 +505,12 @@ async fn list_workers(adapter: &State<Box<dyn DbAdapter>>)
 
 // register_worker注册一个新在线的worker
 #[post("/workers", format = "application/json", data = "<worker>")]
-async fn register_worker(mut worker: Json<WorkerStatus>, adapter: &State<Box<dyn DbAdapter>>)
+async fn register_worker(mut worker: Json<WorkerStatus>,
+                         cert: PeerCert,
+                         adapter: &State<Arc<dyn DbAdapter>>)
     -> Result<Json<WorkerStatus>, (Status, Json<Response>)>
 {
+    cert.check_worker(&worker.id)?;
     worker.last_online = Utc::now();
     worker.last_register = Utc::now();
     match adapter.create_worker(worker.into_inner()){
@@ -201,20 +530,35 @@ async fn register_worker(mut worker: Json<WorkerStatus>, adapter: &State<Box<dyn
 #[delete("/workers/<id>")]
 async fn delete_worker(id: &str,
                        guard: Result<CheckWorkerId, Json<Response>>,
-                       adapter: &State<Box<dyn DbAdapter>>)
+                       info: ClientInfo,
+                       adapter: &State<Arc<dyn DbAdapter>>,
+                       audit: &State<AuditLog>)
     -> Result<Json<Response>, (Status, Json<Response>)>
 {
+    let mut entry = info.audit_entry("rm-worker", id, "", vec![]);
     if let Err(e) = guard{
+        entry.reply = e.text().to_string();
+        audit.record(adapter.inner().as_ref(), entry);
         return Err((Status::BadRequest, e))
     }
+    if let Err((status, e)) = info.cert.check_worker(id) {
+        entry.reply = e.text().to_string();
+        audit.record(adapter.inner().as_ref(), entry);
+        return Err((status, e))
+    }
     match adapter.delete_worker(id) {
         Ok(_) => {
             info!("删除了worker，id为{}",id);
+            entry.success = true;
+            entry.reply = "deleted".into();
+            audit.record(adapter.inner().as_ref(), entry);
             Ok(Json(Response::Message ("deleted".to_owned())))
         }
         Err(e) => {
             let error = format!("删除worker失败：{}", e);
             error!("{}", error);
+            entry.reply = error.clone();
+            audit.record(adapter.inner().as_ref(), entry);
             Err((Status::InternalServerError, Json(Response::Error(error))))
         }
     }
@@ -224,7 +568,7 @@ async fn delete_worker(id: &str,
 #[get("/workers/<id>/jobs")]
 async fn list_jobs_of_worker(id: &str,
                              guard: Result<CheckWorkerId, Json<Response>>,
-                             adapter: &State<Box<dyn DbAdapter>>)
+                             adapter: &State<Arc<dyn DbAdapter>>)
     -> Result<Json<Vec<MirrorStatus>>, (Status, Json<Response>)>
 {
     if let Err(e) = guard{
@@ -246,13 +590,16 @@ async fn list_jobs_of_worker(id: &str,
 async fn update_job_of_worker(id: &str,
                               _job: &str,
                               guard: Result<CheckWorkerId, Json<Response>>,
+                              cert: PeerCert,
                               mut status: Json<MirrorStatus>,
-                              adapter: &State<Box<dyn DbAdapter>>)
+                              adapter: &State<Arc<dyn DbAdapter>>,
+                              disk_alert: &State<DiskAlert>)
     -> Result<Json<MirrorStatus>, (Status, Json<Response>)>
 {
     if let Err(e) = guard{
         return Err((Status::BadRequest, e))
     }
+    cert.check_worker(id)?;
     
     let mirror_name = status.name.clone();
     if mirror_name.len() == 0{
@@ -280,11 +627,33 @@ async fn update_job_of_worker(id: &str,
     }
 
     // 只有大小有意义的消息才会更新镜像大小
-    if cur_status.size.len() > 0 && cur_status.size.ne("unknown"){
-        if status.size.len() == 0 || status.size.eq("unknown"){
-            status.size = cur_status.size;
+    if !cur_status.size.is_unknown() && status.size.is_unknown(){
+        status.size = cur_status.size;
+    }
+
+    // 上报的磁盘空间低于阈值时告警
+    disk_alert.check(id, &status.disks);
+
+    // 同步结束时记录一条同步历史
+    if status.status == Success || status.status == Failed{
+        let entry = SyncHistoryEntry{
+            name: mirror_name.clone(),
+            worker: id.to_string(),
+            status: status.status,
+            started: status.last_started,
+            ended: status.last_ended,
+            size: status.size.clone(),
+            error_msg: status.error_msg.clone(),
+            usage: status.usage,
+        };
+        if let Err(e) = adapter.add_history_entry(entry){
+            error!("记录任务 {} 的同步历史失败，所属worker {} :{}", mirror_name, id, e);
         }
     }
+    // 没有运行同步命令的消息不会覆盖上一次同步的资源消耗
+    if status.usage.is_none(){
+        status.usage = cur_status.usage;
+    }
 
     // 打印日志
     match status.status {
@@ -309,17 +678,66 @@ async fn update_job_of_worker(id: &str,
 
 }
 
+// list_job_history返回一个镜像在某个worker上的同步历史，按结束时间排序，limit限制返回最近的条数
+#[get("/workers/<id>/jobs/<job>/history?<limit>")]
+async fn list_job_history(id: &str,
+                          job: &str,
+                          limit: Option<usize>,
+                          adapter: &State<Arc<dyn DbAdapter>>)
+    -> Result<Json<Vec<SyncHistoryEntry>>, (Status, Json<Response>)>
+{
+    match adapter.list_history_entries(id, job) {
+        Ok(mut entries) => {
+            if let Some(limit) = limit {
+                if entries.len() > limit {
+                    entries.drain(..entries.len() - limit);
+                }
+            }
+            Ok(Json(entries))
+        }
+        Err(e) => {
+            let error = format!("在列出任务 {} 的同步历史的过程中失败，所属worker {} :{}", job, id, e);
+            error!("{}", error);
+            Err((Status::InternalServerError, Json(Response::Error(error))))
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct SizeMsg{
     pub(crate) name: String,
-    pub(crate) size: String,
+    pub(crate) size: MirrorSize,
 }
 #[post("/workers/<id>/jobs/<_job>/size", format = "application/json", data = "<msg>")]
 async fn update_mirror_size(id: &str,
                             _job: &str,
                             guard: Result<CheckWorkerId, Json<Response>>,
                             msg: Json<SizeMsg>,
-                            adapter: &State<Box<dyn DbAdapter>>)
+                            info: ClientInfo,
+                            adapter: &State<Arc<dyn DbAdapter>>,
+                            audit: &State<AuditLog>)
+    -> Result<Json<MirrorStatus>, (Status, Json<Response>)>
+{
+    let mut entry = info.audit_entry("set-size", id, &msg.name, vec![msg.size.to_string()]);
+    let result = info.cert.check_worker(id)
+        .and_then(|_| set_mirror_size(id, guard, msg.into_inner(), adapter.inner().as_ref()));
+    match &result {
+        Ok(status) => {
+            entry.success = true;
+            entry.reply = status.size.to_string();
+        }
+        Err((_, e)) => {
+            entry.reply = e.text().to_string();
+        }
+    }
+    audit.record(adapter.inner().as_ref(), entry);
+    result
+}
+
+fn set_mirror_size(id: &str,
+                   guard: Result<CheckWorkerId, Json<Response>>,
+                   msg: SizeMsg,
+                   adapter: &dyn DbAdapter)
     -> Result<Json<MirrorStatus>, (Status, Json<Response>)>
 {
     if let Err(e) = guard{
@@ -335,8 +753,8 @@ async fn update_mirror_size(id: &str,
         }
         Ok(mut status) => {
             // 只有大小有意义的消息才会更新镜像大小
-            if msg.size.len() >0 || msg.size.ne("unknown"){
-                status.size = msg.size.clone();
+            if !msg.size.is_unknown(){
+                status.size = msg.size;
             }
             info!("镜像[{}] @<{}> 大小: {}", status.name, status.worker, status.size);
 
@@ -359,13 +777,15 @@ async fn update_mirror_size(id: &str,
 #[post("/workers/<id>/schedules", format = "application/json", data = "<schedules>")]
 async fn update_schedules_of_worker(id: &str,
                                     guard: Result<CheckWorkerId, Json<Response>>,
+                                    cert: PeerCert,
                                     schedules: Json<MirrorSchedules>,
-                                    adapter: &State<Box<dyn DbAdapter>>)
+                                    adapter: &State<Arc<dyn DbAdapter>>)
     -> Result<Json<()>, (Status, Json<Response>)>
 {
     if let Err(e) = guard{
         return Err((Status::BadRequest, e))
     }
+    cert.check_worker(id)?;
     for schedule in schedules.into_inner().schedules{
         let mirror_name = schedule.mirror_name;
         if mirror_name.len() == 0{
@@ -400,26 +820,173 @@ async fn update_schedules_of_worker(id: &str,
     Ok(Json(()))
 }
 
+// CmdReply是/cmd的响应，普通命令返回一条消息，批量命令返回每个目标的执行结果
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub(crate) enum CmdReply {
+    Single(Response),
+    Bulk(Vec<CmdResult>),
+}
+
+// 没有指定since时只返回最近这么多天的审计记录
+const AUDIT_DEFAULT_DAYS: i64 = 7;
+
+// 批量命令同时发送给worker的最大命令数
+const BULK_CMD_CONCURRENCY: usize = 16;
+
 #[post("/cmd", format = "application/json", data = "<client_cmd>")]
 async fn handle_client_cmd(client_cmd: Json<ClientCmd>,
-                           adapter: &State<Box<dyn DbAdapter>>,
-                           client: &State<Client>)
-    -> Result<Json<Response>, (Status, Json<Response>)>
+                           info: ClientInfo,
+                           adapter: &State<Arc<dyn DbAdapter>>,
+                           client: &State<SharedClient>,
+                           audit: &State<AuditLog>)
+    -> Result<Json<CmdReply>, (Status, Json<Response>)>
+{
+    let client = client.get();
+    let mut client_cmd = client_cmd.into_inner();
+    if let Some(selector) = client_cmd.selector.take() {
+        return handle_bulk_cmd(client_cmd, selector, &info, adapter.inner(), &client, audit.inner()).await
+            .map(|results| Json(CmdReply::Bulk(results)))
+    }
+    let mut entry = info.audit_entry(&client_cmd.cmd.to_string(),
+                                     &client_cmd.worker_id,
+                                     &client_cmd.mirror_id,
+                                     client_cmd.args.clone());
+    let result = send_client_cmd(client_cmd, adapter.inner().as_ref(), &client).await;
+    match result {
+        Ok((true, reply)) => {
+            entry.success = true;
+            entry.reply = reply;
+            let msg = format!("成功发送命令到worker {}", entry.worker_id);
+            audit.record(adapter.inner().as_ref(), entry);
+            Ok(Json(CmdReply::Single(Response::Message(msg))))
+        }
+        // worker返回了非2xx的状态码，命令没有被执行
+        Ok((false, reply)) => {
+            let error = format!("worker {} 执行命令失败：{}", entry.worker_id, reply);
+            error!("{}", error);
+            entry.reply = reply;
+            audit.record(adapter.inner().as_ref(), entry);
+            Err((Status::BadGateway, Json(Response::Error(error))))
+        }
+        Err((status, error)) => {
+            entry.reply = error.clone();
+            audit.record(adapter.inner().as_ref(), entry);
+            Err((status, Json(Response::Error(error))))
+        }
+    }
+}
+
+// handle_bulk_cmd把命令并发地发送给所有被selector选中的镜像，每个镜像单独记录一条审计记录
+async fn handle_bulk_cmd(client_cmd: ClientCmd,
+                         selector: CmdSelector,
+                         info: &ClientInfo,
+                         adapter: &Arc<dyn DbAdapter>,
+                         client: &Client,
+                         audit: &AuditLog)
+    -> Result<Vec<CmdResult>, (Status, Json<Response>)>
+{
+    if client_cmd.cmd == CmdVerb::Reload {
+        let error = "reload是针对worker的命令，不支持批量发送".to_string();
+        return Err((Status::BadRequest, Json(Response::Error(error))))
+    }
+    if let Err(error) = selector.validate() {
+        return Err((Status::BadRequest, Json(Response::Error(error))))
+    }
+
+    let targets: Vec<MirrorStatus> = match adapter.list_all_mirror_states() {
+        Ok(states) => select_targets(states, &selector, &client_cmd),
+        Err(e) => {
+            let error = format!("在列出所有的镜像的过程中失败：{}", e);
+            error!("{}", error);
+            return Err((Status::InternalServerError, Json(Response::Error(error))))
+        }
+    };
+    info!("批量发送命令'{}'到{}个镜像", client_cmd.cmd, targets.len());
+
+    let semaphore = Arc::new(Semaphore::new(BULK_CMD_CONCURRENCY));
+    let mut tasks = JoinSet::new();
+    for target in targets {
+        let cmd = ClientCmd {
+            cmd: client_cmd.cmd,
+            mirror_id: target.name,
+            worker_id: target.worker,
+            args: client_cmd.args.clone(),
+            options: client_cmd.options.clone(),
+            selector: None,
+        };
+        let adapter = Arc::clone(adapter);
+        let client = client.clone();
+        let semaphore = Arc::clone(&semaphore);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let mut result = CmdResult {
+                worker_id: cmd.worker_id.clone(),
+                mirror_id: cmd.mirror_id.clone(),
+                ..CmdResult::default()
+            };
+            match send_client_cmd(cmd, adapter.as_ref(), &client).await {
+                Ok((success, reply)) => {
+                    result.success = success;
+                    result.reply = reply;
+                }
+                Err((_, error)) => {
+                    result.reply = error;
+                }
+            }
+            result
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok(result) => {
+                let mut entry = info.audit_entry(&client_cmd.cmd.to_string(),
+                                                 &result.worker_id,
+                                                 &result.mirror_id,
+                                                 client_cmd.args.clone());
+                entry.success = result.success;
+                entry.reply = result.reply.clone();
+                audit.record(adapter.as_ref(), entry);
+                results.push(result);
+            }
+            Err(e) => {
+                error!("批量发送命令的任务异常退出：{}", e);
+            }
+        }
+    }
+    results.sort_by(|a, b| (&a.worker_id, &a.mirror_id).cmp(&(&b.worker_id, &b.mirror_id)));
+    Ok(results)
+}
+
+// select_targets返回被selector选中的镜像，命令中的mirror_id和worker_id不为空时作为额外的条件，
+// 只选中同时满足它们的镜像
+pub(crate) fn select_targets(states: Vec<MirrorStatus>, selector: &CmdSelector, client_cmd: &ClientCmd) -> Vec<MirrorStatus> {
+    states.into_iter()
+        .filter(|m| client_cmd.mirror_id.is_empty() || m.name == client_cmd.mirror_id)
+        .filter(|m| client_cmd.worker_id.is_empty() || m.worker == client_cmd.worker_id)
+        .filter(|m| selector.matches(m))
+        .collect()
+}
+
+// send_client_cmd把客户端命令转发给对应的worker，返回worker是否成功处理以及它的响应内容
+async fn send_client_cmd(client_cmd: ClientCmd, adapter: &dyn DbAdapter, client: &Client)
+    -> Result<(bool, String), (Status, String)>
 {
-    let client_cmd = client_cmd.into_inner();
     let worker_id = client_cmd.worker_id.clone();
     if worker_id.len() == 0{
         // TODO：当worker_id为空字符串时，决定哪个worker应该执行此镜像
         let error = "worker_id为空的情况还未实现".to_string();
         error!("{}", error);
-        return Err((Status::InternalServerError, Json(Response::Error(error))))
+        return Err((Status::InternalServerError, error))
     }
     
     let w = adapter.get_worker(&worker_id);
     if let Err(_e) = w {
         let error = format!("worker{}还未注册", worker_id);
         error!("{}", error);
-        return Err((Status::BadRequest, Json(Response::Error(error))))
+        return Err((Status::BadRequest, error))
     }
     
     let w = w.unwrap();
@@ -450,15 +1017,62 @@ async fn handle_client_cmd(client_cmd: Json<ClientCmd>,
     }
 
     info!("对<{}>发送命令'{} {}'", client_cmd.worker_id, client_cmd.cmd, client_cmd.mirror_id);
-    let http_client = client.inner().clone();
-    if let Err(e) = post_json(&worker_url, &worker_cmd, Some(http_client)).await{
-        let error = format!("为worker {}({}) 发送命令失败：{}", worker_id, worker_url, e.to_string());
-        error!("{}", error);
-        return Err((Status::InternalServerError, Json(Response::Error(error))))
+    let http_client = client.clone();
+    match post_json(&worker_url, &worker_cmd, Some(http_client)).await {
+        Err(e) => {
+            let error = format!("为worker {}({}) 发送命令失败：{}", worker_id, worker_url, e.to_string());
+            error!("{}", error);
+            Err((Status::InternalServerError, error))
+        }
+        Ok(resp) => {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            Ok((status.is_success(), format!("{} {}", status, body)))
+        }
     }
-    // TODO: 检查响应是否成功
-    Ok(Json(Response::Message(format!("成功发送命令到worker {}", worker_id))))
 }
 
-
-
+// list_audit_entries返回since（RFC 3339格式，默认为AUDIT_DEFAULT_DAYS天前）之后的审计记录，
+// 可以按worker、镜像和操作过滤，limit限制返回最近的条数；只有管理员可以访问
+#[get("/audit?<worker>&<mirror>&<verb>&<since>&<limit>")]
+async fn list_audit_entries(worker: Option<&str>,
+                            mirror: Option<&str>,
+                            verb: Option<&str>,
+                            since: Option<&str>,
+                            limit: Option<usize>,
+                            info: ClientInfo,
+                            adapter: &State<Arc<dyn DbAdapter>>)
+    -> Result<Json<Vec<AuditEntry>>, (Status, Json<Response>)>
+{
+    info.check_admin()?;
+    let since = match since {
+        Some(since) => match DateTime::parse_from_rfc3339(since) {
+            Ok(since) => since.with_timezone(&Utc),
+            Err(e) => {
+                let error = format!("无效的since参数 {}：{}", since, e);
+                return Err((Status::BadRequest, Json(Response::Error(error))))
+            }
+        },
+        None => Utc::now() - Duration::days(AUDIT_DEFAULT_DAYS),
+    };
+    match adapter.list_audit_entries(since) {
+        Ok(entries) => {
+            let mut entries: Vec<AuditEntry> = entries.into_iter()
+                .filter(|e| worker.is_none_or(|w| e.worker_id == w))
+                .filter(|e| mirror.is_none_or(|m| e.mirror_id == m))
+                .filter(|e| verb.is_none_or(|v| e.verb == v))
+                .collect();
+            if let Some(limit) = limit {
+                if entries.len() > limit {
+                    entries.drain(..entries.len() - limit);
+                }
+            }
+            Ok(Json(entries))
+        }
+        Err(e) => {
+            let error = format!("在列出审计记录的过程中失败：{}", e);
+            error!("{}", error);
+            Err((Status::InternalServerError, Json(Response::Error(error))))
+        }
+    }
+}
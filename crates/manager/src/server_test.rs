@@ -8,7 +8,7 @@ mod tests {
     use std::time;
     use std::sync::atomic::{AtomicU32, Ordering};
     use chrono::{Duration, TimeZone, Utc};
-    use internal::msg::{ClientCmd, CmdVerb, MirrorSchedule, MirrorSchedules, MirrorStatus, WorkerCmd, WorkerStatus};
+    use internal::msg::{AuditEntry, ClientCmd, CmdSelector, CmdVerb, MirrorSchedule, MirrorSchedules, MirrorStatus, ResourceUsage, SyncHistoryEntry, WorkerCmd, WorkerStatus};
     use crate::db::DbAdapter;
     use log::{error, info};
     use rocket::http::Status;
@@ -18,6 +18,7 @@ mod tests {
     use tokio::sync::mpsc;
     use internal::logger::init_logger;
     use internal::status::SyncStatus;
+    use internal::size::MirrorSize;
     use internal::status_web::WebMirrorStatus;
     use internal::util::{get_json, post_json};
     use crate::config::Config;
@@ -27,6 +28,7 @@ mod tests {
     struct MockDbAdapter{
         worker_store: Arc<RwLock<HashMap<String, WorkerStatus>>>,
         status_store: Arc<RwLock<HashMap<String, MirrorStatus>>>,
+        history_store: Arc<RwLock<Vec<SyncHistoryEntry>>>,
     }
 
     impl DbAdapter for MockDbAdapter {
@@ -123,6 +125,26 @@ mod tests {
             Ok(())
         }
 
+        fn add_audit_entry(&self, entry: AuditEntry) -> Result<AuditEntry, Box<dyn Error>> {
+            Ok(entry)
+        }
+
+        fn list_audit_entries(&self, _since: chrono::DateTime<Utc>) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+            Ok(vec![])
+        }
+
+        fn add_history_entry(&self, entry: SyncHistoryEntry) -> Result<SyncHistoryEntry, Box<dyn Error>> {
+            self.history_store.write().unwrap().push(entry.clone());
+            Ok(entry)
+        }
+
+        fn list_history_entries(&self, worker_id: &str, mirror_id: &str) -> Result<Vec<SyncHistoryEntry>, Box<dyn Error>> {
+            Ok(self.history_store.read().unwrap().iter()
+                .filter(|e| e.worker == worker_id && e.name == mirror_id)
+                .cloned()
+                .collect())
+        }
+
         fn close(&self) -> Result<(), Box<dyn Error>> {
             Ok(())
         }
@@ -161,10 +183,11 @@ mod tests {
             _MAGIC_BAD_WORKER_ID.to_string(),
             WorkerStatus{ id: _MAGIC_BAD_WORKER_ID.to_string(), ..WorkerStatus::default() });
 
-        s.engine = s.engine.manage(Box::new(MockDbAdapter{
+        s.engine = s.engine.manage(Arc::new(MockDbAdapter{
             worker_store: worker_status_map,
             status_store: Arc::new(RwLock::new(HashMap::new())),
-        }) as Box<dyn DbAdapter>);
+            history_store: Arc::new(RwLock::new(Vec::new())),
+        }) as Arc<dyn DbAdapter>);
 
         s.engine = s.engine.manage(reqwest::Client::new());
 
@@ -286,7 +309,7 @@ mod tests {
             is_master: true,
             status: SyncStatus::Success,
             upstream: "mirrors.tuna.tsinghua.edu.cn".to_string(),
-            size: "unknown".to_string(),
+            size: MirrorSize::unknown(),
             ..MirrorStatus::default()
         };
         // test_update_mirror_status_of_a_existed_worker(&binding, status).await;
@@ -303,7 +326,7 @@ mod tests {
             last_started: Utc::now(),
             last_ended: Utc::now(),
             upstream: "mirrors.tuna.tsinghua.edu.cn".to_string(),
-            size: "4GB".to_string(),
+            size: "4GB".parse().unwrap(),
             ..MirrorStatus::default()
         };
         // test_update_mirror_status_of_a_nonexistent_worker(&binding, status).await;
@@ -402,11 +425,22 @@ mod tests {
         
         // 如果status改变失败？
         status.status = SyncStatus::Failed;
+        status.usage = Some(ResourceUsage{ user_time_ms: 1200, elapsed_ms: 3000, ..ResourceUsage::default() });
         tokio::time::sleep(time::Duration::from_secs(3)).await;
         let resp = binding.post(format!("/workers/{}/jobs/{}", status.worker, status.name))
             .json(&status)
             .dispatch().await;
         assert_eq!(resp.status(), Status::Ok);
+
+        // 同步结束时记录同步历史
+        let resp = binding.get(format!("/workers/{}/jobs/{}/history", status.worker, status.name))
+            .dispatch().await;
+        assert_eq!(resp.status(), Status::Ok);
+        let history = resp.into_json::<Vec<SyncHistoryEntry>>().await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].status, SyncStatus::Failed);
+        assert_eq!(history[0].usage, status.usage);
+        status.usage = None;
         
         // 如果同步job失败？
         syncing_job_failed(&binding, &status).await;
@@ -461,7 +495,8 @@ mod tests {
         assert_eq!(m.name, status.name);
         assert_eq!(m.status, status.status);
         assert_eq!(m.upstream, status.upstream);
-        assert_eq!(m.size, status.size);
+        assert_eq!(m.size, status.size.to_string());
+        assert_eq!(m.size_bytes, status.size.bytes);
         assert_eq!(m.is_master, status.is_master);
         assert!((Utc::now() - m.last_update.0) < Duration::seconds(3));
         assert!((Utc::now() - m.last_started.0) < Duration::seconds(2));
@@ -472,7 +507,7 @@ mod tests {
     async fn update_size_of_a_valid_mirror(binding: &tokio::sync::RwLockReadGuard<'_, Client>, status: &MirrorStatus){
         let msg = SizeMsg{
             name: status.name.clone(),
-            size: "5GB".to_string(),
+            size: "5GB".parse().unwrap(),
         };
         let resp = binding.post(format!("/workers/{}/jobs/{}/size", status.worker, status.name))
             .json(&msg)
@@ -492,7 +527,7 @@ mod tests {
             assert_eq!(m.worker, status.worker);
             assert_eq!(m.status, status.status);
             assert_eq!(m.upstream, status.upstream);
-            assert_eq!(m.size, "5GB".to_string());
+            assert_eq!(m.size.raw, "5GB".to_string());
             assert_eq!(m.is_master, status.is_master);
             assert!((Utc::now() - m.last_update) < Duration::seconds(3));
             assert!((Utc::now() - m.last_started) < Duration::seconds(2));
@@ -527,7 +562,7 @@ mod tests {
     async fn update_size_of_invalid_mirrors(binding: &tokio::sync::RwLockReadGuard<'_, Client>, status: &MirrorStatus){
         let msg = SizeMsg{
             name: "Invalid mirror".to_string(),
-            size: "5GB".to_string(),
+            size: "5GB".parse().unwrap(),
         };
         let resp = binding.post(format!("/workers/{}/jobs/{}/size", status.worker, status.name))
             .json(&msg)
@@ -604,10 +639,11 @@ mod tests {
             _MAGIC_BAD_WORKER_ID.to_string(),
             WorkerStatus{ id: _MAGIC_BAD_WORKER_ID.to_string(), ..WorkerStatus::default() });
 
-        s.engine = s.engine.manage(Box::new(MockDbAdapter{
+        s.engine = s.engine.manage(Arc::new(MockDbAdapter{
             worker_store: worker_status_map,
             status_store: Arc::new(RwLock::new(HashMap::new())),
-        }) as Box<dyn DbAdapter>);
+            history_store: Arc::new(RwLock::new(Vec::new())),
+        }) as Arc<dyn DbAdapter>);
 
         s.engine = s.engine.manage(reqwest::Client::new());
         let rocket_handle = tokio::spawn(async move {
@@ -694,5 +730,124 @@ mod tests {
         
     }
     
+
+    fn reload_config(tmp_dir: &std::path::Path, port: u32) -> Config {
+        let db_file = tmp_dir.join("leveldb.db");
+        std::fs::create_dir_all(&db_file).unwrap();
+        let mut cfg = Config::default();
+        cfg.server.addr = Some("127.0.0.1".to_string());
+        cfg.server.port = Some(port);
+        cfg.files.db_type = Some("leveldb".to_string());
+        cfg.files.db_file = Some(db_file.display().to_string());
+        cfg
+    }
+
+    fn free_port() -> u32 {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port() as u32
+    }
+
+    #[test]
+    fn test_select_targets() {
+        let status = |name: &str, worker: &str| MirrorStatus {
+            name: name.to_string(),
+            worker: worker.to_string(),
+            ..MirrorStatus::default()
+        };
+        let states = vec![status("debian", "w1"), status("debian-cd", "w1"), status("debian", "w2"), status("ubuntu", "w1")];
+        let selector = CmdSelector { mirrors: vec!["debian*".to_string()], ..CmdSelector::default() };
+        let names = |targets: Vec<MirrorStatus>| targets.into_iter().map(|m| format!("{}@{}", m.name, m.worker)).collect::<Vec<_>>();
+
+        let cmd = |mirror_id: &str, worker_id: &str| ClientCmd {
+            mirror_id: mirror_id.to_string(),
+            worker_id: worker_id.to_string(),
+            ..ClientCmd::default()
+        };
+        assert_eq!(names(select_targets(states.clone(), &selector, &cmd("", ""))), vec!["debian@w1", "debian-cd@w1", "debian@w2"]);
+        // worker_id和mirror_id缩小选中的范围，而不是加入新的镜像
+        assert_eq!(names(select_targets(states.clone(), &selector, &cmd("", "w1"))), vec!["debian@w1", "debian-cd@w1"]);
+        assert!(select_targets(states.clone(), &selector, &cmd("ubuntu", "")).is_empty());
+        assert_eq!(names(select_targets(states, &selector, &cmd("debian", "w2"))), vec!["debian@w2"]);
+    }
+
+    #[test]
+    fn test_reload_config() {
+        let tmp_dir = tempfile::Builder::new().prefix("rtsync").tempdir().unwrap();
+        let tests_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../tests");
+        let mut cfg = reload_config(tmp_dir.path(), free_port());
+        cfg.server.ssl_cert = Some(tests_dir.join("manager.crt").display().to_string());
+        cfg.server.ssl_key = Some(tests_dir.join("manager.key").display().to_string());
+        let mut s = get_rtsync_manager(&cfg).unwrap();
+
+        // 配置没有变化或者只修改了API key时不需要重启服务器
+        assert!(!s.apply_config(cfg.clone()));
+        let mut new_cfg = cfg.clone();
+        new_cfg.auth.api_keys.insert("alice".to_string(), "alice-secret".to_string());
+        assert!(!s.apply_config(new_cfg));
+
+        // 内容相同的证书文件不需要重启
+        let cert = tmp_dir.path().join("manager.crt");
+        std::fs::copy(tests_dir.join("manager.crt"), &cert).unwrap();
+        let mut new_cfg = cfg.clone();
+        new_cfg.server.ssl_cert = Some(cert.display().to_string());
+        assert!(!s.apply_config(new_cfg));
+
+        // 无效或者不存在的证书不会被应用
+        let mut bad_cfg = cfg.clone();
+        bad_cfg.server.ssl_cert = Some(tests_dir.join("manager.key").display().to_string());
+        bad_cfg.server.port = Some(free_port());
+        assert!(!s.apply_config(bad_cfg));
+        assert_eq!(s.cfg.server.port, cfg.server.port);
+        let mut bad_cfg = cfg.clone();
+        bad_cfg.server.ssl_client_ca = Some(tmp_dir.path().join("nonexistent.crt").display().to_string());
+        assert!(!s.apply_config(bad_cfg));
+        assert!(s.cfg.server.ssl_client_ca.is_none());
+
+        // 启用双向TLS或者修改监听地址时需要重启
+        let mut new_cfg = cfg.clone();
+        new_cfg.server.ssl_client_ca = Some(tests_dir.join("rootCA.crt").display().to_string());
+        assert!(s.apply_config(new_cfg.clone()));
+        new_cfg.server.port = Some(free_port());
+        assert!(s.apply_config(new_cfg));
+    }
+
+    async fn wait_for_ping(port: u32) -> bool {
+        for _ in 0..50 {
+            if let Ok(resp) = reqwest::get(format!("http://127.0.0.1:{}/ping", port)).await {
+                if resp.status() == reqwest::StatusCode::OK {
+                    return true
+                }
+            }
+            tokio::time::sleep(time::Duration::from_millis(100)).await;
+        }
+        false
+    }
+
+    #[rocket::async_test]
+    async fn test_reload_keeps_server_running() {
+        let tmp_dir = tempfile::Builder::new().prefix("rtsync").tempdir().unwrap();
+        let port = free_port();
+        let cfg = reload_config(tmp_dir.path(), port);
+        let s = get_rtsync_manager(&cfg).unwrap();
+        let handle = s.handle();
+        let server = tokio::spawn(s.run());
+        assert!(wait_for_ping(port).await);
+
+        // 新的端口已被占用，服务器启动失败后恢复原有的配置
+        let occupied = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut new_cfg = cfg.clone();
+        new_cfg.server.port = Some(occupied.local_addr().unwrap().port() as u32);
+        handle.reload(new_cfg).await;
+        tokio::time::sleep(time::Duration::from_millis(500)).await;
+        assert!(wait_for_ping(port).await);
+
+        // 无效的监听地址不会使正在运行的服务器停止
+        let mut new_cfg = cfg.clone();
+        new_cfg.server.addr = Some("not an address".to_string());
+        handle.reload(new_cfg).await;
+        assert!(wait_for_ping(port).await);
+
+        handle.shutdown().await;
+        tokio::time::timeout(time::Duration::from_secs(10), server).await.unwrap().unwrap();
+    }
 }
 
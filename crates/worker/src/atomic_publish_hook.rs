@@ -0,0 +1,432 @@
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anymap::AnyMap;
+use async_trait::async_trait;
+use chrono::Local;
+use log::{debug, info, warn};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use crate::config::{MirrorConfig, ProviderEnum};
+use crate::context::Context;
+use crate::hooks::JobHook;
+use crate::provider::_WORKING_DIR_KEY;
+
+const STAGING: &str = "staging";
+const PREVIOUS: &str = "previous";
+// 这些rsync选项让rsync直接改写已有的文件，不能和硬链接初始化的staging目录一起使用
+const RSYNC_INPLACE_OPTIONS: [&str; 3] = ["--inplace", "--append", "--append-verify"];
+
+// SeedMode决定用什么方式把当前的目录复制到staging目录
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SeedMode {
+    // cp -al，不占用额外的空间，同步程序必须先写临时文件再重命名（rsync默认如此，不能使用--inplace），
+    // 否则会改动正在发布的目录中的文件，所以只能用于已知安全的provider
+    Hardlink,
+    // cp --reflink=always，需要文件系统支持（btrfs、xfs等）
+    Reflink,
+}
+
+// AtomicPublishHook让同步在staging目录中进行，同步成功后原子地切换working_dir这个符号链接，
+// 客户端不会看到同步到一半的目录。目录结构如下：
+//   /data/mirrors/debian -> .debian.publish/20261018-120000
+//   /data/mirrors/.debian.publish/20261018-120000   当前的目录
+//   /data/mirrors/.debian.publish/previous -> 20261017-120000   上一次的目录，用于回滚
+//   /data/mirrors/.debian.publish/staging   正在同步的目录，同步失败时保留给下一次同步继续使用
+#[derive(Debug, Clone)]
+pub(crate) struct AtomicPublishHook {
+    seed: SeedMode,
+}
+
+// Layout是working_dir对应的各个路径
+struct Layout {
+    working_dir: PathBuf,
+    // 保存所有目录的目录，和working_dir在同一个文件系统上
+    publish_dir: PathBuf,
+    // working_dir指向的目录相对于working_dir所在目录的路径前缀
+    link_prefix: PathBuf,
+    staging: PathBuf,
+}
+
+impl Layout {
+    fn new(working_dir: &str) -> Result<Self> {
+        let working_dir = Path::new(working_dir.trim_end_matches('/')).to_path_buf();
+        let (Some(parent), Some(base)) = (working_dir.parent(), working_dir.file_name()) else {
+            return Err(anyhow!("无法在 {} 上使用原子发布", working_dir.display()))
+        };
+        let link_prefix = PathBuf::from(format!(".{}.publish", base.to_string_lossy()));
+        let publish_dir = parent.join(&link_prefix);
+        let staging = publish_dir.join(STAGING);
+        Ok(Layout { working_dir, publish_dir, link_prefix, staging })
+    }
+
+    // current返回working_dir最终指向的目录的名字。回滚时working_dir指向previous，previous再指向真正的目录，
+    // 所以要解析整条符号链接链；working_dir还是普通目录、不存在或者没有指向publish_dir中的目录时返回None
+    fn current(&self) -> Option<String> {
+        if !fs::symlink_metadata(&self.working_dir).ok()?.file_type().is_symlink() {
+            return None
+        }
+        let target = fs::canonicalize(&self.working_dir).ok()?;
+        let publish_dir = fs::canonicalize(&self.publish_dir).ok()?;
+        if target.parent() != Some(publish_dir.as_path()) {
+            return None
+        }
+        target.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .filter(|name| name != PREVIOUS && name != STAGING)
+    }
+
+    // check_same_filesystem检查working_dir和publish_dir所在的目录在同一个文件系统上，
+    // working_dir单独挂载时无法用硬链接初始化staging目录，也无法在第一次发布时把它移到publish_dir中
+    fn check_same_filesystem(&self) -> Result<()> {
+        let Ok(meta) = fs::symlink_metadata(&self.working_dir) else {
+            return Ok(())
+        };
+        let parent = self.publish_dir.parent().unwrap_or(Path::new("/"));
+        if meta.is_dir() && fs::metadata(parent)?.dev() != meta.dev() {
+            return Err(anyhow!("{} 是单独挂载的文件系统，和 {} 不在同一个文件系统上，请挂载它的上一级目录",
+                self.working_dir.display(), self.publish_dir.display()))
+        }
+        Ok(())
+    }
+
+    // next_version返回新目录的名字，同一秒内发布多次时加上序号
+    fn next_version(&self) -> String {
+        let name = Local::now().format("%Y%m%d-%H%M%S").to_string();
+        (1..).map(|i| if i == 1 { name.clone() } else { format!("{}-{}", name, i) })
+            .find(|version| fs::symlink_metadata(self.publish_dir.join(version)).is_err())
+            .unwrap()
+    }
+
+    // publish把staging目录改名为新的目录，再原子地切换working_dir，上一次的目录保留为previous，更早的目录被删除
+    fn publish(&self, provider_name: &str) -> Result<()> {
+        let name = self.next_version();
+        fs::rename(&self.staging, self.publish_dir.join(&name))?;
+
+        let previous = match fs::symlink_metadata(&self.working_dir) {
+            Ok(meta) if meta.file_type().is_symlink() => self.current(),
+            Ok(meta) if meta.is_dir() => {
+                // 第一次发布时把原来的目录移到publish_dir中，改名和创建符号链接之间working_dir短暂不存在
+                let initial = format!("{}.initial", name);
+                fs::rename(&self.working_dir, self.publish_dir.join(&initial))?;
+                Some(initial)
+            }
+            Ok(_) => return Err(anyhow!("{} 不是目录或符号链接", self.working_dir.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Self::replace_symlink(&self.link_prefix.join(&name), &self.working_dir)?;
+        info!("{} 已发布为 {}", provider_name, self.publish_dir.join(&name).display());
+
+        if let Some(previous) = &previous {
+            Self::replace_symlink(Path::new(previous), &self.publish_dir.join(PREVIOUS))?;
+        }
+        // 删除更早的目录
+        for entry in fs::read_dir(&self.publish_dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if file_name == name || file_name == PREVIOUS || file_name == STAGING || Some(&file_name) == previous.as_ref() {
+                continue
+            }
+            let result = if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())
+            } else {
+                fs::remove_file(entry.path())
+            };
+            if let Err(e) = result {
+                warn!("删除 {} 的旧目录 {} 失败: {}", provider_name, entry.path().display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    // replace_symlink把link原子地替换为指向target的符号链接
+    fn replace_symlink(target: &Path, link: &Path) -> io::Result<()> {
+        let tmp = link.with_file_name(format!(".{}.tmp", link.file_name().unwrap_or_default().to_string_lossy()));
+        let _ = fs::remove_file(&tmp);
+        symlink(target, &tmp)?;
+        fs::rename(&tmp, link)
+    }
+}
+
+impl AtomicPublishHook {
+    // new根据mirror的publish_mode创建hook，publish_mode不是atomic时返回None，
+    // 同时检查provider能否使用publish_seed，以及working_dir能否和publish目录互相改名
+    pub(crate) fn new(mirror: &MirrorConfig, working_dir: &str) -> Result<Option<Self>> {
+        match mirror.publish_mode.as_deref().unwrap_or("direct") {
+            "" | "direct" => return Ok(None),
+            "atomic" => {}
+            other => return Err(anyhow!("无效的publish_mode：{}，应为 direct 或 atomic", other)),
+        }
+        let seed = match mirror.publish_seed.as_deref().unwrap_or("hardlink") {
+            "" | "hardlink" => SeedMode::Hardlink,
+            "reflink" => SeedMode::Reflink,
+            other => return Err(anyhow!("无效的publish_seed：{}，应为 hardlink 或 reflink", other)),
+        };
+        if seed == SeedMode::Hardlink {
+            check_hardlink_seed(mirror)?;
+        }
+        Layout::new(working_dir)?.check_same_filesystem()?;
+        Ok(Some(AtomicPublishHook { seed }))
+    }
+
+    // seed用当前的目录初始化staging目录，当前的目录不存在时创建空的staging目录
+    async fn seed(&self, layout: &Layout) -> Result<()> {
+        if !layout.working_dir.exists() {
+            fs::create_dir_all(&layout.staging)?;
+            return Ok(())
+        }
+        let mut command = Command::new("cp");
+        match self.seed {
+            SeedMode::Hardlink => command.arg("-al"),
+            SeedMode::Reflink => command.args(["-a", "--reflink=always"]),
+        };
+        // 复制working_dir中的内容而不是符号链接本身
+        let output = command.arg(format!("{}/.", layout.working_dir.display()))
+            .arg(&layout.staging)
+            .output().await?;
+        if !output.status.success() {
+            let _ = fs::remove_dir_all(&layout.staging);
+            return Err(anyhow!("初始化staging目录 {} 失败: {}", layout.staging.display(),
+                String::from_utf8_lossy(&output.stderr).trim()))
+        }
+        Ok(())
+    }
+}
+
+// check_hardlink_seed检查provider是否会先写临时文件再改名：rsync（不使用--inplace等选项时）、http和s3如此，
+// lftp由provider打开 xfer:use-temp-file；command和git可能直接改写已有的文件，需要使用reflink
+fn check_hardlink_seed(mirror: &MirrorConfig) -> Result<()> {
+    match mirror.provider {
+        Some(ProviderEnum::Rsync) | Some(ProviderEnum::TwoStageRsync) => {
+            let mut options = mirror.rsync_options.iter().chain(mirror.rsync_override.iter()).flatten();
+            match options.find(|option| RSYNC_INPLACE_OPTIONS.contains(&option.split('=').next().unwrap_or_default())) {
+                Some(option) => Err(anyhow!("rsync 选项 {} 会直接改写已有的文件，不能和 publish_seed = \"hardlink\" 一起使用", option)),
+                None => Ok(()),
+            }
+        }
+        Some(ProviderEnum::Http) | Some(ProviderEnum::S3) | Some(ProviderEnum::Lftp) => Ok(()),
+        _ => Err(anyhow!("{:?} provider 可能直接改写已有的文件，请使用 publish_seed = \"reflink\"",
+            mirror.provider.clone().unwrap_or(ProviderEnum::Command))),
+    }
+}
+
+#[async_trait]
+impl JobHook for AtomicPublishHook {
+    // 准备staging目录，之后的同步在staging目录中进行
+    async fn pre_exec(&self,
+                      _provider_name: String,
+                      _log_dir: String,
+                      _log_file: String,
+                      working_dir: String,
+                      context: Arc<Mutex<Option<Context>>>)
+                      -> Result<()>
+    {
+        let layout = Layout::new(&working_dir)?;
+        fs::create_dir_all(&layout.publish_dir)?;
+        if layout.staging.exists() {
+            debug!("继续使用上一次没有发布的staging目录 {}", layout.staging.display());
+        } else {
+            self.seed(&layout).await?;
+        }
+        let staging = layout.staging.display().to_string();
+
+        // 重写working_dir
+        let mut cur_ctx = context.lock().await;
+        *cur_ctx = cur_ctx.take().map(|ctx| ctx.enter());
+        if let Some(ctx) = cur_ctx.as_mut() {
+            // 使用docker时staging目录也需要挂载到容器中
+            let mut volumes = ctx.get("volumes")
+                .and_then(|v| v.get::<Vec<String>>().cloned())
+                .unwrap_or_default();
+            volumes.push(format!("{}:{}", staging, staging));
+            let mut value = AnyMap::new();
+            value.insert(volumes);
+            ctx.set("volumes".to_string(), value);
+
+            let mut value = AnyMap::new();
+            value.insert(staging);
+            ctx.set(_WORKING_DIR_KEY.to_string(), value);
+        }
+        Ok(())
+    }
+
+    async fn post_exec(&self,
+                       context: Arc<Mutex<Option<Context>>>,
+                       _provider_name: String)
+                       -> Result<()>
+    {
+        let mut cur_ctx = context.lock().await;
+        *cur_ctx = cur_ctx.take().and_then(|ctx| ctx.exit().ok());
+        Ok(())
+    }
+
+    // 发布staging目录
+    async fn post_success(&self,
+                          _context: Arc<Mutex<Option<Context>>>,
+                          provider_name: String,
+                          working_dir: String,
+                          _upstream: String,
+                          _log_dir: String,
+                          _log_file: String)
+                          -> Result<()>
+    {
+        // 改名和删除旧目录可能要花很长时间，不能阻塞其他任务
+        let layout = Layout::new(&working_dir)?;
+        tokio::task::spawn_blocking(move || layout.publish(&provider_name)).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile::Builder;
+
+    fn context(working_dir: &str) -> Arc<Mutex<Option<Context>>> {
+        let mut ctx = Context::new();
+        let mut value = AnyMap::new();
+        value.insert(working_dir.to_string());
+        ctx.set(_WORKING_DIR_KEY.to_string(), value);
+        Arc::new(Mutex::new(Some(ctx)))
+    }
+
+    async fn context_working_dir(context: &Arc<Mutex<Option<Context>>>) -> String {
+        context.lock().await.as_ref().unwrap().get(_WORKING_DIR_KEY).unwrap().get::<String>().unwrap().clone()
+    }
+
+    // sync模拟一次同步：执行pre_exec，像rsync一样先写临时文件再改名，再执行post_exec
+    async fn sync(hook: &AtomicPublishHook, working_dir: &str, file: &str, content: &str) -> PathBuf {
+        let ctx = context(working_dir);
+        hook.pre_exec("debian".to_string(), String::new(), String::new(), working_dir.to_string(), ctx.clone()).await.unwrap();
+        let staging = PathBuf::from(context_working_dir(&ctx).await);
+        let tmp = staging.join(format!(".{}.tmp", file));
+        fs::write(&tmp, content).unwrap();
+        fs::rename(&tmp, staging.join(file)).unwrap();
+        hook.post_exec(ctx.clone(), "debian".to_string()).await.unwrap();
+        assert_eq!(context_working_dir(&ctx).await, working_dir);
+        staging
+    }
+
+    async fn publish(hook: &AtomicPublishHook, working_dir: &str) {
+        hook.post_success(context(working_dir), "debian".to_string(), working_dir.to_string(),
+                          String::new(), String::new(), String::new()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_atomic_publish(){
+        let tmp_dir = Builder::new().prefix("rtsync").tempdir().unwrap();
+        let working_dir = tmp_dir.path().join("debian");
+        fs::create_dir(&working_dir).unwrap();
+        fs::write(working_dir.join("Release"), "v1").unwrap();
+        let working_dir_str = working_dir.display().to_string();
+        let hook = AtomicPublishHook { seed: SeedMode::Hardlink };
+
+        // staging目录用硬链接初始化，同步时working_dir保持不变
+        let staging = sync(&hook, &working_dir_str, "Release.new", "v2").await;
+        assert_eq!(staging, tmp_dir.path().join(".debian.publish/staging"));
+        assert_eq!(fs::metadata(staging.join("Release")).unwrap().ino(), fs::metadata(working_dir.join("Release")).unwrap().ino());
+        assert!(!working_dir.join("Release.new").exists());
+
+        publish(&hook, &working_dir_str).await;
+        assert!(fs::symlink_metadata(&working_dir).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(working_dir.join("Release.new")).unwrap(), "v2");
+        assert!(!staging.exists());
+        let previous = tmp_dir.path().join(".debian.publish/previous");
+        assert!(previous.join("Release").exists());
+        assert!(!previous.join("Release.new").exists());
+
+        // 同步失败时保留staging目录，下一次同步继续使用
+        let staging = sync(&hook, &working_dir_str, "Packages", "v3").await;
+        assert!(!working_dir.join("Packages").exists());
+        sync(&hook, &working_dir_str, "Sources", "v3").await;
+        assert!(staging.join("Packages").exists());
+
+        // 同一秒内再次发布时新目录的名字加上序号
+        let first = fs::read_link(&working_dir).unwrap();
+        publish(&hook, &working_dir_str).await;
+        assert_ne!(fs::read_link(&working_dir).unwrap(), first);
+        assert!(working_dir.join("Packages").exists() && working_dir.join("Sources").exists());
+        assert_eq!(Path::new(".debian.publish").join(fs::read_link(&previous).unwrap()), first);
+        // 只保留当前和上一次的目录
+        let mut entries: Vec<_> = fs::read_dir(tmp_dir.path().join(".debian.publish")).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect();
+        entries.sort();
+        assert_eq!(entries.len(), 3, "{:?}", entries);
+        assert!(!entries.iter().any(|e| e.ends_with(".initial")));
+    }
+
+    #[tokio::test]
+    async fn test_publish_after_rollback(){
+        let tmp_dir = Builder::new().prefix("rtsync").tempdir().unwrap();
+        let working_dir = tmp_dir.path().join("debian");
+        let working_dir_str = working_dir.display().to_string();
+        let publish_dir = tmp_dir.path().join(".debian.publish");
+        let hook = AtomicPublishHook { seed: SeedMode::Hardlink };
+
+        sync(&hook, &working_dir_str, "Release", "v1").await;
+        publish(&hook, &working_dir_str).await;
+        let good = fs::read_link(&working_dir).unwrap();
+        sync(&hook, &working_dir_str, "Release", "v2").await;
+        publish(&hook, &working_dir_str).await;
+        assert_eq!(fs::read_to_string(working_dir.join("Release")).unwrap(), "v2");
+
+        // 按照README的说明回滚：把working_dir重新指向previous
+        Layout::replace_symlink(Path::new(".debian.publish/previous"), &working_dir).unwrap();
+        assert_eq!(fs::read_to_string(working_dir.join("Release")).unwrap(), "v1");
+
+        // 回滚后的下一次发布把回滚到的版本保留为previous，而不是让previous指向自己
+        sync(&hook, &working_dir_str, "Release", "v3").await;
+        publish(&hook, &working_dir_str).await;
+        assert_eq!(fs::read_to_string(working_dir.join("Release")).unwrap(), "v3");
+        let previous = publish_dir.join("previous");
+        assert_eq!(Path::new(".debian.publish").join(fs::read_link(&previous).unwrap()), good);
+        assert_eq!(fs::read_to_string(previous.join("Release")).unwrap(), "v1");
+        let mut entries: Vec<_> = fs::read_dir(&publish_dir).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect();
+        entries.sort();
+        assert_eq!(entries.len(), 3, "{:?}", entries);
+    }
+
+    #[test]
+    fn test_publish_mode(){
+        let mirror = |mode: Option<&str>, seed: Option<&str>| MirrorConfig {
+            provider: Some(ProviderEnum::Rsync),
+            publish_mode: mode.map(str::to_string),
+            publish_seed: seed.map(str::to_string),
+            ..MirrorConfig::default()
+        };
+        let new = |mirror: &MirrorConfig| AtomicPublishHook::new(mirror, "/tmp/rtsync-publish/debian");
+        assert!(new(&mirror(None, None)).unwrap().is_none());
+        assert!(new(&mirror(Some("direct"), Some("reflink"))).unwrap().is_none());
+        assert_eq!(new(&mirror(Some("atomic"), None)).unwrap().unwrap().seed, SeedMode::Hardlink);
+        assert_eq!(new(&mirror(Some("atomic"), Some("reflink"))).unwrap().unwrap().seed, SeedMode::Reflink);
+        assert!(new(&mirror(Some("swap"), None)).is_err());
+        assert!(new(&mirror(Some("atomic"), Some("copy"))).is_err());
+    }
+
+    #[test]
+    fn test_hardlink_seed(){
+        let mirror = |provider: ProviderEnum, rsync_options: &[&str], seed: &str| MirrorConfig {
+            provider: Some(provider),
+            rsync_options: Some(rsync_options.iter().map(|s| s.to_string()).collect()),
+            publish_mode: Some("atomic".to_string()),
+            publish_seed: Some(seed.to_string()),
+            ..MirrorConfig::default()
+        };
+        let new = |mirror: &MirrorConfig| AtomicPublishHook::new(mirror, "/tmp/rtsync-publish/debian");
+        for provider in [ProviderEnum::Rsync, ProviderEnum::TwoStageRsync, ProviderEnum::Http, ProviderEnum::S3, ProviderEnum::Lftp] {
+            assert!(new(&mirror(provider, &["--delete-after"], "hardlink")).is_ok());
+        }
+        // 直接改写已有文件的rsync选项和provider只能使用reflink
+        assert!(new(&mirror(ProviderEnum::Rsync, &["--inplace"], "hardlink")).is_err());
+        assert!(new(&mirror(ProviderEnum::TwoStageRsync, &["--append-verify"], "hardlink")).is_err());
+        assert!(new(&mirror(ProviderEnum::Command, &[], "hardlink")).is_err());
+        assert!(new(&mirror(ProviderEnum::Git, &[], "hardlink")).is_err());
+        assert!(new(&mirror(ProviderEnum::Rsync, &["--inplace"], "reflink")).is_ok());
+        assert!(new(&mirror(ProviderEnum::Command, &[], "reflink")).is_ok());
+    }
+}
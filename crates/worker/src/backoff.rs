@@ -0,0 +1,69 @@
+use std::time::Duration;
+use rand::Rng;
+
+// RetryPolicy决定同步失败后等待多久再尝试
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RetryPolicy {
+    // 第一次重试前等待的时间，之后每次重试翻倍，不超过max_delay；为0时立即重试
+    pub(crate) delay: Duration,
+    pub(crate) max_delay: Duration,
+    // 重试全部失败后隔多久再次同步，连续失败时翻倍，不超过interval；为None时等待interval
+    pub(crate) fail_interval: Option<Duration>,
+}
+
+// backoff返回第n次（从0开始）退避的时间：base * 2^n，不超过max
+pub(crate) fn backoff(base: Duration, n: u32, max: Duration) -> Duration {
+    base.checked_mul(2u32.saturating_pow(n)).unwrap_or(max).min(max)
+}
+
+// jitter在 [d/2, d] 之间随机选取等待时间，避免多个任务同时重试
+pub(crate) fn jitter(d: Duration) -> Duration {
+    if d.is_zero() {
+        return d
+    }
+    d / 2 + rand::thread_rng().gen_range(Duration::ZERO..=d / 2)
+}
+
+impl RetryPolicy {
+    // retry_delay返回第retry次重试之前等待的时间，retry从1开始
+    pub(crate) fn retry_delay(&self, retry: u32) -> Duration {
+        jitter(backoff(self.delay, retry.saturating_sub(1), self.max_delay.max(self.delay)))
+    }
+
+    // fail_delay返回连续失败failures次后到下一次同步的时间，没有配置fail_interval时返回None
+    pub(crate) fn fail_delay(&self, failures: u32, interval: Duration) -> Option<Duration> {
+        let fail_interval = self.fail_interval?;
+        Some(jitter(backoff(fail_interval, failures.saturating_sub(1), interval.max(fail_interval))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff(){
+        let s = Duration::from_secs;
+        assert_eq!(backoff(s(30), 0, s(600)), s(30));
+        assert_eq!(backoff(s(30), 3, s(600)), s(240));
+        assert_eq!(backoff(s(30), 5, s(600)), s(600));
+        assert_eq!(backoff(s(30), 100, s(600)), s(600));
+
+        for _ in 0..100 {
+            let d = jitter(s(60));
+            assert!(d >= s(30) && d <= s(60), "{:?}", d);
+        }
+        assert_eq!(jitter(Duration::ZERO), Duration::ZERO);
+
+        let policy = RetryPolicy{ delay: s(10), max_delay: s(60), fail_interval: Some(s(1800)) };
+        let d = policy.retry_delay(3);
+        assert!(d >= s(20) && d <= s(40), "{:?}", d);
+        assert!(policy.retry_delay(10) <= s(60));
+        assert!(RetryPolicy::default().retry_delay(2).is_zero());
+
+        let d = policy.fail_delay(2, s(86400)).unwrap();
+        assert!(d >= s(1800) && d <= s(3600), "{:?}", d);
+        assert!(policy.fail_delay(20, s(86400)).unwrap() <= s(86400));
+        assert!(RetryPolicy::default().fail_delay(1, s(86400)).is_none());
+    }
+}
@@ -1,7 +1,6 @@
 use anyhow::{anyhow, Result};
 use std::fmt::Debug;
 use std::fs::{File, OpenOptions};
-use tokio::process::Command;
 use tokio::sync::Mutex;
 use std::sync::{Arc};
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -13,7 +12,7 @@ use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 use crate::cgroup::CGroupHook;
 use crate::context::Context;
-use crate::docker::DockerHook;
+use crate::docker::{ContainerSpec, DockerHook};
 use crate::hooks::{HookType, JobHook, JobIntoBox};
 use crate::provider::{_LOG_DIR_KEY, _LOG_FILE_KEY, _WORKING_DIR_KEY};
 use crate::runner::{err_process_not_started, CmdJob};
@@ -21,6 +20,8 @@ use crate::zfs_hook::ZfsHook;
 #[cfg(target_os = "linux")]
 use crate::btrfs_snapshot_hook::BtrfsSnapshotHook;
 use crate::config::{DockerConfig, MirrorConfig};
+use crate::rate_limit::TokenBucket;
+use internal::msg::ResourceUsage;
 
 // baseProvider是providers的基本混合
 // Mutex<BaseProvider>
@@ -37,6 +38,10 @@ pub(crate) struct BaseProvider {
     pub(crate) cmd: Option<CmdJob>,
     pub(crate) log_file_fd: Option<File>,
     pub(crate) is_running: AtomicBool,
+    // 本次同步消耗的资源，two-stage-rsync等运行多个命令的provider会累加每个命令的消耗
+    pub(crate) usage: Mutex<Option<ResourceUsage>>,
+    // 本次同步分到的带宽，没有带宽限制时为None
+    pub(crate) bandwidth: Option<Arc<TokenBucket>>,
 
     pub(crate) cgroup: Arc<Option<CGroupHook>>,
     pub(crate) zfs: Arc<Option<ZfsHook>>,
@@ -237,6 +242,12 @@ impl BaseProvider {
                     vols.extend(ivs.get::<Vec<String>>().cloned().unwrap());
                 }
             }
+            // docker和podman都不允许两个卷挂载到同一个位置，保留先配置的卷
+            let mut targets = std::collections::HashSet::new();
+            vols.retain(|vol| {
+                let target = vol.split(':').nth(1).unwrap_or(vol);
+                targets.insert(target.to_string())
+            });
             vols
         }else {
             Vec::new()
@@ -246,9 +257,17 @@ impl BaseProvider {
 
     /// 启动配置好的命令
     pub(crate) async fn start(&mut self) -> Result<()>{
+        if let Some(spec) = self.cmd.as_ref().unwrap().container.clone() {
+            return self.start_container(spec).await
+        }
+        let (cgroup, name) = (self.cgroup_ref(), self.name());
         let cmd_job = self.cmd.as_mut().unwrap();
         let mut cmd_lock = cmd_job.cmd.lock().await;
         debug!("命令启动：{:?}", *cmd_lock);
+        // 启用了cgroup时，同步进程在exec之前进入mirror的子group
+        if let Some(cgroup) = cgroup.as_ref() {
+            cgroup.attach(&mut cmd_lock, &name)?;
+        }
         let (mut tx_lock, mut rx_lock) = 
             (cmd_job.finished_tx.lock().await, cmd_job.finished_rx.lock().await);
         let (tx, rx) = channel(1);
@@ -257,6 +276,7 @@ impl BaseProvider {
         drop((tx_lock, rx_lock));
         
         
+        cmd_job.started_at = Some(std::time::Instant::now());
         let spawn_result = cmd_lock.spawn();
         // drop(cmd_lock);
         match spawn_result {
@@ -276,13 +296,112 @@ impl BaseProvider {
         Ok(())
     }
 
+    // start_container通过docker API创建并启动容器，容器的输出由后台任务写入日志文件
+    async fn start_container(&mut self, spec: ContainerSpec) -> Result<()> {
+        let docker = self.docker_ref();
+        let docker = docker.as_ref().as_ref().ok_or_else(|| anyhow!("没有配置docker"))?;
+        let config = docker.container_config(&spec)?;
+        let client = docker.client();
+        // 删除上一次同步残留的同名容器
+        client.remove_container(&spec.name).await?;
+        let id = client.create_container(&spec.name, &docker.image, &config).await?;
+        debug!("容器 {} ({}) 启动：{:?}", spec.name, id, spec.cmd);
+        let logs = match client.start_container(&id).await {
+            Ok(()) => client.logs(&id).await,
+            Err(e) => Err(e),
+        };
+        let mut logs = match logs {
+            Ok(logs) => logs,
+            Err(e) => {
+                let _ = client.remove_container(&id).await;
+                return Err(e)
+            }
+        };
+
+        let cmd_job = self.cmd.as_mut().unwrap();
+        let log_file = match cmd_job.log_file.lock().await.as_ref() {
+            Some(file) => Some(file.try_clone()?),
+            None => None,
+        };
+        *cmd_job.container_logs.lock().await = Some(tokio::spawn(async move { logs.copy_logs(log_file).await }));
+        // 资源统计只用于记录同步历史，读取失败时不影响同步
+        match client.stats(&id).await {
+            Ok(mut stats) => {
+                let usage = Arc::new(std::sync::Mutex::new(ResourceUsage::default()));
+                let collected = usage.clone();
+                let task = tokio::spawn(async move {
+                    if let Err(e) = stats.collect_usage(&collected).await {
+                        warn!("读取容器的资源统计失败：{}", e);
+                    }
+                });
+                *cmd_job.container_stats.lock().await = Some((task, usage));
+            }
+            Err(e) => warn!("{}", e),
+        }
+        *cmd_job.container_id.lock().await = Some(id);
+        cmd_job.started_at = Some(std::time::Instant::now());
+        Ok(())
+    }
+
+    // wait_container等待容器退出并删除容器，容器因为超出内存限制被杀死时返回错误
+    async fn wait_container(&self) -> Result<i32> {
+        let cmd_job = self.cmd.as_ref().unwrap();
+        let id = match cmd_job.container_id.lock().await.take() {
+            Some(id) => id,
+            None => return Ok(0),
+        };
+        let docker = self.docker_ref();
+        let client = docker.as_ref().as_ref().ok_or_else(|| anyhow!("没有配置docker"))?.client();
+        let result = client.wait_container(&id).await;
+        let state = client.inspect_container(&id).await;
+        if let Some(logs) = cmd_job.container_logs.lock().await.take() {
+            match logs.await {
+                Ok(Err(e)) => warn!("读取容器 {} 的日志失败：{}", id, e),
+                Err(e) => warn!("读取容器 {} 的日志失败：{}", id, e),
+                _ => {}
+            }
+        }
+        if let Some((mut task, usage)) = cmd_job.container_stats.lock().await.take() {
+            // 容器退出后统计流应当很快结束，超时的话使用已经统计到的值
+            if tokio::time::timeout(std::time::Duration::from_secs(5), &mut task).await.is_err() {
+                task.abort();
+            }
+            let mut usage = *usage.lock().unwrap();
+            usage.elapsed_ms = cmd_job.started_at.map(|t| t.elapsed().as_millis() as u64).unwrap_or(0);
+            *cmd_job.usage.lock().await = Some(usage);
+        }
+        if let Err(e) = client.remove_container(&id).await {
+            warn!("删除容器 {} 失败：{}", id, e);
+        }
+        let code = result?;
+        if let Ok(Some(state)) = state {
+            if state.oom_killed {
+                return Err(anyhow!("容器超出内存限制，被OOM killer杀死（退出码 {}）", code))
+            }
+        }
+        Ok(code as i32)
+    }
+
     // 等待命令结束
     pub(crate) async fn wait(&self) -> Result<i32>{
         debug!("调用了wait函数，调用者：{}", self.name());
         
-        let ret = self.cmd.as_ref().unwrap()
-            .wait().await;
-        
+        let cmd_job = self.cmd.as_ref().unwrap();
+        let ret = if cmd_job.container.is_some() {
+            self.wait_container().await
+        } else {
+            cmd_job.wait().await
+        };
+
+        // 累加这一次运行的资源消耗，启用了cgroup时优先使用cgroup的统计，
+        // 它只包括这个mirror的进程（以及同步进程没有回收的子进程）
+        if let Some(mut usage) = *cmd_job.usage.lock().await {
+            if let Some(cgroup) = self.cgroup.as_ref() {
+                usage.merge(&cgroup.usage(&self.name()));
+            }
+            self.usage.lock().await.get_or_insert_with(ResourceUsage::default).add(&usage);
+        }
+
         debug!("将is_running字段设置为false，调用者：{}", self.name());
         self.is_running.store(false, Ordering::Release);
         // println!("debug: 将is_running字段设置为false，调用者：{}", self.name());
@@ -298,6 +417,12 @@ impl BaseProvider {
         }
         /////////////////////////////////
         let cmd_job = self.cmd.as_ref().unwrap();
+        if cmd_job.container.is_some() {
+            if let Some(docker) = self.docker.as_ref() {
+                docker.client().stop_container(&docker.name(self.name()), 2).await?;
+            }
+            return Ok(())
+        }
         let pid = cmd_job.pid;
         let cmd_lock = cmd_job.cmd.lock().await;
         let mut finished_rx_lock = cmd_job.finished_rx.lock().await;
@@ -306,17 +431,6 @@ impl BaseProvider {
             return Err(err_process_not_started())
         }
         
-        if let Some(docker) = self.docker.as_ref() {
-            // println!("debug: 终止docker");
-            let name = docker.name(self.name());
-            let _ = Command::new("docker").arg("stop")
-                .arg("-t").arg("2")
-                .arg(name)
-                .output().await?;
-            // println!("debug: output是 {output:?}");
-            return Ok(());
-        }
-        
         // println!("debug: 发送 SIGTERM 信号");
         let pid = Pid::from_raw(pid.unwrap());
         // 发送 SIGTERM 信号
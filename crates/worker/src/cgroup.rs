@@ -1,217 +1,304 @@
-use std::env;
-use std::fs::File;
-use std::io::{self, BufRead, Read};
-use std::process::{Command, exit};
-use std::os::unix::io::FromRawFd;
-use crate::hooks::JobHook;
-use crate::config::{CGroupConfig, MemBytes};
+use std::ffi::CString;
 use std::fs;
-use anyhow::Result;
+use std::io::{self, BufRead};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use log::{debug, info, warn};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::stat::{major, minor};
+use nix::unistd::Pid;
+use tokio::process::Command;
 use tokio::sync::Mutex;
+use crate::config::{CGroupConfig, MemBytes, ResourceLimits};
 use crate::context::Context;
-// use cgroups_rs::hierarchies;
+use crate::hooks::JobHook;
+use internal::msg::ResourceUsage;
+
+// 只支持cgroup v2，通过直接读写cgroupfs实现
+// 每个mirror在配置的group下有一个以mirror名字命名的子group，
+// 同步进程在exec之前把自己移入这个子group，同步结束后杀死子group中残留的进程并删除子group
+const DEFAULT_BASE_PATH: &str = "/sys/fs/cgroup";
+// cpu.max 的周期，单位为微秒
+const CPU_MAX_PERIOD: u64 = 100000;
+// 开启子group的控制器时，cgroup v2要求group本身不能有进程，
+// 此时把group中原有的进程（一般是worker自身）移入这个子group
+const WORKER_GROUP: &str = "__worker";
 
 #[derive(Debug, Clone)]
 pub struct CGroupHook{
-    cg_cfg: CGroupConfig,
+    base_path: PathBuf,
+    group: String,
     mem_limit: MemBytes,
-    // cg_mgr_v1: cgroups_rs::Cgroup,
-    // cg_mgr_v2: cgroups_rs::Cgroup,
+    limits: ResourceLimits,
 }
 
-#[derive(Debug)]
-enum ExecCmd {
-    Cont,
-    Abrt,
+// get_self_cgroup_path读取当前进程在cgroup v2中的路径，格式为 0::<path>
+fn get_self_cgroup_path() -> io::Result<String> {
+    let file = fs::File::open("/proc/self/cgroup")?;
+    for line in io::BufReader::new(file).lines() {
+        let line = line?;
+        if let Some(path) = line.strip_prefix("0::") {
+            return Ok(path.to_string())
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "未找到cgroup v2的路径"))
 }
 
-const CMD_CONT: &str = "cont";
-const CMD_ABRT: &str = "abrt";
-
-fn exec_cmd(command: &str) -> Option<ExecCmd> {
-    match command {
-        CMD_CONT => Some(ExecCmd::Cont),
-        CMD_ABRT => Some(ExecCmd::Abrt),
-        _ => None,
-    }
+fn read_procs(group_path: &Path) -> io::Result<Vec<i32>> {
+    let content = match fs::read_to_string(group_path.join("cgroup.procs")) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        result => result?,
+    };
+    Ok(content.split_whitespace().filter_map(|pid| pid.parse().ok()).collect())
 }
-fn wait_exec() -> io::Result<()> {
-    // 1. Check for the command passed as an argument
-    let binary = env::args().nth(1).expect("No command specified");
-
-    // 2. Open the pipe (file descriptor 3)
-    let mut pipe = unsafe { File::from_raw_fd(3) };
-
-    // 3. Read from the pipe if available
-    let mut cmd_bytes = Vec::new();
-    if let Ok(_) = pipe.read_to_end(&mut cmd_bytes) {
-        let cmd_str = String::from_utf8_lossy(&cmd_bytes);
-        if let Some(cmd) = exec_cmd(&cmd_str) {
-            match cmd {
-                ExecCmd::Abrt => {
-                    eprintln!("Aborting as requested");
-                    exit(1);
-                }
-                ExecCmd::Cont => {
-                    // Continue execution (no-op here, can be expanded)
-                    println!("Continuing execution...");
-                }
-            }
+
+impl CGroupHook{
+    pub(crate) fn new(cg_cfg: CGroupConfig, mem_limit: MemBytes, limits: ResourceLimits) -> Self {
+        let base_path = cg_cfg.base_path
+            .filter(|p| !p.is_empty())
+            .unwrap_or(DEFAULT_BASE_PATH.to_string());
+        // 没有配置group时使用worker自身所在的group
+        let group = match cg_cfg.group.filter(|g| !g.is_empty()) {
+            Some(group) => group,
+            None => get_self_cgroup_path().unwrap_or_else(|e| {
+                warn!("获取worker所在的cgroup失败：{}，使用根group", e);
+                String::new()
+            }),
+        };
+        CGroupHook{
+            base_path: PathBuf::from(base_path),
+            group: group.trim_matches('/').to_string(),
+            mem_limit,
+            limits,
         }
     }
 
-    // 4. Execute the binary command
-    let args: Vec<String> = env::args().skip(1).collect();
-    let env_vars = env::vars().collect::<Vec<_>>();
+    pub(crate) fn group_path(&self) -> PathBuf {
+        self.base_path.join(&self.group)
+    }
 
-    // Run the external command using Command
-    let child = Command::new(&binary)
-        .args(&args)
-        .envs(env_vars.iter().map(|(k, v)| (k.as_str(), v.as_str())))
-        .spawn();
+    // sub_group_path返回mirror对应的子group的路径
+    pub(crate) fn sub_group_path(&self, provider_name: &str) -> PathBuf {
+        self.group_path().join(provider_name)
+    }
 
-    // In case exec fails (it doesn't return on success), we panic
-    match child {
-        Err(e) => {
-            panic!("Exec failed: {}", e);
+    // controllers返回实施资源限制需要开启的控制器
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = vec![];
+        if self.mem_limit.value() > 0 {
+            controllers.push("memory");
         }
-        Ok(_) => {}
+        if self.limits.cpu_weight.is_some() || self.limits.cpu_max.is_some() {
+            controllers.push("cpu");
+        }
+        if self.limits.io_weight.is_some() || !self.limits.io_max.is_empty() {
+            controllers.push("io");
+        }
+        if self.limits.pids_max.is_some() {
+            controllers.push("pids");
+        }
+        controllers
     }
-    Ok(())
-}
 
-fn init() {
-    // Here, we would put any setup or initialization code you need
-    println!("Initialization code goes here...");
-}
-
-fn get_self_cgroup_path() -> Result<String, io::Error> {
-    let file_path = "/proc/self/cgroup"; // 当前进程的 CGroup 信息文件
-    let file = fs::File::open(file_path)?; // 打开文件
-    let reader = io::BufReader::new(file);
+    // init_group创建group，并为子group开启需要的控制器
+    fn init_group(&self) -> Result<()> {
+        if !self.base_path.join("cgroup.controllers").exists() {
+            return Err(anyhow!("{} 不是cgroup v2的挂载点，目前只支持cgroup v2", self.base_path.display()))
+        }
+        let group_path = self.group_path();
+        fs::create_dir_all(&group_path)?;
 
-    for line in reader.lines() {
-        let line = line?; // 读取每一行
-        // 每行格式通常为: <subsystem>:<cgroup_id>:<path>
-        let parts: Vec<&str> = line.split(':').collect();
+        let subtree_control = group_path.join("cgroup.subtree_control");
+        let enabled = fs::read_to_string(&subtree_control).unwrap_or_default();
+        let available = fs::read_to_string(group_path.join("cgroup.controllers")).unwrap_or_default();
+        let mut missing = vec![];
+        for controller in self.controllers() {
+            if enabled.split_whitespace().any(|c| c == controller) {
+                continue
+            }
+            if !available.split_whitespace().any(|c| c == controller) {
+                return Err(anyhow!("cgroup {} 中没有可用的{}控制器", group_path.display(), controller))
+            }
+            missing.push(format!("+{}", controller));
+        }
+        if missing.is_empty() {
+            return Ok(())
+        }
+        let missing = missing.join(" ");
+        match fs::write(&subtree_control, &missing) {
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                let worker_group = group_path.join(WORKER_GROUP);
+                info!("cgroup {} 中存在进程，将其移入 {}", group_path.display(), worker_group.display());
+                fs::create_dir_all(&worker_group)?;
+                for pid in read_procs(&group_path)? {
+                    fs::write(worker_group.join("cgroup.procs"), pid.to_string())?;
+                }
+                fs::write(&subtree_control, &missing)?;
+            }
+            result => result?,
+        }
+        Ok(())
+    }
 
-        // 如果读取到有效的 CGroup 行
-        if parts.len() >= 3 {
-            let cgroup_path = parts[2]; // CGroup 路径
-            return Ok(cgroup_path.to_string())
+    // attach让cmd启动的进程在exec之前把自己移入子group，
+    // 这样同步进程创建的所有子进程都在子group中
+    pub(crate) fn attach(&self, cmd: &mut Command, provider_name: &str) -> Result<()> {
+        let procs = self.sub_group_path(provider_name).join("cgroup.procs");
+        let procs = CString::new(procs.as_os_str().as_bytes())?;
+        // fork之后的子进程中只能调用异步信号安全的函数，所以这里不能分配内存
+        unsafe {
+            cmd.pre_exec(move || {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error())
+                }
+                let ret = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                if ret < 0 {
+                    return Err(err)
+                }
+                Ok(())
+            });
         }
+        Ok(())
     }
-    Err(io::ErrorKind::InvalidData.into())
-}
 
-impl CGroupHook{
-    fn new(cg_cfg: CGroupConfig, mem_limit: MemBytes) -> Self {
-        CGroupHook{
-            cg_cfg,
-            mem_limit,
+    // apply_limits把资源限制写入子group
+    fn apply_limits(&self, sub_group: &Path) -> Result<()> {
+        if self.mem_limit.value() > 0 {
+            fs::write(sub_group.join("memory.max"), self.mem_limit.value().to_string())?;
+        }
+        if let Some(weight) = self.limits.cpu_weight {
+            fs::write(sub_group.join("cpu.weight"), weight.to_string())?;
+        }
+        if let Some(cpu_max) = self.limits.cpu_max {
+            let quota = cpu_max.0 * CPU_MAX_PERIOD / 1000;
+            fs::write(sub_group.join("cpu.max"), format!("{} {}", quota, CPU_MAX_PERIOD))?;
+        }
+        if let Some(weight) = self.limits.io_weight {
+            fs::write(sub_group.join("io.weight"), format!("default {}", weight))?;
         }
+        for io_max in self.limits.io_max.iter() {
+            // io.max 中的设备用 主设备号:次设备号 表示
+            let rdev = fs::metadata(&io_max.device)
+                .map_err(|e| anyhow!("无法读取设备 {}：{}", io_max.device, e))?
+                .rdev();
+            let mut line = format!("{}:{}", major(rdev), minor(rdev));
+            if let Some(rbps) = io_max.rbps {
+                line.push_str(&format!(" rbps={}", rbps));
+            }
+            if let Some(wbps) = io_max.wbps {
+                line.push_str(&format!(" wbps={}", wbps));
+            }
+            fs::write(sub_group.join("io.max"), line)?;
+        }
+        if let Some(pids_max) = self.limits.pids_max {
+            fs::write(sub_group.join("pids.max"), pids_max.to_string())?;
+        }
+        Ok(())
     }
-    
-    /*
-    fn init_cgroup(&mut self) -> io::Result<()> {
-        let os = env::consts::OS;
-        if os != "linux" {
-            panic!("Only linux is supported");
-        }
-        
-        debug!("初始化cgroup");
-    
-        // 如果 base_group 为空，表示使用当前进程的 cgroup，否则指定一个绝对路径
-        let mut base_group = self.group.clone();
-        if let Some(group) = base_group{
-            if !group.is_empty(){
-                base_group = Some(format!("/{}", group)); // Ensure absolute path
-            }else { 
-                base_group = None;
+
+    // usage读取子group中所有进程消耗的资源，memory.peak需要5.19以上的内核，
+    // io.stat只有开启了io控制器才有，读取不到的项为0
+    pub(crate) fn usage(&self, provider_name: &str) -> ResourceUsage {
+        let sub_group = self.sub_group_path(provider_name);
+        let mut usage = ResourceUsage::default();
+        if let Ok(cpu_stat) = fs::read_to_string(sub_group.join("cpu.stat")) {
+            for line in cpu_stat.lines() {
+                match line.split_once(' ') {
+                    Some(("user_usec", v)) => usage.user_time_ms = v.trim().parse::<u64>().unwrap_or_default() / 1000,
+                    Some(("system_usec", v)) => usage.system_time_ms = v.trim().parse::<u64>().unwrap_or_default() / 1000,
+                    _ => {}
+                }
             }
         }
-        
-        // 检查现在是v1还是v2，is_unified为true表示v2
-        let is_unified = hierarchies::is_cgroup2_unified_mode();
-        self.is_unified = Some(is_unified); // You can check cgroup mode here
-        
-        // v2的处理
-        if is_unified {
-            debug!("监测到Cgroup V2");
-            let mut group_path = base_group.clone();
-            if group_path.is_none() {
-                debug!("检测我的cgroup路径");
-                match get_self_cgroup_path() {
-                    Ok(cgroup_path) => {
-                        group_path = Some(cgroup_path);
-                    }
-                    Err(e) => {
-                        return Err(e);
-                    }
+        if let Ok(peak) = fs::read_to_string(sub_group.join("memory.peak")) {
+            usage.max_rss_bytes = peak.trim().parse().unwrap_or_default();
+        }
+        // io.stat 每行是一个设备，例如 8:0 rbytes=1024 wbytes=0 rios=1 wios=0 dbytes=0 dios=0
+        if let Ok(io_stat) = fs::read_to_string(sub_group.join("io.stat")) {
+            for field in io_stat.split_whitespace() {
+                match field.split_once('=') {
+                    Some(("rbytes", v)) => usage.read_bytes += v.parse::<u64>().unwrap_or_default(),
+                    Some(("wbytes", v)) => usage.write_bytes += v.parse::<u64>().unwrap_or_default(),
+                    _ => {}
                 }
             }
-            info!("使用cgroup路径：{}", group_path.unwrap());
-            
-            let group_path = if base_group.is_empty() {
-                self.detect_cgroup_path()?
-            } else {
-                base_group
-            };
-            println!("Using cgroup path: {}", group_path);
-            self.cg_mgr_v2 = Some(group_path);
-        } else {
-            println!("Cgroup V1 detected");
-            let group_path = if base_group.is_empty() {
-                "/sys/fs/cgroup" // Default path
-            } else {
-                &base_group
-            };
-            self.cg_mgr_v1 = Some(group_path.to_string());
-        }
-    
-        // Further logic for creating subgroups and moving processes (simplified)
-        self.create_and_move_processes()
+        }
+        usage
     }
-     */
 
-    fn detect_cgroup_path(&self) -> io::Result<String> {
-        // Simulate detection of the current cgroup path for the process
-        Ok("/sys/fs/cgroup/unified".to_string())
+    // kill_all杀死子group中残留的进程
+    fn kill_all(&self, sub_group: &Path) -> Result<()> {
+        let pids = read_procs(sub_group)?;
+        if pids.is_empty() {
+            return Ok(())
+        }
+        warn!("cgroup {} 中残留了 {} 个进程，将其杀死", sub_group.display(), pids.len());
+        // cgroup.kill 需要5.14以上的内核
+        let kill_file = sub_group.join("cgroup.kill");
+        if kill_file.exists() {
+            fs::write(kill_file, "1")?;
+            return Ok(())
+        }
+        for pid in pids {
+            if let Err(e) = kill(Pid::from_raw(pid), Signal::SIGKILL) {
+                debug!("杀死进程 {} 失败：{}", pid, e);
+            }
+        }
+        Ok(())
     }
-
-    // fn create_and_move_processes(&self) -> io::Result<()> {
-    //     // Logic to create subgroups and move processes into it (simplified)
-    //     println!("Creating subgroups and moving processes...");
-    // 
-    //     if let Some(group_path) = &self.cg_mgr_v2 {
-    //         println!("Creating sub group in cgroup v2: {}", group_path);
-    //         // Here you could interact with the cgroup v2 filesystem to create subgroups
-    //     }
-    // 
-    //     Ok(())
-    // }
 }
 
 #[async_trait]
 impl JobHook for CGroupHook  {
 
     async fn pre_exec(&self,
-                _provider_name: String,
+                provider_name: String,
                 _log_dir: String,
                 _log_file: String,
                 _working_dir: String,
                 _context: Arc<Mutex<Option<Context>>>)
-        -> Result<()> 
+        -> Result<()>
     {
-        todo!()
+        self.init_group()?;
+        let sub_group = self.sub_group_path(&provider_name);
+        debug!("为 {} 创建cgroup：{}", provider_name, sub_group.display());
+        if let Err(e) = fs::create_dir(&sub_group) {
+            if e.kind() != io::ErrorKind::AlreadyExists {
+                return Err(anyhow!("创建cgroup {} 失败：{}", sub_group.display(), e))
+            }
+        }
+        self.apply_limits(&sub_group)
+            .map_err(|e| anyhow!("设置 {} 的资源限制失败：{}", provider_name, e))
     }
-    
-    async fn post_exec(&self, context: Arc<Mutex<Option<Context>>>, provider_name: String) -> Result<()> {
-        todo!()
+
+    async fn post_exec(&self, _context: Arc<Mutex<Option<Context>>>, provider_name: String) -> Result<()> {
+        let sub_group = self.sub_group_path(&provider_name);
+        if !sub_group.exists() {
+            return Ok(())
+        }
+        self.kill_all(&sub_group)?;
+        // 进程被杀死之后需要一点时间才会离开cgroup，在此之前无法删除
+        for _ in 0..10 {
+            match fs::remove_dir(&sub_group) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                Err(e) => {
+                    warn!("删除cgroup {} 失败：{}", sub_group.display(), e);
+                    return Ok(())
+                }
+            }
+        }
+        warn!("cgroup {} 中仍有进程，未能删除", sub_group.display());
+        Ok(())
     }
 }
-
-
-
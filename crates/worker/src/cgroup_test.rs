@@ -1,8 +1,98 @@
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::os::unix::process::ExitStatusExt;
+    use std::sync::Arc;
+    use tempfile::Builder;
+    use tokio::process::Command;
+    use tokio::sync::Mutex;
     use crate::cgroup::*;
-    #[test]
-    fn test_cgroup() {
-        
+    use crate::config::{CGroupConfig, CpuMax, IoMax, MemBytes, ResourceLimits};
+    use crate::context::Context;
+    use crate::hooks::JobHook;
+
+    #[tokio::test]
+    async fn test_cgroup() {
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let base_path = tmp_dir.path();
+        let cfg: CGroupConfig = toml::from_str(&format!(r#"
+enable = true
+base_path = "{}"
+group = "rtsync"
+"#, base_path.display())).unwrap();
+
+        let limits = ResourceLimits {
+            cpu_weight: Some(50),
+            cpu_max: Some(CpuMax(1500)),
+            io_weight: None,
+            io_max: vec![IoMax { device: "/dev/null".to_string(), rbps: Some(1048576), wbps: Some(2048) }],
+            pids_max: Some(512),
+        };
+        let hook = CGroupHook::new(cfg.clone(), MemBytes(512 * 1024 * 1024), limits);
+        let ctx = Arc::new(Mutex::new(Some(Context::new())));
+
+        // 不是cgroup v2的挂载点
+        assert!(hook.pre_exec("test".into(), "".into(), "".into(), "".into(), ctx.clone()).await.is_err());
+
+        // 用普通文件模拟cgroupfs
+        fs::write(base_path.join("cgroup.controllers"), "cpu io memory pids").unwrap();
+        fs::create_dir(base_path.join("rtsync")).unwrap();
+        fs::write(base_path.join("rtsync/cgroup.controllers"), "cpu io memory").unwrap();
+        // 需要的控制器不可用
+        assert!(hook.pre_exec("test".into(), "".into(), "".into(), "".into(), ctx.clone()).await.is_err());
+        fs::write(base_path.join("rtsync/cgroup.controllers"), "cpu io memory pids").unwrap();
+
+        hook.pre_exec("test".into(), "".into(), "".into(), "".into(), ctx.clone()).await.unwrap();
+        let sub_group = base_path.join("rtsync/test");
+        assert_eq!(hook.sub_group_path("test"), sub_group);
+        assert!(sub_group.is_dir());
+        assert_eq!(fs::read_to_string(base_path.join("rtsync/cgroup.subtree_control")).unwrap(), "+memory +cpu +io +pids");
+        assert_eq!(fs::read_to_string(sub_group.join("memory.max")).unwrap(), "536870912");
+        assert_eq!(fs::read_to_string(sub_group.join("cpu.weight")).unwrap(), "50");
+        assert_eq!(fs::read_to_string(sub_group.join("cpu.max")).unwrap(), "150000 100000");
+        assert!(!sub_group.join("io.weight").exists());
+        assert_eq!(fs::read_to_string(sub_group.join("io.max")).unwrap(), "1:3 rbps=1048576 wbps=2048");
+        assert_eq!(fs::read_to_string(sub_group.join("pids.max")).unwrap(), "512");
+
+        // 子进程在exec之前把自己写入cgroup.procs
+        fs::write(sub_group.join("cgroup.procs"), "").unwrap();
+        let mut cmd = Command::new("sleep");
+        cmd.arg("30");
+        hook.attach(&mut cmd, "test").unwrap();
+        let mut child = cmd.spawn().unwrap();
+        assert_eq!(fs::read_to_string(sub_group.join("cgroup.procs")).unwrap(), "0");
+
+        // 读取子group的资源消耗
+        fs::write(sub_group.join("cpu.stat"), "usage_usec 3500000\nuser_usec 2500000\nsystem_usec 1000000\n").unwrap();
+        fs::write(sub_group.join("memory.peak"), "10485760\n").unwrap();
+        fs::write(sub_group.join("io.stat"), "8:0 rbytes=1024 wbytes=2048 rios=1 wios=2\n8:16 rbytes=1 wbytes=2 rios=1 wios=1\n").unwrap();
+        let usage = hook.usage("test");
+        assert_eq!(usage.user_time_ms, 2500);
+        assert_eq!(usage.system_time_ms, 1000);
+        assert_eq!(usage.max_rss_bytes, 10485760);
+        assert_eq!(usage.read_bytes, 1025);
+        assert_eq!(usage.write_bytes, 2050);
+        assert_eq!(hook.usage("not-exist"), Default::default());
+
+        // 同步结束后杀死残留的进程
+        fs::write(sub_group.join("cgroup.procs"), child.id().unwrap().to_string()).unwrap();
+        hook.post_exec(ctx.clone(), "test".into()).await.unwrap();
+        let status = child.wait().await.unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+
+        // 子group不存在时无法启动
+        let mut cmd = Command::new("true");
+        hook.attach(&mut cmd, "not-exist").unwrap();
+        assert!(cmd.spawn().is_err());
+
+        // 没有内存限制时不写入memory.max
+        let hook = CGroupHook::new(cfg, MemBytes(0), ResourceLimits::default());
+        hook.pre_exec("test2".into(), "".into(), "".into(), "".into(), ctx.clone()).await.unwrap();
+        assert!(base_path.join("rtsync/test2").is_dir());
+        assert!(!base_path.join("rtsync/test2/memory.max").exists());
+        hook.post_exec(ctx, "test2".into()).await.unwrap();
+        assert!(!base_path.join("rtsync/test2").exists());
     }
-}
\ No newline at end of file
+}
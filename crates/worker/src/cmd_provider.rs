@@ -10,7 +10,6 @@ use std::sync::Arc;
 use std::sync::atomic::Ordering;
 use anymap::AnyMap;
 use chrono::Duration;
-use libc::{getgid, getuid};
 use log::{debug, error, info};
 use regex::Regex;
 use shlex::Shlex;
@@ -19,7 +18,10 @@ use crate::common::{Empty, DEFAULT_MAX_RETRY};
 use crate::config::ProviderEnum;
 use crate::context::Context;
 use crate::provider::{MirrorProvider, _LOG_DIR_KEY, _LOG_FILE_KEY, _WORKING_DIR_KEY};
+use crate::rate_limit::TokenBucket;
 use internal::util::{find_all_submatches_in_file, extract_size_from_log};
+use internal::size::MirrorSize;
+use internal::msg::ResourceUsage;
 use crate::docker::DockerHook;
 use crate::hooks::{HookType, JobHook};
 use crate::runner::CmdJob;
@@ -49,7 +51,7 @@ pub(crate) struct CmdProvider{
     pub(crate) base_provider: Arc<RwLock<BaseProvider>>,
     pub(crate) cmd_config: Arc<CmdConfig>,
     command: Arc<Vec<String>>,
-    data_size: Arc<Mutex<String>>,
+    data_size: Arc<Mutex<MirrorSize>>,
     fail_on_match: Arc<Option<Regex>>,
     size_pattern: Arc<Option<Regex>>,
 }
@@ -129,49 +131,22 @@ impl CmdProvider{
             ("RTSYNC_LOG_FILE".to_string(), base_provider_lock.log_file().await)
         ].into();
 
+        // 命令可以根据分到的带宽（字节每秒）自己限速
+        if let Some(limiter) = base_provider_lock.bandwidth.as_ref() {
+            env.insert("RTSYNC_BANDWIDTH_LIMIT".to_string(), limiter.rate().to_string());
+        }
         for (k, v) in self.cmd_config.env.iter(){
             env.insert(k.to_string(), v.to_string());
         }
 
         let cmd_job: CmdJob;
-        let mut args: Vec<String> = Vec::new();
         let use_docker = base_provider_lock.docker_ref().is_some();
 
         if let Some(d) = base_provider_lock.docker_ref().as_ref(){
-            let c = "docker";
-            args.extend(vec!["run".to_string(), "--rm".to_string(),
-                             // "-a".to_string(), "STDOUT".to_string(), "-a".to_string(), "STDERR".to_string(),
-                             "--name".to_string(), d.name(self.name().parse().unwrap()),
-                             "-w".to_string(), working_dir.clone()]);
-            // 指定用户
-            unsafe {
-                args.extend(vec!["-u".to_string(),
-                                 format!("{}:{}",getuid().to_string(), getgid().to_string())]);
-            }
-            // 添加卷
-            for vol in base_provider_lock.docker_volumes().await{
-                // println!("debug: 数据卷 {}", &vol);
-                debug!("数据卷: {}", &vol);
-                args.extend(vec!["-v".to_string(), vol.clone()])
-            }
-            // 设置环境变量
-            for (key, value) in env.iter(){
-                let kv = format!("{}={}", key, value);
-                args.extend(vec!["-e".to_string(), kv.clone()])
-            }
-            // 设置内存限制
-            if d.memory_limit.0 != 0{
-                args.extend(vec!["-m".to_string(), format!("{}b", d.memory_limit.value())])
-            }
-            // 添加选项
-            args.extend(d.options.iter().cloned());
-            // 添加镜像和command
-            args.push(d.image.clone());
-            // 添加command
-            args.extend(self.command.iter().cloned());
-
-            cmd_job = CmdJob::new(Command::new(c), working_dir.clone(), env.clone());
-            { cmd_job.cmd.lock().await.args(&args); }
+            // 容器由BaseProvider通过docker API创建，这里只记录容器的配置
+            let spec = d.container_spec(self.name(), self.command.to_vec(), env.clone(),
+                                        working_dir.clone(), base_provider_lock.docker_volumes().await);
+            cmd_job = CmdJob::new_container(spec, working_dir.clone(), env.clone());
             
         }else {
             if self.command.len() == 1{
@@ -235,7 +210,8 @@ impl MirrorProvider for CmdProvider{
     }
 
     async fn run(&self, started: Sender<Empty>) -> Result<()> {
-        { *self.data_size.lock().await = String::new(); }
+        { *self.data_size.lock().await = MirrorSize::default(); }
+        { *self.base_provider.read().await.usage.lock().await = None; }
 
         // println!("debug: 等待启动。。。");
 
@@ -273,8 +249,8 @@ impl MirrorProvider for CmdProvider{
         }
         if let Some(size_pattern) = self.size_pattern.as_ref(){
             *self.data_size.lock().await = extract_size_from_log(&*self.log_file().await, size_pattern)
-                .unwrap_or_default()
-                .into();
+                .map(|size| MirrorSize::parse(&size))
+                .unwrap_or_default();
             // println!("debug: 找到的匹配项：{:?}", self.data_size);
         }
         Ok(())
@@ -352,8 +328,16 @@ impl MirrorProvider for CmdProvider{
         self.base_provider.read().await.is_master
     }
 
-    async fn data_size(&self) -> String {
-        self.data_size.lock().await.to_string()
+    async fn data_size(&self) -> MirrorSize {
+        self.data_size.lock().await.clone()
+    }
+
+    async fn resource_usage(&self) -> Option<ResourceUsage> {
+        *self.base_provider.read().await.usage.lock().await
+    }
+
+    async fn set_bandwidth(&self, limiter: Option<Arc<TokenBucket>>) {
+        self.base_provider.write().await.bandwidth = limiter;
     }
 
     async fn enter_context(&mut self) -> Arc<Mutex<Option<Context>>> {
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{AcquireError, Notify, OwnedSemaphorePermit, Semaphore};
+use crate::config::GlobalConfig;
+
+// priority不小于CRITICAL_PRIORITY的任务总是最先同步，其他任务的优先级随等待时间增加也不会超过它们
+pub(crate) const CRITICAL_PRIORITY: i64 = 100;
+// 等待中的任务每等待一分钟优先级加一，避免低优先级的任务一直得不到同步
+const AGING_INTERVAL: Duration = Duration::from_secs(60);
+// 等待中的任务重新检查优先级的间隔
+const AGING_CHECK: Duration = Duration::from_secs(10);
+
+// effective_priority返回等待了waited的任务当前的优先级
+pub(crate) fn effective_priority(priority: i64, waited: Duration) -> i64 {
+    if priority >= CRITICAL_PRIORITY {
+        return priority
+    }
+    let aged = (waited.as_secs() / AGING_INTERVAL.as_secs()) as i64;
+    priority.saturating_add(aged).min(priority.max(CRITICAL_PRIORITY - 1))
+}
+
+// WaitQueue让等待全局并发许可的任务按照优先级获取许可：只有优先级最高的任务去获取信号量，
+// 优先级相同时先等待的任务优先
+#[derive(Debug, Default)]
+pub(crate) struct WaitQueue {
+    // id -> (priority, 开始等待的时间)
+    waiters: std::sync::Mutex<HashMap<u64, (i64, Instant)>>,
+    next_id: AtomicU64,
+    notify: Notify,
+}
+
+impl WaitQueue {
+    // first返回当前应该获取许可的任务的id
+    fn first(&self) -> Option<u64> {
+        let now = Instant::now();
+        self.waiters.lock().unwrap().iter()
+            .map(|(id, (priority, since))| (effective_priority(*priority, now - *since), std::cmp::Reverse((*since, *id))))
+            .max()
+            .map(|(_, std::cmp::Reverse((_, id)))| id)
+    }
+
+    pub(crate) async fn acquire(&self, semaphore: Arc<Semaphore>, priority: i64) -> Result<OwnedSemaphorePermit, AcquireError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.waiters.lock().unwrap().insert(id, (priority, Instant::now()));
+        // 新任务加入后正在获取许可的任务可能不再是优先级最高的
+        self.notify.notify_waiters();
+        // 获取到许可或者等待被取消时都要离开队列，并让其他任务重新检查
+        scopeguard::defer! {
+            self.waiters.lock().unwrap().remove(&id);
+            self.notify.notify_waiters();
+        }
+        loop {
+            let notified = self.notify.notified();
+            if self.first() == Some(id) {
+                tokio::select! {
+                    permit = semaphore.clone().acquire_owned() => return permit,
+                    _ = notified => {}
+                    _ = tokio::time::sleep(AGING_CHECK) => {}
+                }
+            } else {
+                tokio::select! {
+                    _ = notified => {}
+                    _ = tokio::time::sleep(AGING_CHECK) => {}
+                }
+            }
+        }
+    }
+}
+
+// ConcurrencyGroups为每个并发分组（上游主机名或concurrency_group）保存一个信号量，
+// 同一分组的任务除了全局的concurrent之外还要获取分组的信号量才能开始同步
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConcurrencyGroups {
+    // 没有单独配置的分组的并发数，为0时不限制
+    default_limit: usize,
+    limits: HashMap<String, usize>,
+    semaphores: Arc<std::sync::Mutex<HashMap<String, Arc<Semaphore>>>>,
+    // 等待全局并发许可的任务
+    pub(crate) queue: Arc<WaitQueue>,
+}
+
+impl ConcurrencyGroups {
+    pub(crate) fn new(global: &GlobalConfig) -> Self {
+        let limits = global.concurrency_groups.iter().flatten()
+            .map(|(group, limit)| (group.to_ascii_lowercase(), *limit as usize))
+            .collect();
+        ConcurrencyGroups {
+            default_limit: global.per_host_concurrent.unwrap_or_default() as usize,
+            limits,
+            semaphores: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            queue: Arc::new(WaitQueue::default()),
+        }
+    }
+
+    // limit返回分组的并发数，为0时表示不限制
+    pub(crate) fn limit(&self, group: &str) -> usize {
+        self.limits.get(&group.to_ascii_lowercase()).copied().unwrap_or(self.default_limit)
+    }
+
+    // semaphore返回分组共享的信号量，分组不限制并发时返回None
+    pub(crate) fn semaphore(&self, group: &str) -> Option<Arc<Semaphore>> {
+        let limit = self.limit(group);
+        if limit == 0 {
+            return None
+        }
+        let mut semaphores = self.semaphores.lock().unwrap();
+        let semaphore = semaphores.entry(group.to_ascii_lowercase())
+            .or_insert_with(|| Arc::new(Semaphore::new(limit)));
+        Some(semaphore.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrency_groups(){
+        let global = GlobalConfig {
+            per_host_concurrent: Some(2),
+            concurrency_groups: Some(HashMap::from([("rsync.kernel.org".to_string(), 1), ("local".to_string(), 0)])),
+            ..GlobalConfig::default()
+        };
+        let groups = ConcurrencyGroups::new(&global);
+        let kernel = groups.semaphore("rsync.kernel.org").unwrap();
+        assert_eq!(kernel.available_permits(), 1);
+        // 同一分组共享同一个信号量
+        assert!(Arc::ptr_eq(&kernel, &groups.semaphore("RSYNC.kernel.org").unwrap()));
+        assert_eq!(groups.semaphore("mirrors.tuna.tsinghua.edu.cn").unwrap().available_permits(), 2);
+        assert!(groups.semaphore("local").is_none());
+        assert!(ConcurrencyGroups::new(&GlobalConfig::default()).semaphore("rsync.kernel.org").is_none());
+    }
+
+    #[test]
+    fn test_effective_priority(){
+        let m = |minutes: u64| Duration::from_secs(minutes * 60);
+        assert_eq!(effective_priority(0, m(0)), 0);
+        assert_eq!(effective_priority(0, Duration::from_secs(59)), 0);
+        assert_eq!(effective_priority(-10, m(15)), 5);
+        // 等待再久也不会超过关键任务
+        assert_eq!(effective_priority(0, m(10000)), CRITICAL_PRIORITY - 1);
+        assert_eq!(effective_priority(CRITICAL_PRIORITY, m(10000)), CRITICAL_PRIORITY);
+        assert_eq!(effective_priority(CRITICAL_PRIORITY + 1, m(0)), CRITICAL_PRIORITY + 1);
+    }
+
+    #[tokio::test]
+    async fn test_wait_queue(){
+        let queue = Arc::new(WaitQueue::default());
+        let semaphore = Arc::new(Semaphore::new(1));
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(3);
+        for (name, priority) in [("low", -1), ("normal", 0), ("critical", CRITICAL_PRIORITY)] {
+            let (queue, semaphore, tx) = (queue.clone(), semaphore.clone(), tx.clone());
+            tokio::spawn(async move {
+                let _permit = queue.acquire(semaphore, priority).await.unwrap();
+                tx.send(name).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            });
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        drop(permit);
+        assert_eq!(rx.recv().await, Some("critical"));
+        assert_eq!(rx.recv().await, Some("normal"));
+        assert_eq!(rx.recv().await, Some("low"));
+        assert!(queue.waiters.lock().unwrap().is_empty());
+    }
+}
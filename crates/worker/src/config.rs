@@ -1,12 +1,16 @@
 use std::cell::RefCell;
 use std::str::FromStr;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 #[derive(Debug, PartialEq, Deserialize, Clone, Eq)]
 pub enum ProviderEnum {
     Rsync,
     TwoStageRsync,
     Command,
+    Git,
+    Http,
+    Lftp,
+    S3,
 }
 
 use serde::de::{self, Deserializer};
@@ -20,11 +24,45 @@ where
         Some("command") => Ok(Some(ProviderEnum::Command)),
         Some("rsync") => Ok(Some(ProviderEnum::Rsync)),
         Some("two-stage-rsync") => Ok(Some(ProviderEnum::TwoStageRsync)),
+        Some("git") => Ok(Some(ProviderEnum::Git)),
+        Some("http") => Ok(Some(ProviderEnum::Http)),
+        Some("lftp") => Ok(Some(ProviderEnum::Lftp)),
+        Some("s3") => Ok(Some(ProviderEnum::S3)),
         None => Ok(None),
         _ => Err(de::Error::custom("invalid provider value")),
     }
 }
 
+// ContainerRuntime是运行容器的引擎，podman通过兼容docker的API提供服务
+#[derive(Debug, Default, PartialEq, Deserialize, Clone, Copy, Eq)]
+pub enum ContainerRuntime {
+    #[default]
+    Docker,
+    Podman,
+}
+
+impl std::fmt::Display for ContainerRuntime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerRuntime::Docker => write!(f, "docker"),
+            ContainerRuntime::Podman => write!(f, "podman"),
+        }
+    }
+}
+
+fn deserialize_container_runtime<'de, D>(deserializer: D) -> Result<Option<ContainerRuntime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    match s.as_deref() {
+        Some("docker") => Ok(Some(ContainerRuntime::Docker)),
+        Some("podman") => Ok(Some(ContainerRuntime::Podman)),
+        None => Ok(None),
+        _ => Err(de::Error::custom("invalid container runtime value")),
+    }
+}
+
 //Config代表worker配置选项
 #[derive(Debug, Default, Deserialize, Clone)]
 #[serde(default)]
@@ -55,6 +93,41 @@ pub(crate) struct GlobalConfig {
 
     pub(crate) exec_on_success: Option<Vec<String>>,
     pub(crate) exec_on_failure: Option<Vec<String>>,
+
+    // 所有mirror默认的同步时间段，mirror中配置了同名字段时使用mirror的配置
+    pub(crate) allowed_windows: Option<Vec<String>>,
+    pub(crate) blackout: Option<Vec<String>>,
+    pub(crate) pause_outside_window: Option<bool>,
+
+    // 所有mirror默认的重试间隔（秒）和失败后的同步间隔（分钟）
+    pub(crate) retry_delay: Option<i64>,
+    pub(crate) retry_max_delay: Option<i64>,
+    pub(crate) fail_interval: Option<i64>,
+
+    // 同一上游（或同一concurrency_group）的mirror最多同时同步的数量，为0或不配置时只受concurrent限制
+    pub(crate) per_host_concurrent: Option<u64>,
+    // 为指定的上游主机或concurrency_group单独设置并发数，如 { "rsync.kernel.org" = 2 }
+    pub(crate) concurrency_groups: Option<HashMap<String, u64>>,
+    // worker的总带宽（字节每秒，如 "100M"），由正在同步的mirror平分，不配置时不限制
+    pub(crate) bandwidth_limit: Option<String>,
+}
+
+impl GlobalConfig {
+    // bandwidth_limit_bytes检查并解析总带宽，没有配置时返回None
+    pub(crate) fn bandwidth_limit_bytes(&self) -> Result<Option<u64>> {
+        parse_bandwidth_limit(self.bandwidth_limit.as_deref())
+    }
+}
+
+// parse_bandwidth_limit把 "50M" 这样的带宽解析为每秒的字节数
+fn parse_bandwidth_limit(limit: Option<&str>) -> Result<Option<u64>> {
+    match limit.map(str::trim).filter(|s| !s.is_empty()) {
+        Some(limit) => match MirrorSize::parse(limit).bytes {
+            Some(bytes) if bytes > 0 => Ok(Some(bytes)),
+            _ => Err(anyhow!("无效的带宽：{}", limit)),
+        },
+        None => Ok(None),
+    }
 }
 
 #[derive(Debug, Default, Deserialize, Clone)]
@@ -64,6 +137,9 @@ pub(crate) struct ManagerConfig{
     //该选项覆盖APIBase
     pub(crate) api_list: Option<Vec<String>>,
     pub(crate) ca_cert: Option<String>,
+    // 双向TLS时向manager出示的客户端证书和私钥，证书的CN应与worker名一致
+    pub(crate) client_cert: Option<String>,
+    pub(crate) client_key: Option<String>,
     // token: Option<String>,
 }
 impl ManagerConfig {
@@ -89,13 +165,17 @@ pub(crate) struct ServerConfig {
     pub(crate) listen_port: Option<usize>,
     pub(crate) ssl_cert: Option<String>,
     pub(crate) ssl_key: Option<String>,
+    // 用于校验manager客户端证书的CA，设置后启用双向TLS
+    pub(crate) ssl_client_ca: Option<String>,
+    // 为true时拒绝没有提供客户端证书的连接
+    pub(crate) ssl_client_required: Option<bool>,
 }
 
 #[derive(Debug, Default, Deserialize, Clone)]
 #[serde(default)]
 pub(crate) struct CGroupConfig {
     pub(crate) enable: Option<bool>,
-    base_path: Option<String>,
+    pub(crate) base_path: Option<String>,
     pub(crate) group: Option<String>,
     #[serde(rename = "subsystem")]
     sub_system: Option<String>,
@@ -126,6 +206,12 @@ pub(crate) struct BtrfsSnapshotConfig {
 #[serde(default)]
 pub struct DockerConfig {
     pub(crate) enable: Option<bool>,
+    // docker或podman，默认为docker
+    #[serde(deserialize_with = "deserialize_container_runtime")]
+    pub(crate) runtime: Option<ContainerRuntime>,
+    // 容器引擎的unix socket，docker默认使用DOCKER_HOST或/var/run/docker.sock，
+    // podman默认使用CONTAINER_HOST或podman服务的默认socket
+    pub(crate) socket: Option<String>,
     pub(crate) volumes: Option<Vec<String>>,
     pub(crate) options: Option<Vec<String>>,
 }
@@ -210,9 +296,83 @@ where
     }
 }
 
+// CpuMax是同步进程最多可以使用的CPU数量，以千分之一个CPU为单位保存
+// 配置文件中可以写成 cpu_max = 1.5 或 cpu_max = "1.5"
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct CpuMax(pub(crate) u64);
+fn deserialize_cpu_max<'de, D>(deserializer: D) -> Result<Option<CpuMax>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Int(i64),
+        Float(f64),
+        Str(String),
+    }
+    let cpus = match Option::<Repr>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(Repr::Int(i)) => i as f64,
+        Some(Repr::Float(f)) => f,
+        Some(Repr::Str(s)) => s.trim().parse::<f64>()
+            .map_err(|_| de::Error::custom(format!("无效的cpu_max：{}", s)))?,
+    };
+    let millis = (cpus * 1000.0).round();
+    if !millis.is_finite() || millis < 1.0 {
+        return Err(de::Error::custom(format!("cpu_max必须大于0：{}", cpus)))
+    }
+    Ok(Some(CpuMax(millis as u64)))
+}
+
+// PrecheckConfig在同步前检查上游的标记，和上一次同步成功时相同就跳过这次同步，例如
+// precheck = { url = "https://ftp.debian.org/debian/project/trace/master" }
+#[derive(Debug, Default, Deserialize, Clone, Eq, PartialEq)]
+#[serde(default)]
+pub(crate) struct PrecheckConfig {
+    // http(s)的标记文件（如 lastsync、TIME、Release），或者用 rsync --list-only 列出的rsync路径
+    pub(crate) url: Option<String>,
+    // 输出上游标记的命令，和url只能配置一个
+    pub(crate) command: Option<String>,
+    // 比较的内容：auto、content、etag 或 mtime，默认为auto
+    pub(crate) marker: Option<String>,
+    // 检查的超时时间（秒），默认为30
+    pub(crate) timeout: Option<u64>,
+}
+
+// IoMaxConfig限制同步进程对一个块设备的读写带宽，例如
+// io_max = [{ device = "/dev/sda", rbps = "100M", wbps = "50M" }]
+#[derive(Debug, Default, Deserialize, Clone, Eq, PartialEq)]
+#[serde(default)]
+pub(crate) struct IoMaxConfig {
+    pub(crate) device: String,
+    pub(crate) rbps: Option<String>,
+    pub(crate) wbps: Option<String>,
+}
+
+// IoMax是解析之后的IoMaxConfig，带宽以字节每秒为单位
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub(crate) struct IoMax {
+    pub(crate) device: String,
+    pub(crate) rbps: Option<u64>,
+    pub(crate) wbps: Option<u64>,
+}
+
+// ResourceLimits是memory_limit以外的资源限制，
+// 启用了docker时通过docker run的参数实施，否则由cgroup hook写入cgroup
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub(crate) struct ResourceLimits {
+    pub(crate) cpu_weight: Option<u64>,
+    pub(crate) cpu_max: Option<CpuMax>,
+    pub(crate) io_weight: Option<u64>,
+    pub(crate) io_max: Vec<IoMax>,
+    pub(crate) pids_max: Option<u64>,
+}
+
 use std::collections::HashMap;
 use std::fs;
 use std::rc::Rc;
+use std::time::Duration;
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize, Default, Clone, Eq, PartialEq)]
@@ -223,7 +383,32 @@ pub struct MirrorConfig {
     pub(crate) provider: Option<ProviderEnum>,
     pub(crate) upstream: Option<String>,
     pub(crate) interval: Option<i64>,
+    // crontab格式的表达式或 "at 02:30 and 14:30"，配置后代替interval决定同步时间
+    pub(crate) schedule: Option<String>,
+    // schedule和同步时间段使用的时区，如 "Asia/Shanghai"，默认为worker所在机器的时区
+    pub(crate) schedule_timezone: Option<String>,
+    // 允许开始同步的时间段，如 "01:00-07:00 weekdays"，为空时全天允许
+    pub(crate) allowed_windows: Option<Vec<String>>,
+    // 不允许同步的时间段，格式与allowed_windows相同
+    pub(crate) blackout: Option<Vec<String>>,
+    // 离开允许同步的时间段时是否暂停正在运行的同步，等到下一个允许的时间再继续
+    pub(crate) pause_outside_window: Option<bool>,
     pub(crate) retry: Option<i64>,
+    // 第一次重试前等待的秒数，之后每次重试翻倍（加上随机抖动），不超过retry_max_delay秒，默认立即重试
+    pub(crate) retry_delay: Option<i64>,
+    pub(crate) retry_max_delay: Option<i64>,
+    // 重试全部失败后隔多少分钟再次同步，连续失败时翻倍，不超过interval，默认等待interval
+    pub(crate) fail_interval: Option<i64>,
+    // 并发分组，同一分组的mirror共享per_host_concurrent的限制，默认按照upstream的主机名分组
+    pub(crate) concurrency_group: Option<String>,
+    // 多个任务同时等待同步时优先级高的先同步，默认为0，不小于100的任务总是最先同步
+    pub(crate) priority: Option<i64>,
+    // mirror的带宽上限（字节每秒，如 "50M"），配置了全局的bandwidth_limit时分到的带宽不会超过它
+    pub(crate) bandwidth_limit: Option<String>,
+    // 依赖的mirror，配置后不再按照interval同步，而是在这些mirror都同步成功后开始同步
+    pub(crate) after: Option<Vec<String>>,
+    // 同步前检查上游是否有更新，没有更新时跳过同步
+    pub(crate) precheck: Option<PrecheckConfig>,
     pub(crate) timeout: Option<i64>,
     pub(crate) mirror_dir: Option<String>,
     #[serde(rename = "mirror_subdir")]
@@ -255,15 +440,65 @@ pub struct MirrorConfig {
     pub(crate) rsync_override: Option<Vec<String>>,
     pub(crate) stage1_profile: Option<String>,
 
+    // git provider要同步的仓库，每一项为 "<url> [目录名]"，
+    // 不是完整url时拼接在upstream之后，也可以从git_repos_file中按行读取
+    pub(crate) git_repos: Option<Vec<String>>,
+    pub(crate) git_repos_file: Option<String>,
+
+    // http provider的文件列表地址，为空时爬取upstream的目录索引页；http_concurrency为同时下载的文件数
+    pub(crate) http_manifest: Option<String>,
+    pub(crate) http_concurrency: Option<usize>,
+
+    // lftp provider的选项：同时传输的文件数、是否使用被动模式、排除的文件（glob）和额外的mirror参数
+    pub(crate) lftp_parallel: Option<u32>,
+    pub(crate) lftp_passive: Option<bool>,
+    pub(crate) lftp_exclude: Option<Vec<String>>,
+    pub(crate) lftp_options: Option<Vec<String>>,
+
+    // s3 provider的upstream格式为 s3://<bucket>/<prefix>/，s3_endpoint默认为 https://s3.amazonaws.com；
+    // 密钥从s3_credentials_file的s3_profile中读取，未配置时使用 AWS_ACCESS_KEY_ID 等环境变量，都没有时匿名访问
+    pub(crate) s3_endpoint: Option<String>,
+    pub(crate) s3_region: Option<String>,
+    pub(crate) s3_credentials_file: Option<String>,
+    pub(crate) s3_profile: Option<String>,
+    pub(crate) s3_concurrency: Option<usize>,
+    // 每秒下载的字节数，如 "10M"
+    pub(crate) s3_bandwidth_limit: Option<String>,
+    pub(crate) s3_no_delete: Option<bool>,
+
     #[serde(deserialize_with = "deserialize_mem_bytes", default = "memory_limit_default")]
     pub(crate) memory_limit: Option<MemBytes>,
 
+    // cgroup v2的 cpu.weight 和 io.weight，取值范围1~10000，默认为100
+    pub(crate) cpu_weight: Option<u64>,
+    #[serde(deserialize_with = "deserialize_cpu_max")]
+    pub(crate) cpu_max: Option<CpuMax>,
+    pub(crate) io_weight: Option<u64>,
+    pub(crate) io_max: Option<Vec<IoMaxConfig>>,
+    pub(crate) pids_max: Option<u64>,
+
     pub(crate) docker_image: Option<String>,
     pub(crate) docker_volumes: Option<Vec<String>>,
     pub(crate) docker_options: Option<Vec<String>>,
 
     pub(crate) snapshot_path: Option<String>,
 
+    // 同步开始前要求工作目录所在的文件系统至少还有这么多可用空间，例如 "50G"
+    pub(crate) min_free_space: Option<String>,
+
+    // 发布方式：direct直接同步到工作目录；atomic同步到staging目录，成功后原子地切换工作目录这个符号链接
+    pub(crate) publish_mode: Option<String>,
+    // atomic发布时初始化staging目录的方式：hardlink（默认）或reflink
+    pub(crate) publish_seed: Option<String>,
+
+    // 镜像的描述信息，会随状态一起上报给manager
+    // tags用于对镜像分组，也可以作为批量命令的筛选条件
+    pub(crate) tags: Option<Vec<String>>,
+    pub(crate) description: Option<String>,
+    pub(crate) homepage: Option<String>,
+    pub(crate) help_url: Option<String>,
+    pub(crate) maintainers: Option<Vec<String>>,
+
     #[serde(rename = "mirrors")]
     pub(crate) child_mirrors: Option<Vec<MirrorConfig>>
 }
@@ -277,19 +512,176 @@ macro_rules! merge {
 }
 
 
+use internal::msg::MirrorMetadata;
+use internal::size::MirrorSize;
+use crate::cron_schedule::MirrorSchedule;
+use crate::sync_window::SyncWindow;
+use crate::backoff::RetryPolicy;
+use crate::precheck::Precheck;
+
+// upstream_host从上游地址中取出主机名，支持 scheme://[user@]host[:port]/path、rsync的 host::module
+// 以及 [user@]host:path 的写法
+pub(crate) fn upstream_host(upstream: &str) -> Option<String> {
+    let upstream = upstream.trim();
+    let rest = match upstream.split_once("://") {
+        Some((_, rest)) => rest.split('/').next().unwrap_or_default(),
+        None => upstream.split([':', '/']).next().unwrap_or_default(),
+    };
+    let host = rest.rsplit_once('@').map(|(_, host)| host).unwrap_or(rest);
+    let host = match host.strip_prefix('[') {
+        // IPv6地址
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
 impl MirrorConfig {
     
     fn merge(&mut self, other: Self) {
         merge!(self, other, {
-            name, provider, upstream, interval, retry, timeout, mirror_dir, 
+            name, provider, upstream, interval, schedule, schedule_timezone, allowed_windows, blackout, pause_outside_window,
+            retry, retry_delay, retry_max_delay, fail_interval, concurrency_group, priority, bandwidth_limit, after, precheck, timeout, mirror_dir, 
             mirror_sub_dir, log_dir, env, role,
             exec_on_success, exec_on_failure, exec_on_success_extra, exec_on_failure_extra,
             command, fail_on_match, size_pattern, use_ipv4, use_ipv6, exclude_file,
             username, password, rsync_no_timeo, rsync_timeout, rsync_options, rsync_override,
-            stage1_profile, memory_limit, 
-            docker_image, docker_volumes, docker_options, snapshot_path, child_mirrors
+            stage1_profile, git_repos, git_repos_file,
+            http_manifest, http_concurrency, lftp_parallel, lftp_passive, lftp_exclude, lftp_options,
+            s3_endpoint, s3_region, s3_credentials_file, s3_profile, s3_concurrency, s3_bandwidth_limit, s3_no_delete,
+            memory_limit, cpu_weight, cpu_max, io_weight, io_max, pids_max,
+            docker_image, docker_volumes, docker_options, snapshot_path, min_free_space, publish_mode, publish_seed,
+            tags, description, homepage, help_url, maintainers, child_mirrors
         });
     }
+
+    // metadata返回需要上报给manager的镜像描述信息
+    pub(crate) fn metadata(&self) -> MirrorMetadata {
+        MirrorMetadata {
+            tags: self.tags.clone().unwrap_or_default(),
+            description: self.description.clone().unwrap_or_default(),
+            homepage: self.homepage.clone().unwrap_or_default(),
+            help_url: self.help_url.clone().unwrap_or_default(),
+            maintainers: self.maintainers.clone().unwrap_or_default(),
+            after: self.after.clone().unwrap_or_default(),
+        }
+    }
+
+    // cron_schedule检查并解析schedule，没有配置schedule时返回None
+    pub(crate) fn cron_schedule(&self) -> Result<Option<MirrorSchedule>> {
+        match self.schedule.as_deref().filter(|s| !s.trim().is_empty()) {
+            Some(schedule) => Ok(Some(MirrorSchedule::parse(schedule, self.schedule_timezone.as_deref())?)),
+            None => Ok(None),
+        }
+    }
+
+    // sync_window检查并解析同步时间段，mirror没有配置时使用global中的配置，都没有配置时返回None
+    pub(crate) fn sync_window(&self, global: &GlobalConfig) -> Result<Option<SyncWindow>> {
+        let allowed = self.allowed_windows.as_ref().or(global.allowed_windows.as_ref()).cloned().unwrap_or_default();
+        let blackout = self.blackout.as_ref().or(global.blackout.as_ref()).cloned().unwrap_or_default();
+        if allowed.is_empty() && blackout.is_empty() {
+            return Ok(None)
+        }
+        let pause = self.pause_outside_window.or(global.pause_outside_window).unwrap_or(false);
+        Ok(Some(SyncWindow::parse(&allowed, &blackout, self.schedule_timezone.as_deref(), pause)?))
+    }
+
+    // retry_policy检查并解析重试和失败后的同步间隔，mirror没有配置时使用global中的配置
+    pub(crate) fn retry_policy(&self, global: &GlobalConfig) -> Result<RetryPolicy> {
+        let check = |name: &str, value: Option<i64>| -> Result<u64> {
+            match value {
+                Some(v) if v < 0 => Err(anyhow!("{} 不能是负数：{}", name, v)),
+                _ => Ok(value.unwrap_or_default() as u64),
+            }
+        };
+        let delay = check("retry_delay", self.retry_delay.or(global.retry_delay))?;
+        let max_delay = check("retry_max_delay", self.retry_max_delay.or(global.retry_max_delay))?;
+        let fail_interval = check("fail_interval", self.fail_interval.or(global.fail_interval))?;
+        Ok(RetryPolicy {
+            delay: Duration::from_secs(delay),
+            // 没有配置retry_max_delay时最多等待10分钟
+            max_delay: Duration::from_secs(if max_delay == 0 { 600 } else { max_delay }),
+            fail_interval: (fail_interval > 0).then(|| Duration::from_secs(fail_interval * 60)),
+        })
+    }
+
+    // concurrency_group返回mirror所属的并发分组，没有配置时使用上游的主机名，
+    // s3 provider配置了s3_endpoint时使用endpoint的主机名
+    pub(crate) fn concurrency_group(&self) -> Option<String> {
+        if let Some(group) = self.concurrency_group.as_deref().filter(|g| !g.is_empty()) {
+            return Some(group.to_string())
+        }
+        let upstream = match (&self.provider, self.s3_endpoint.as_deref()) {
+            (Some(ProviderEnum::S3), Some(endpoint)) if !endpoint.is_empty() => endpoint,
+            _ => self.upstream.as_deref()?,
+        };
+        upstream_host(upstream)
+    }
+
+    // bandwidth_limit_bytes检查并解析mirror的带宽上限，s3 provider没有配置bandwidth_limit时使用s3_bandwidth_limit
+    pub(crate) fn bandwidth_limit_bytes(&self) -> Result<Option<u64>> {
+        match (&self.provider, self.bandwidth_limit.as_deref()) {
+            (Some(ProviderEnum::S3), None) => parse_bandwidth_limit(self.s3_bandwidth_limit.as_deref()),
+            (_, limit) => parse_bandwidth_limit(limit),
+        }
+    }
+
+    // precheck检查并创建同步前的上游检查，没有配置时返回None
+    pub(crate) fn precheck(&self) -> Result<Option<Precheck>> {
+        let Some(cfg) = &self.precheck else {
+            return Ok(None)
+        };
+        // rsync上游需要的密码也用于检查
+        let mut env = self.env.clone().unwrap_or_default();
+        if let Some(password) = self.password.as_ref().filter(|p| !p.is_empty()) {
+            env.entry("RSYNC_PASSWORD".to_string()).or_insert_with(|| password.clone());
+        }
+        Precheck::new(cfg, env).map(Some)
+    }
+
+    // resource_limits检查并解析资源限制
+    pub(crate) fn resource_limits(&self) -> Result<ResourceLimits> {
+        let check_weight = |name: &str, weight: Option<u64>| -> Result<Option<u64>> {
+            match weight {
+                Some(w) if !(1..=10000).contains(&w) => Err(anyhow!("{} 的取值范围是1~10000：{}", name, w)),
+                _ => Ok(weight),
+            }
+        };
+        let parse_bps = |bps: &Option<String>| -> Result<Option<u64>> {
+            match bps.as_deref() {
+                None => Ok(None),
+                Some(raw) => MirrorSize::parse(raw).bytes
+                    .map(Some)
+                    .ok_or_else(|| anyhow!("无效的io_max带宽：{}", raw)),
+            }
+        };
+
+        let mut io_max = vec![];
+        for cfg in self.io_max.iter().flatten() {
+            if cfg.device.is_empty() {
+                return Err(anyhow!("io_max 中的 device 不能为空"))
+            }
+            let limit = IoMax {
+                device: cfg.device.clone(),
+                rbps: parse_bps(&cfg.rbps)?,
+                wbps: parse_bps(&cfg.wbps)?,
+            };
+            if limit.rbps.is_none() && limit.wbps.is_none() {
+                return Err(anyhow!("io_max 中的 {} 没有设置 rbps 或 wbps", cfg.device))
+            }
+            io_max.push(limit);
+        }
+        if self.pids_max == Some(0) {
+            return Err(anyhow!("pids_max 必须大于0"))
+        }
+
+        Ok(ResourceLimits {
+            cpu_weight: check_weight("cpu_weight", self.cpu_weight)?,
+            cpu_max: self.cpu_max,
+            io_weight: check_weight("io_weight", self.io_weight)?,
+            io_max,
+            pids_max: self.pids_max,
+        })
+    }
 }
 
 
@@ -317,9 +709,52 @@ pub fn load_config(cfg_file: &str) -> Result<Config> {
     for m in mirrors_config{
         recursive_mirrors(&mut cfg, Rc::new(RefCell::new(None)), m)?
     }
+    check_dependencies(&cfg.mirrors)?;
     Ok(cfg)
 }
 
+// check_dependencies检查mirror的after：依赖的mirror必须在同一个worker上，并且依赖关系中不能有环
+pub(crate) fn check_dependencies(mirrors: &[MirrorConfig]) -> Result<()> {
+    let deps: HashMap<&str, &[String]> = mirrors.iter()
+        .map(|m| (m.name.as_deref().unwrap_or_default(), m.after.as_deref().unwrap_or_default()))
+        .collect();
+    let mut names: Vec<&str> = deps.keys().copied().collect();
+    names.sort();
+    for name in names.iter() {
+        if let Some(dep) = deps[name].iter().find(|dep| !deps.contains_key(dep.as_str())) {
+            return Err(anyhow!("mirror {} 依赖的 {} 不存在", name, dep))
+        }
+    }
+
+    // 深度优先搜索，visited中为false表示还在搜索路径上，再次遇到说明有环
+    fn visit<'a>(name: &'a str, deps: &HashMap<&'a str, &'a [String]>,
+                 visited: &mut HashMap<&'a str, bool>, path: &mut Vec<&'a str>) -> Result<()> {
+        match visited.get(name) {
+            Some(true) => return Ok(()),
+            Some(false) => {
+                let start = path.iter().position(|n| *n == name).unwrap_or_default();
+                let mut cycle = path[start..].to_vec();
+                cycle.push(name);
+                return Err(anyhow!("mirror的依赖关系中有环：{}", cycle.join(" -> ")))
+            }
+            None => {}
+        }
+        visited.insert(name, false);
+        path.push(name);
+        for dep in deps[name].iter() {
+            visit(dep, deps, visited, path)?;
+        }
+        path.pop();
+        visited.insert(name, true);
+        Ok(())
+    }
+    let mut visited = HashMap::new();
+    for name in names {
+        visit(name, &deps, &mut visited, &mut vec![])?;
+    }
+    Ok(())
+}
+
 // 依据mirror_config配置cfg的mirror字段
 fn recursive_mirrors(cfg: &mut Config, parent: Rc<RefCell<Option<MirrorConfig>>>, mirror: MirrorConfig) -> Result<()> {
     let cur_mir = match &*parent.borrow() {
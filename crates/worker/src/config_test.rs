@@ -18,6 +18,8 @@ timeout = 86400
 [manager]
 api_base = "https://127.0.0.1:5000"
 token = "some_token"
+client_cert = "/etc/rtsync.d/worker1-client.cert"
+client_key = "/etc/rtsync.d/worker1-client.key"
 
 [server]
 hostname = "worker1.example.com"
@@ -25,6 +27,7 @@ listen_addr = "127.0.0.1"
 listen_port = 6000
 ssl_cert = "/etc/rtsync.d/worker1.cert"
 ssl_key = "/etc/rtsync.d/worker1.key"
+ssl_client_ca = "/etc/rtsync.d/manager-ca.pem"
 
 [[mirrors]]
 name = "AOSP"
@@ -47,6 +50,12 @@ stage1_profile = "debian"
 upstream = "rsync://ftp.debian.org/debian/"
 use_ipv6 = true
 memory_limit = "256MiB"
+tags = ["distro"]
+description = "Debian GNU/Linux"
+homepage = "https://www.debian.org/"
+help_url = "/help/debian/"
+maintainers = ["alice", "bob"]
+min_free_space = "50G"
 
 [[mirrors]]
 name = "fedora"
@@ -54,6 +63,11 @@ provider = "rsync"
 upstream = "rsync://ftp.fedoraproject.org/fedora/"
 use_ipv6 = true
 memory_limit = "128M"
+cpu_weight = 50
+cpu_max = 1.5
+io_weight = 200
+io_max = [{ device = "/dev/sda", rbps = "100M", wbps = "50M" }]
+pids_max = 512
 
 exclude_file = "/etc/rtsync.d/fedora-exclude.txt"
 exec_on_failure = [
@@ -74,6 +88,7 @@ exec_on_failure = [
     use std::os::unix::fs::PermissionsExt;
     use chrono::Duration;
     use crate::config::ProviderEnum::{Command, Rsync, TwoStageRsync};
+    use internal::msg::MirrorMetadata;
 
     // 当配置文件有效
     #[test]
@@ -143,6 +158,10 @@ use_ipv6 = true
 
         assert_eq!(cfg.manager.api_base, Some("https://127.0.0.1:5000".to_string()));
         assert_eq!(cfg.server.hostname, Some("worker1.example.com".to_string()));
+        assert_eq!(cfg.server.ssl_client_ca, Some("/etc/rtsync.d/manager-ca.pem".to_string()));
+        assert_eq!(cfg.server.ssl_client_required, None);
+        assert_eq!(cfg.manager.client_cert, Some("/etc/rtsync.d/worker1-client.cert".to_string()));
+        assert_eq!(cfg.manager.client_key, Some("/etc/rtsync.d/worker1-client.key".to_string()));
 
         let m = cfg.mirrors[0].clone();
         assert_eq!(m.name, Some("AOSP".to_string()));
@@ -156,17 +175,36 @@ use_ipv6 = true
         assert_eq!(m.name, Some("debian".to_string()));
         assert_eq!(m.mirror_dir, None);
         assert_eq!(m.provider, Some(TwoStageRsync));
+        assert_eq!(m.min_free_space, Some("50G".to_string()));
+        let metadata = m.metadata();
+        assert_eq!(metadata.tags, vec!["distro".to_string()]);
+        assert_eq!(metadata.description, "Debian GNU/Linux");
+        assert_eq!(metadata.homepage, "https://www.debian.org/");
+        assert_eq!(metadata.help_url, "/help/debian/");
+        assert_eq!(metadata.maintainers, vec!["alice".to_string(), "bob".to_string()]);
 
         let m = cfg.mirrors[2].clone();
         assert_eq!(m.name, Some("fedora".to_string()));
         assert_eq!(m.mirror_dir, None);
         assert_eq!(m.provider, Some(Rsync));
         assert_eq!(m.exclude_file, Some("/etc/rtsync.d/fedora-exclude.txt".to_string()));
+        assert_eq!(m.metadata(), MirrorMetadata::default());
+        let limits = m.resource_limits().unwrap();
+        assert_eq!(limits.cpu_weight, Some(50));
+        assert_eq!(limits.cpu_max, Some(CpuMax(1500)));
+        assert_eq!(limits.io_weight, Some(200));
+        assert_eq!(limits.io_max, vec![IoMax{
+            device: "/dev/sda".to_string(),
+            rbps: Some(100 * 1024 * 1024),
+            wbps: Some(50 * 1024 * 1024),
+        }]);
+        assert_eq!(limits.pids_max, Some(512));
 
         let m = cfg.mirrors[3].clone();
         assert_eq!(m.name, Some("debian-cd".to_string()));
         assert_eq!(m.mirror_dir, None);
         assert_eq!(m.provider, Some(TwoStageRsync));
+        assert_eq!(m.resource_limits().unwrap(), ResourceLimits::default());
         assert_eq!(m.memory_limit.unwrap().0, 0);
 
         let m = cfg.mirrors[4].clone();
@@ -178,6 +216,238 @@ use_ipv6 = true
         assert_eq!(cfg.mirrors.len(), 6);
     }
 
+    #[test]
+    fn test_cron_schedule(){
+        let m: MirrorConfig = toml::from_str(r#"
+schedule = "0 */4 * * *"
+schedule_timezone = "Asia/Shanghai"
+"#).unwrap();
+        assert_eq!(m.cron_schedule().unwrap().unwrap().expr, "0 */4 * * *");
+        assert!(MirrorConfig::default().cron_schedule().unwrap().is_none());
+        let m: MirrorConfig = toml::from_str(r#"schedule = "at 02:30 and 26:00""#).unwrap();
+        assert!(m.cron_schedule().is_err());
+    }
+
+    #[test]
+    fn test_sync_window(){
+        let cfg: Config = toml::from_str(r#"
+[global]
+blackout = ["08:00-18:00 weekdays"]
+pause_outside_window = true
+
+[[mirrors]]
+name = "a"
+
+[[mirrors]]
+name = "b"
+allowed_windows = ["01:00-07:00"]
+blackout = []
+pause_outside_window = false
+"#).unwrap();
+        assert!(MirrorConfig::default().sync_window(&GlobalConfig::default()).unwrap().is_none());
+        let a = cfg.mirrors_config[0].sync_window(&cfg.global).unwrap().unwrap();
+        assert!(a.pause);
+        let b = cfg.mirrors_config[1].sync_window(&cfg.global).unwrap().unwrap();
+        assert!(!b.pause);
+        let m: MirrorConfig = toml::from_str(r#"allowed_windows = ["01:00-07:00 someday"]"#).unwrap();
+        assert!(m.sync_window(&cfg.global).is_err());
+    }
+
+    #[test]
+    fn test_retry_policy(){
+        let cfg: Config = toml::from_str(r#"
+[global]
+retry_delay = 30
+fail_interval = 10
+
+[[mirrors]]
+name = "a"
+
+[[mirrors]]
+name = "b"
+retry_delay = 5
+retry_max_delay = 60
+fail_interval = 0
+"#).unwrap();
+        let a = cfg.mirrors_config[0].retry_policy(&cfg.global).unwrap();
+        assert_eq!(a.delay, std::time::Duration::from_secs(30));
+        assert_eq!(a.max_delay, std::time::Duration::from_secs(600));
+        assert_eq!(a.fail_interval, Some(std::time::Duration::from_secs(600)));
+        let b = cfg.mirrors_config[1].retry_policy(&cfg.global).unwrap();
+        assert_eq!(b.delay, std::time::Duration::from_secs(5));
+        assert_eq!(b.max_delay, std::time::Duration::from_secs(60));
+        assert_eq!(b.fail_interval, None);
+        let m: MirrorConfig = toml::from_str("retry_delay = -1").unwrap();
+        assert!(m.retry_policy(&cfg.global).is_err());
+    }
+
+    #[test]
+    fn test_concurrency_group(){
+        for (upstream, host) in [
+            ("rsync://rsync.kernel.org/pub/", Some("rsync.kernel.org")),
+            ("rsync://user@Mirrors.Example.com:873/debian/", Some("mirrors.example.com")),
+            ("mirrors.example.com::debian/", Some("mirrors.example.com")),
+            ("https://[2001:db8::1]:8443/ubuntu/", Some("2001:db8::1")),
+            ("git@github.com:rust-lang/rust.git", Some("github.com")),
+            ("", None),
+        ] {
+            assert_eq!(upstream_host(upstream).as_deref(), host, "{}", upstream);
+        }
+
+        let m: MirrorConfig = toml::from_str(r#"upstream = "rsync://rsync.kernel.org/pub/""#).unwrap();
+        assert_eq!(m.concurrency_group().as_deref(), Some("rsync.kernel.org"));
+        let m: MirrorConfig = toml::from_str(r#"
+upstream = "rsync://rsync.kernel.org/pub/"
+concurrency_group = "kernel"
+"#).unwrap();
+        assert_eq!(m.concurrency_group().as_deref(), Some("kernel"));
+        let m: MirrorConfig = toml::from_str(r#"
+provider = "s3"
+upstream = "s3://bucket/prefix/"
+s3_endpoint = "https://s3.example.com"
+"#).unwrap();
+        assert_eq!(m.concurrency_group().as_deref(), Some("s3.example.com"));
+    }
+
+    #[test]
+    fn test_bandwidth_limit(){
+        let cfg: Config = toml::from_str(r#"
+[global]
+bandwidth_limit = "100M"
+
+[[mirrors]]
+name = "a"
+bandwidth_limit = "50M"
+
+[[mirrors]]
+name = "b"
+provider = "s3"
+s3_bandwidth_limit = "1M"
+
+[[mirrors]]
+name = "c"
+"#).unwrap();
+        assert_eq!(cfg.global.bandwidth_limit_bytes().unwrap(), Some(100 * 1024 * 1024));
+        assert_eq!(cfg.mirrors_config[0].bandwidth_limit_bytes().unwrap(), Some(50 * 1024 * 1024));
+        assert_eq!(cfg.mirrors_config[1].bandwidth_limit_bytes().unwrap(), Some(1024 * 1024));
+        assert_eq!(cfg.mirrors_config[2].bandwidth_limit_bytes().unwrap(), None);
+        for invalid in [r#"bandwidth_limit = "fast""#, r#"bandwidth_limit = "0""#] {
+            let m: MirrorConfig = toml::from_str(invalid).unwrap();
+            assert!(m.bandwidth_limit_bytes().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_mirror_dependencies(){
+        let mirrors = |toml_str: &str| toml::from_str::<Config>(toml_str).unwrap().mirrors_config;
+        let ok = mirrors(r#"
+[[mirrors]]
+name = "debian"
+
+[[mirrors]]
+name = "debian-index"
+after = ["debian"]
+
+[[mirrors]]
+name = "debian-subset"
+after = ["debian", "debian-index"]
+"#);
+        assert!(check_dependencies(&ok).is_ok());
+        assert_eq!(ok[2].metadata().after, vec!["debian", "debian-index"]);
+
+        let missing = mirrors(r#"
+[[mirrors]]
+name = "debian-index"
+after = ["debian"]
+"#);
+        let err = check_dependencies(&missing).unwrap_err().to_string();
+        assert!(err.contains("debian-index 依赖的 debian 不存在"), "{}", err);
+
+        let cycle = mirrors(r#"
+[[mirrors]]
+name = "a"
+after = ["c"]
+
+[[mirrors]]
+name = "b"
+after = ["a"]
+
+[[mirrors]]
+name = "c"
+after = ["b"]
+"#);
+        let err = check_dependencies(&cycle).unwrap_err().to_string();
+        assert!(err.contains("a -> c -> b -> a"), "{}", err);
+        let self_cycle = mirrors(r#"
+[[mirrors]]
+name = "a"
+after = ["a"]
+"#);
+        assert!(check_dependencies(&self_cycle).is_err());
+    }
+
+    #[test]
+    fn test_precheck(){
+        let mirrors = toml::from_str::<Config>(r#"
+[[mirrors]]
+name = "debian"
+password = "secret"
+precheck = { url = "rsync://ftp.debian.org/debian/project/trace/", timeout = 10 }
+
+[[mirrors]]
+name = "archlinux"
+precheck = { url = "https://mirrors.kernel.org/archlinux/lastsync", marker = "content" }
+
+[[mirrors]]
+name = "pypi"
+
+[[mirrors]]
+name = "ubuntu"
+precheck = { url = "rsync://archive.ubuntu.com/ubuntu/", marker = "etag" }
+"#).unwrap().mirrors_config;
+        assert_eq!(mirrors[0].precheck, Some(PrecheckConfig {
+            url: Some("rsync://ftp.debian.org/debian/project/trace/".to_string()),
+            timeout: Some(10),
+            ..PrecheckConfig::default()
+        }));
+        assert!(mirrors[0].precheck().unwrap().is_some());
+        assert_eq!(mirrors[1].precheck.as_ref().unwrap().marker.as_deref(), Some("content"));
+        assert!(mirrors[1].precheck().unwrap().is_some());
+        assert!(mirrors[2].precheck().unwrap().is_none());
+        // rsync上游不支持etag
+        assert!(mirrors[3].precheck().is_err());
+    }
+
+    #[test]
+    fn test_resource_limits(){
+        let m: MirrorConfig = toml::from_str(r#"cpu_max = "2""#).unwrap();
+        assert_eq!(m.cpu_max, Some(CpuMax(2000)));
+        assert!(toml::from_str::<MirrorConfig>("cpu_max = 0").is_err());
+        assert!(toml::from_str::<MirrorConfig>(r#"cpu_max = "many""#).is_err());
+
+        for invalid in [
+            "cpu_weight = 0",
+            "io_weight = 10001",
+            "pids_max = 0",
+            r#"io_max = [{ device = "/dev/sda" }]"#,
+            r#"io_max = [{ rbps = "1M" }]"#,
+            r#"io_max = [{ device = "/dev/sda", wbps = "fast" }]"#,
+        ] {
+            let m: MirrorConfig = toml::from_str(invalid).unwrap();
+            assert!(m.resource_limits().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_container_runtime(){
+        let cfg: Config = toml::from_str("[docker]\nenable = true").unwrap();
+        assert_eq!(cfg.docker.runtime, None);
+        let cfg: Config = toml::from_str("[docker]\nenable = true\nruntime = \"podman\"\nsocket = \"/run/user/1000/podman/podman.sock\"").unwrap();
+        assert_eq!(cfg.docker.runtime, Some(ContainerRuntime::Podman));
+        assert_eq!(cfg.docker.socket.as_deref(), Some("/run/user/1000/podman/podman.sock"));
+        assert!(toml::from_str::<Config>("[docker]\nruntime = \"lxc\"").is_err());
+    }
+
     // 当配置文件嵌套
     #[test]
     fn test_nested_file(){
@@ -416,4 +686,113 @@ use_ipv6 = true
         assert_eq!(p.working_dir().await, "/data/mirrors/debian-cd");
         assert_eq!(p.timeout(), Duration::seconds(86400));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_git_provider_config(){
+        const CFG_BLOB_GIT: &str = r#"
+[global]
+name = "test_worker"
+log_dir = "/var/log/rtsync/{{ name }}"
+mirror_dir = "/data/mirrors"
+
+[[mirrors]]
+name = "gnu-git"
+provider = "git"
+upstream = "https://git.savannah.gnu.org/git/"
+git_repos = ["bash.git", "https://github.com/coreutils/coreutils coreutils.git"]
+    "#;
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let tmp_file_path = tmp_dir.path().join("rtsync");
+        fs::write(&tmp_file_path, CFG_BLOB_GIT).unwrap();
+        let cfg = load_config(tmp_file_path.to_str().unwrap()).unwrap();
+        let m = &cfg.mirrors[0];
+        assert_eq!(m.provider, Some(ProviderEnum::Git));
+        assert_eq!(m.git_repos.as_ref().unwrap().len(), 2);
+        assert_eq!(m.git_repos_file, None);
+
+        let p = new_mirror_provider(m.clone(), cfg.clone()).await;
+        assert_eq!(p.r#type(), ProviderEnum::Git);
+        assert_eq!(p.upstream(), "https://git.savannah.gnu.org/git/");
+        assert_eq!(p.working_dir().await, "/data/mirrors/gnu-git");
+        assert_eq!(p.log_file().await, "/var/log/rtsync/gnu-git/latest.log");
+    }
+
+    #[tokio::test]
+    async fn test_lftp_provider_config(){
+        const CFG_BLOB_LFTP: &str = r#"
+[global]
+name = "test_worker"
+log_dir = "/var/log/rtsync/{{ name }}"
+mirror_dir = "/data/mirrors"
+
+[[mirrors]]
+name = "ftp-only"
+provider = "lftp"
+upstream = "ftp://ftp.example.com/pub/"
+lftp_parallel = 8
+lftp_passive = false
+lftp_exclude = ["*.iso", ".snapshot/"]
+lftp_options = ["--only-newer"]
+    "#;
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let tmp_file_path = tmp_dir.path().join("rtsync");
+        fs::write(&tmp_file_path, CFG_BLOB_LFTP).unwrap();
+        let cfg = load_config(tmp_file_path.to_str().unwrap()).unwrap();
+        let m = &cfg.mirrors[0];
+        assert_eq!(m.provider, Some(ProviderEnum::Lftp));
+        assert_eq!(m.lftp_parallel, Some(8));
+        assert_eq!(m.lftp_passive, Some(false));
+        assert_eq!(m.lftp_exclude.as_ref().unwrap().len(), 2);
+        assert_eq!(m.lftp_options, Some(vec!["--only-newer".to_string()]));
+
+        let p = new_mirror_provider(m.clone(), cfg.clone()).await;
+        assert_eq!(p.r#type(), ProviderEnum::Lftp);
+        assert_eq!(p.upstream(), "ftp://ftp.example.com/pub/");
+        assert_eq!(p.working_dir().await, "/data/mirrors/ftp-only");
+        assert_eq!(p.log_file().await, "/var/log/rtsync/ftp-only/latest.log");
+    }
+
+    #[tokio::test]
+    async fn test_s3_provider_config(){
+        const CFG_BLOB_S3: &str = r#"
+[global]
+name = "test_worker"
+log_dir = "/var/log/rtsync/{{ name }}"
+mirror_dir = "/data/mirrors"
+
+[[mirrors]]
+name = "pytorch"
+provider = "s3"
+upstream = "s3://pytorch/whl/"
+s3_endpoint = "http://127.0.0.1:9000"
+s3_region = "us-west-2"
+s3_concurrency = 8
+s3_bandwidth_limit = "10M"
+s3_no_delete = true
+    "#;
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let tmp_file_path = tmp_dir.path().join("rtsync");
+        fs::write(&tmp_file_path, CFG_BLOB_S3).unwrap();
+        let cfg = load_config(tmp_file_path.to_str().unwrap()).unwrap();
+        let m = &cfg.mirrors[0];
+        assert_eq!(m.provider, Some(ProviderEnum::S3));
+        assert_eq!(m.s3_endpoint.as_deref(), Some("http://127.0.0.1:9000"));
+        assert_eq!(m.s3_region.as_deref(), Some("us-west-2"));
+        assert_eq!(m.s3_concurrency, Some(8));
+        assert_eq!(m.s3_bandwidth_limit.as_deref(), Some("10M"));
+        assert_eq!(m.s3_no_delete, Some(true));
+        assert_eq!(m.s3_credentials_file, None);
+
+        let p = new_mirror_provider(m.clone(), cfg.clone()).await;
+        assert_eq!(p.r#type(), ProviderEnum::S3);
+        assert_eq!(p.upstream(), "s3://pytorch/whl/");
+        assert_eq!(p.working_dir().await, "/data/mirrors/pytorch");
+        assert_eq!(p.log_file().await, "/var/log/rtsync/pytorch/latest.log");
+    }
+}
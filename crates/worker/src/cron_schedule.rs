@@ -0,0 +1,252 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+
+// 最多向后查找的天数，超过后认为表达式永远不会触发（例如 0 0 30 2 *）
+const MAX_SEARCH_DAYS: i64 = 366 * 5;
+
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+pub(crate) const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// CronExpr是一个crontab格式的表达式：分 时 日 月 周，每个字段用位图保存允许的取值
+#[derive(Debug, Clone, PartialEq)]
+struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // 日和周都不是*时，两者满足其一即可（和crontab一致）
+    any_day: bool,
+    any_weekday: bool,
+}
+
+// parse_field解析一个字段，支持 *、数字、名字、a-b、*/n、a-b/n 以及逗号分隔的列表
+pub(crate) fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let value = |s: &str| -> Result<u32> {
+        if let Some(i) = names.iter().position(|name| s.eq_ignore_ascii_case(name)) {
+            return Ok(i as u32 + min)
+        }
+        s.parse::<u32>().map_err(|_| anyhow!("无效的取值：{}", s))
+    };
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)
+                .ok_or_else(|| anyhow!("无效的步长：{}", part))?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (value(a)?, value(b)?)
+        } else if part.contains('/') {
+            (value(range)?, max)
+        } else {
+            let v = value(range)?;
+            (v, v)
+        };
+        if start < min || end > max || start > end {
+            return Err(anyhow!("{} 超出了取值范围 {}~{}", part, min, max))
+        }
+        for v in (start..=end).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+impl CronExpr {
+    fn parse(expr: &str) -> Result<Self> {
+        let expr = match expr {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            _ => expr,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(anyhow!("cron表达式应该有5个字段（分 时 日 月 周）：{}", expr))
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7, &WEEKDAY_NAMES)?;
+        // 7和0都表示周日
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(CronExpr {
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, &MONTH_NAMES)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    // next_after返回after之后第一个满足表达式的时间，时间按照时区tz的本地时间计算
+    fn next_after<Z: TimeZone>(&self, tz: &Z, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local = after.with_timezone(tz).naive_local();
+        let mut t = local.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = local + Duration::days(MAX_SEARCH_DAYS);
+        while t < limit {
+            if self.months & (1 << t.month()) == 0 {
+                // 跳到下个月的第一天
+                let (year, month) = if t.month() == 12 { (t.year() + 1, 1) } else { (t.year(), t.month() + 1) };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+                continue
+            }
+            if !self.matches_day(t.date()) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue
+            }
+            if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0)? + Duration::hours(1);
+                continue
+            }
+            if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+                continue
+            }
+            // 夏令时切换时本地时间可能不存在或者出现两次，不存在时跳过，出现两次时取较早的一个
+            if let Some(dt) = resolve(tz, t) {
+                if dt > after {
+                    return Some(dt)
+                }
+            }
+            t += Duration::minutes(1);
+        }
+        None
+    }
+}
+
+// parse_timezone解析时区的名字，为空时返回None，表示使用worker所在机器的时区
+pub(crate) fn parse_timezone(timezone: Option<&str>) -> Result<Option<Tz>> {
+    match timezone.filter(|tz| !tz.is_empty()) {
+        Some(tz) => Ok(Some(tz.parse::<Tz>().map_err(|_| anyhow!("无效的时区：{}", tz))?)),
+        None => Ok(None),
+    }
+}
+
+fn resolve<Z: TimeZone>(tz: &Z, t: NaiveDateTime) -> Option<DateTime<Utc>> {
+    match tz.from_local_datetime(&t) {
+        LocalResult::Single(dt) => Some(dt.with_timezone(&Utc)),
+        LocalResult::Ambiguous(dt, _) => Some(dt.with_timezone(&Utc)),
+        LocalResult::None => None,
+    }
+}
+
+// MirrorSchedule是mirror的schedule配置，可以是crontab格式的表达式（如 "0 */4 * * *"），
+// 也可以是 "at 02:30 and 14:30" 这样的每天固定时间；timezone为空时使用worker所在机器的时区
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MirrorSchedule {
+    pub(crate) expr: String,
+    crons: Vec<CronExpr>,
+    timezone: Option<Tz>,
+}
+
+impl MirrorSchedule {
+    pub(crate) fn parse(expr: &str, timezone: Option<&str>) -> Result<Self> {
+        let trimmed = expr.trim();
+        let crons = match trimmed.strip_prefix("at ") {
+            Some(times) => {
+                let mut crons = vec![];
+                for time in times.split([',', ' ']).filter(|s| !s.is_empty() && *s != "and") {
+                    let (hour, minute) = time.split_once(':')
+                        .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)))
+                        .filter(|(h, m)| *h < 24 && *m < 60)
+                        .ok_or_else(|| anyhow!("无效的时间：{}", time))?;
+                    crons.push(CronExpr::parse(&format!("{} {} * * *", minute, hour))?);
+                }
+                if crons.is_empty() {
+                    return Err(anyhow!("at 之后至少需要一个时间：{}", expr))
+                }
+                crons
+            }
+            None => vec![CronExpr::parse(trimmed)?],
+        };
+        let timezone = parse_timezone(timezone)?;
+        let schedule = MirrorSchedule { expr: trimmed.to_string(), crons, timezone };
+        if schedule.next_after(Utc::now()).is_none() {
+            return Err(anyhow!("schedule永远不会触发：{}", expr))
+        }
+        Ok(schedule)
+    }
+
+    // next_after返回after之后的下一次同步时间
+    pub(crate) fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.crons.iter()
+            .filter_map(|cron| match &self.timezone {
+                Some(tz) => cron.next_after(tz, after),
+                None => cron.next_after(&chrono::Local, after),
+            })
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_cron_next(){
+        let s = MirrorSchedule::parse("0 */4 * * *", Some("UTC")).unwrap();
+        assert_eq!(s.next_after(utc("2024-05-01T01:20:00Z")), Some(utc("2024-05-01T04:00:00Z")));
+        assert_eq!(s.next_after(utc("2024-05-01T04:00:00Z")), Some(utc("2024-05-01T08:00:00Z")));
+        assert_eq!(s.next_after(utc("2024-05-01T23:59:59Z")), Some(utc("2024-05-02T00:00:00Z")));
+
+        // 周一到周五的17分
+        let s = MirrorSchedule::parse("17 9-18/3 * * mon-fri", Some("UTC")).unwrap();
+        assert_eq!(s.next_after(utc("2024-05-03T18:30:00Z")), Some(utc("2024-05-06T09:17:00Z")));
+        assert_eq!(s.next_after(utc("2024-05-06T09:17:00Z")), Some(utc("2024-05-06T12:17:00Z")));
+
+        // 日和周都指定时满足其一即可
+        let s = MirrorSchedule::parse("0 0 1 * 7", Some("UTC")).unwrap();
+        assert_eq!(s.next_after(utc("2024-05-01T00:00:00Z")), Some(utc("2024-05-05T00:00:00Z")));
+        assert_eq!(s.next_after(utc("2024-05-26T00:00:00Z")), Some(utc("2024-06-01T00:00:00Z")));
+
+        let s = MirrorSchedule::parse("@monthly", Some("UTC")).unwrap();
+        assert_eq!(s.next_after(utc("2024-12-15T00:00:00Z")), Some(utc("2025-01-01T00:00:00Z")));
+    }
+
+    #[test]
+    fn test_cron_at_and_timezone(){
+        let s = MirrorSchedule::parse("at 02:30 and 14:45", Some("Asia/Shanghai")).unwrap();
+        // 上海时间 2024-05-01 10:00
+        assert_eq!(s.next_after(utc("2024-05-01T02:00:00Z")), Some(utc("2024-05-01T06:45:00Z")));
+        assert_eq!(s.next_after(utc("2024-05-01T06:45:00Z")), Some(utc("2024-05-01T18:30:00Z")));
+
+        // 夏令时开始时 02:30 不存在，当天跳过
+        let s = MirrorSchedule::parse("30 2 * * *", Some("Europe/Berlin")).unwrap();
+        assert_eq!(s.next_after(utc("2024-03-30T12:00:00Z")), Some(utc("2024-04-01T00:30:00Z")));
+        // 夏令时结束时 02:30 出现两次，只执行较早的一次
+        assert_eq!(s.next_after(utc("2024-10-26T12:00:00Z")), Some(utc("2024-10-27T00:30:00Z")));
+        assert_eq!(s.next_after(utc("2024-10-27T00:30:00Z")), Some(utc("2024-10-28T01:30:00Z")));
+    }
+
+    #[test]
+    fn test_cron_invalid(){
+        for invalid in ["", "* * * *", "60 * * * *", "* 24 * * *", "0 0 0 * *", "*/0 * * * *",
+                        "0 0 * * funday", "5-1 * * * *", "0 0 30 2 *", "at 25:00", "at"] {
+            assert!(MirrorSchedule::parse(invalid, None).is_err(), "{}", invalid);
+        }
+        assert!(MirrorSchedule::parse("0 0 * * *", Some("Mars/Olympus")).is_err());
+    }
+}
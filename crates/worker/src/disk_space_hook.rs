@@ -0,0 +1,81 @@
+use anyhow::{anyhow, Result};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use async_trait::async_trait;
+use chrono::Utc;
+use log::error;
+use nix::sys::statvfs::statvfs;
+use internal::msg::DiskUsage;
+use internal::size::format_bytes;
+use crate::hooks::JobHook;
+
+// existing_ancestor返回path自身或者它最近的一个已经存在的上级目录，
+// 镜像第一次同步之前工作目录可能还不存在
+fn existing_ancestor(path: &str) -> Option<&Path> {
+    Path::new(path).ancestors().find(|p| p.exists())
+}
+
+// disk_usage返回path所在的文件系统的空间使用情况
+pub(crate) fn disk_usage(path: &str) -> Result<DiskUsage> {
+    let dir = existing_ancestor(path).ok_or_else(|| anyhow!("目录 {} 不存在", path))?;
+    let stat = statvfs(dir)?;
+    let fragment_size = stat.fragment_size() as u64;
+    Ok(DiskUsage {
+        path: path.to_string(),
+        total_bytes: stat.blocks() as u64 * fragment_size,
+        free_bytes: stat.blocks_available() as u64 * fragment_size,
+        updated: Utc::now(),
+    })
+}
+
+// disk_usages返回多个目录所在的文件系统的空间使用情况，位于同一个文件系统上的目录只返回第一个
+pub(crate) fn disk_usages(paths: &[String]) -> Vec<DiskUsage> {
+    let mut devices = vec![];
+    let mut usages = vec![];
+    for path in paths.iter().filter(|p| !p.is_empty()) {
+        let Some(dev) = existing_ancestor(path)
+            .and_then(|dir| dir.metadata().ok())
+            .map(|m| m.dev()) else { continue };
+        if devices.contains(&dev) {
+            continue
+        }
+        match disk_usage(path) {
+            Ok(usage) => {
+                devices.push(dev);
+                usages.push(usage);
+            }
+            Err(e) => error!("获取 {} 的磁盘空间失败: {}", path, e),
+        }
+    }
+    usages
+}
+
+// DiskSpaceHook在同步开始前检查工作目录所在的文件系统是否还有足够的可用空间，
+// 避免rsync在 --delay-updates 的过程中写满磁盘
+#[derive(Debug, Clone)]
+pub(crate) struct DiskSpaceHook {
+    pub(crate) min_free_space: u64,
+}
+
+impl DiskSpaceHook {
+    pub(crate) fn new(min_free_space: u64) -> Self {
+        DiskSpaceHook {
+            min_free_space,
+        }
+    }
+}
+
+#[async_trait]
+impl JobHook for DiskSpaceHook {
+    fn pre_job(&self, working_dir: String, provider_name: String) -> Result<()> {
+        let usage = disk_usage(&working_dir)?;
+        if usage.free_bytes < self.min_free_space {
+            let err = format!("{} 所在的文件系统只剩 {} 可用空间，低于 {} 的 min_free_space {}",
+                              working_dir, format_bytes(usage.free_bytes),
+                              provider_name, format_bytes(self.min_free_space));
+            error!("{err}");
+            return Err(anyhow!(err));
+        }
+        Ok(())
+    }
+}
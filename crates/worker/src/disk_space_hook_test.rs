@@ -0,0 +1,36 @@
+#[cfg(test)]
+mod tests {
+    use tempfile::Builder;
+    use crate::disk_space_hook::*;
+    use crate::hooks::JobHook;
+
+    #[test]
+    fn test_disk_space_hook() {
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let tmp_dir_path = tmp_dir.path().display().to_string();
+
+        let usage = disk_usage(&tmp_dir_path).unwrap();
+        assert_eq!(usage.path, tmp_dir_path);
+        assert!(usage.total_bytes > 0);
+        assert!(usage.free_bytes <= usage.total_bytes);
+
+        // 工作目录还不存在时检查上级目录所在的文件系统
+        let working_dir = tmp_dir.path().join("not-synced-yet").display().to_string();
+        let usage2 = disk_usage(&working_dir).unwrap();
+        assert_eq!(usage2.total_bytes, usage.total_bytes);
+
+        // 位于同一个文件系统上的目录只报告一次
+        let usages = disk_usages(&[tmp_dir_path.clone(), working_dir.clone(), String::new()]);
+        assert_eq!(usages.len(), 1);
+        assert_eq!(usages[0].path, tmp_dir_path);
+
+        let hook = DiskSpaceHook::new(0);
+        assert!(hook.pre_job(working_dir.clone(), "test".into()).is_ok());
+
+        let hook = DiskSpaceHook::new(u64::MAX);
+        let err = hook.pre_job(working_dir, "test".into()).unwrap_err();
+        assert!(err.to_string().contains("min_free_space"));
+    }
+}
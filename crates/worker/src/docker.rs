@@ -1,12 +1,15 @@
-use std::{fs, io, time::Duration};
+use std::{fs, io};
+use std::collections::HashMap;
 use anyhow::{anyhow, Result};
 use std::fs::Permissions;
 use std::os::unix::fs::PermissionsExt;
-use std::process::Command;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use log::{debug, error, warn};
-use crate::config::{DockerConfig, MemBytes, MirrorConfig};
+use log::{debug, warn};
+use serde_json::{json, Map, Value};
+use internal::size::MirrorSize;
+use crate::config::{ContainerRuntime, DockerConfig, MemBytes, MirrorConfig, ResourceLimits};
+use crate::docker_api::{default_socket, DockerClient};
 use anymap::AnyMap;
 use crate::context::Context;
 use crate::hooks::JobHook;
@@ -14,10 +17,28 @@ use async_trait::async_trait;
 
 #[derive(Debug, Clone, Default)]
 pub(crate) struct DockerHook {
+    pub(crate) runtime: ContainerRuntime,
+    // 容器引擎的unix socket，为空时使用default_socket
+    pub(crate) socket: String,
+    // rootless的podman中容器的root映射为worker的用户，需要--userns=keep-id让容器中的用户和worker一致
+    pub(crate) rootless: bool,
     pub(crate) image: String,
     pub(crate) volumes: Vec<String>,
     pub(crate) options: Vec<String>,
     pub(crate) memory_limit: MemBytes,
+    pub(crate) limits: ResourceLimits,
+}
+
+// ContainerSpec是在容器中运行一个命令所需的信息，由provider生成，BaseProvider通过docker API运行
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ContainerSpec {
+    pub(crate) name: String,
+    pub(crate) cmd: Vec<String>,
+    pub(crate) env: HashMap<String, String>,
+    pub(crate) working_dir: String,
+    // uid:gid
+    pub(crate) user: String,
+    pub(crate) volumes: Vec<String>,
 }
 
 impl DockerHook{
@@ -45,19 +66,218 @@ impl DockerHook{
             options.extend(opts.iter().cloned());
         }
 
+        // 资源限制在创建provider时已经检查过
+        let limits = m_cfg.resource_limits().unwrap_or_default();
+        let runtime = g_cfg.runtime.unwrap_or_default();
         DockerHook{
+            runtime,
+            socket: g_cfg.socket.unwrap_or_default(),
+            rootless: runtime == ContainerRuntime::Podman && unsafe { libc::getuid() } != 0,
             image: m_cfg.docker_image.unwrap_or_default(),
             volumes,
             options,
+            limits,
             memory_limit: m_cfg.memory_limit.unwrap_or_default(),
         }
     }
 
+    // name返回容器的名字，docker和podman的容器名只能包含字母、数字和 _.- ，其他字符替换为 -
     pub(crate) fn name(&self, provider_name: String) -> String {
-        format!("rtsync-job-{}", provider_name)
+        let name: String = provider_name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || "_.-".contains(c) { c } else { '-' })
+            .collect();
+        format!("rtsync-job-{}", name)
+    }
+
+    pub(crate) fn client(&self) -> DockerClient {
+        if self.socket.is_empty() {
+            DockerClient::new(self.runtime, &default_socket(self.runtime))
+        } else {
+            DockerClient::new(self.runtime, &self.socket)
+        }
+    }
+
+    // container_spec生成在容器中运行command的ContainerSpec，容器中的进程使用worker的uid和gid
+    pub(crate) fn container_spec(&self, provider_name: String, command: Vec<String>, env: HashMap<String, String>,
+                                 working_dir: String, volumes: Vec<String>) -> ContainerSpec {
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        ContainerSpec {
+            name: self.name(provider_name),
+            cmd: command,
+            env,
+            working_dir,
+            user: format!("{}:{}", uid, gid),
+            volumes,
+        }
+    }
+
+    // limit_config把资源限制转换为docker API中HostConfig的字段
+    pub(crate) fn limit_config(&self) -> Map<String, Value> {
+        let mut host = Map::new();
+        if self.memory_limit.0 != 0{
+            host.insert("Memory".to_string(), json!(self.memory_limit.value()));
+        }
+        // docker的CpuShares和BlkioWeight是cgroup v1的取值范围，从cgroup v2的weight换算过去
+        if let Some(weight) = self.limits.cpu_weight{
+            host.insert("CpuShares".to_string(), json!(2 + (weight - 1) * 262142 / 9999));
+        }
+        if let Some(cpu_max) = self.limits.cpu_max{
+            host.insert("NanoCpus".to_string(), json!(cpu_max.0 * 1_000_000));
+        }
+        if let Some(weight) = self.limits.io_weight{
+            host.insert("BlkioWeight".to_string(), json!(10 + (weight - 1) * 990 / 9999));
+        }
+        let (mut read_bps, mut write_bps) = (vec![], vec![]);
+        for io_max in self.limits.io_max.iter(){
+            if let Some(rbps) = io_max.rbps{
+                read_bps.push(json!({"Path": io_max.device, "Rate": rbps}));
+            }
+            if let Some(wbps) = io_max.wbps{
+                write_bps.push(json!({"Path": io_max.device, "Rate": wbps}));
+            }
+        }
+        if !read_bps.is_empty() {
+            host.insert("BlkioDeviceReadBps".to_string(), Value::from(read_bps));
+        }
+        if !write_bps.is_empty() {
+            host.insert("BlkioDeviceWriteBps".to_string(), Value::from(write_bps));
+        }
+        if let Some(pids_max) = self.limits.pids_max{
+            host.insert("PidsLimit".to_string(), json!(pids_max));
+        }
+        host
     }
-    
 
+    // container_config生成创建容器时的请求体，docker_options中的选项缺少参数或参数无效时返回错误
+    pub(crate) fn container_config(&self, spec: &ContainerSpec) -> Result<Value> {
+        let mut host = self.limit_config();
+        host.insert("Binds".to_string(), json!(spec.volumes));
+        let mut env: Vec<String> = spec.env.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        env.sort();
+        let mut config = Map::new();
+        config.insert("Image".to_string(), json!(self.image));
+        config.insert("Cmd".to_string(), json!(spec.cmd));
+        config.insert("Env".to_string(), json!(env));
+        config.insert("WorkingDir".to_string(), json!(spec.working_dir));
+        config.insert("User".to_string(), json!(spec.user));
+        config.insert("AttachStdout".to_string(), json!(true));
+        config.insert("AttachStderr".to_string(), json!(true));
+        config.insert("Tty".to_string(), json!(false));
+        apply_options(&self.options, &mut config, &mut host)?;
+        if self.rootless && !host.contains_key("UsernsMode") {
+            host.insert("UsernsMode".to_string(), json!("keep-id"));
+        }
+        config.insert("HostConfig".to_string(), Value::Object(host));
+        Ok(Value::Object(config))
+    }
+}
+
+fn push(map: &mut Map<String, Value>, key: &str, value: &str) {
+    let entry = map.entry(key.to_string()).or_insert_with(|| json!([]));
+    if let Some(array) = entry.as_array_mut() {
+        array.push(json!(value));
+    }
+}
+
+// apply_options把docker run选项转换为容器配置（config）和HostConfig（host）中的字段，
+// 支持 --opt value 和 --opt=value 两种写法。不支持的选项返回错误，在加载配置时就会发现，
+// 避免容器少了卷、用户等设置却照常同步
+pub(crate) fn apply_options(options: &[String], config: &mut Map<String, Value>, host: &mut Map<String, Value>) -> Result<()> {
+    let mut iter = options.iter();
+    while let Some(option) = iter.next() {
+        let (flag, inline) = match option.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (option.as_str(), None),
+        };
+        let mut value = || -> Result<String> {
+            match inline.clone() {
+                Some(value) => Ok(value),
+                None => iter.next().cloned().ok_or_else(|| anyhow!("docker选项 {} 缺少参数", flag)),
+            }
+        };
+        let bytes = |flag: &str, size: &str| -> Result<i64> {
+            match size {
+                "-1" => Ok(-1),
+                _ => MirrorSize::parse(size).bytes.map(|b| b as i64).ok_or_else(|| anyhow!("无效的 {}：{}", flag, size)),
+            }
+        };
+        match flag {
+            "--network" | "--net" => { host.insert("NetworkMode".to_string(), json!(value()?)); }
+            "--dns" => push(host, "Dns", &value()?),
+            "--add-host" => push(host, "ExtraHosts", &value()?),
+            "--cap-add" => push(host, "CapAdd", &value()?),
+            "--cap-drop" => push(host, "CapDrop", &value()?),
+            "--security-opt" => push(host, "SecurityOpt", &value()?),
+            "--userns" => { host.insert("UsernsMode".to_string(), json!(value()?)); }
+            "--shm-size" => { host.insert("ShmSize".to_string(), json!(bytes(flag, &value()?)?)); }
+            "--tmpfs" => {
+                let tmpfs = value()?;
+                let (path, opts) = tmpfs.split_once(':').unwrap_or((&tmpfs, ""));
+                let entry = host.entry("Tmpfs".to_string()).or_insert_with(|| json!({}));
+                entry[path] = json!(opts);
+            }
+            "-v" | "--volume" => push(host, "Binds", &value()?),
+            "--mount" => {
+                let mount = parse_mount(&value()?)?;
+                host.entry("Mounts".to_string()).or_insert_with(|| json!([]))
+                    .as_array_mut().unwrap().push(mount);
+            }
+            "--device" => {
+                let device = value()?;
+                let mut parts = device.splitn(3, ':');
+                let on_host = parts.next().unwrap_or_default().to_string();
+                let in_container = parts.next().filter(|p| !p.is_empty()).unwrap_or(&on_host).to_string();
+                let permissions = parts.next().unwrap_or("rwm");
+                host.entry("Devices".to_string()).or_insert_with(|| json!([]))
+                    .as_array_mut().unwrap()
+                    .push(json!({"PathOnHost": on_host, "PathInContainer": in_container, "CgroupPermissions": permissions}));
+            }
+            "--ulimit" => {
+                let ulimit = value()?;
+                let invalid = || anyhow!("无效的 --ulimit：{}", ulimit);
+                let (name, limits) = ulimit.split_once('=').ok_or_else(invalid)?;
+                let (soft, hard) = limits.split_once(':').unwrap_or((limits, limits));
+                let (soft, hard): (i64, i64) = (soft.parse().map_err(|_| invalid())?, hard.parse().map_err(|_| invalid())?);
+                host.entry("Ulimits".to_string()).or_insert_with(|| json!([]))
+                    .as_array_mut().unwrap()
+                    .push(json!({"Name": name, "Soft": soft, "Hard": hard}));
+            }
+            "--cpus" => {
+                let cpus = value()?;
+                let cpus: f64 = cpus.parse().map_err(|_| anyhow!("无效的 --cpus：{}", cpus))?;
+                host.insert("NanoCpus".to_string(), json!((cpus * 1e9) as i64));
+            }
+            "-m" | "--memory" => { host.insert("Memory".to_string(), json!(bytes(flag, &value()?)?)); }
+            "--memory-swap" => { host.insert("MemorySwap".to_string(), json!(bytes(flag, &value()?)?)); }
+            "-u" | "--user" => { config.insert("User".to_string(), json!(value()?)); }
+            "-w" | "--workdir" => { config.insert("WorkingDir".to_string(), json!(value()?)); }
+            "--entrypoint" => { config.insert("Entrypoint".to_string(), json!([value()?])); }
+            "-e" | "--env" => push(config, "Env", &value()?),
+            "-h" | "--hostname" => { config.insert("Hostname".to_string(), json!(value()?)); }
+            "--privileged" => { host.insert("Privileged".to_string(), json!(true)); }
+            "--read-only" => { host.insert("ReadonlyRootfs".to_string(), json!(true)); }
+            "--init" => { host.insert("Init".to_string(), json!(true)); }
+            _ => return Err(anyhow!("不支持的docker选项：{}", option)),
+        }
+    }
+    Ok(())
+}
+
+// parse_mount把 --mount type=bind,source=/a,target=/b,readonly 转换为HostConfig.Mounts中的一项
+fn parse_mount(mount: &str) -> Result<Value> {
+    let (mut kind, mut source, mut target, mut read_only) = ("volume".to_string(), None, None, false);
+    for field in mount.split(',') {
+        let (key, value) = field.split_once('=').unwrap_or((field, "true"));
+        match key {
+            "type" => kind = value.to_string(),
+            "source" | "src" => source = Some(value.to_string()),
+            "target" | "destination" | "dst" => target = Some(value.to_string()),
+            "readonly" | "ro" => read_only = value == "true" || value == "1",
+            _ => return Err(anyhow!("不支持的 --mount 参数：{}", field)),
+        }
+    }
+    let target = target.ok_or_else(|| anyhow!("--mount {} 缺少target", mount))?;
+    Ok(json!({"Type": kind, "Source": source.unwrap_or_default(), "Target": target, "ReadOnly": read_only}))
 }
 
 
@@ -108,37 +328,18 @@ impl JobHook for DockerHook {
                        provider_name: String)
                        -> Result<()>
     {
-        // Command::new("docker")
-        //     .args(["rm", "-f", &provider_name])
-        //     .output()
-        //     .unwrap();
-        
+        // 正常情况下容器在wait之后已经被删除，这里删除因为异常没有删除的容器
         let name = self.name(provider_name);
-        let mut retry = 10;
-        loop{
-            if retry == 0 { break }
-            let output = Command::new("docker")
-                .args(["ps", "-a",
-                    "--filter", format!("name=^{}$", name).as_str(),
-                    "--format", "{{.Status}}"])
-                .output();
-            match output {
-                Ok(output) if !output.stdout.is_empty() => {
-                    debug!("container {} 仍存在:{}",name, String::from_utf8_lossy(&output.stdout));
+        let client = self.client();
+        match client.inspect_container(&name).await {
+            Ok(Some(state)) => {
+                debug!("container {} 仍存在:{}", name, state.status);
+                if let Err(e) = client.remove_container(&name).await {
+                    warn!("删除容器 {} 失败，下一次同步可能失败：{}", name, e)
                 }
-                Ok(output) if output.stdout.is_empty() => {
-                    break
-                }
-                Ok(output) if !output.stderr.is_empty() => {
-                    error!("docker ps 命令失败: {}", String::from_utf8_lossy(&output.stderr));
-                }
-                _ => {}
             }
-            tokio::time::sleep(Duration::from_secs(1)).await;  // 一秒
-            retry -= 1;
-        }
-        if retry == 0{
-            warn!("容器 {} 未自动删除，下一次同步可能失败", name)
+            Ok(None) => {}
+            Err(e) => warn!("检查容器 {} 失败：{}", name, e),
         }
 
         // 😅
@@ -0,0 +1,359 @@
+use std::io::Write;
+use std::fs::File;
+use anyhow::{anyhow, Result};
+use log::debug;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use crate::config::ContainerRuntime;
+use internal::msg::ResourceUsage;
+
+pub(crate) const DEFAULT_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+pub(crate) const DEFAULT_PODMAN_SOCKET: &str = "/run/podman/podman.sock";
+
+// default_socket返回DOCKER_HOST（podman为CONTAINER_HOST）指定的unix socket，没有时使用默认的socket
+pub(crate) fn default_socket(runtime: ContainerRuntime) -> String {
+    let host = match runtime {
+        ContainerRuntime::Docker => "DOCKER_HOST",
+        ContainerRuntime::Podman => "CONTAINER_HOST",
+    };
+    if let Some(socket) = std::env::var(host).ok()
+        .and_then(|host| host.strip_prefix("unix://").map(|s| s.to_string())) {
+        return socket
+    }
+    match runtime {
+        ContainerRuntime::Docker => DEFAULT_DOCKER_SOCKET.to_string(),
+        ContainerRuntime::Podman => podman_socket(unsafe { libc::getuid() }, std::env::var("XDG_RUNTIME_DIR").ok()),
+    }
+}
+
+// podman_socket返回podman服务的默认socket，rootless的podman服务在用户的运行时目录下监听
+pub(crate) fn podman_socket(uid: u32, runtime_dir: Option<String>) -> String {
+    if uid == 0 {
+        return DEFAULT_PODMAN_SOCKET.to_string()
+    }
+    let runtime_dir = runtime_dir.filter(|dir| !dir.is_empty())
+        .unwrap_or_else(|| format!("/run/user/{}", uid));
+    format!("{}/podman/podman.sock", runtime_dir)
+}
+
+// ContainerState是inspect返回的容器状态中我们关心的部分
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub(crate) struct ContainerState {
+    #[serde(rename = "Status")]
+    pub(crate) status: String,
+    #[serde(rename = "ExitCode")]
+    pub(crate) exit_code: i64,
+    #[serde(rename = "OOMKilled")]
+    pub(crate) oom_killed: bool,
+}
+
+// Body按照Content-Length、chunked或者直到连接关闭读取响应体
+pub(crate) struct Body {
+    reader: BufReader<UnixStream>,
+    chunked: bool,
+    // 当前chunk或者Content-Length剩余的字节数，None表示读到连接关闭为止
+    remaining: Option<usize>,
+    done: bool,
+}
+
+impl Body {
+    // next_chunk返回响应体的下一段数据，读完后返回None
+    pub(crate) async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.done {
+            return Ok(None)
+        }
+        if self.chunked && self.remaining.unwrap_or(0) == 0 {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).await?;
+            // 上一个chunk结尾的换行
+            if line.trim().is_empty() {
+                line.clear();
+                self.reader.read_line(&mut line).await?;
+            }
+            let size = usize::from_str_radix(line.trim().split(';').next().unwrap_or_default(), 16)
+                .map_err(|_| anyhow!("无效的chunk长度：{:?}", line))?;
+            if size == 0 {
+                self.done = true;
+                return Ok(None)
+            }
+            self.remaining = Some(size);
+        }
+        let mut buf = vec![0u8; self.remaining.unwrap_or(8192).min(8192)];
+        let n = self.reader.read(&mut buf).await?;
+        if n == 0 {
+            self.done = true;
+            return match self.remaining {
+                Some(remaining) if remaining > 0 => Err(anyhow!("docker的响应不完整")),
+                _ => Ok(None),
+            }
+        }
+        buf.truncate(n);
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= n;
+            if *remaining == 0 && !self.chunked {
+                self.done = true;
+            }
+        }
+        Ok(Some(buf))
+    }
+
+    pub(crate) async fn read_all(&mut self) -> Result<Vec<u8>> {
+        let mut body = vec![];
+        while let Some(chunk) = self.next_chunk().await? {
+            body.extend(chunk);
+        }
+        Ok(body)
+    }
+
+    // copy_logs把docker的多路复用日志流（8字节的头部加数据）中的stdout和stderr写入文件
+    pub(crate) async fn copy_logs(&mut self, mut out: Option<File>) -> Result<()> {
+        let mut buf: Vec<u8> = vec![];
+        while let Some(chunk) = self.next_chunk().await? {
+            buf.extend(chunk);
+            while buf.len() >= 8 {
+                let size = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]) as usize;
+                if buf.len() < 8 + size {
+                    break
+                }
+                if let Some(out) = out.as_mut() {
+                    out.write_all(&buf[8..8 + size])?;
+                }
+                buf.drain(..8 + size);
+            }
+        }
+        Ok(())
+    }
+
+    // collect_usage读取容器的资源统计流（每行一个JSON对象），每收到一次统计就更新usage，容器退出后统计流结束。
+    // CPU时间和读写的字节数是累计值，内存取统计到的峰值
+    pub(crate) async fn collect_usage(&mut self, usage: &std::sync::Mutex<ResourceUsage>) -> Result<()> {
+        let mut buf: Vec<u8> = vec![];
+        while let Some(chunk) = self.next_chunk().await? {
+            buf.extend(chunk);
+            while let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=pos).collect();
+                if let Ok(stats) = serde_json::from_slice::<Value>(&line) {
+                    let sample = stats_to_usage(&stats);
+                    let mut usage = usage.lock().unwrap();
+                    usage.user_time_ms = usage.user_time_ms.max(sample.user_time_ms);
+                    usage.system_time_ms = usage.system_time_ms.max(sample.system_time_ms);
+                    usage.max_rss_bytes = usage.max_rss_bytes.max(sample.max_rss_bytes);
+                    usage.read_bytes = usage.read_bytes.max(sample.read_bytes);
+                    usage.write_bytes = usage.write_bytes.max(sample.write_bytes);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// stats_to_usage从docker的一次统计中取出资源消耗，cgroup v1的blkio操作名是大写开头的，并且带有Total汇总项
+pub(crate) fn stats_to_usage(stats: &Value) -> ResourceUsage {
+    let cpu = &stats["cpu_stats"]["cpu_usage"];
+    let memory = &stats["memory_stats"];
+    let mut usage = ResourceUsage {
+        user_time_ms: cpu["usage_in_usermode"].as_u64().unwrap_or(0) / 1_000_000,
+        system_time_ms: cpu["usage_in_kernelmode"].as_u64().unwrap_or(0) / 1_000_000,
+        max_rss_bytes: memory["max_usage"].as_u64().unwrap_or(0).max(memory["usage"].as_u64().unwrap_or(0)),
+        ..ResourceUsage::default()
+    };
+    if let Some(entries) = stats["blkio_stats"]["io_service_bytes_recursive"].as_array() {
+        for entry in entries {
+            let value = entry["value"].as_u64().unwrap_or(0);
+            match entry["op"].as_str().map(|op| op.to_lowercase()).as_deref() {
+                Some("read") => usage.read_bytes += value,
+                Some("write") => usage.write_bytes += value,
+                _ => {}
+            }
+        }
+    }
+    usage
+}
+
+// DockerClient通过unix socket调用Docker Engine API（或podman兼容docker的API），每个请求使用一个新的连接
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DockerClient {
+    pub(crate) runtime: ContainerRuntime,
+    pub(crate) socket: String,
+}
+
+impl DockerClient {
+    pub(crate) fn new(runtime: ContainerRuntime, socket: &str) -> Self {
+        DockerClient { runtime, socket: socket.to_string() }
+    }
+
+    // send发送请求并读取响应头，返回状态码和响应体
+    pub(crate) async fn send(&self, method: &str, path: &str, body: Option<&Value>) -> Result<(u16, Body)> {
+        let stream = UnixStream::connect(&self.socket).await
+            .map_err(|e| anyhow!("无法连接到{}守护进程 {}：{}", self.runtime, self.socket, e))?;
+        let mut reader = BufReader::new(stream);
+        let body = body.map(|b| b.to_string()).unwrap_or_default();
+        let mut req = format!("{} {} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\n", method, path);
+        if !body.is_empty() {
+            req.push_str(&format!("Content-Type: application/json\r\nContent-Length: {}\r\n", body.len()));
+        }
+        req.push_str("\r\n");
+        req.push_str(&body);
+        reader.get_mut().write_all(req.as_bytes()).await?;
+        debug!("{} API：{} {}", self.runtime, method, path);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let status: u16 = line.split_whitespace().nth(1).and_then(|s| s.parse().ok())
+            .ok_or_else(|| anyhow!("无效的docker响应：{:?}", line))?;
+        let (mut chunked, mut length) = (false, None);
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                break
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                match key.trim().to_lowercase().as_str() {
+                    "transfer-encoding" => chunked = value.trim().eq_ignore_ascii_case("chunked"),
+                    "content-length" => length = value.trim().parse::<usize>().ok(),
+                    _ => {}
+                }
+            }
+        }
+        let done = !chunked && length == Some(0) || status == 204 || status == 304;
+        Ok((status, Body { reader, chunked, remaining: if chunked { Some(0) } else { length }, done }))
+    }
+
+    // call发送请求并读取整个响应体，状态码不是2xx时返回docker的错误信息
+    async fn call(&self, method: &str, path: &str, body: Option<&Value>) -> Result<(u16, Vec<u8>)> {
+        let (status, mut resp) = self.send(method, path, body).await?;
+        let content = resp.read_all().await?;
+        if status >= 400 {
+            let message = serde_json::from_slice::<Value>(&content).ok()
+                .and_then(|v| v["message"].as_str().map(|s| s.to_string()))
+                .unwrap_or_else(|| String::from_utf8_lossy(&content).trim().to_string());
+            return Err(DockerError { status, message }.into())
+        }
+        Ok((status, content))
+    }
+
+    // pull_image拉取镜像，进度以JSON流的形式返回，其中的error字段表示拉取失败
+    pub(crate) async fn pull_image(&self, image: &str) -> Result<()> {
+        let (name, tag) = match image.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, tag),
+            _ => (image, "latest"),
+        };
+        let path = format!("/images/create?fromImage={}&tag={}", query_encode(name), query_encode(tag));
+        let (_, content) = self.call("POST", &path, None).await?;
+        for line in String::from_utf8_lossy(&content).lines() {
+            if let Ok(progress) = serde_json::from_str::<Value>(line) {
+                if let Some(err) = progress["error"].as_str() {
+                    return Err(anyhow!("拉取镜像 {} 失败：{}", image, err))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // create_container创建容器，镜像不存在时先拉取镜像，返回容器的id
+    pub(crate) async fn create_container(&self, name: &str, image: &str, config: &Value) -> Result<String> {
+        let path = format!("/containers/create?name={}", query_encode(name));
+        let content = match self.call("POST", &path, Some(config)).await {
+            Err(e) if e.downcast_ref::<DockerError>().is_some_and(|e| e.status == 404) => {
+                debug!("镜像 {} 不存在，开始拉取", image);
+                self.pull_image(image).await?;
+                self.call("POST", &path, Some(config)).await?.1
+            }
+            result => result?.1,
+        };
+        let resp: Value = serde_json::from_slice(&content)?;
+        resp["Id"].as_str().map(|id| id.to_string())
+            .ok_or_else(|| anyhow!("docker没有返回容器的id"))
+    }
+
+    pub(crate) async fn start_container(&self, id: &str) -> Result<()> {
+        self.call("POST", &format!("/containers/{}/start", id), None).await.map(|_| ())
+    }
+
+    // logs返回容器的日志流，容器退出后日志流结束
+    pub(crate) async fn logs(&self, id: &str) -> Result<Body> {
+        let (status, mut body) = self.send("GET", &format!("/containers/{}/logs?follow=1&stdout=1&stderr=1", id), None).await?;
+        if status >= 400 {
+            let content = body.read_all().await?;
+            return Err(anyhow!("读取容器 {} 的日志失败：{}", id, String::from_utf8_lossy(&content).trim()))
+        }
+        Ok(body)
+    }
+
+    // stats返回容器的资源统计流，容器退出后统计流结束
+    pub(crate) async fn stats(&self, id: &str) -> Result<Body> {
+        let (status, mut body) = self.send("GET", &format!("/containers/{}/stats?stream=1", id), None).await?;
+        if status >= 400 {
+            let content = body.read_all().await?;
+            return Err(anyhow!("读取容器 {} 的资源统计失败：{}", id, String::from_utf8_lossy(&content).trim()))
+        }
+        Ok(body)
+    }
+
+    // wait_container等待容器退出，返回退出码
+    pub(crate) async fn wait_container(&self, id: &str) -> Result<i64> {
+        let (_, content) = self.call("POST", &format!("/containers/{}/wait", id), None).await?;
+        let resp: Value = serde_json::from_slice(&content)?;
+        if let Some(err) = resp["Error"]["Message"].as_str().filter(|s| !s.is_empty()) {
+            return Err(anyhow!("等待容器 {} 退出失败：{}", id, err))
+        }
+        resp["StatusCode"].as_i64().ok_or_else(|| anyhow!("docker没有返回容器的退出码"))
+    }
+
+    // inspect_container返回容器的状态，容器不存在时返回None
+    pub(crate) async fn inspect_container(&self, id: &str) -> Result<Option<ContainerState>> {
+        match self.call("GET", &format!("/containers/{}/json", id), None).await {
+            Ok((_, content)) => {
+                let resp: Value = serde_json::from_slice(&content)?;
+                Ok(Some(serde_json::from_value(resp["State"].clone())?))
+            }
+            Err(e) if e.downcast_ref::<DockerError>().is_some_and(|e| e.status == 404) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // stop_container停止容器，超过timeout秒后强制杀死，容器不存在或已经停止时不返回错误
+    pub(crate) async fn stop_container(&self, id: &str, timeout: u64) -> Result<()> {
+        match self.call("POST", &format!("/containers/{}/stop?t={}", id, timeout), None).await {
+            Err(e) if e.downcast_ref::<DockerError>().is_some_and(|e| e.status == 404) => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    // remove_container强制删除容器，容器不存在时不返回错误
+    pub(crate) async fn remove_container(&self, id: &str) -> Result<()> {
+        match self.call("DELETE", &format!("/containers/{}?force=1", id), None).await {
+            Err(e) if e.downcast_ref::<DockerError>().is_some_and(|e| e.status == 404) => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+// DockerError是docker API返回的错误
+#[derive(Debug)]
+pub(crate) struct DockerError {
+    pub(crate) status: u16,
+    pub(crate) message: String,
+}
+
+impl std::fmt::Display for DockerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "docker API错误 {}：{}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for DockerError {}
+
+fn query_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
@@ -4,15 +4,21 @@ mod tests {
     use std::fs::File;
     use std::io::Write;
     use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
     use crate::docker::*;
     use std::process::Command;
-    use std::sync::{Arc, RwLock};
+    use std::sync::{Arc, Mutex};
     use chrono::Duration;
+    use serde_json::{json, Value};
     use tempfile::Builder;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{UnixListener, UnixStream};
     use tokio::sync::mpsc::channel;
     use crate::cmd_provider::{CmdConfig, CmdProvider};
     use crate::common::Empty;
-    use crate::config::MemBytes;
+    use std::collections::HashMap;
+    use crate::config::{ContainerRuntime, IoMax, MemBytes, ResourceLimits};
+    use crate::docker_api::podman_socket;
     use crate::hooks::{HookType, JobHook};
     use crate::provider::MirrorProvider;
 
@@ -154,6 +160,319 @@ sleep 30
             .unwrap();
     }
 
+    #[test]
+    fn test_docker_container_config() {
+        let d = DockerHook{
+            image: "alpine:3.8".to_string(),
+            memory_limit: MemBytes(512*1024_i64.pow(2)),
+            limits: ResourceLimits{
+                cpu_weight: Some(100),
+                cpu_max: Some(crate::config::CpuMax(1500)),
+                io_weight: Some(10000),
+                io_max: vec![IoMax{ device: "/dev/sda".to_string(), rbps: Some(1048576), wbps: None }],
+                pids_max: Some(512),
+            },
+            options: vec!["--network=host".to_string(), "--cap-add".to_string(), "NET_ADMIN".to_string(),
+                          "-e".to_string(), "LANG=C".to_string(), "--read-only".to_string()],
+            ..DockerHook::default()
+        };
+        assert_eq!(Value::Object(d.limit_config()), json!({
+            "Memory": 536870912,
+            "CpuShares": 2597,
+            "NanoCpus": 1500000000,
+            "BlkioWeight": 1000,
+            "BlkioDeviceReadBps": [{"Path": "/dev/sda", "Rate": 1048576}],
+            "PidsLimit": 512,
+        }));
+        assert!(DockerHook::default().limit_config().is_empty());
+
+        let spec = ContainerSpec{
+            name: d.name("alpine".to_string()),
+            cmd: vec!["sh".to_string(), "/bin/cmd.sh".to_string()],
+            env: [("TEST_CONTENT".to_string(), "HELLO_WORLD".to_string())].into(),
+            working_dir: "/data/alpine".to_string(),
+            user: "1000:1000".to_string(),
+            volumes: vec!["/data/alpine:/data/alpine".to_string()],
+        };
+        let config = d.container_config(&spec).unwrap();
+        assert_eq!(config["Image"], "alpine:3.8");
+        assert_eq!(config["Cmd"], json!(["sh", "/bin/cmd.sh"]));
+        // docker_options中的环境变量追加在后面
+        assert_eq!(config["Env"], json!(["TEST_CONTENT=HELLO_WORLD", "LANG=C"]));
+        assert_eq!(config["WorkingDir"], "/data/alpine");
+        assert_eq!(config["User"], "1000:1000");
+        assert_eq!(config["HostConfig"]["Binds"], json!(["/data/alpine:/data/alpine"]));
+        assert_eq!(config["HostConfig"]["NetworkMode"], "host");
+        assert_eq!(config["HostConfig"]["CapAdd"], json!(["NET_ADMIN"]));
+        assert_eq!(config["HostConfig"]["ReadonlyRootfs"], true);
+        assert_eq!(config["HostConfig"]["Memory"], 536870912);
+
+        // 卷、用户、设备等选项转换为对应的字段
+        let options = ["-v", "/srv/cache:/cache:ro", "--mount", "type=bind,source=/srv/a,target=/a,readonly",
+                       "-u", "0:0", "--ulimit", "nofile=1024:4096", "--device", "/dev/fuse", "--cpus=1.5",
+                       "--memory-swap", "-1", "--entrypoint", "/bin/sh", "-w", "/work"];
+        let d = DockerHook{ options: options.iter().map(|s| s.to_string()).collect(), ..DockerHook::default() };
+        let config = d.container_config(&spec).unwrap();
+        assert_eq!(config["HostConfig"]["Binds"], json!(["/data/alpine:/data/alpine", "/srv/cache:/cache:ro"]));
+        assert_eq!(config["HostConfig"]["Mounts"], json!([{"Type": "bind", "Source": "/srv/a", "Target": "/a", "ReadOnly": true}]));
+        assert_eq!(config["User"], "0:0");
+        assert_eq!(config["HostConfig"]["Ulimits"], json!([{"Name": "nofile", "Soft": 1024, "Hard": 4096}]));
+        assert_eq!(config["HostConfig"]["Devices"], json!([{"PathOnHost": "/dev/fuse", "PathInContainer": "/dev/fuse", "CgroupPermissions": "rwm"}]));
+        assert_eq!(config["HostConfig"]["NanoCpus"], 1500000000);
+        assert_eq!(config["HostConfig"]["MemorySwap"], -1);
+        assert_eq!(config["Entrypoint"], json!(["/bin/sh"]));
+        assert_eq!(config["WorkingDir"], "/work");
+
+        // 不支持的选项在加载配置时报错
+        let d = DockerHook{ options: vec!["--gpus".to_string(), "all".to_string()], ..DockerHook::default() };
+        let err = d.container_config(&spec).unwrap_err();
+        assert!(err.to_string().contains("不支持的docker选项：--gpus"), "{}", err);
+        let d = DockerHook{ options: vec!["--mount=type=bind,target=/a,bind-propagation=shared".to_string()], ..DockerHook::default() };
+        assert!(d.container_config(&spec).is_err());
+
+        let d = DockerHook{ options: vec!["--shm-size".to_string()], ..DockerHook::default() };
+        let err = d.container_config(&spec).unwrap_err();
+        assert!(err.to_string().contains("缺少参数"), "{}", err);
+    }
+
+    #[test]
+    fn test_podman() {
+        assert_eq!(podman_socket(0, Some("/run/user/0".to_string())), "/run/podman/podman.sock");
+        assert_eq!(podman_socket(1000, Some("/run/user/1000".to_string())), "/run/user/1000/podman/podman.sock");
+        assert_eq!(podman_socket(1000, None), "/run/user/1000/podman/podman.sock");
+
+        let d = DockerHook{
+            runtime: ContainerRuntime::Podman,
+            rootless: true,
+            image: "docker.io/library/alpine:3.8".to_string(),
+            ..DockerHook::default()
+        };
+        // 容器名在docker和podman中相同，不合法的字符被替换
+        assert_eq!(d.name("debian/security".to_string()), "rtsync-job-debian-security");
+        let spec = d.container_spec("alpine".to_string(), vec!["true".to_string()], HashMap::new(),
+                                    "/data/alpine".to_string(), vec!["/data/alpine:/data/alpine".to_string()]);
+        let config = d.container_config(&spec).unwrap();
+        assert_eq!(config["HostConfig"]["UsernsMode"], "keep-id");
+        assert_eq!(config["HostConfig"]["Binds"], json!(["/data/alpine:/data/alpine"]));
+        assert_eq!(config["User"], spec.user);
+
+        // 显式配置的--userns优先
+        let d = DockerHook{ options: vec!["--userns=auto".to_string()], ..d };
+        assert_eq!(d.container_config(&spec).unwrap()["HostConfig"]["UsernsMode"], "auto");
+        // rootful的podman和docker一样直接使用uid:gid
+        let d = DockerHook{ rootless: false, options: vec![], ..d };
+        assert!(d.container_config(&spec).unwrap()["HostConfig"].get("UsernsMode").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_podman_socket_error() {
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let d = DockerHook{
+            runtime: ContainerRuntime::Podman,
+            socket: tmp_dir.path().join("podman.sock").display().to_string(),
+            ..DockerHook::default()
+        };
+        let err = d.client().remove_container("rtsync-job-alpine").await.unwrap_err();
+        assert!(err.to_string().contains("无法连接到podman守护进程"), "{}", err);
+    }
+
+    // FakeDocker模拟docker守护进程，记录收到的请求
+    #[derive(Default)]
+    struct FakeDocker {
+        requests: Mutex<Vec<(String, String, String)>>,
+        exit_code: i64,
+        oom_killed: bool,
+    }
+
+    fn chunked(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut body = vec![];
+        for chunk in chunks {
+            body.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+            body.extend(*chunk);
+            body.extend(b"\r\n");
+        }
+        body.extend(b"0\r\n\r\n");
+        body
+    }
+
+    fn log_frame(stream: u8, content: &str) -> Vec<u8> {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend((content.len() as u32).to_be_bytes());
+        frame.extend(content.as_bytes());
+        frame
+    }
+
+    async fn handle(fake: Arc<FakeDocker>, stream: UnixStream) {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        let mut parts = line.split_whitespace();
+        let (method, path) = (parts.next().unwrap().to_string(), parts.next().unwrap().to_string());
+        let mut length = 0;
+        loop {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            if line.trim().is_empty() {
+                break
+            }
+            if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).await.unwrap();
+        let created = {
+            let mut requests = fake.requests.lock().unwrap();
+            requests.push((method.clone(), path.clone(), String::from_utf8(body).unwrap()));
+            requests.iter().filter(|(m, p, _)| m == "POST" && p.starts_with("/containers/create")).count()
+        };
+
+        let json = |status: &str, body: Value| (status.to_string(), "Content-Type: application/json".to_string(),
+                                                body.to_string().into_bytes());
+        let (status, header, body) = match (method.as_str(), path.as_str()) {
+            // 第一次创建容器时镜像还不存在
+            ("POST", p) if p.starts_with("/containers/create") && created == 1 =>
+                json("404 Not Found", json!({"message": "No such image: alpine:3.8"})),
+            ("POST", p) if p.starts_with("/containers/create") => json("201 Created", json!({"Id": "abc123"})),
+            ("POST", p) if p.starts_with("/images/create") => ("200 OK".to_string(), "Transfer-Encoding: chunked".to_string(),
+                chunked(&[b"{\"status\":\"Pulling from library/alpine\"}\n", b"{\"status\":\"Downloaded newer image\"}\n"])),
+            ("POST", "/containers/abc123/start") => ("204 No Content".to_string(), String::new(), vec![]),
+            ("GET", p) if p.starts_with("/containers/abc123/logs") => {
+                let mut frames = log_frame(1, "HELLO\n");
+                frames.extend(log_frame(2, "WORLD\n"));
+                // 帧头被拆到不同的chunk中
+                let body = chunked(&[&frames[..3], &frames[3..10], &frames[10..]]);
+                ("200 OK".to_string(), "Transfer-Encoding: chunked".to_string(), body)
+            }
+            ("GET", "/containers/abc123/stats?stream=1") => {
+                let sample = |user: u64, memory: u64, read: u64| format!("{}\n", json!({
+                    "cpu_stats": {"cpu_usage": {"usage_in_usermode": user, "usage_in_kernelmode": 20_000_000}},
+                    "memory_stats": {"usage": memory},
+                    "blkio_stats": {"io_service_bytes_recursive": [
+                        {"op": "Read", "value": read}, {"op": "Write", "value": 4096}, {"op": "Total", "value": read + 4096}]},
+                }));
+                let (first, second) = (sample(10_000_000, 2048, 512), sample(30_000_000, 1024, 1024));
+                ("200 OK".to_string(), "Transfer-Encoding: chunked".to_string(),
+                 chunked(&[first.as_bytes(), &second.as_bytes()[..10], &second.as_bytes()[10..]]))
+            }
+            ("POST", "/containers/abc123/wait") => json("200 OK", json!({"StatusCode": fake.exit_code})),
+            ("GET", "/containers/abc123/json") => json("200 OK", json!({"State": {
+                "Status": "exited", "ExitCode": fake.exit_code, "OOMKilled": fake.oom_killed}})),
+            ("DELETE", "/containers/abc123?force=1") => ("204 No Content".to_string(), String::new(), vec![]),
+            _ => json("404 Not Found", json!({"message": "No such container"})),
+        };
+        let mut resp = format!("HTTP/1.1 {}\r\n", status);
+        if !header.is_empty() {
+            resp.push_str(&format!("{}\r\n", header));
+        }
+        if !header.starts_with("Transfer-Encoding") {
+            resp.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        resp.push_str("\r\n");
+        let stream = reader.get_mut();
+        stream.write_all(resp.as_bytes()).await.unwrap();
+        stream.write_all(&body).await.unwrap();
+        stream.shutdown().await.unwrap();
+    }
+
+    fn serve(fake: Arc<FakeDocker>, socket: &Path) {
+        let listener = UnixListener::bind(socket).unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(handle(fake.clone(), stream));
+            }
+        });
+    }
+
+    async fn docker_provider(tmp_dir: &Path, socket: &Path) -> CmdProvider {
+        let c = CmdConfig{
+            name: "rt-docker-api".to_string(),
+            upstream_url: "http://mirrors.tuna.moe/".to_string(),
+            command: "sh /bin/cmd.sh".to_string(),
+            working_dir: tmp_dir.display().to_string(),
+            log_dir: tmp_dir.display().to_string(),
+            log_file: tmp_dir.join("log_file").display().to_string(),
+            interval: Duration::seconds(600),
+            env: [("TEST_CONTENT".to_string(), "HELLO_WORLD".to_string())].into(),
+            ..CmdConfig::default()
+        };
+        let mut provider = CmdProvider::new(c).await.unwrap();
+        provider.add_hook(HookType::Docker(DockerHook{
+            socket: socket.display().to_string(),
+            image: "alpine:3.8".to_string(),
+            memory_limit: MemBytes(512*1024_i64.pow(2)),
+            ..DockerHook::default()
+        })).await;
+        provider
+    }
+
+    #[tokio::test]
+    async fn test_docker_api() {
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let socket = tmp_dir.path().join("docker.sock");
+        let fake = Arc::new(FakeDocker::default());
+        serve(fake.clone(), &socket);
+
+        let provider = docker_provider(tmp_dir.path(), &socket).await;
+        provider.run(channel(1).0).await.unwrap();
+
+        let logged_content = fs::read_to_string(provider.log_file().await).unwrap();
+        assert_eq!(logged_content, "HELLO\nWORLD\n");
+
+        let requests = fake.requests.lock().unwrap().clone();
+        let paths: Vec<String> = requests.iter().map(|(m, p, _)| format!("{} {}", m, p)).collect();
+        assert_eq!(paths, vec![
+            "DELETE /containers/rtsync-job-rt-docker-api?force=1",
+            "POST /containers/create?name=rtsync-job-rt-docker-api",
+            "POST /images/create?fromImage=alpine&tag=3.8",
+            "POST /containers/create?name=rtsync-job-rt-docker-api",
+            "POST /containers/abc123/start",
+            "GET /containers/abc123/logs?follow=1&stdout=1&stderr=1",
+            "GET /containers/abc123/stats?stream=1",
+            "POST /containers/abc123/wait",
+            "GET /containers/abc123/json",
+            "DELETE /containers/abc123?force=1",
+        ]);
+        let config: Value = serde_json::from_str(&requests[3].2).unwrap();
+        assert_eq!(config["Image"], "alpine:3.8");
+        assert_eq!(config["Cmd"], json!(["sh", "/bin/cmd.sh"]));
+        assert_eq!(config["WorkingDir"], tmp_dir.path().display().to_string());
+        assert_eq!(config["HostConfig"]["Memory"], 536870912);
+        assert!(config["Env"].as_array().unwrap().contains(&json!("TEST_CONTENT=HELLO_WORLD")));
+
+        // 资源消耗来自容器的统计流，CPU时间和读写字节数取最后的累计值，内存取峰值
+        let usage = provider.resource_usage().await.unwrap();
+        assert_eq!((usage.user_time_ms, usage.system_time_ms), (30, 20));
+        assert_eq!(usage.max_rss_bytes, 2048);
+        assert_eq!((usage.read_bytes, usage.write_bytes), (1024, 4096));
+    }
+
+    #[tokio::test]
+    async fn test_docker_api_errors() {
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+
+        // 容器因为超出内存限制被杀死
+        let socket = tmp_dir.path().join("docker.sock");
+        let fake = Arc::new(FakeDocker{ exit_code: 137, oom_killed: true, ..FakeDocker::default() });
+        serve(fake.clone(), &socket);
+        let provider = docker_provider(tmp_dir.path(), &socket).await;
+        let err = provider.run(channel(1).0).await.unwrap_err();
+        assert!(err.to_string().contains("OOM"), "{}", err);
+        assert!(fake.requests.lock().unwrap().iter().any(|(m, p, _)| m == "DELETE" && p.starts_with("/containers/abc123")));
+
+        // docker守护进程没有运行
+        let provider = docker_provider(tmp_dir.path(), &tmp_dir.path().join("missing.sock")).await;
+        let err = provider.run(channel(1).0).await.unwrap_err();
+        assert!(err.to_string().contains("无法连接到docker守护进程"), "{}", err);
+    }
+
     #[test]
     fn t(){
         let output = Command::new("docker")
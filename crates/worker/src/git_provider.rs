@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use anyhow::{anyhow, Result};
+use std::{fs, io};
+use std::fs::Permissions;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path};
+use tokio::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{Mutex, RwLock};
+use async_trait::async_trait;
+use std::sync::Arc;
+use anymap::AnyMap;
+use chrono::Duration;
+use log::{debug, error, warn};
+use internal::util::dir_size;
+use internal::size::MirrorSize;
+use internal::msg::ResourceUsage;
+use crate::base_provider::BaseProvider;
+use crate::common::{Empty, DEFAULT_MAX_RETRY};
+use crate::config::ProviderEnum;
+use crate::context::Context;
+use crate::docker::DockerHook;
+use crate::hooks::{HookType, JobHook};
+use crate::provider::{MirrorProvider, _LOG_DIR_KEY, _LOG_FILE_KEY, _WORKING_DIR_KEY};
+use crate::runner::CmdJob;
+
+#[derive(Clone, Default, Debug)]
+pub(crate) struct GitConfig {
+    pub(crate) name: String,
+    pub(crate) git_cmd: String,
+
+    pub(crate) upstream_url: String,
+    pub(crate) repos: Vec<String>,
+    pub(crate) repos_file: String,
+    pub(crate) env: HashMap<String, String>,
+
+    pub(crate) working_dir: String,
+    pub(crate) log_dir: String,
+    pub(crate) log_file: String,
+
+    pub(crate) interval: Duration,
+    pub(crate) retry: i64,
+    pub(crate) timeout: Duration,
+}
+
+// GitRepo是一个要同步的仓库，dir是裸仓库相对于工作目录的路径
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct GitRepo {
+    pub(crate) url: String,
+    pub(crate) dir: String,
+}
+
+// parse_repo解析一行仓库配置，格式为 "<url> [目录名]"，空行和#开头的行返回None
+// url不是完整地址时拼接在upstream之后，没有指定目录名时使用url的最后一段
+pub(crate) fn parse_repo(line: &str, upstream: &str) -> Result<Option<GitRepo>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None)
+    }
+    let mut fields = line.split_whitespace();
+    let mut url = fields.next().unwrap().to_string();
+    let dir = fields.next().map(|d| d.to_string());
+    if fields.next().is_some() {
+        return Err(anyhow!("仓库配置格式错误：{}", line))
+    }
+
+    // 完整地址包括 https://... 、本地路径和 git@host:path 形式的ssh地址
+    let is_full_url = url.contains("://") || url.starts_with('/') || url.contains(':');
+    if !is_full_url && !upstream.is_empty() {
+        url = format!("{}/{}", upstream.trim_end_matches('/'), url);
+    }
+
+    let dir = match dir {
+        Some(dir) => dir,
+        None => {
+            let base = url.trim_end_matches('/')
+                .rsplit(['/', ':'])
+                .next()
+                .unwrap_or_default();
+            if base.ends_with(".git") { base.to_string() } else { format!("{}.git", base) }
+        }
+    };
+    // 仓库必须位于工作目录之内
+    let path = Path::new(&dir);
+    if dir.is_empty() || dir == ".git" || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(anyhow!("仓库 {} 的目录名不合法：{}", url, dir))
+    }
+    Ok(Some(GitRepo { url, dir }))
+}
+
+// GitProvider使用 git clone --mirror 和 git remote update --prune 维护裸仓库镜像
+#[derive(Clone, Default, Debug)]
+pub(crate) struct GitProvider {
+    pub(crate) base_provider: Arc<RwLock<BaseProvider>>,
+    pub(crate) git_config: Arc<GitConfig>,
+    data_size: Arc<Mutex<MirrorSize>>,
+    // 仓库是逐个同步的，终止时要阻止后面的仓库开始同步
+    terminated: Arc<AtomicBool>,
+}
+
+unsafe impl Send for GitProvider{}
+unsafe impl Sync for GitProvider{}
+
+impl GitProvider {
+    pub(crate) async fn new(mut c: GitConfig) -> Result<Self>{
+        if c.retry == 0{
+            c.retry = DEFAULT_MAX_RETRY;
+        }
+        if c.git_cmd.is_empty(){
+            c.git_cmd = "git".to_string();
+        }
+        if c.repos.is_empty() && c.repos_file.is_empty() && c.upstream_url.is_empty() {
+            return Err(anyhow!("git provider {} 没有配置upstream、git_repos或git_repos_file", c.name))
+        }
+        // 认证失败时不要等待输入用户名和密码
+        c.env.entry("GIT_TERMINAL_PROMPT".to_string()).or_insert("0".to_string());
+
+        // 提前检查配置的仓库列表，仓库列表文件在每次同步时读取
+        for line in c.repos.iter() {
+            parse_repo(line, &c.upstream_url)?;
+        }
+
+        let provider = GitProvider{
+            base_provider: Arc::new(RwLock::new(BaseProvider::new(c.name.clone(), c.interval, c.retry, c.timeout))),
+            git_config: Arc::from(c.clone()),
+            ..GitProvider::default()
+        };
+
+        let base_provider_lock = provider.base_provider.write().await;
+        if let Some(ctx) = base_provider_lock.ctx.lock().await.as_mut(){
+            let mut value = AnyMap::new();
+            value.insert(c.working_dir);
+            ctx.set(_WORKING_DIR_KEY.to_string(), value);
+
+            let mut value = AnyMap::new();
+            value.insert(c.log_dir);
+            ctx.set(_LOG_DIR_KEY.to_string(), value);
+
+            let mut value = AnyMap::new();
+            value.insert(c.log_file);
+            ctx.set(_LOG_FILE_KEY.to_string(), value);
+        }
+        drop(base_provider_lock);
+        Ok(provider)
+    }
+
+    // repos返回要同步的仓库，没有配置仓库列表时把upstream同步到工作目录
+    pub(crate) fn repos(&self) -> Result<Vec<GitRepo>> {
+        let c = self.git_config.as_ref();
+        let mut repos = vec![];
+        for line in c.repos.iter() {
+            repos.extend(parse_repo(line, &c.upstream_url)?);
+        }
+        if !c.repos_file.is_empty() {
+            let content = fs::read_to_string(&c.repos_file)
+                .map_err(|e| anyhow!("读取仓库列表 {} 失败：{}", c.repos_file, e))?;
+            for line in content.lines() {
+                repos.extend(parse_repo(line, &c.upstream_url)?);
+            }
+        }
+        if c.repos.is_empty() && c.repos_file.is_empty() {
+            repos.push(GitRepo { url: c.upstream_url.clone(), dir: String::new() });
+        }
+        for (i, repo) in repos.iter().enumerate() {
+            if repos[..i].iter().any(|r| r.dir == repo.dir) {
+                return Err(anyhow!("多个仓库使用了相同的目录：{}", repo.dir))
+            }
+        }
+        Ok(repos)
+    }
+
+    // repo_command返回同步一个仓库的命令，已经存在的裸仓库只更新，否则重新克隆
+    fn repo_command(&self, repo: &GitRepo, working_dir: &str) -> Vec<String> {
+        let git = self.git_config.git_cmd.clone();
+        let path = Path::new(working_dir).join(&repo.dir).display().to_string();
+        if Path::new(&path).join("HEAD").is_file() {
+            vec![git, "-C".to_string(), path, "remote".to_string(), "update".to_string(), "--prune".to_string()]
+        } else {
+            vec![git, "clone".to_string(), "--mirror".to_string(), repo.url.clone(), path]
+        }
+    }
+
+    // write_log向日志文件中写入一行提示信息
+    async fn write_log(&self, msg: &str) {
+        if let Some(log_file_fd) = self.base_provider.write().await.log_file_fd.as_mut(){
+            if let Err(e) = log_file_fd.write_all(format!("{}\n", msg).as_bytes()) {
+                warn!("写入日志文件失败：{}", e);
+            }
+        }
+    }
+
+    /// cmd配置base_provider字段中的cmd字段
+    async fn cmd(&self, cmd_and_args: Vec<String>, working_dir: String, env: HashMap<String, String>){
+        let mut base_provider_lock = self.base_provider.write().await;
+        let cmd_job: CmdJob;
+        let use_docker = base_provider_lock.docker_ref().is_some();
+
+        if let Some(d) = base_provider_lock.docker_ref().as_ref(){
+            // 容器由BaseProvider通过docker API创建，这里只记录容器的配置
+            let spec = d.container_spec(self.name(), cmd_and_args.to_vec(), env.clone(),
+                                        working_dir.clone(), base_provider_lock.docker_volumes().await);
+            cmd_job = CmdJob::new_container(spec, working_dir.clone(), env.clone());
+        }else {
+            cmd_job = CmdJob::new(Command::new(&cmd_and_args[0]), working_dir.clone(), env.clone());
+            { cmd_job.cmd.lock().await.args(&cmd_and_args[1..]); }
+        }
+
+        if !use_docker {
+            debug!("在 {} 位置执行 {:?} 命令", &working_dir, cmd_and_args);
+
+            // 如果目录不存在，则创建目录
+            if let Err(err) = fs::read_dir(&working_dir) {
+                if err.kind() == io::ErrorKind::NotFound {
+                    debug!("创建文件夹：{}", &working_dir);
+                    if fs::create_dir_all(&working_dir).is_ok(){
+                        if fs::set_permissions(&working_dir, Permissions::from_mode(0o755)).is_err()  {
+                            error!("更改文件夹 {} 权限失败: {}",&working_dir, err)
+                        }
+                    }else {
+                        error!("创建文件夹 {} 失败: {}", &working_dir, err)
+                    }
+                }
+            }
+            {
+                cmd_job.cmd
+                    .lock().await
+                    .current_dir(&working_dir)
+                    .envs(crate::runner::new_environ(env, true));
+            }
+        }
+
+        base_provider_lock.cmd = Some(cmd_job);
+        drop(base_provider_lock);
+    }
+}
+
+#[async_trait]
+impl MirrorProvider for GitProvider {
+    fn name(&self) -> String {
+        self.git_config.name.clone()
+    }
+
+    fn upstream(&self) -> String {
+        self.git_config.upstream_url.clone()
+    }
+
+    fn r#type(&self) -> ProviderEnum {
+        ProviderEnum::Git
+    }
+
+    async fn run(&self, started: Sender<Empty>) -> Result<()> {
+        if self.is_running().await {
+            return Err(anyhow!("provider现在正在运行"))
+        }
+        { *self.data_size.lock().await = MirrorSize::default(); }
+        { *self.base_provider.read().await.usage.lock().await = None; }
+        self.terminated.store(false, Ordering::Release);
+
+        let repos = self.repos()?;
+        let working_dir = self.working_dir().await;
+        let mut failed = vec![];
+        for (i, repo) in repos.iter().enumerate() {
+            if self.terminated.load(Ordering::Acquire) {
+                return Err(anyhow!("同步被终止"))
+            }
+            let command = self.repo_command(repo, &working_dir);
+            self.cmd(command, working_dir.clone(), self.git_config.env.clone()).await;
+
+            let mut base_provider_lock = self.base_provider.write().await;
+            base_provider_lock.prepare_log_file(i > 0).await?;
+            drop(base_provider_lock);
+            self.write_log(&format!("==> 同步仓库 {} ({}/{})", repo.url, i + 1, repos.len())).await;
+
+            let mut base_provider_lock = self.base_provider.write().await;
+            base_provider_lock.start().await?;
+            base_provider_lock.is_running.store(true, Ordering::Release);
+            debug!("将is_running字段设置为true :{}", self.name());
+            drop(base_provider_lock);
+            // 只在第一个仓库开始同步时通知job，之后job就可以终止provider了
+            if i == 0 {
+                started.send(()).await.expect("无法发送");
+            }
+
+            let result = self.base_provider.read().await.wait().await;
+            let err = match result {
+                Err(err) => err.to_string(),
+                Ok(0) => continue,
+                Ok(exit_code) => format!("错误状态码： {}", exit_code),
+            };
+            warn!("{} 的仓库 {} 同步失败：{}", self.name(), repo.url, err);
+            self.write_log(&format!("==> 仓库 {} 同步失败：{}", repo.url, err)).await;
+            failed.push(repo.url.clone());
+        }
+
+        match dir_size(Path::new(&working_dir)) {
+            Ok(size) => *self.data_size.lock().await = MirrorSize::from_bytes(size),
+            Err(e) => warn!("统计 {} 的大小失败：{}", working_dir, e),
+        }
+        if self.terminated.load(Ordering::Acquire) {
+            return Err(anyhow!("同步被终止"))
+        }
+        if !failed.is_empty() {
+            return Err(anyhow!("{}/{} 个仓库同步失败：{}", failed.len(), repos.len(), failed.join(", ")))
+        }
+        Ok(())
+    }
+
+    async fn terminate(&self) -> Result<()> {
+        self.terminated.store(true, Ordering::Release);
+        self.base_provider.read().await.terminate().await
+    }
+
+    async fn is_running(&self) -> bool {
+        self.base_provider.read().await.is_running()
+    }
+
+    async fn docker(&self) -> Arc<Option<DockerHook>> {
+        self.base_provider.read().await.docker_ref()
+    }
+
+    async fn add_hook(&mut self, hook: HookType) {
+        self.base_provider.write().await.add_hook(hook).await;
+    }
+
+    async fn hooks(&self) -> Arc<Mutex<Vec<Box<dyn JobHook>>>> {
+        self.base_provider.read().await
+            .hooks()
+    }
+
+    fn interval(&self) -> Duration {
+        self.git_config.interval
+    }
+
+    fn retry(&self) -> i64 {
+        self.git_config.retry
+    }
+
+    fn timeout(&self) -> Duration {
+        self.git_config.timeout
+    }
+
+    async fn working_dir(&self) -> String {
+        self.base_provider.read().await.working_dir().await
+    }
+
+    async fn log_dir(&self) -> String {
+        self.base_provider.read().await.log_dir().await
+    }
+
+    async fn log_file(&self) -> String {
+        self.base_provider.read().await.log_file().await
+    }
+
+    async fn is_master(&self) -> bool {
+        self.base_provider.read().await.is_master
+    }
+
+    async fn data_size(&self) -> MirrorSize {
+        self.data_size.lock().await.clone()
+    }
+
+    async fn resource_usage(&self) -> Option<ResourceUsage> {
+        *self.base_provider.read().await.usage.lock().await
+    }
+
+    async fn enter_context(&mut self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .enter_context().await
+    }
+
+    async fn exit_context(&mut self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .exit_context().await
+    }
+
+    async fn context(&self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .context()
+    }
+}
@@ -8,7 +8,9 @@ use enum_dispatch::enum_dispatch;
 use crate::btrfs_snapshot_hook;
 #[cfg(not(target_os = "linux"))]
 use crate::{btrfs_snapshot_hook_nolinux};
+use crate::atomic_publish_hook::AtomicPublishHook;
 use crate::cgroup::CGroupHook;
+use crate::disk_space_hook::DiskSpaceHook;
 use crate::docker::DockerHook;
 use crate::exec_post_hook::ExecPostHook;
 use crate::loglimit_hook::LogLimiter;
@@ -81,7 +83,9 @@ macro_rules! impl_into_box_for {
 impl_into_box_for!(btrfs_snapshot_hook::BtrfsSnapshotHook);
 #[cfg(not(target_os = "linux"))]
 impl_into_box_for!(btrfs_snapshot_hook_nolinux::BtrfsSnapshotHook);
+impl_into_box_for!(AtomicPublishHook);
 impl_into_box_for!(CGroupHook);
+impl_into_box_for!(DiskSpaceHook);
 impl_into_box_for!(DockerHook);
 impl_into_box_for!(ExecPostHook);
 impl_into_box_for!(LogLimiter);
@@ -94,7 +98,9 @@ pub(crate) enum HookType{
     Btrfs(btrfs_snapshot_hook::BtrfsSnapshotHook),
     #[cfg(not(target_os = "linux"))]
     BtrfsNoLinux(btrfs_snapshot_hook_nolinux::BtrfsSnapshotHook),
+    AtomicPublish(AtomicPublishHook),
     Cgroup(CGroupHook),
+    DiskSpace(DiskSpaceHook),
     Docker(DockerHook),
     ExecPost(ExecPostHook),
     LogLimiter(LogLimiter),
@@ -0,0 +1,498 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Component, Path};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use anyhow::{anyhow, Result};
+use anymap::AnyMap;
+use async_trait::async_trait;
+use chrono::Duration;
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use regex::Regex;
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, ETAG, LAST_MODIFIED};
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{oneshot, Mutex, RwLock, Semaphore};
+use tokio::task::JoinSet;
+use internal::util::dir_size;
+use internal::size::MirrorSize;
+use internal::msg::ResourceUsage;
+use crate::base_provider::BaseProvider;
+use crate::common::{Empty, DEFAULT_MAX_RETRY};
+use crate::config::ProviderEnum;
+use crate::context::Context;
+use crate::docker::DockerHook;
+use crate::hooks::{HookType, JobHook};
+use crate::provider::{MirrorProvider, _LOG_DIR_KEY, _LOG_FILE_KEY, _WORKING_DIR_KEY};
+use crate::rate_limit::TokenBucket;
+use crate::staged_sync::{write_log, StagedSync};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+
+lazy_static! {
+    static ref HREF_REGEX: Regex = Regex::new(r#"(?i)href\s*=\s*["']([^"']+)["']"#).unwrap();
+}
+
+#[derive(Clone, Default, Debug)]
+pub(crate) struct HttpConfig {
+    pub(crate) name: String,
+    pub(crate) upstream_url: String,
+    // 文件列表的地址，每行一个相对于upstream的路径，为空时爬取upstream的目录索引页
+    pub(crate) manifest_url: String,
+    pub(crate) concurrency: usize,
+
+    pub(crate) working_dir: String,
+    pub(crate) log_dir: String,
+    pub(crate) log_file: String,
+
+    pub(crate) interval: Duration,
+    pub(crate) retry: i64,
+    pub(crate) timeout: Duration,
+}
+
+// RemoteFile是上游文件的元信息，来自HEAD或GET的响应头
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct RemoteFile {
+    pub(crate) size: Option<u64>,
+    pub(crate) mtime: Option<String>,
+    pub(crate) etag: Option<String>,
+}
+
+impl RemoteFile {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(|v| v.to_string());
+        RemoteFile {
+            size: header(CONTENT_LENGTH).and_then(|v| v.parse().ok()),
+            mtime: header(LAST_MODIFIED),
+            etag: header(ETAG),
+        }
+    }
+
+    // modified解析Last-Modified，格式如 Wed, 21 Oct 2015 07:28:00 GMT
+    pub(crate) fn modified(&self) -> Option<SystemTime> {
+        self.mtime.as_deref()
+            .and_then(|t| chrono::DateTime::parse_from_rfc2822(t).ok())
+            .map(SystemTime::from)
+    }
+
+    // same判断上游的文件是否发生了变化，有ETag时只比较ETag
+    fn same(&self, other: &RemoteFile) -> bool {
+        if self.etag.is_some() && other.etag.is_some() {
+            return self.etag == other.etag
+        }
+        self.size.is_some() && self.size == other.size && self.mtime == other.mtime
+    }
+}
+
+// local_is_up_to_date判断本地文件是否和上游一致，没有上一次的记录时比较本地文件的大小和修改时间
+fn local_is_up_to_date(local: &Path, old: Option<&RemoteFile>, remote: &RemoteFile) -> bool {
+    let meta = match fs::metadata(local) {
+        Ok(meta) if meta.is_file() => meta,
+        _ => return false,
+    };
+    if remote.size.is_some_and(|size| size != meta.len()) {
+        return false
+    }
+    match old {
+        Some(old) => old.same(remote),
+        None => remote.size.is_some() && remote.modified().is_some_and(|t| meta.modified().ok() == Some(t)),
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(out).ok()
+}
+
+// relative_path返回url相对于base的本地路径，不在base之下或者路径不合法时返回None
+pub(crate) fn relative_path(base: &Url, url: &Url) -> Option<String> {
+    if url.origin() != base.origin() {
+        return None
+    }
+    let path = percent_decode(url.path().strip_prefix(base.path())?)?;
+    let valid = !path.is_empty()
+        && Path::new(&path).components().all(|c| matches!(c, Component::Normal(_)));
+    if valid { Some(path) } else { None }
+}
+
+// parse_index从目录索引页中提取链接，去掉了排序用的参数和锚点
+pub(crate) fn parse_index(page: &Url, html: &str) -> Vec<Url> {
+    HREF_REGEX.captures_iter(html)
+        .filter_map(|cap| {
+            let href = cap[1].replace("&amp;", "&");
+            if href.starts_with('?') || href.starts_with('#') {
+                return None
+            }
+            let mut url = page.join(&href).ok()?;
+            url.set_query(None);
+            url.set_fragment(None);
+            Some(url)
+        })
+        .collect()
+}
+
+// fetch_file检查上游的文件，有变化时下载到staged，返回文件的元信息和是否下载了文件，下载速度受limiter限制
+async fn fetch_file(client: &Client, url: &Url, old: Option<&RemoteFile>, local: &Path, staged: &Path,
+                    limiter: Option<Arc<TokenBucket>>) -> Result<(RemoteFile, bool)> {
+    // 不支持HEAD的上游直接下载
+    let head = client.head(url.clone()).send().await?;
+    if head.status().is_success() {
+        let remote = RemoteFile::from_headers(head.headers());
+        if local_is_up_to_date(local, old, &remote) {
+            return Ok((remote, false))
+        }
+    }
+
+    let mut resp = client.get(url.clone()).send().await?.error_for_status()?;
+    let remote = RemoteFile::from_headers(resp.headers());
+    if let Some(parent) = staged.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = tokio::fs::File::create(staged).await?;
+    let mut written = 0u64;
+    while let Some(chunk) = resp.chunk().await? {
+        if let Some(limiter) = limiter.as_ref() {
+            limiter.acquire(chunk.len() as u64).await;
+        }
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+    file.flush().await?;
+    if remote.size.is_some_and(|size| size != written) {
+        return Err(anyhow!("文件不完整，应为 {} 字节，实际收到 {} 字节", remote.size.unwrap(), written))
+    }
+    if let Some(mtime) = remote.modified() {
+        file.into_std().await.set_modified(mtime)?;
+    }
+    Ok((RemoteFile { size: Some(written), ..remote }, true))
+}
+
+// HttpProvider通过HTTP下载只提供目录索引页或文件列表的上游，在worker进程内完成同步
+#[derive(Clone, Default, Debug)]
+pub(crate) struct HttpProvider {
+    pub(crate) base_provider: Arc<RwLock<BaseProvider>>,
+    pub(crate) http_config: Arc<HttpConfig>,
+    upstream: Arc<Option<Url>>,
+    client: Client,
+    data_size: Arc<Mutex<MirrorSize>>,
+    // 终止同步时通过这个channel通知run
+    cancel: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+}
+
+unsafe impl Send for HttpProvider{}
+unsafe impl Sync for HttpProvider{}
+
+impl HttpProvider {
+    pub(crate) async fn new(mut c: HttpConfig) -> Result<Self>{
+        if c.retry == 0{
+            c.retry = DEFAULT_MAX_RETRY;
+        }
+        if c.concurrency == 0{
+            c.concurrency = DEFAULT_CONCURRENCY;
+        }
+        // 目录地址必须以/结尾，否则相对链接会解析到上一级目录
+        if !c.upstream_url.ends_with('/') {
+            c.upstream_url.push('/');
+        }
+        let upstream = Url::parse(&c.upstream_url)
+            .map_err(|e| anyhow!("upstream {} 不是合法的地址：{}", c.upstream_url, e))?;
+        if upstream.scheme() != "http" && upstream.scheme() != "https" {
+            return Err(anyhow!("http provider只支持http和https，upstream：{}", c.upstream_url))
+        }
+        if !c.manifest_url.is_empty() {
+            upstream.join(&c.manifest_url)
+                .map_err(|e| anyhow!("文件列表 {} 不是合法的地址：{}", c.manifest_url, e))?;
+        }
+
+        let provider = HttpProvider{
+            base_provider: Arc::new(RwLock::new(BaseProvider::new(c.name.clone(), c.interval, c.retry, c.timeout))),
+            http_config: Arc::from(c.clone()),
+            upstream: Arc::new(Some(upstream)),
+            client: Client::builder()
+                .connect_timeout(std::time::Duration::from_secs(30))
+                .user_agent(format!("rtsync/{}", env!("CARGO_PKG_VERSION")))
+                .build()?,
+            ..HttpProvider::default()
+        };
+        let base_provider_lock = provider.base_provider.write().await;
+        if let Some(ctx) = base_provider_lock.ctx.lock().await.as_mut(){
+            let mut value = AnyMap::new();
+            value.insert(c.working_dir);
+            ctx.set(_WORKING_DIR_KEY.to_string(), value);
+
+            let mut value = AnyMap::new();
+            value.insert(c.log_dir);
+            ctx.set(_LOG_DIR_KEY.to_string(), value);
+
+            let mut value = AnyMap::new();
+            value.insert(c.log_file);
+            ctx.set(_LOG_FILE_KEY.to_string(), value);
+        }
+        drop(base_provider_lock);
+        Ok(provider)
+    }
+
+    fn upstream_url(&self) -> &Url {
+        self.upstream.as_ref().as_ref().unwrap()
+    }
+
+    // list_files返回上游所有文件的相对路径和下载地址
+    pub(crate) async fn list_files(&self) -> Result<BTreeMap<String, Url>> {
+        let base = self.upstream_url();
+        let mut files = BTreeMap::new();
+        if !self.http_config.manifest_url.is_empty() {
+            let manifest = base.join(&self.http_config.manifest_url)?;
+            let content = self.client.get(manifest).send().await?.error_for_status()?.text().await?;
+            for line in content.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue
+                }
+                let url = base.join(line)?;
+                match relative_path(base, &url) {
+                    Some(path) => { files.insert(path, url); }
+                    None => return Err(anyhow!("文件列表中的路径不在upstream之下：{}", line)),
+                }
+            }
+            return Ok(files)
+        }
+
+        // 从upstream开始逐层爬取目录索引页，只进入下层目录
+        let mut queue = VecDeque::from([base.clone()]);
+        let mut visited = HashSet::from([base.clone()]);
+        while let Some(page) = queue.pop_front() {
+            debug!("读取目录索引页：{}", page);
+            let html = self.client.get(page.clone()).send().await?.error_for_status()?.text().await?;
+            for link in parse_index(&page, &html) {
+                if link == page || !link.as_str().starts_with(page.as_str()) {
+                    continue
+                }
+                if link.path().ends_with('/') {
+                    if visited.insert(link.clone()) {
+                        queue.push_back(link);
+                    }
+                } else if let Some(path) = relative_path(base, &link) {
+                    files.insert(path, link);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    async fn open_log_file(&self) -> Result<Option<File>> {
+        let log_file = self.log_file().await;
+        if log_file.eq("/dev/null") {
+            return Ok(None)
+        }
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(&log_file)
+            .map_err(|e| anyhow!("无法打开log文件：{} : {}", log_file, e))?;
+        Ok(Some(file))
+    }
+
+    // sync下载有变化的文件到临时目录，全部成功后先替换镜像目录中的文件，再删除上游已经不存在的文件，
+    // 这样上游新的索引文件引用的文件在任何时候都是存在的
+    async fn sync(&self, log: &mut Option<File>) -> Result<()> {
+        let staged_sync = StagedSync::new(&self.working_dir().await, &self.log_dir().await, &self.name(), "http")?;
+        let old_state: BTreeMap<String, RemoteFile> = staged_sync.load_state();
+
+        let files = self.list_files().await?;
+        if files.is_empty() {
+            return Err(anyhow!("上游 {} 中没有任何文件", self.upstream_url()))
+        }
+        write_log(log, &format!("上游共有 {} 个文件", files.len()));
+
+        let limiter = self.base_provider.read().await.bandwidth.clone();
+        let semaphore = Arc::new(Semaphore::new(self.http_config.concurrency));
+        let mut tasks = JoinSet::new();
+        for (path, url) in files.iter() {
+            let (client, semaphore, limiter) = (self.client.clone(), semaphore.clone(), limiter.clone());
+            let (path, url, old) = (path.clone(), url.clone(), old_state.get(path).cloned());
+            let (local, staged) = (staged_sync.local(&path), staged_sync.staged(&path));
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let result = fetch_file(&client, &url, old.as_ref(), &local, &staged, limiter).await;
+                (path, result)
+            });
+        }
+
+        let mut new_state = BTreeMap::new();
+        let mut downloaded = vec![];
+        let mut failed = 0;
+        while let Some(joined) = tasks.join_next().await {
+            let (path, result) = joined?;
+            match result {
+                Ok((remote, true)) => {
+                    write_log(log, &format!("下载 {} ({} 字节)", path, remote.size.unwrap_or_default()));
+                    downloaded.push(path.clone());
+                    new_state.insert(path, remote);
+                }
+                Ok((remote, false)) => { new_state.insert(path, remote); }
+                Err(e) => {
+                    write_log(log, &format!("下载 {} 失败：{}", path, e));
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            staged_sync.abort();
+            return Err(anyhow!("{}/{} 个文件下载失败，镜像未更新", failed, files.len()))
+        }
+
+        staged_sync.install(&downloaded)?;
+        let removed = staged_sync.remove_stale(|path| files.contains_key(path), log)?;
+        staged_sync.save_state(&new_state)?;
+        write_log(log, &format!("下载了 {} 个文件，删除了 {} 个文件", downloaded.len(), removed));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MirrorProvider for HttpProvider {
+    fn name(&self) -> String {
+        self.http_config.name.clone()
+    }
+
+    fn upstream(&self) -> String {
+        self.http_config.upstream_url.clone()
+    }
+
+    fn r#type(&self) -> ProviderEnum {
+        ProviderEnum::Http
+    }
+
+    async fn run(&self, started: Sender<Empty>) -> Result<()> {
+        if self.is_running().await {
+            return Err(anyhow!("provider现在正在运行"))
+        }
+        { *self.data_size.lock().await = MirrorSize::default(); }
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        { *self.cancel.lock().await = Some(cancel_tx); }
+
+        let mut log = self.open_log_file().await?;
+        self.base_provider.read().await.is_running.store(true, Ordering::Release);
+        debug!("将is_running字段设置为true :{}", self.name());
+        started.send(()).await.expect("无法发送");
+
+        let result = tokio::select! {
+            result = self.sync(&mut log) => result,
+            _ = cancel_rx => Err(anyhow!("同步被终止")),
+        };
+        if let Err(e) = result.as_ref() {
+            write_log(&mut log, &format!("同步失败：{}", e));
+        }
+        match dir_size(Path::new(&self.working_dir().await)) {
+            Ok(size) => *self.data_size.lock().await = MirrorSize::from_bytes(size),
+            Err(e) => warn!("统计 {} 的大小失败：{}", self.working_dir().await, e),
+        }
+
+        self.cancel.lock().await.take();
+        self.base_provider.read().await.is_running.store(false, Ordering::Release);
+        debug!("将is_running字段设置为false，调用者：{}", self.name());
+        result
+    }
+
+    async fn terminate(&self) -> Result<()> {
+        debug!("正在终止provider：{}", self.name());
+        if let Some(cancel) = self.cancel.lock().await.take() {
+            let _ = cancel.send(());
+        }
+        Ok(())
+    }
+
+    async fn is_running(&self) -> bool {
+        self.base_provider.read().await.is_running()
+    }
+
+    async fn docker(&self) -> Arc<Option<DockerHook>> {
+        self.base_provider.read().await.docker_ref()
+    }
+
+    async fn add_hook(&mut self, hook: HookType) {
+        self.base_provider.write().await.add_hook(hook).await;
+    }
+
+    async fn hooks(&self) -> Arc<Mutex<Vec<Box<dyn JobHook>>>> {
+        self.base_provider.read().await
+            .hooks()
+    }
+
+    fn interval(&self) -> Duration {
+        self.http_config.interval
+    }
+
+    fn retry(&self) -> i64 {
+        self.http_config.retry
+    }
+
+    fn timeout(&self) -> Duration {
+        self.http_config.timeout
+    }
+
+    async fn working_dir(&self) -> String {
+        self.base_provider.read().await.working_dir().await
+    }
+
+    async fn log_dir(&self) -> String {
+        self.base_provider.read().await.log_dir().await
+    }
+
+    async fn log_file(&self) -> String {
+        self.base_provider.read().await.log_file().await
+    }
+
+    async fn is_master(&self) -> bool {
+        self.base_provider.read().await.is_master
+    }
+
+    async fn data_size(&self) -> MirrorSize {
+        self.data_size.lock().await.clone()
+    }
+
+    // 同步在worker进程内完成，没有单独统计资源消耗
+    async fn resource_usage(&self) -> Option<ResourceUsage> {
+        *self.base_provider.read().await.usage.lock().await
+    }
+
+    async fn set_bandwidth(&self, limiter: Option<Arc<TokenBucket>>) {
+        self.base_provider.write().await.bandwidth = limiter;
+    }
+
+    fn bandwidth_adjustable(&self) -> bool {
+        true
+    }
+
+    async fn enter_context(&mut self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .enter_context().await
+    }
+
+    async fn exit_context(&mut self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .exit_context().await
+    }
+
+    async fn context(&self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .context()
+    }
+}
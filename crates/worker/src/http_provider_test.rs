@@ -0,0 +1,163 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use chrono::Duration;
+    use reqwest::Url;
+    use tempfile::Builder;
+    use tokio::sync::mpsc::channel;
+    use crate::config::ProviderEnum;
+    use crate::http_provider::*;
+    use crate::provider::MirrorProvider;
+    use crate::test_server::{self, Response};
+
+    const MTIME1: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+    const MTIME2: &str = "Thu, 22 Oct 2015 07:28:00 GMT";
+
+    // 路径 -> (状态码, 内容, Last-Modified)
+    type Files = Arc<Mutex<HashMap<String, (u16, Vec<u8>, &'static str)>>>;
+
+    // serve用files模拟上游，返回服务器的地址
+    async fn serve(files: Files) -> String {
+        let addr = test_server::serve(move |req| {
+            let file = files.lock().unwrap().get(&req.path).cloned();
+            let (status, body, mtime) = file.unwrap_or((404, b"not found".to_vec(), ""));
+            Response::new(status, body).header("Last-Modified", mtime)
+        }).await;
+        format!("{}/", addr)
+    }
+
+    #[test]
+    fn test_parse_index(){
+        let base = Url::parse("http://example.com/pub/").unwrap();
+        let html = r#"
+<a href="?C=N;O=D">Name</a>
+<a href="../">Parent Directory</a>
+<a href="sub/">sub/</a>
+<A HREF='a.txt#top'>a.txt</A>
+<a href="my%20file.txt">my file.txt</a>
+<a href="http://other.com/pub/x">x</a>
+"#;
+        let links: Vec<String> = parse_index(&base, html).iter().map(|u| u.to_string()).collect();
+        assert_eq!(links, vec![
+            "http://example.com/",
+            "http://example.com/pub/sub/",
+            "http://example.com/pub/a.txt",
+            "http://example.com/pub/my%20file.txt",
+            "http://other.com/pub/x",
+        ]);
+
+        let rel = |url: &str| relative_path(&base, &Url::parse(url).unwrap());
+        assert_eq!(rel("http://example.com/pub/sub/%E4%B8%AD.txt"), Some("sub/中.txt".to_string()));
+        assert_eq!(rel("http://example.com/pub/my%20file.txt"), Some("my file.txt".to_string()));
+        assert_eq!(rel("http://example.com/other/a.txt"), None);
+        assert_eq!(rel("http://other.com/pub/a.txt"), None);
+        assert_eq!(rel("http://example.com/pub/%2E%2E/a.txt"), None);
+    }
+
+    #[tokio::test]
+    async fn test_http_provider(){
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let tmp_dir_path = tmp_dir.path();
+        let working_dir = tmp_dir_path.join("mirror");
+        let log_file = tmp_dir_path.join("log_file");
+
+        let files: Files = Arc::new(Mutex::new(HashMap::from([
+            ("/pub/".to_string(), (200, br#"<a href="../">up</a><a href="?C=M">sort</a>
+<a href="a.txt">a.txt</a><a href="sub/">sub/</a><a href="my%20file.txt">my file.txt</a>"#.to_vec(), "")),
+            ("/pub/sub/".to_string(), (200, br#"<a href="/pub/">up</a><a href="b.txt">b.txt</a>"#.to_vec(), "")),
+            ("/pub/a.txt".to_string(), (200, b"aaa".to_vec(), MTIME1)),
+            ("/pub/sub/b.txt".to_string(), (200, b"bbbb".to_vec(), MTIME1)),
+            ("/pub/my%20file.txt".to_string(), (200, b"c".to_vec(), MTIME1)),
+            ("/pub/filelist.txt".to_string(), (200, b"# manifest\na.txt\n\nsub/b.txt\n".to_vec(), "")),
+        ])));
+        let addr = serve(files.clone()).await;
+
+        // 镜像目录中原有的文件在上游不存在，会被删除；上游的sub是目录，本地的同名文件会被替换
+        fs::create_dir_all(working_dir.join("old-dir")).unwrap();
+        fs::write(working_dir.join("old-dir/x"), "x").unwrap();
+        fs::write(working_dir.join("old.txt"), "old").unwrap();
+        fs::write(working_dir.join("sub"), "file").unwrap();
+
+        let c = HttpConfig{
+            name: "rt-http".to_string(),
+            upstream_url: format!("{}pub", addr),
+            working_dir: working_dir.display().to_string(),
+            log_dir: tmp_dir_path.display().to_string(),
+            log_file: log_file.display().to_string(),
+            interval: Duration::seconds(600),
+            ..HttpConfig::default()
+        };
+        let provider = HttpProvider::new(c.clone()).await.unwrap();
+        assert_eq!(provider.r#type(), ProviderEnum::Http);
+        assert_eq!(provider.upstream(), format!("{}pub/", addr));
+        assert_eq!(provider.list_files().await.unwrap().keys().collect::<Vec<_>>(), vec!["a.txt", "my file.txt", "sub/b.txt"]);
+
+        provider.run(channel(1).0).await.unwrap();
+        assert_eq!(fs::read_to_string(working_dir.join("a.txt")).unwrap(), "aaa");
+        assert_eq!(fs::read_to_string(working_dir.join("sub/b.txt")).unwrap(), "bbbb");
+        assert_eq!(fs::read_to_string(working_dir.join("my file.txt")).unwrap(), "c");
+        assert!(!working_dir.join("old.txt").exists());
+        assert!(!working_dir.join("old-dir").exists());
+        // 临时目录和状态文件都不在镜像目录中
+        assert!(!tmp_dir_path.join(".mirror.http-tmp").exists());
+        assert!(!tmp_dir_path.join(".http-tmp-rt-http").exists());
+        assert!(tmp_dir_path.join(".http-state-rt-http.json").is_file());
+        assert_eq!(fs::read_dir(&working_dir).unwrap().count(), 3);
+        let mtime = RemoteFile{ mtime: Some(MTIME1.to_string()), ..RemoteFile::default() }.modified().unwrap();
+        assert_eq!(fs::metadata(working_dir.join("a.txt")).unwrap().modified().unwrap(), mtime);
+        assert!(provider.data_size().await.bytes.unwrap() >= 8);
+        let logged_content = fs::read_to_string(&log_file).unwrap();
+        assert!(logged_content.contains("下载 a.txt (3 字节)"));
+        assert!(logged_content.contains("删除 old.txt"));
+
+        // 只下载有变化的文件
+        {
+            let mut files = files.lock().unwrap();
+            files.insert("/pub/sub/b.txt".to_string(), (200, b"bbbbb".to_vec(), MTIME2));
+            files.insert("/pub/".to_string(), (200, br#"<a href="a.txt">a.txt</a><a href="sub/">sub/</a>"#.to_vec(), ""));
+        }
+        provider.run(channel(1).0).await.unwrap();
+        assert_eq!(fs::read_to_string(working_dir.join("sub/b.txt")).unwrap(), "bbbbb");
+        assert!(!working_dir.join("my file.txt").exists());
+        let logged_content = fs::read_to_string(&log_file).unwrap();
+        assert!(!logged_content.contains("下载 a.txt"));
+        assert!(logged_content.contains("下载 sub/b.txt (5 字节)"));
+        assert!(logged_content.contains("删除 my file.txt"));
+
+        // 有文件下载失败时镜像目录保持不变
+        {
+            let mut files = files.lock().unwrap();
+            files.insert("/pub/a.txt".to_string(), (200, b"new".to_vec(), MTIME2));
+            files.insert("/pub/sub/b.txt".to_string(), (500, b"error".to_vec(), MTIME2));
+        }
+        let err = provider.run(channel(1).0).await.unwrap_err();
+        assert_eq!(err.to_string(), "1/2 个文件下载失败，镜像未更新");
+        assert_eq!(fs::read_to_string(working_dir.join("a.txt")).unwrap(), "aaa");
+        assert_eq!(fs::read_to_string(working_dir.join("sub/b.txt")).unwrap(), "bbbbb");
+        assert!(!tmp_dir_path.join(".http-tmp-rt-http").exists());
+
+        // 从文件列表中读取要下载的文件
+        files.lock().unwrap().insert("/pub/sub/b.txt".to_string(), (200, b"bbbbb".to_vec(), MTIME2));
+        let manifest_dir = tmp_dir_path.join("manifest");
+        let provider = HttpProvider::new(HttpConfig{
+            manifest_url: "filelist.txt".to_string(),
+            working_dir: manifest_dir.display().to_string(),
+            ..c.clone()
+        }).await.unwrap();
+        provider.run(channel(1).0).await.unwrap();
+        assert_eq!(fs::read_to_string(manifest_dir.join("a.txt")).unwrap(), "new");
+        assert_eq!(fs::read_to_string(manifest_dir.join("sub/b.txt")).unwrap(), "bbbbb");
+        assert!(!manifest_dir.join("my file.txt").exists());
+
+        // 上游没有任何文件时不删除镜像
+        files.lock().unwrap().insert("/pub/filelist.txt".to_string(), (200, b"# empty\n".to_vec(), ""));
+        assert!(provider.run(channel(1).0).await.is_err());
+        assert!(manifest_dir.join("a.txt").exists());
+
+        assert!(HttpProvider::new(HttpConfig{ upstream_url: "ftp://example.com/".to_string(), ..c }).await.is_err());
+    }
+}
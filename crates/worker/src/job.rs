@@ -1,18 +1,33 @@
 use std::fmt::{Display, Formatter};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use anyhow::{anyhow, Result};
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::time::Duration;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Semaphore};
+use tokio::sync::{mpsc, AcquireError, OwnedSemaphorePermit, Semaphore};
 use tokio::sync::Mutex;
 use log::{debug, error, info, warn};
+use chrono::{DateTime, Utc};
 use crate::common::Empty;
+use crate::config::{GlobalConfig, MirrorConfig};
+use crate::cron_schedule::MirrorSchedule;
+use crate::sync_window::SyncWindow;
+use crate::backoff::RetryPolicy;
+use crate::concurrency::{ConcurrencyGroups, WaitQueue};
+use crate::rate_limit::BandwidthBudget;
+use crate::precheck::{Precheck, UP_TO_DATE};
 use crate::hooks::JobHook;
 use crate::provider::MirrorProvider;
 use internal::status::SyncStatus;
+use internal::size::MirrorSize;
+use internal::msg::ResourceUsage;
 use tokio::sync::mpsc::{Receiver, Sender};
 // 这个文件描述一个mirror job的工作流
 
+// 同步时持有的全局并发许可和并发分组的许可
+type Permits = (OwnedSemaphorePermit, Option<OwnedSemaphorePermit>);
+
 // 控制动作枚举
 #[derive(Debug, Clone)]
 pub(crate) enum JobCtrlAction {
@@ -22,7 +37,7 @@ pub(crate) enum JobCtrlAction {
     Restart,   // 重启同步
     Ping,      // 确保goroutine存活
     Halt,      // worker停止
-    ForceStart, // 忽略并发限制
+    ForceStart, // 忽略并发限制和允许同步的时间段
 }
 
 // Job消息结构
@@ -85,7 +100,35 @@ pub struct MirrorJob {
     disabled_tx: Arc<Mutex<Option<Sender<Empty>>>>,
     pub(crate) disabled_rx: Arc<Mutex<Option<Receiver<Empty>>>>,
     state: Arc<AtomicU32>,
-    pub(crate) size: Arc<Mutex<String>>,
+    pub(crate) size: Arc<Mutex<MirrorSize>>,
+    // 本次尝试同步消耗的资源，没有运行同步命令时为None
+    pub(crate) usage: Arc<Mutex<Option<ResourceUsage>>>,
+    // 配置了schedule时按照cron表达式安排同步，否则每隔interval同步一次
+    pub(crate) schedule: Option<MirrorSchedule>,
+    // 允许开始同步的时间段
+    pub(crate) window: Option<SyncWindow>,
+    // 本次同步是否由 start --force 启动，强制启动的同步不受时间段限制
+    forced: Arc<AtomicBool>,
+    pub(crate) retry_policy: RetryPolicy,
+    // 连续失败的次数，同步成功后清零
+    failures: Arc<AtomicU32>,
+    // 并发分组和分组共享的信号量，分组不限制并发时信号量为None
+    pub(crate) concurrency_group: Option<String>,
+    group_semaphore: Option<Arc<Semaphore>>,
+    // 优先级，越大越先同步；wait_queue让等待全局并发许可的任务按照优先级获取许可
+    pub(crate) priority: i64,
+    wait_queue: Option<Arc<WaitQueue>>,
+    // worker的总带宽和mirror自己的带宽上限（字节每秒），每次尝试同步时分配带宽
+    bandwidth: Option<Arc<BandwidthBudget>>,
+    bandwidth_limit: Option<u64>,
+    // 依赖的mirror，不为空时不按照interval同步，在依赖的mirror都同步成功后开始同步
+    pub(crate) after: Vec<String>,
+    // 上一次开始同步之后已经同步成功的依赖
+    finished_after: Arc<std::sync::Mutex<HashSet<String>>>,
+    // 同步过程中收到了Start（例如依赖的mirror在这次同步的中途又同步成功了），这次同步结束后需要再同步一次
+    rerun: Arc<AtomicBool>,
+    // 同步前检查上游的标记，没有变化时跳过同步
+    precheck: Option<Precheck>,
 }
 impl PartialEq for MirrorJob {
     fn eq(&self, other: &Self) -> bool {
@@ -103,10 +146,90 @@ impl MirrorJob {
             disabled_tx: Arc::new(Mutex::new(None)),
             disabled_rx: Arc::new(Mutex::new(None)),
             state: Arc::new(AtomicU32::new(JobState::None as u32)),
-            size: Arc::new(Mutex::new(String::new())),
+            size: Arc::new(Mutex::new(MirrorSize::default())),
+            usage: Arc::new(Mutex::new(None)),
+            schedule: None,
+            window: None,
+            forced: Arc::new(AtomicBool::new(false)),
+            retry_policy: RetryPolicy::default(),
+            failures: Arc::new(AtomicU32::new(0)),
+            concurrency_group: None,
+            group_semaphore: None,
+            priority: 0,
+            wait_queue: None,
+            bandwidth: None,
+            bandwidth_limit: None,
+            after: vec![],
+            finished_after: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            rerun: Arc::new(AtomicBool::new(false)),
+            precheck: None,
+        }
+    }
+
+    // set_config设置mirror配置中与调度有关的选项，这些选项在创建provider时已经检查过
+    pub(crate) fn set_config(&mut self, mirror: &MirrorConfig, global: &GlobalConfig,
+                             groups: &ConcurrencyGroups, bandwidth: &Arc<BandwidthBudget>) {
+        self.schedule = mirror.cron_schedule().unwrap_or_default();
+        self.window = mirror.sync_window(global).unwrap_or_default();
+        self.retry_policy = mirror.retry_policy(global).unwrap_or_default();
+        self.concurrency_group = mirror.concurrency_group();
+        self.group_semaphore = self.concurrency_group.as_deref().and_then(|group| groups.semaphore(group));
+        self.priority = mirror.priority.unwrap_or_default();
+        self.wait_queue = Some(groups.queue.clone());
+        self.bandwidth = Some(bandwidth.clone());
+        self.bandwidth_limit = mirror.bandwidth_limit_bytes().unwrap_or_default();
+        self.after = mirror.after.clone().unwrap_or_default();
+        self.precheck = mirror.precheck().unwrap_or_default();
+    }
+
+    // next_sync_time_after_failure返回同步失败后的下一次同步时间，配置了fail_interval时提前再次同步
+    pub(crate) fn next_sync_time_after_failure(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let next = self.next_sync_time(now);
+        let interval = self.provider.interval().to_std().unwrap_or_default();
+        match self.retry_policy.fail_delay(self.failures.load(Ordering::SeqCst), interval)
+            .and_then(|delay| chrono::Duration::from_std(delay).ok()) {
+            Some(delay) => next.min(now + delay),
+            None => next,
         }
     }
 
+    // dependency_finished记录依赖的mirror同步成功，所有依赖都同步成功时返回true，并重新开始记录
+    pub(crate) fn dependency_finished(&self, name: &str) -> bool {
+        if !self.after.iter().any(|dep| dep == name) {
+            return false
+        }
+        let mut finished = self.finished_after.lock().unwrap();
+        finished.insert(name.to_string());
+        if self.after.iter().all(|dep| finished.contains(dep)) {
+            finished.clear();
+            return true
+        }
+        false
+    }
+
+    // precheck_state_file是保存上一次同步成功时上游标记的文件，以点开头避免被日志轮转删除
+    async fn precheck_state_file(&self) -> PathBuf {
+        Path::new(&self.provider.log_dir().await).join(format!(".precheck-{}", self.name()))
+    }
+
+    pub(crate) fn forced(&self) -> bool {
+        self.forced.load(Ordering::SeqCst)
+    }
+
+    // deferred_until在now不允许开始同步时返回下一个允许同步的时间
+    pub(crate) fn deferred_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.window.as_ref()
+            .filter(|window| !window.allows(now))
+            .and_then(|window| window.next_allowed(now))
+    }
+
+    // next_sync_time返回after之后的下一次同步时间
+    pub(crate) fn next_sync_time(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        self.schedule.as_ref()
+            .and_then(|schedule| schedule.next_after(after))
+            .unwrap_or_else(|| after + self.provider.interval())
+    }
+
     pub fn name(&self) -> String {
         self.provider.name()
     }
@@ -236,6 +359,8 @@ impl MirrorJob {
         mut kill: Receiver<Empty>,
         job_done: Sender<Empty>,
         manager_chan: Sender<JobMessage>,
+        semaphore: Arc<Semaphore>,
+        mut permits: Option<Permits>,
     ) -> Result<()> 
     {
         scopeguard::defer!{
@@ -251,6 +376,30 @@ impl MirrorJob {
         }).await?;
         info!("开始同步: {}", self.name());
 
+        // 检查上游的标记，和上一次同步成功时相同就不再同步，强制启动的同步不检查，总是同步
+        let mut marker = None;
+        if let Some(precheck) = self.precheck.as_ref().filter(|_| !self.forced()) {
+            match precheck.probe().await {
+                Ok(current) => {
+                    let state_file = self.precheck_state_file().await;
+                    if Precheck::last_marker(&state_file).as_deref() == Some(current.as_str()) {
+                        info!("{} 的上游没有更新，跳过同步", self.name());
+                        self.failures.store(0, Ordering::SeqCst);
+                        { *self.usage.lock().await = None; }
+                        manager_chan.send(JobMessage {
+                            status: SyncStatus::Success,
+                            name: self.name(),
+                            msg: UP_TO_DATE.to_string(),
+                            schedule: self.state() == JobState::Ready,
+                        }).await?;
+                        return Ok(());
+                    }
+                    marker = Some((state_file, current));
+                }
+                Err(e) => warn!("检查 {} 的上游是否有更新失败，继续同步: {}", self.name(), e),
+            }
+        }
+
         let hooks = self.provider.hooks().await;
 
         // Pre-job hooks
@@ -262,8 +411,31 @@ impl MirrorJob {
         // 非强制终止类型的同步失败发生时，会根据retry再次尝试同步
         for retry in 0..self.provider.retry() {
             let mut stop_asap = false;  // stop job as soon as possible
+            { *self.usage.lock().await = None; }
 
             if retry > 0 {
+                let delay = self.retry_policy.retry_delay(retry as u32);
+                if !delay.is_zero() {
+                    info!("{} 在 {:?} 后重试同步", self.name(), delay);
+                    // 等待重试时归还并发许可，让其他任务先同步，等待结束后重新获取；忽略了并发限制的任务没有许可
+                    let released = permits.take().is_some();
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        None = kill.recv() => {
+                            debug!("等待重试时收到终止信号");
+                            return Ok(());
+                        }
+                    }
+                    if released {
+                        tokio::select! {
+                            acquired = self.acquire_permits(semaphore.clone()) => permits = Some(acquired?),
+                            None = kill.recv() => {
+                                debug!("等待重试时收到终止信号");
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
                 info!("重试同步: {}, 重试次数: {}", self.name(), retry);
             }
 
@@ -278,6 +450,11 @@ impl MirrorJob {
                 schedule: false,
             }).await?;
 
+            // 每次尝试同步时分配带宽，同步结束后归还，其他任务分到的带宽随之调整
+            let adjustable = self.provider.bandwidth_adjustable();
+            let bandwidth = self.bandwidth.as_ref().and_then(|budget| budget.join(self.bandwidth_limit, adjustable));
+            self.provider.set_bandwidth(bandwidth.as_ref().map(|share| share.limiter.clone())).await;
+
             let (sync_done_tx, mut sync_done_rx) = mpsc::channel(1);
             let (started_tx, mut started_rx) = mpsc::channel(10); // we may receive "started" more than one time (e.g. two_stage_rsync)
             
@@ -340,6 +517,9 @@ impl MirrorJob {
                 return Err(e.into());
             }
 
+            drop(bandwidth);
+            { *self.usage.lock().await = self.provider.resource_usage().await; }
+
             // post-exec hooks
             self.run_hooks(Arc::clone(&hooks), HookAction::PostExec, &manager_chan).await?;
 
@@ -351,6 +531,12 @@ impl MirrorJob {
                 self.run_hooks(Arc::clone(&hooks), HookAction::PostSuccess, &manager_chan).await?;
 
                 { *self.size.lock().await = self.provider.data_size().await; }
+                self.failures.store(0, Ordering::SeqCst);
+                if let Some((state_file, marker)) = &marker {
+                    if let Err(e) = Precheck::save_marker(state_file, marker) {
+                        warn!("保存 {} 的上游标记失败: {}", self.name(), e);
+                    }
+                }
                 manager_chan.send(JobMessage {
                     status: SyncStatus::Success,
                     name: self.name(),
@@ -364,6 +550,9 @@ impl MirrorJob {
                 debug!("post-fail hooks");
 
                 self.run_hooks(Arc::clone(&hooks), HookAction::PostFail,&manager_chan).await?;
+                if retry == self.provider.retry() - 1 && !stop_asap {
+                    self.failures.fetch_add(1, Ordering::SeqCst);
+                }
 
                 manager_chan.send(JobMessage {
                     status: SyncStatus::Failed,
@@ -385,6 +574,20 @@ impl MirrorJob {
         Ok(())
     }
 
+    // 先获取并发分组的许可再获取全局的许可，避免等待同一上游的任务占用全局的许可
+    // 全局的许可按照优先级获取
+    async fn acquire_permits(&self, semaphore: Arc<Semaphore>) -> Result<Permits, AcquireError> {
+        let group_permit = match self.group_semaphore.clone() {
+            Some(group_semaphore) => Some(group_semaphore.acquire_owned().await?),
+            None => None,
+        };
+        let permit = match self.wait_queue.as_ref() {
+            Some(wait_queue) => wait_queue.acquire(semaphore, self.priority).await?,
+            None => semaphore.acquire_owned().await?,
+        };
+        Ok((permit, group_permit))
+    }
+
     async fn run_job(
         &self,
         mut kill: Receiver<Empty>,
@@ -395,19 +598,16 @@ impl MirrorJob {
     ) 
     {
         let mut bypass_semaphore_lock = bypass_semaphore_rx.lock().await;
-        
+
         tokio::select! {
-            Ok(permit) = semaphore.acquire() => {
+            Ok(permits) = self.acquire_permits(semaphore.clone()) => {
                 drop(bypass_semaphore_lock);
-                let _ = self.run_job_wrapper(kill, job_done, manager_chan).await;
-                scopeguard::defer! {
-                    drop(permit);
-                }
+                let _ = self.run_job_wrapper(kill, job_done, manager_chan, semaphore, Some(permits)).await;
             }
             Some(_) = bypass_semaphore_lock.recv() => {
                 drop(bypass_semaphore_lock);
                 warn!("{} 忽略了并发限制", self.name());
-                let _ = self.run_job_wrapper(kill, job_done, manager_chan).await;
+                let _ = self.run_job_wrapper(kill, job_done, manager_chan, semaphore, None).await;
             }
             None = kill.recv() => {
                 drop(bypass_semaphore_lock);
@@ -497,6 +697,7 @@ impl MirrorJob {
                                 }
                                 JobCtrlAction::Restart => {
                                     self.set_state(JobState::Ready);
+                                    self.rerun.store(false, Ordering::SeqCst);
                                     drop(kill_tx);
                                     let _ = job_done_rx.recv().await;
                                     // 等待 1 秒，防止重启时任务还未完全退出。
@@ -505,11 +706,13 @@ impl MirrorJob {
                                 }
                                 JobCtrlAction::ForceStart => {
                                     let _ = bypass_semaphore_tx.try_send(());
+                                    self.forced.store(true, Ordering::SeqCst);
                                     self.set_state(JobState::Ready);
                                     continue 'wait_for_job;
                                 }
                                 JobCtrlAction::Start => {
                                     self.set_state(JobState::Ready);
+                                    self.rerun.store(true, Ordering::SeqCst);
                                     continue 'wait_for_job;
                                 }
                                 JobCtrlAction::Halt => {
@@ -528,6 +731,13 @@ impl MirrorJob {
                 }// wait_for_job 循环
             }
 
+            // 同步过程中收到的Start在这次同步结束后执行，被停止或禁用的任务不再同步
+            if self.rerun.swap(false, Ordering::SeqCst) && self.state() == JobState::Ready {
+                debug!("{} 在同步过程中收到了Start，再同步一次", self.name());
+                self.forced.store(false, Ordering::SeqCst);
+                continue 'whole_syncing;
+            }
+
             // 一次wait_for_job完成后，将阻塞等待ctrl_chan的下一次的动作信号，没有信号不会开始
             if let Some(ctrl) = self.ctrl_chan_rx.lock().await.recv().await {
                 match ctrl {
@@ -541,9 +751,11 @@ impl MirrorJob {
                     JobCtrlAction::ForceStart => {
                         //non-blocking
                         let _ = bypass_semaphore_tx.try_send(());
+                        self.forced.store(true, Ordering::SeqCst);
                         self.set_state(JobState::Ready);
                     }
                     JobCtrlAction::Restart | JobCtrlAction::Start => {
+                        self.forced.store(false, Ordering::SeqCst);
                         self.set_state(JobState::Ready);
                     }
                     _ => return Ok(()),
@@ -634,23 +634,135 @@ echo $RTSYNC_WORKING_DIR
         }
         
     }
-}
-
-
-
-
-
-
-
-
-
-
-
 
+    #[tokio::test]
+    async fn test_release_permits_while_waiting_for_retry(){
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let tmp_dir_path = tmp_dir.path();
+        let new_job = |name: &str, exit_code: i32| {
+            let script_file_path = tmp_dir_path.join(format!("{name}.sh"));
+            fs::write(&script_file_path, format!("#!/bin/bash\nexit {exit_code}\n")).expect("failed to write to tmp file");
+            CmdConfig{
+                name: name.to_string(),
+                upstream_url: "http://mirrors.tuna.moe/".to_string(),
+                command: script_file_path.display().to_string(),
+                working_dir: tmp_dir_path.display().to_string(),
+                log_dir: tmp_dir_path.display().to_string(),
+                log_file: "/dev/null".to_string(),
+                interval: Duration::seconds(10),
+                retry: 2,
+                ..CmdConfig::default()
+            }
+        };
+        let mut failing = MirrorJob::new(Box::new(CmdProvider::new(new_job("failing", 1)).await.unwrap()));
+        failing.retry_policy.delay = time::Duration::from_secs(4);
+        failing.retry_policy.max_delay = time::Duration::from_secs(4);
+        let other = MirrorJob::new(Box::new(CmdProvider::new(new_job("other", 0)).await.unwrap()));
 
+        let (manager_tx, mut manager_rx) = channel::<JobMessage>(10);
+        let semaphore = Arc::new(Semaphore::new(1));
+        for job in [&failing, &other] {
+            let (job, manager_tx, semaphore) = (job.clone(), manager_tx.clone(), semaphore.clone());
+            tokio::spawn(async move { job.run(manager_tx, semaphore).await });
+        }
 
+        failing.ctrl_chan_tx.send(JobCtrlAction::Start).await.unwrap();
+        loop {
+            let msg = manager_rx.recv().await.unwrap();
+            if msg.name == "failing" && msg.status == SyncStatus::Failed {
+                break
+            }
+        }
+        // failing在等待重试时不占用许可，other不需要等它重试结束
+        other.ctrl_chan_tx.send(JobCtrlAction::Start).await.unwrap();
+        let msg = tokio::time::timeout(time::Duration::from_secs(1), async {
+            loop {
+                let msg = manager_rx.recv().await.unwrap();
+                if msg.name == "other" && msg.status == SyncStatus::Success {
+                    return msg
+                }
+            }
+        }).await.expect("other should not wait for the retry backoff");
+        assert_eq!(msg.status, SyncStatus::Success);
 
+        // 重试时failing重新获取许可
+        loop {
+            let msg = manager_rx.recv().await.unwrap();
+            if msg.name == "failing" && msg.status == SyncStatus::Failed {
+                assert!(msg.schedule);
+                break
+            }
+        }
+        for job in [&failing, &other] {
+            job.ctrl_chan_tx.send(JobCtrlAction::Disable).await.unwrap();
+        }
+    }
 
+    #[tokio::test]
+    async fn test_start_while_syncing(){
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let tmp_dir_path = tmp_dir.path();
+        let script_file_path = tmp_dir_path.join("cmd.sh");
+        fs::write(&script_file_path, "#!/bin/bash\nsleep 1\n").expect("failed to write to tmp file");
+        let c = CmdConfig{
+            name: "debian-subset".to_string(),
+            upstream_url: "http://mirrors.tuna.moe/".to_string(),
+            command: script_file_path.display().to_string(),
+            working_dir: tmp_dir_path.display().to_string(),
+            log_dir: tmp_dir_path.display().to_string(),
+            log_file: "/dev/null".to_string(),
+            interval: Duration::seconds(10),
+            ..CmdConfig::default()
+        };
+        let job = MirrorJob::new(Box::new(CmdProvider::new(c).await.unwrap()));
+        let (manager_tx, mut manager_rx) = channel::<JobMessage>(10);
+        let semaphore = Arc::new(Semaphore::new(1));
+        let job_ = job.clone();
+        tokio::spawn(async move { job_.run(manager_tx, semaphore).await });
 
+        job.ctrl_chan_tx.send(JobCtrlAction::Start).await.unwrap();
+        loop {
+            if manager_rx.recv().await.unwrap().status == SyncStatus::Syncing {
+                break
+            }
+        }
+        // 同步过程中依赖的mirror又同步成功，worker再次发送Start，这次同步结束后应当再同步一次
+        job.ctrl_chan_tx.send(JobCtrlAction::Start).await.unwrap();
+        let statuses = tokio::time::timeout(time::Duration::from_secs(5), async {
+            let mut statuses = vec![];
+            while statuses.iter().filter(|s| **s == SyncStatus::Success).count() < 2 {
+                statuses.push(manager_rx.recv().await.unwrap().status);
+            }
+            statuses
+        }).await.expect("the job should sync again after the current run");
+        assert_eq!(statuses, vec![SyncStatus::Success, SyncStatus::PreSyncing, SyncStatus::Syncing, SyncStatus::Success]);
 
+        // 只再同步一次
+        assert!(tokio::time::timeout(time::Duration::from_millis(1500), manager_rx.recv()).await.is_err());
+        job.ctrl_chan_tx.send(JobCtrlAction::Disable).await.unwrap();
+    }
 
+    #[tokio::test]
+    async fn test_dependency_finished(){
+        let c = CmdConfig{
+            name: "debian-subset".to_string(),
+            ..CmdConfig::default()
+        };
+        let provider = CmdProvider::new(c).await.unwrap();
+        let mut job = MirrorJob::new(Box::new(provider));
+        job.after = vec!["debian".to_string(), "debian-index".to_string()];
+
+        assert!(!job.dependency_finished("ubuntu"));
+        // 所有依赖都同步成功后才开始同步
+        assert!(!job.dependency_finished("debian"));
+        assert!(!job.dependency_finished("debian"));
+        assert!(job.dependency_finished("debian-index"));
+        // 开始同步后重新记录
+        assert!(!job.dependency_finished("debian-index"));
+        assert!(job.dependency_finished("debian"));
+    }
+}
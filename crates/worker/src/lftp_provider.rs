@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use anyhow::{anyhow, Result};
+use std::{fs, io};
+use std::fs::Permissions;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::process::Command;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{Mutex, RwLock};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use anymap::AnyMap;
+use chrono::Duration;
+use log::{debug, error, warn};
+use internal::util::{dir_size, translate_lftp_error};
+use internal::size::MirrorSize;
+use internal::msg::ResourceUsage;
+use crate::base_provider::BaseProvider;
+use crate::common::{Empty, DEFAULT_MAX_RETRY};
+use crate::config::ProviderEnum;
+use crate::context::Context;
+use crate::docker::DockerHook;
+use crate::hooks::{HookType, JobHook};
+use crate::provider::{MirrorProvider, _LOG_DIR_KEY, _LOG_FILE_KEY, _WORKING_DIR_KEY};
+use crate::rate_limit::TokenBucket;
+use crate::runner::CmdJob;
+use async_trait::async_trait;
+
+#[derive(Clone, Default, Debug)]
+pub(crate) struct LftpConfig {
+    pub(crate) name: String,
+    pub(crate) lftp_cmd: String,
+
+    pub(crate) upstream_url: String,
+    pub(crate) username: String,
+    pub(crate) password: String,
+    pub(crate) exclude_file: String,
+    pub(crate) exclude: Vec<String>,
+
+    // 同时传输的文件数，0表示使用lftp的默认值
+    pub(crate) parallel: u32,
+    // None表示使用lftp的默认值（被动模式）
+    pub(crate) passive: Option<bool>,
+    pub(crate) extra_options: Vec<String>,
+    // 先下载到临时文件再改名，不直接改写已有的文件，atomic发布时用硬链接初始化的staging目录需要这样
+    pub(crate) use_temp_file: bool,
+    pub(crate) lftp_env: HashMap<String, String>,
+
+    pub(crate) working_dir: String,
+    pub(crate) log_dir: String,
+    pub(crate) log_file: String,
+
+    pub(crate) use_ipv6: bool,
+    pub(crate) use_ipv4: bool,
+
+    pub(crate) interval: Duration,
+    pub(crate) retry: i64,
+    pub(crate) timeout: Duration,
+}
+
+// lftp_quote把参数用双引号括起来，作为lftp命令中的一个参数
+fn lftp_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// LftpProvider使用 lftp mirror 同步只提供FTP的上游
+#[derive(Clone, Default, Debug)]
+pub(crate) struct LftpProvider {
+    pub(crate) base_provider: Arc<RwLock<BaseProvider>>,
+    pub(crate) lftp_config: Arc<LftpConfig>,
+    data_size: Arc<Mutex<MirrorSize>>,
+}
+
+unsafe impl Send for LftpProvider{}
+unsafe impl Sync for LftpProvider{}
+
+impl LftpProvider {
+    pub(crate) async fn new(mut c: LftpConfig) -> Result<Self> {
+        if !c.upstream_url.ends_with('/'){
+            return Err(anyhow!("lftp上游URL应该以'/'结尾"));
+        }
+        if c.retry == 0{
+            c.retry = DEFAULT_MAX_RETRY;
+        }
+        if c.lftp_cmd.is_empty() {
+            c.lftp_cmd = "lftp".to_string();
+        }
+        // 密码通过环境变量传给lftp，避免出现在进程的命令行中
+        if !c.password.is_empty(){
+            c.lftp_env.insert("LFTP_PASSWORD".to_string(), c.password.clone());
+        }
+
+        let provider = LftpProvider{
+            base_provider: Arc::new(RwLock::new(BaseProvider::new(c.name.clone(), c.interval, c.retry, c.timeout))),
+            lftp_config: Arc::from(c.clone()),
+            ..LftpProvider::default()
+        };
+        if let Some(ctx) = provider.base_provider.write().await.context().lock().await.as_mut(){
+            let mut value = AnyMap::new();
+            value.insert(c.working_dir);
+            ctx.set(_WORKING_DIR_KEY.to_string(), value);
+
+            let mut value = AnyMap::new();
+            value.insert(c.log_dir);
+            ctx.set(_LOG_DIR_KEY.to_string(), value);
+
+            let mut value = AnyMap::new();
+            value.insert(c.log_file);
+            ctx.set(_LOG_FILE_KEY.to_string(), value);
+        }
+        Ok(provider)
+    }
+
+    // script生成传给 lftp -c 的命令
+    pub(crate) fn script(&self, working_dir: &str) -> String {
+        let c = self.lftp_config.as_ref();
+        // lftp默认会无限重试，这里限制重试次数，失败交给job的重试处理
+        let mut commands = vec![
+            "set net:max-retries 3".to_string(),
+            "set net:timeout 120".to_string(),
+        ];
+        if let Some(passive) = c.passive {
+            commands.push(format!("set ftp:passive-mode {}", if passive { "on" } else { "off" }));
+        }
+        if c.use_temp_file {
+            commands.push("set xfer:use-temp-file on".to_string());
+        }
+        if c.use_ipv6 {
+            commands.push("set dns:order \"inet6\"".to_string());
+        } else if c.use_ipv4 {
+            commands.push("set dns:order \"inet\"".to_string());
+        }
+        if c.username.is_empty() {
+            commands.push(format!("open {}", lftp_quote(&c.upstream_url)));
+        } else if c.password.is_empty() {
+            commands.push(format!("open -u {} {}", lftp_quote(&c.username), lftp_quote(&c.upstream_url)));
+        } else {
+            commands.push(format!("open --env-password -u {} {}", lftp_quote(&c.username), lftp_quote(&c.upstream_url)));
+        }
+
+        // 删除上游已经不存在的文件
+        let mut mirror = vec!["mirror".to_string(), "--verbose".to_string(), "--delete".to_string(), "--no-perms".to_string()];
+        if c.parallel > 0 {
+            mirror.push(format!("--parallel={}", c.parallel));
+        }
+        for pattern in c.exclude.iter() {
+            mirror.push("--exclude-glob".to_string());
+            mirror.push(lftp_quote(pattern));
+        }
+        if !c.exclude_file.is_empty() {
+            mirror.push(format!("--exclude-glob-from={}", lftp_quote(&c.exclude_file)));
+        }
+        mirror.extend(c.extra_options.iter().cloned());
+        mirror.push(".".to_string());
+        mirror.push(lftp_quote(working_dir));
+        commands.push(mirror.join(" "));
+        commands.join("; ")
+    }
+
+    /// cmd配置base_provider字段中的cmd字段
+    async fn cmd(&self){
+        let working_dir = self.working_dir().await;
+        let mut script = self.script(&working_dir);
+        // lftp运行时不能调整速度，使用开始同步时分到的带宽
+        if let Some(limiter) = self.base_provider.read().await.bandwidth.as_ref() {
+            script = format!("set net:limit-total-rate {}; {}", limiter.rate(), script);
+        }
+        let command = [self.lftp_config.lftp_cmd.clone(), "-c".to_string(), script];
+        let env = self.lftp_config.lftp_env.clone();
+
+        let cmd_job: CmdJob;
+        let mut base_provider_lock = self.base_provider.write().await;
+        let use_docker = base_provider_lock.docker_ref().is_some();
+
+        if let Some(d) = base_provider_lock.docker_ref().as_ref(){
+            // 容器由BaseProvider通过docker API创建，这里只记录容器的配置
+            let spec = d.container_spec(self.name(), command.to_vec(), env.clone(),
+                                        working_dir.clone(), base_provider_lock.docker_volumes().await);
+            cmd_job = CmdJob::new_container(spec, working_dir.clone(), env.clone());
+        }else {
+            cmd_job = CmdJob::new(Command::new(&command[0]), working_dir.clone(), env.clone());
+            { cmd_job.cmd.lock().await.args(&command[1..]); }
+        }
+
+        if !use_docker {
+            debug!("在 {} 位置执行 {} 命令", &working_dir, command[0]);
+
+            // 如果目录不存在，则创建目录
+            if let Err(err) = fs::read_dir(&working_dir) {
+                if err.kind() == io::ErrorKind::NotFound {
+                    debug!("创建文件夹：{}", &working_dir);
+                    if fs::create_dir_all(&working_dir).is_ok(){
+                        if fs::set_permissions(&working_dir, Permissions::from_mode(0o755)).is_err() {
+                            error!("更改文件夹 {} 权限失败: {}",&working_dir, err)
+                        }
+                    }else {
+                        error!("创建文件夹 {} 失败: {}", &working_dir, err)
+                    }
+                }
+            }
+            {
+                cmd_job.cmd
+                    .lock().await
+                    .current_dir(&working_dir)
+                    .envs(crate::runner::new_environ(env, true));
+            }
+        }
+
+        base_provider_lock.cmd = Some(cmd_job);
+        drop(base_provider_lock)
+    }
+}
+
+#[async_trait]
+impl MirrorProvider for LftpProvider {
+    fn name(&self) -> String {
+        self.lftp_config.name.clone()
+    }
+
+    fn upstream(&self) -> String {
+        self.lftp_config.upstream_url.clone()
+    }
+
+    fn r#type(&self) -> ProviderEnum {
+        ProviderEnum::Lftp
+    }
+
+    // 运行lftp并等待结果，lftp不输出镜像的总大小，成功后统计工作目录的大小
+    async fn run(&self, started: Sender<Empty>) -> Result<()> {
+        { *self.data_size.lock().await = MirrorSize::default(); }
+        { *self.base_provider.read().await.usage.lock().await = None; }
+
+        MirrorProvider::start(self).await?;
+
+        started.send(()).await.expect("发送失败");
+
+        let base_provider = self.base_provider.read().await;
+        let result = base_provider.wait().await;
+        drop(base_provider);
+
+        match result {
+            Err(err) => return Err(anyhow!(err)),
+            Ok(exit_code) if exit_code != 0 => {
+                let msg = translate_lftp_error(exit_code, &self.log_file().await)
+                    .unwrap_or_else(|| format!("错误状态码： {}", exit_code));
+                debug!("lftp 异常终止： {} ({})", exit_code, msg);
+                if let Some(log_file_fd) = self.base_provider.write().await.log_file_fd.as_mut() {
+                    log_file_fd.write_all(format!("{}\n", msg).as_bytes())?;
+                }
+                return Err(anyhow!(msg));
+            }
+            _ => {}
+        }
+
+        let working_dir = self.working_dir().await;
+        match dir_size(Path::new(&working_dir)) {
+            Ok(size) => *self.data_size.lock().await = MirrorSize::from_bytes(size),
+            Err(e) => warn!("统计 {} 的大小失败：{}", working_dir, e),
+        }
+        Ok(())
+    }
+
+    async fn start(&self) -> Result<()> {
+        if self.is_running().await{
+            return Err(anyhow!("provider现在正在运行"))
+        }
+
+        self.cmd().await;
+
+        let mut base_provider_lock = self.base_provider.write().await;
+        if let Err(e) = base_provider_lock.prepare_log_file(false).await{
+            error!("prepare_log_file 失败{}", e);
+            return Err(e);
+        }
+        if let Err(e) = base_provider_lock.start().await{
+            error!("start失败: {}", e);
+            return Err(e);
+        }
+        base_provider_lock.is_running.store(true, Ordering::Release);
+        debug!("将is_running字段设置为true :{}", self.name());
+
+        drop(base_provider_lock);
+        Ok(())
+    }
+
+    async fn terminate(&self) -> Result<()> {
+        self.base_provider.read().await.terminate().await
+    }
+
+    async fn is_running(&self) -> bool {
+        self.base_provider.read().await.is_running()
+    }
+
+    async fn docker(&self) -> Arc<Option<DockerHook>> {
+        self.base_provider.read().await.docker_ref()
+    }
+
+    async fn add_hook(&mut self, hook: HookType) {
+        self.base_provider.write().await.add_hook(hook).await;
+    }
+
+    async fn hooks(&self) -> Arc<Mutex<Vec<Box<dyn JobHook>>>> {
+        self.base_provider.read().await
+            .hooks()
+    }
+
+    fn interval(&self) -> Duration {
+        self.lftp_config.interval
+    }
+
+    fn retry(&self) -> i64 {
+        self.lftp_config.retry
+    }
+
+    fn timeout(&self) -> Duration {
+        self.lftp_config.timeout
+    }
+
+    async fn working_dir(&self) -> String {
+        self.base_provider.read().await.working_dir().await
+    }
+
+    async fn log_dir(&self) -> String {
+        self.base_provider.read().await.log_dir().await
+    }
+
+    async fn log_file(&self) -> String {
+        self.base_provider.read().await.log_file().await
+    }
+
+    async fn is_master(&self) -> bool {
+        self.base_provider.read().await.is_master
+    }
+
+    async fn data_size(&self) -> MirrorSize {
+        self.data_size.lock().await.clone()
+    }
+
+    async fn resource_usage(&self) -> Option<ResourceUsage> {
+        *self.base_provider.read().await.usage.lock().await
+    }
+
+    async fn set_bandwidth(&self, limiter: Option<Arc<TokenBucket>>) {
+        self.base_provider.write().await.bandwidth = limiter;
+    }
+
+    async fn enter_context(&mut self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .enter_context().await
+    }
+
+    async fn exit_context(&mut self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .exit_context().await
+    }
+
+    async fn context(&self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .context()
+    }
+}
@@ -6,17 +6,31 @@ mod common;
 mod cgroup;
 mod hooks;
 mod zfs_hook;
+mod disk_space_hook;
 mod context;
 mod cmd_provider;
 mod base_provider;
 mod runner;
 mod docker;
+mod docker_api;
 mod config_diff;
 mod schedule;
+mod cron_schedule;
+mod sync_window;
+mod backoff;
+mod concurrency;
+mod precheck;
 mod rsync_provider;
 mod two_stage_rsync_provider;
+mod git_provider;
+mod http_provider;
+mod lftp_provider;
+mod s3_provider;
+mod staged_sync;
+mod rate_limit;
 mod loglimit_hook;
 mod exec_post_hook;
+mod atomic_publish_hook;
 mod btrfs_snapshot_hook_nolinux;
 mod btrfs_snapshot_hook;
 mod job;
@@ -26,7 +40,11 @@ mod provider_test;
 mod docker_test;
 mod exec_post_test;
 mod zfs_hook_test;
+mod disk_space_hook_test;
 mod cgroup_test;
+mod http_provider_test;
+mod s3_provider_test;
+mod test_server;
 mod job_test;
 mod loglimit_test;
 mod worker_test;
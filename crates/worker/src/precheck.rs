@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use reqwest::header::{ETAG, LAST_MODIFIED};
+use reqwest::{Client, Url};
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+use crate::config::PrecheckConfig;
+
+// 上游没有变化、跳过同步时上报的消息
+pub(crate) const UP_TO_DATE: &str = "up to date";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// MarkerKind决定用上游的哪个信息判断是否有变化
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MarkerKind {
+    // http上游依次使用ETag、Last-Modified、文件内容，其他上游使用输出的内容
+    Auto,
+    Content,
+    Etag,
+    Mtime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Source {
+    // 标记文件，如 lastsync、TIME 或者 Release
+    Http(Url),
+    // rsync --list-only 的输出
+    Rsync(String),
+    // 命令的输出
+    Command(String),
+}
+
+// Precheck在同步前检查上游的标记，标记和上一次同步成功时相同时跳过同步
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Precheck {
+    source: Source,
+    kind: MarkerKind,
+    timeout: Duration,
+    // 运行rsync和命令时的环境变量，如 RSYNC_PASSWORD
+    env: HashMap<String, String>,
+}
+
+fn sha256(content: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(content)))
+}
+
+impl Precheck {
+    pub(crate) fn new(cfg: &PrecheckConfig, env: HashMap<String, String>) -> Result<Self> {
+        let source = match (cfg.url.as_deref().filter(|s| !s.is_empty()), cfg.command.as_deref().filter(|s| !s.is_empty())) {
+            (Some(_), Some(_)) => return Err(anyhow!("precheck的url和command只能配置一个")),
+            (None, None) => return Err(anyhow!("precheck需要配置url或command")),
+            (None, Some(command)) => Source::Command(command.to_string()),
+            (Some(url), None) if url.starts_with("http://") || url.starts_with("https://") => {
+                Source::Http(Url::parse(url).map_err(|e| anyhow!("无效的precheck url {}：{}", url, e))?)
+            }
+            (Some(url), None) if url.starts_with("rsync://") || url.contains("::") => Source::Rsync(url.to_string()),
+            (Some(url), None) => return Err(anyhow!("precheck的url只支持http(s)和rsync：{}", url)),
+        };
+        let kind = match cfg.marker.as_deref().unwrap_or("auto") {
+            "auto" => MarkerKind::Auto,
+            "content" => MarkerKind::Content,
+            "etag" => MarkerKind::Etag,
+            "mtime" => MarkerKind::Mtime,
+            other => return Err(anyhow!("无效的precheck marker：{}，应为 auto、content、etag 或 mtime", other)),
+        };
+        if kind != MarkerKind::Auto && kind != MarkerKind::Content && !matches!(source, Source::Http(_)) {
+            return Err(anyhow!("只有http的precheck支持 marker = \"{}\"", cfg.marker.as_deref().unwrap_or_default()))
+        }
+        let timeout = match cfg.timeout {
+            Some(0) | None => DEFAULT_TIMEOUT,
+            Some(secs) => Duration::from_secs(secs),
+        };
+        Ok(Precheck { source, kind, timeout, env })
+    }
+
+    // probe获取上游当前的标记
+    pub(crate) async fn probe(&self) -> Result<String> {
+        match &self.source {
+            Source::Http(url) => self.probe_http(url).await,
+            Source::Rsync(url) => self.run(Command::new("rsync").args(["--list-only", "--no-motd", url])).await,
+            Source::Command(command) => self.run(Command::new("bash").args(["-c", command])).await,
+        }
+    }
+
+    async fn probe_http(&self, url: &Url) -> Result<String> {
+        let client = Client::builder().timeout(self.timeout).build()?;
+        if self.kind != MarkerKind::Content {
+            let head = client.head(url.clone()).send().await?.error_for_status()?;
+            let header = |name| head.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+            let marker = match self.kind {
+                MarkerKind::Etag => Some(format!("etag:{}", header(ETAG).ok_or_else(|| anyhow!("{} 没有ETag", url))?)),
+                MarkerKind::Mtime => Some(format!("mtime:{}", header(LAST_MODIFIED).ok_or_else(|| anyhow!("{} 没有Last-Modified", url))?)),
+                _ => header(ETAG).map(|etag| format!("etag:{}", etag))
+                    .or_else(|| header(LAST_MODIFIED).map(|mtime| format!("mtime:{}", mtime))),
+            };
+            if let Some(marker) = marker {
+                return Ok(marker)
+            }
+        }
+        let content = client.get(url.clone()).send().await?.error_for_status()?.bytes().await?;
+        Ok(sha256(&content))
+    }
+
+    async fn run(&self, command: &mut Command) -> Result<String> {
+        let output = tokio::time::timeout(self.timeout, command.envs(&self.env).kill_on_drop(true).output()).await
+            .map_err(|_| anyhow!("precheck超时"))??;
+        if !output.status.success() {
+            return Err(anyhow!("precheck失败（{}）：{}", output.status, String::from_utf8_lossy(&output.stderr).trim()))
+        }
+        Ok(sha256(&output.stdout))
+    }
+
+    // last_marker读取上一次同步成功时保存的标记
+    pub(crate) fn last_marker(state_file: &Path) -> Option<String> {
+        fs::read_to_string(state_file).ok().map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+    }
+
+    pub(crate) fn save_marker(state_file: &Path, marker: &str) -> Result<()> {
+        Ok(fs::write(state_file, format!("{}\n", marker))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+    use crate::test_server::{self, Response};
+
+    // serve启动一个http服务器，每个请求都返回headers和body
+    async fn serve(headers: &'static [(&'static str, &'static str)], body: &'static str) -> String {
+        let addr = test_server::serve(move |_| {
+            headers.iter().fold(Response::new(200, body), |resp, (name, value)| resp.header(name, value))
+        }).await;
+        format!("{}/debian/project/trace/master", addr)
+    }
+
+    fn precheck(url: &str, marker: Option<&str>) -> Result<Precheck> {
+        let cfg = PrecheckConfig { url: Some(url.to_string()), marker: marker.map(str::to_string), ..PrecheckConfig::default() };
+        Precheck::new(&cfg, HashMap::new())
+    }
+
+    #[tokio::test]
+    async fn test_precheck_http(){
+        const HEADERS: &[(&str, &str)] = &[("ETag", "\"abc\""), ("Last-Modified", "Wed, 01 May 2024 00:00:00 GMT")];
+        let url = serve(HEADERS, "2024-05-01").await;
+        assert_eq!(precheck(&url, None).unwrap().probe().await.unwrap(), "etag:\"abc\"");
+        assert_eq!(precheck(&url, Some("mtime")).unwrap().probe().await.unwrap(), "mtime:Wed, 01 May 2024 00:00:00 GMT");
+
+        // 没有ETag和Last-Modified时比较文件内容
+        let url = serve(&[], "2024-05-01").await;
+        assert_eq!(precheck(&url, None).unwrap().probe().await.unwrap(), sha256(b"2024-05-01"));
+        assert!(precheck(&url, Some("etag")).unwrap().probe().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_precheck_command(){
+        let cfg = PrecheckConfig { command: Some("echo $RELEASE".to_string()), ..PrecheckConfig::default() };
+        let p = Precheck::new(&cfg, HashMap::from([("RELEASE".to_string(), "bookworm".to_string())])).unwrap();
+        assert_eq!(p.probe().await.unwrap(), sha256(b"bookworm\n"));
+
+        let cfg = PrecheckConfig { command: Some("exit 3".to_string()), ..PrecheckConfig::default() };
+        assert!(Precheck::new(&cfg, HashMap::new()).unwrap().probe().await.is_err());
+
+        let tmp_dir = Builder::new().prefix("rtsync").tempdir().unwrap();
+        let state_file = tmp_dir.path().join("debian.precheck");
+        assert_eq!(Precheck::last_marker(&state_file), None);
+        Precheck::save_marker(&state_file, "etag:\"abc\"").unwrap();
+        assert_eq!(Precheck::last_marker(&state_file).as_deref(), Some("etag:\"abc\""));
+    }
+
+    #[test]
+    fn test_precheck_invalid(){
+        assert!(Precheck::new(&PrecheckConfig::default(), HashMap::new()).is_err());
+        assert!(precheck("ftp://ftp.example.com/TIME", None).is_err());
+        assert!(precheck("rsync://rsync.example.com/debian/", Some("etag")).is_err());
+        assert!(precheck("https://example.com/TIME", Some("size")).is_err());
+        assert!(precheck("rsync://rsync.example.com/debian/project/trace/", None).is_ok());
+        assert!(precheck("mirrors.example.com::debian/", None).is_ok());
+    }
+}
@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use crate::config::{ProviderEnum, MirrorConfig, Config};
 use crate::common;
+use crate::atomic_publish_hook::AtomicPublishHook;
 use crate::cgroup::CGroupHook;
 use crate::hooks::{HookType, JobHook};
 use crate::context::Context;
@@ -12,11 +13,18 @@ use chrono::Duration;
 use log::{error, warn};
 use tera::Tera;
 use crate::cmd_provider::{CmdConfig, CmdProvider};
-use crate::docker::DockerHook;
+use crate::disk_space_hook::DiskSpaceHook;
+use crate::git_provider::{GitConfig, GitProvider};
+use crate::http_provider::{HttpConfig, HttpProvider};
+use crate::lftp_provider::{LftpConfig, LftpProvider};
+use crate::s3_provider::{S3Config, S3Provider};
+use crate::docker::{ContainerSpec, DockerHook};
 use crate::rsync_provider::{RsyncConfig, RsyncProvider};
 use crate::two_stage_rsync_provider::{TwoStageRsyncConfig, TwoStageRsyncProvider};
 use crate::zfs_hook::ZfsHook;
 use async_trait::async_trait;
+use internal::size::MirrorSize;
+use internal::msg::ResourceUsage;
 
 #[cfg(not(target_os = "linux"))]
 use crate::btrfs_snapshot_hook_nolinux;
@@ -24,6 +32,7 @@ use crate::btrfs_snapshot_hook_nolinux;
 use crate::btrfs_snapshot_hook;
 use crate::exec_post_hook::{ExecOn, ExecPostHook};
 use crate::loglimit_hook::LogLimiter;
+use crate::rate_limit::TokenBucket;
 
 // mirror provider是mirror job的包装器
 
@@ -70,7 +79,13 @@ pub trait MirrorProvider: Send + Sync + std::fmt::Debug {
     async fn log_file(&self) -> String;
     
     async fn is_master(&self) -> bool;
-    async fn data_size(&self) -> String;
+    async fn data_size(&self) -> MirrorSize;
+    // 最近一次同步消耗的资源
+    async fn resource_usage(&self) -> Option<ResourceUsage>;
+    // 设置下一次同步使用的带宽限制，不支持限速的provider忽略它
+    async fn set_bandwidth(&self, _limiter: Option<Arc<TokenBucket>>) {}
+    // 同步过程中能否随着带宽的重新分配调整速度，不能调整的provider分到的带宽在同步结束前不会被收回
+    fn bandwidth_adjustable(&self) -> bool { false }
 
     // enter context
     async fn enter_context(&mut self) -> Arc<Mutex<Option<Context>>>;
@@ -223,12 +238,176 @@ pub(crate) async fn new_mirror_provider(mut mirror: MirrorConfig, cfg: Config) -
             }
 
         },
+        Some(_provider) if _provider.eq(&ProviderEnum::Git) => {
+            let gc = GitConfig{
+                name: mirror.name.clone().unwrap_or_default(),
+                git_cmd: mirror.command.clone().unwrap_or_default(),
+                upstream_url: mirror.upstream.clone().unwrap_or_default(),
+                repos: mirror.git_repos.clone().unwrap_or_default(),
+                repos_file: mirror.git_repos_file.clone().unwrap_or_default(),
+                env: mirror.env.clone().unwrap_or_default(),
+                working_dir: mirror_dir,
+                log_dir: log_dir.clone(),
+                log_file: PathBuf::new().join(log_dir).join("latest.log").display().to_string(),
+                interval: Duration::minutes(mirror.interval.unwrap_or_default()),
+                retry: mirror.retry.unwrap_or_default(),
+                timeout: Duration::seconds(mirror.timeout.unwrap_or_default()),
+            };
+            match GitProvider::new(gc).await {
+                Ok(p) => {
+                    p.base_provider.write().await.is_master = is_master;
+                    provider = Box::new(p);
+                },
+                Err(e) => {
+                    panic!("{}", e);
+                }
+            }
+        },
+        Some(_provider) if _provider.eq(&ProviderEnum::Http) => {
+            let hc = HttpConfig{
+                name: mirror.name.clone().unwrap_or_default(),
+                upstream_url: mirror.upstream.clone().unwrap_or_default(),
+                manifest_url: mirror.http_manifest.clone().unwrap_or_default(),
+                concurrency: mirror.http_concurrency.unwrap_or_default(),
+                working_dir: mirror_dir,
+                log_dir: log_dir.clone(),
+                log_file: PathBuf::new().join(log_dir).join("latest.log").display().to_string(),
+                interval: Duration::minutes(mirror.interval.unwrap_or_default()),
+                retry: mirror.retry.unwrap_or_default(),
+                timeout: Duration::seconds(mirror.timeout.unwrap_or_default()),
+            };
+            match HttpProvider::new(hc).await {
+                Ok(p) => {
+                    p.base_provider.write().await.is_master = is_master;
+                    provider = Box::new(p);
+                },
+                Err(e) => {
+                    panic!("{}", e);
+                }
+            }
+        },
+        Some(_provider) if _provider.eq(&ProviderEnum::Lftp) => {
+            let lc = LftpConfig{
+                name: mirror.name.clone().unwrap_or_default(),
+                lftp_cmd: mirror.command.clone().unwrap_or_default(),
+                upstream_url: mirror.upstream.clone().unwrap_or_default(),
+                username: mirror.username.clone().unwrap_or_default(),
+                password: mirror.password.clone().unwrap_or_default(),
+                exclude_file: mirror.exclude_file.clone().unwrap_or_default(),
+                exclude: mirror.lftp_exclude.clone().unwrap_or_default(),
+                parallel: mirror.lftp_parallel.unwrap_or_default(),
+                passive: mirror.lftp_passive,
+                extra_options: mirror.lftp_options.clone().unwrap_or_default(),
+                use_temp_file: mirror.publish_mode.as_deref() == Some("atomic"),
+                lftp_env: mirror.env.clone().unwrap_or_default(),
+                working_dir: mirror_dir,
+                log_dir: log_dir.clone(),
+                log_file: PathBuf::new().join(log_dir).join("latest.log").display().to_string(),
+                use_ipv4: mirror.use_ipv4.unwrap_or_default(),
+                use_ipv6: mirror.use_ipv6.unwrap_or_default(),
+                interval: Duration::minutes(mirror.interval.unwrap_or_default()),
+                retry: mirror.retry.unwrap_or_default(),
+                timeout: Duration::seconds(mirror.timeout.unwrap_or_default()),
+            };
+            match LftpProvider::new(lc).await {
+                Ok(p) => {
+                    p.base_provider.write().await.is_master = is_master;
+                    provider = Box::new(p);
+                },
+                Err(e) => {
+                    panic!("{}", e);
+                }
+            }
+        },
+        Some(_provider) if _provider.eq(&ProviderEnum::S3) => {
+            let bandwidth_limit = match mirror.s3_bandwidth_limit.as_deref().filter(|s| !s.is_empty()) {
+                Some(limit) => match MirrorSize::parse(limit).bytes {
+                    Some(bytes) => bytes,
+                    None => panic!("mirror {} 的 s3_bandwidth_limit 无效：{}", mirror.name.clone().unwrap_or_default(), limit),
+                },
+                None => 0,
+            };
+            let sc = S3Config{
+                name: mirror.name.clone().unwrap_or_default(),
+                upstream_url: mirror.upstream.clone().unwrap_or_default(),
+                endpoint: mirror.s3_endpoint.clone().unwrap_or_default(),
+                region: mirror.s3_region.clone().unwrap_or_default(),
+                credentials_file: mirror.s3_credentials_file.clone().unwrap_or_default(),
+                profile: mirror.s3_profile.clone().unwrap_or_default(),
+                env: mirror.env.clone().unwrap_or_default(),
+                concurrency: mirror.s3_concurrency.unwrap_or_default(),
+                bandwidth_limit,
+                no_delete: mirror.s3_no_delete.unwrap_or_default(),
+                working_dir: mirror_dir,
+                log_dir: log_dir.clone(),
+                log_file: PathBuf::new().join(log_dir).join("latest.log").display().to_string(),
+                interval: Duration::minutes(mirror.interval.unwrap_or_default()),
+                retry: mirror.retry.unwrap_or_default(),
+                timeout: Duration::seconds(mirror.timeout.unwrap_or_default()),
+            };
+            match S3Provider::new(sc).await {
+                Ok(p) => {
+                    p.base_provider.write().await.is_master = is_master;
+                    provider = Box::new(p);
+                },
+                Err(e) => {
+                    panic!("{}", e);
+                }
+            }
+        },
         _ => { panic!("mirror的provider字段无效") }
     }
 
     // add logging hook
     provider.add_hook(HookType::LogLimiter(LogLimiter::new())).await;
 
+    // add disk space hook
+    if let Some(min_free_space) = mirror.min_free_space.as_deref().filter(|s| !s.is_empty()){
+        match MirrorSize::parse(min_free_space).bytes {
+            Some(bytes) => provider.add_hook(HookType::DiskSpace(DiskSpaceHook::new(bytes))).await,
+            None => {
+                let err = format!("mirror {} 的 min_free_space 无效：{}", mirror.name.clone().unwrap_or_default(), min_free_space);
+                error!("{}", err);
+                panic!("{}", err);
+            }
+        }
+    }
+
+    let limits = match mirror.resource_limits() {
+        Ok(limits) => limits,
+        Err(e) => {
+            let err = format!("mirror {} 的资源限制无效：{}", mirror.name.clone().unwrap_or_default(), e);
+            error!("{}", err);
+            panic!("{}", err);
+        }
+    };
+
+    if let Err(e) = mirror.cron_schedule() {
+        let err = format!("mirror {} 的schedule无效：{}", mirror.name.clone().unwrap_or_default(), e);
+        error!("{}", err);
+        panic!("{}", err);
+    }
+    if let Err(e) = mirror.bandwidth_limit_bytes() {
+        let err = format!("mirror {} 的带宽限制无效：{}", mirror.name.clone().unwrap_or_default(), e);
+        error!("{}", err);
+        panic!("{}", err);
+    }
+    if let Err(e) = mirror.precheck() {
+        let err = format!("mirror {} 的precheck无效：{}", mirror.name.clone().unwrap_or_default(), e);
+        error!("{}", err);
+        panic!("{}", err);
+    }
+    if let Err(e) = mirror.retry_policy(&cfg.global) {
+        let err = format!("mirror {} 的重试间隔无效：{}", mirror.name.clone().unwrap_or_default(), e);
+        error!("{}", err);
+        panic!("{}", err);
+    }
+    if let Err(e) = mirror.sync_window(&cfg.global) {
+        let err = format!("mirror {} 的同步时间段无效：{}", mirror.name.clone().unwrap_or_default(), e);
+        error!("{}", err);
+        panic!("{}", err);
+    }
+
     // add zfs hook
     if let Some(true) = cfg.zfs.enable{
         if let Some(z_pool) = cfg.zfs.z_pool{
@@ -247,15 +426,19 @@ pub(crate) async fn new_mirror_provider(mut mirror: MirrorConfig, cfg: Config) -
     }
 
     // add docker hook
-    if let Some(true) = cfg.docker.enable{
-        if mirror.docker_image.is_some(){
-            provider.add_hook(HookType::Docker(DockerHook::new(cfg.docker.clone(), mirror.clone()))).await;
+    if cfg.docker.enable == Some(true) && mirror.docker_image.is_some(){
+        let docker = DockerHook::new(cfg.docker.clone(), mirror.clone());
+        // 在创建provider时检查docker_options，避免同步时才发现不支持、缺少参数或参数无效的选项
+        if let Err(e) = docker.container_config(&ContainerSpec::default()) {
+            let err = format!("mirror {} 的docker选项无效：{}", mirror.name.clone().unwrap_or_default(), e);
+            error!("{}", err);
+            panic!("{}", err);
         }
+        provider.add_hook(HookType::Docker(docker)).await;
+    }else if let Some(true) = cfg.c_group.enable {
+        // add cgroup hook
+        provider.add_hook(HookType::Cgroup(CGroupHook::new(cfg.c_group.clone(), mirror.memory_limit.clone().unwrap_or_default(), limits))).await;
     }
-    // else if cfg.c_group.enable.unwrap() {
-    //     // add cgroup hook
-    //     provider.add_hook(HookType::Cgroup(CGroupHook::new()))
-    // }
 
     async fn add_hook_from_cmd_list(provider: &mut Box<dyn MirrorProvider>, mirror: &MirrorConfig, cmd_list: Vec<String>, exec_on: u8) {
         for cmd in cmd_list {
@@ -300,6 +483,18 @@ pub(crate) async fn new_mirror_provider(mut mirror: MirrorConfig, cfg: Config) -
     add_hook_from_cmd_list(&mut provider, &mirror, 
                            mirror.exec_on_failure_extra.clone().unwrap_or_default(),
                            ExecOn::Failure.as_u8()).await;
+
+    // add atomic publish hook
+    // 最后添加：pre_exec在docker hook之后运行以便挂载staging目录，post_success在exec_on_success之前发布
+    match AtomicPublishHook::new(&mirror, &provider.working_dir().await) {
+        Ok(Some(hook)) => provider.add_hook(HookType::AtomicPublish(hook)).await,
+        Ok(None) => {}
+        Err(e) => {
+            let err = format!("mirror {} 的发布方式无效：{}", mirror.name.clone().unwrap_or_default(), e);
+            error!("{}", err);
+            panic!("{}", err);
+        }
+    }
     
     provider
 }
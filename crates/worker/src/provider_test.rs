@@ -22,6 +22,8 @@ mod tests{
     use crate::provider::*;
     use crate::rsync_provider::{RsyncConfig, RsyncProvider};
     use crate::two_stage_rsync_provider::{TwoStageRsyncConfig, TwoStageRsyncProvider};
+    use crate::git_provider::{parse_repo, GitConfig, GitProvider, GitRepo};
+    use crate::lftp_provider::{LftpConfig, LftpProvider};
 
     fn resolve_symlink(mut path: PathBuf) -> Result<PathBuf, std::io::Error> {
         loop {
@@ -129,7 +131,8 @@ exit 0
         provider.run(channel(1).0).await.unwrap();
         let logged_content = fs::read_to_string(provider.log_file().await).unwrap();
         assert_eq!(expected_output, logged_content);
-        assert_eq!(provider.data_size().await, "1.33T".to_string());
+        assert_eq!(provider.data_size().await.raw, "1.33T".to_string());
+        assert_eq!(provider.data_size().await.bytes, Some(1_330_000_000_000));
     }
 
     #[tokio::test]
@@ -473,6 +476,8 @@ echo $AOSP_REPO_BIN
 
         let logged_content = fs::read_to_string(&provider.log_file().await).unwrap();
         assert_eq!(logged_content, expected_output);
+        let usage = provider.resource_usage().await.unwrap();
+        assert!(usage.max_rss_bytes > 0);
     }
 
     async fn test_command_fails(mut provider: CmdProvider, mut script_file: File, script_file_path: PathBuf){
@@ -575,7 +580,7 @@ sleep 10
         c.fail_on_match = "[a-z]+".to_string();
         let mut provider = CmdProvider::new(c).await.unwrap();
         assert!(provider.run(channel(1).0).await.is_err());
-        assert_eq!(provider.data_size().await, "");
+        assert_eq!(provider.data_size().await.raw, "");
     }
 
     async fn test_fail_on_match_regex_does_not_matches(mut c: CmdConfig) {
@@ -596,8 +601,8 @@ sleep 10
         let mut provider = CmdProvider::new(c).await.unwrap();
         provider.run(channel(1).0).await.unwrap();
 
-        assert!(!provider.data_size().await.is_empty());
-        let datasize = provider.data_size().await.parse::<f32>().unwrap();
+        assert!(!provider.data_size().await.raw.is_empty());
+        let datasize = provider.data_size().await.raw.parse::<f32>().unwrap();
     }
 
     async fn test_size_pattern_regex_does_not_matches(mut c: CmdConfig) {
@@ -605,7 +610,7 @@ sleep 10
         let mut provider = CmdProvider::new(c).await.unwrap();
         provider.run(channel(1).0).await.unwrap();
 
-        assert!(provider.data_size().await.is_empty());
+        assert!(provider.data_size().await.is_unknown());
     }
 
     async fn test_size_pattern_regex_meets_dev_null(mut c: CmdConfig) {
@@ -614,7 +619,7 @@ sleep 10
         let mut provider = CmdProvider::new(c).await.unwrap();
         // FIXME: 源代码里判断run失败，但是run里在解析size_pattern时忽略了find_all_submatches_in_file返回的错误，所以不应该为失败
         assert!(provider.run(channel(1).0).await.is_ok());
-        assert!(provider.data_size().await.is_empty());
+        assert!(provider.data_size().await.is_unknown());
     }
 
     #[tokio::test]
@@ -762,15 +767,169 @@ exit 0
         let logged_content = fs::read_to_string(provider.log_file().await).expect("failed to read logged file");
         assert!(logged_content.contains("Error in socket I/O"))
     }
-}
-
-
-
-
 
+    #[test]
+    fn test_parse_repo(){
+        let repo = |url: &str, dir: &str| Some(GitRepo{ url: url.to_string(), dir: dir.to_string() });
+        assert_eq!(parse_repo("# comment", "").unwrap(), None);
+        assert_eq!(parse_repo("  ", "").unwrap(), None);
+        assert_eq!(parse_repo("foo", "https://github.com/org/").unwrap(), repo("https://github.com/org/foo", "foo.git"));
+        assert_eq!(parse_repo("bar.git", "https://github.com/org").unwrap(), repo("https://github.com/org/bar.git", "bar.git"));
+        assert_eq!(parse_repo("https://example.com/a/b/ mirrors/b", "https://github.com/org").unwrap(), repo("https://example.com/a/b/", "mirrors/b"));
+        assert_eq!(parse_repo("git@github.com:org/c.git", "").unwrap(), repo("git@github.com:org/c.git", "c.git"));
+        assert!(parse_repo("https://example.com/a ../a", "").is_err());
+        assert!(parse_repo("https://example.com/a /a", "").is_err());
+        assert!(parse_repo("https://example.com/a a b", "").is_err());
+    }
 
+    #[tokio::test]
+    async fn test_git_provider(){
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let tmp_dir_path = tmp_dir.path();
+        let upstream = tmp_dir_path.join("upstream");
+        let working_dir = tmp_dir_path.join("mirror");
+        let log_file = tmp_dir_path.join("log_file");
+
+        // 准备两个上游仓库
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=rtsync", "-c", "user.email=rtsync@example.com"])
+                .args(args)
+                .output().unwrap().status;
+            assert!(status.success(), "git {:?} 失败", args);
+        };
+        for name in ["a", "b"] {
+            let repo = upstream.join(name).display().to_string();
+            git(&["init", "-q", &repo]);
+            git(&["-C", &repo, "commit", "-q", "--allow-empty", "-m", "init"]);
+        }
+        let repos_file = tmp_dir_path.join("repos.txt");
+        fs::write(&repos_file, "# 从文件中读取的仓库\nb mirror-b\n").unwrap();
+
+        let mut c = GitConfig{
+            name: "rt-git".to_string(),
+            upstream_url: upstream.display().to_string(),
+            repos: vec!["a".to_string(), "not-exist".to_string()],
+            repos_file: repos_file.display().to_string(),
+            working_dir: working_dir.display().to_string(),
+            log_dir: tmp_dir_path.display().to_string(),
+            log_file: log_file.display().to_string(),
+            interval: Duration::seconds(600),
+            ..GitConfig::default()
+        };
+        let provider = GitProvider::new(c.clone()).await.unwrap();
+        assert_eq!(provider.r#type(), ProviderEnum::Git);
+        assert_eq!(provider.repos().unwrap().len(), 3);
+
+        // 一个仓库失败时其他仓库仍然会同步
+        let err = provider.run(channel(1).0).await.unwrap_err();
+        assert_eq!(err.to_string(), format!("1/3 个仓库同步失败：{}", upstream.join("not-exist").display()));
+        assert!(working_dir.join("a.git/HEAD").is_file());
+        assert!(working_dir.join("mirror-b/HEAD").is_file());
+        assert!(provider.data_size().await.bytes.unwrap() > 0);
+        let logged_content = fs::read_to_string(&log_file).unwrap();
+        assert!(logged_content.contains(&format!("==> 同步仓库 {} (1/3)", upstream.join("a").display())));
+        assert!(logged_content.contains(&format!("==> 仓库 {} 同步失败", upstream.join("not-exist").display())));
+
+        // 已经存在的仓库只更新
+        git(&["-C", &upstream.join("a").display().to_string(), "commit", "-q", "--allow-empty", "-m", "second"]);
+        c.repos = vec!["a".to_string()];
+        let provider = GitProvider::new(c.clone()).await.unwrap();
+        provider.run(channel(1).0).await.unwrap();
+        let output = std::process::Command::new("git")
+            .args(["-C", &working_dir.join("a.git").display().to_string(), "rev-list", "--count", "--all"])
+            .output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "2");
+
+        // 多个仓库使用了相同的目录
+        c.repos = vec!["a".to_string(), "b a.git".to_string()];
+        let provider = GitProvider::new(c.clone()).await.unwrap();
+        assert!(provider.repos().is_err());
+        assert!(provider.run(channel(1).0).await.is_err());
 
+        // 配置中的仓库格式错误
+        c.repos = vec!["a ../a".to_string()];
+        assert!(GitProvider::new(c).await.is_err());
+    }
 
+    #[tokio::test]
+    async fn test_lftp_provider(){
+        let tmp_dir = Builder::new()
+            .prefix("rtsync")
+            .tempdir().expect("failed to create tmp dir");
+        let tmp_dir_path = tmp_dir.path();
+        let working_dir = tmp_dir_path.join("mirror");
+        let log_file = tmp_dir_path.join("log_file");
+
+        // 用脚本代替lftp，输出收到的参数，退出码由环境变量决定
+        let script_file_path = tmp_dir_path.join("mylftp");
+        fs::write(&script_file_path, r#"#!/bin/bash
+echo "$1"
+echo "$2"
+echo "password: $LFTP_PASSWORD"
+echo "data" > "$PWD/file"
+if [[ -n "$FAKE_ERROR" ]]; then
+    echo "mirror: Access failed: 550 /pub/x: No such file or directory"
+    echo "$FAKE_ERROR"
+fi
+exit ${FAKE_EXIT:-0}
+"#).unwrap();
+        fs::set_permissions(&script_file_path, fs::Permissions::from_mode(0o755)).unwrap();
 
+        let c = LftpConfig{
+            name: "rt-lftp".to_string(),
+            lftp_cmd: script_file_path.display().to_string(),
+            upstream_url: "ftp://ftp.example.com/pub/".to_string(),
+            username: "rtsync".to_string(),
+            password: "rtsyncpassword".to_string(),
+            exclude_file: "/etc/rtsync.d/exclude.txt".to_string(),
+            exclude: vec!["*.iso".to_string(), "a \"b\"".to_string()],
+            parallel: 4,
+            passive: Some(false),
+            extra_options: vec!["--only-newer".to_string()],
+            working_dir: working_dir.display().to_string(),
+            log_dir: tmp_dir_path.display().to_string(),
+            log_file: log_file.display().to_string(),
+            use_ipv4: true,
+            interval: Duration::seconds(600),
+            ..LftpConfig::default()
+        };
+        let provider = LftpProvider::new(c.clone()).await.unwrap();
+        assert_eq!(provider.r#type(), ProviderEnum::Lftp);
+        assert_eq!(provider.upstream(), c.upstream_url);
 
+        let expected_script = format!("set net:max-retries 3; set net:timeout 120; set ftp:passive-mode off; \
+set dns:order \"inet\"; open --env-password -u \"rtsync\" \"ftp://ftp.example.com/pub/\"; \
+mirror --verbose --delete --no-perms --parallel=4 --exclude-glob \"*.iso\" --exclude-glob \"a \\\"b\\\"\" \
+--exclude-glob-from=\"/etc/rtsync.d/exclude.txt\" --only-newer . \"{}\"", working_dir.display());
+        assert_eq!(provider.script(&working_dir.display().to_string()), expected_script);
 
+        provider.run(channel(1).0).await.unwrap();
+        let logged_content = fs::read_to_string(&log_file).unwrap();
+        assert_eq!(logged_content, format!("-c\n{}\npassword: rtsyncpassword\n", expected_script));
+        assert_eq!(provider.data_size().await.bytes, Some(5));
+
+        // 根据日志中的错误信息翻译lftp的错误
+        let provider = LftpProvider::new(LftpConfig{
+            lftp_env: [
+                ("FAKE_EXIT".to_string(), "1".to_string()),
+                ("FAKE_ERROR".to_string(), "mirror: Login failed: 530 Login incorrect.".to_string()),
+            ].into(),
+            ..c.clone()
+        }).await.unwrap();
+        let err = provider.run(channel(1).0).await.unwrap_err();
+        assert_eq!(err.to_string(), "lftp error: Login failed (mirror: Login failed: 530 Login incorrect.)");
+        let logged_content = fs::read_to_string(&log_file).unwrap();
+        assert!(logged_content.ends_with(&format!("{}\n", err)));
+        assert_eq!(provider.data_size().await.bytes, None);
+
+        // atomic发布时先下载到临时文件再改名
+        let provider = LftpProvider::new(LftpConfig{ use_temp_file: true, ..c.clone() }).await.unwrap();
+        assert!(provider.script(&working_dir.display().to_string())
+            .contains("set ftp:passive-mode off; set xfer:use-temp-file on; set dns:order"));
+
+        assert!(LftpProvider::new(LftpConfig{ upstream_url: "ftp://ftp.example.com/pub".to_string(), ..c }).await.is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use log::debug;
+use tokio::time::{sleep, Duration, Instant};
+
+// TokenBucket按字节限制传输速度，同一个镜像的所有下载任务共享一个TokenBucket
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    // 每秒的字节数，全局带宽重新分配时会被修改
+    rate: AtomicU64,
+    // (剩余的令牌数, 上一次补充令牌的时间)，令牌数为负数表示已经透支
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(bytes_per_second: u64) -> Self {
+        let rate = bytes_per_second.max(1);
+        TokenBucket {
+            rate: AtomicU64::new(rate),
+            state: Mutex::new((rate as f64, Instant::now())),
+        }
+    }
+
+    pub(crate) fn rate(&self) -> u64 {
+        self.rate.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_rate(&self, bytes_per_second: u64) {
+        self.rate.store(bytes_per_second.max(1), Ordering::SeqCst);
+    }
+
+    // acquire取走n个令牌，令牌不足时等待到透支的部分补充完为止，最多积累1秒的令牌
+    pub(crate) async fn acquire(&self, n: u64) {
+        let wait = {
+            let rate = self.rate() as f64;
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(state.1).as_secs_f64();
+            state.0 = (state.0 + elapsed * rate).min(rate) - n as f64;
+            state.1 = now;
+            if state.0 < 0.0 { -state.0 / rate } else { 0.0 }
+        };
+        if wait > 0.0 {
+            sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+}
+
+// rsync_bwlimit把分到的带宽转换为rsync的 --bwlimit 参数，rsync的单位是KiB每秒
+pub(crate) fn rsync_bwlimit(limiter: &TokenBucket) -> String {
+    format!("--bwlimit={}", (limiter.rate() / 1024).max(1))
+}
+
+// id -> (任务自己的限制, 运行时能否调整速度, 分给任务的TokenBucket)
+type Shares = HashMap<u64, (Option<u64>, bool, Arc<TokenBucket>)>;
+
+// BandwidthBudget把worker的总带宽公平地分给正在同步的任务：每个任务分到相同的带宽，
+// 任务自己的限制比平均值小时只分给它自己的限制，剩下的带宽由其他任务平分；
+// 任务开始和结束时重新分配，已经分出去的TokenBucket的速度随之改变。
+// rsync、lftp等运行时不能调整速度的任务在开始时按照当时的情况分到带宽，之后不会被收回，
+// 剩下的带宽才由能调整速度的任务平分，这样总速度不会超过总带宽
+#[derive(Debug, Default)]
+pub(crate) struct BandwidthBudget {
+    // 每秒的字节数，为0时不限制总带宽
+    total: u64,
+    shares: Mutex<Shares>,
+    next_id: AtomicU64,
+}
+
+// BandwidthShare是一个正在同步的任务分到的带宽，被drop时归还给BandwidthBudget
+#[derive(Debug)]
+pub(crate) struct BandwidthShare {
+    budget: Arc<BandwidthBudget>,
+    id: u64,
+    pub(crate) limiter: Arc<TokenBucket>,
+}
+
+impl Drop for BandwidthShare {
+    fn drop(&mut self) {
+        let mut shares = self.budget.shares.lock().unwrap();
+        shares.remove(&self.id);
+        self.budget.rebalance(&shares);
+    }
+}
+
+impl BandwidthBudget {
+    pub(crate) fn new(total: u64) -> Self {
+        BandwidthBudget { total, ..BandwidthBudget::default() }
+    }
+
+    // join为一个开始同步的任务分配带宽，limit是任务自己的限制，adjustable表示任务运行时能否调整速度，
+    // 总带宽和任务都没有限制时返回None
+    pub(crate) fn join(self: &Arc<Self>, limit: Option<u64>, adjustable: bool) -> Option<BandwidthShare> {
+        if self.total == 0 && limit.is_none() {
+            return None
+        }
+        let mut shares = self.shares.lock().unwrap();
+        let rate = match (limit, adjustable) {
+            // 不能调整速度的任务和正在同步的、能调整速度的任务平分还没有固定分出去的带宽
+            (limit, false) if self.total > 0 => {
+                let running = shares.values().filter(|(_, adjustable, _)| *adjustable).count() as u64;
+                (self.remaining(&shares) / (running + 1)).min(limit.unwrap_or(u64::MAX))
+            }
+            (limit, _) => limit.unwrap_or(self.total),
+        };
+        let limiter = Arc::new(TokenBucket::new(rate));
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        shares.insert(id, (limit, adjustable, limiter.clone()));
+        self.rebalance(&shares);
+        Some(BandwidthShare { budget: self.clone(), id, limiter })
+    }
+
+    fn rebalance(&self, shares: &Shares) {
+        if self.total == 0 {
+            return
+        }
+        // 先扣除不能调整速度的任务分到的带宽，剩下的按照任务自己的限制从小到大分配，没有限制的排在最后
+        let mut sorted: Vec<_> = shares.values().filter(|(_, adjustable, _)| *adjustable).collect();
+        sorted.sort_by_key(|(limit, _, _)| limit.unwrap_or(u64::MAX));
+        let mut remaining = self.remaining(shares);
+        for (i, (limit, _, limiter)) in sorted.iter().enumerate() {
+            let fair = remaining / (sorted.len() - i) as u64;
+            let rate = limit.map_or(fair, |limit| limit.min(fair));
+            limiter.set_rate(rate);
+            remaining -= rate;
+        }
+        debug!("重新分配带宽：{} 个任务共享 {} 字节每秒", shares.len(), self.total);
+    }
+
+    // remaining返回总带宽中没有被不能调整速度的任务占用的部分
+    fn remaining(&self, shares: &Shares) -> u64 {
+        let fixed: u64 = shares.values().filter(|(_, adjustable, _)| !adjustable)
+            .map(|(_, _, limiter)| limiter.rate()).sum();
+        self.total.saturating_sub(fixed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_token_bucket(){
+        let bucket = TokenBucket::new(10000);
+        let start = Instant::now();
+        // 第一秒的令牌可以直接使用
+        bucket.acquire(10000).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        bucket.acquire(1000).await;
+        bucket.acquire(2000).await;
+        assert!(start.elapsed() >= Duration::from_millis(290));
+        assert!(start.elapsed() < Duration::from_millis(600));
+    }
+
+    #[test]
+    fn test_bandwidth_budget(){
+        let budget = Arc::new(BandwidthBudget::new(100));
+        let a = budget.join(None, true).unwrap();
+        assert_eq!(a.limiter.rate(), 100);
+        // 自己的限制比平均值小的任务只分到自己的限制
+        let b = budget.join(Some(20), true).unwrap();
+        assert_eq!((a.limiter.rate(), b.limiter.rate()), (80, 20));
+        let c = budget.join(Some(60), true).unwrap();
+        assert_eq!((a.limiter.rate(), b.limiter.rate(), c.limiter.rate()), (40, 20, 40));
+        // 任务结束后其他任务分到更多的带宽
+        drop(a);
+        assert_eq!((b.limiter.rate(), c.limiter.rate()), (20, 60));
+        drop((b, c));
+        assert!(budget.shares.lock().unwrap().is_empty());
+
+        // 不限制总带宽时只使用任务自己的限制
+        let unlimited = Arc::new(BandwidthBudget::new(0));
+        assert!(unlimited.join(None, true).is_none());
+        let d = unlimited.join(Some(50), true).unwrap();
+        let _e = unlimited.join(Some(30), false).unwrap();
+        assert_eq!(d.limiter.rate(), 50);
+    }
+
+    #[test]
+    fn test_fixed_bandwidth_share(){
+        let budget = Arc::new(BandwidthBudget::new(100));
+        let adjustable = budget.join(None, true).unwrap();
+        assert_eq!(adjustable.limiter.rate(), 100);
+        // 不能调整速度的任务开始时和正在同步的任务平分带宽，能调整速度的任务分到剩下的带宽
+        let fixed = budget.join(None, false).unwrap();
+        assert_eq!((fixed.limiter.rate(), adjustable.limiter.rate()), (50, 50));
+        let other = budget.join(None, true).unwrap();
+        assert_eq!(fixed.limiter.rate(), 50);
+        assert_eq!(adjustable.limiter.rate() + other.limiter.rate(), 50);
+        // 其他任务开始和结束时，不能调整速度的任务分到的带宽不变，总速度不超过总带宽
+        drop(adjustable);
+        assert_eq!((fixed.limiter.rate(), other.limiter.rate()), (50, 50));
+        // 之后开始的不能调整速度的任务只能平分没有被固定占用的带宽
+        let second = budget.join(None, false).unwrap();
+        assert_eq!((fixed.limiter.rate(), second.limiter.rate(), other.limiter.rate()), (50, 25, 25));
+        drop(other);
+        assert_eq!((fixed.limiter.rate(), second.limiter.rate()), (50, 25));
+        // 自己的限制更小时只分到自己的限制
+        let limited = budget.join(Some(10), false).unwrap();
+        assert_eq!(limited.limiter.rate(), 10);
+        // 单独运行时分到全部带宽
+        drop((fixed, second, limited));
+        assert_eq!(budget.join(None, false).unwrap().limiter.rate(), 100);
+    }
+}
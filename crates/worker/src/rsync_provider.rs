@@ -11,10 +11,11 @@ use std::sync::Arc;
 use std::sync::atomic::Ordering;
 use anymap::AnyMap;
 use chrono::Duration;
-use libc::{getgid, getuid};
 use log::{debug, error};
 use internal;
 use internal::util::extract_size_from_rsync_log;
+use internal::size::MirrorSize;
+use internal::msg::ResourceUsage;
 use crate::base_provider::{BaseProvider};
 use crate::common::{Empty, DEFAULT_MAX_RETRY};
 use crate::config::ProviderEnum;
@@ -23,6 +24,7 @@ use crate::docker::DockerHook;
 use crate::hooks::{HookType, JobHook};
 use crate::provider::{MirrorProvider, _LOG_DIR_KEY, _LOG_FILE_KEY, _WORKING_DIR_KEY};
 use crate::runner::CmdJob;
+use crate::rate_limit::{rsync_bwlimit, TokenBucket};
 use async_trait::async_trait;
 
 #[derive(Clone, Default, Debug)]
@@ -59,7 +61,7 @@ pub(crate) struct RsyncProvider {
     pub(crate) base_provider: Arc<RwLock<BaseProvider>>,
     pub(crate) rsync_config: Arc<RsyncConfig>,
     options: Arc<Vec<String>>,
-    data_size: Arc<Mutex<String>>,
+    data_size: Arc<Mutex<MirrorSize>>,
 }
 
 unsafe impl Send for RsyncProvider{}
@@ -148,53 +150,23 @@ impl RsyncProvider {
         let mut command = Vec::new();
         command.push(self.rsync_config.rsync_cmd.clone());
         command.extend(self.options.as_ref().clone());
+        let mut base_provider_lock = self.base_provider.write().await;
+        // rsync运行时不能调整速度，使用开始同步时分到的带宽
+        if let Some(limiter) = base_provider_lock.bandwidth.as_ref() {
+            command.push(rsync_bwlimit(limiter));
+        }
         command.push(self.rsync_config.upstream_url.clone());
         command.push(working_dir.clone());
         let env = self.rsync_config.rsync_env.clone();
         
         let cmd_job: CmdJob;
-        let mut args: Vec<String> = Vec::new();
-        let mut base_provider_lock = self.base_provider.write().await;
         let use_docker = base_provider_lock.docker_ref().is_some();
 
         if let Some(d) = base_provider_lock.docker_ref().as_ref(){
-            let c = "docker";
-            // --rm 容器在执行完任务后会自动删除
-            // -a 
-            // --name 容器命名
-            // -w 容器内部工作目录
-            args.extend(vec!["run".to_string(), "--rm".to_string(),
-                             // "-a".to_string(), "STDOUT".to_string(), "-a".to_string(), "STDERR".to_string(),
-                             "--name".to_string(), d.name(self.name().parse().unwrap()),
-                             "-w".to_string(), working_dir.clone()]);
-            // -u 设置容器运行时的用户:用户组
-            unsafe {
-                args.extend(vec!["-u".to_string(),
-                                 format!("{}:{}",getuid().to_string(), getgid().to_string())]);
-            }
-            // -v 添加卷: 把主机的文件或目录挂载到容器内
-            for vol in base_provider_lock.docker_volumes().await{
-                debug!("数据卷: {}", &vol);
-                args.extend(vec!["-v".to_string(), vol.clone()])
-            }
-            // 设置环境变量
-            for (key, value) in env.iter(){
-                let kv = format!("{}={}", key, value);
-                args.extend(vec!["-e".to_string(), kv.clone()])
-            }
-            // 设置内存限制
-            if d.memory_limit.0 != 0{
-                args.extend(vec!["-m".to_string(), format!("{}b", d.memory_limit.value())])
-            }
-            // 添加选项
-            args.extend(d.options.iter().cloned());
-            // 添加镜像和command
-            args.push(d.image.clone());
-            // 添加command
-            args.extend(command.iter().cloned());
-            
-            cmd_job = CmdJob::new(Command::new(c), working_dir.clone(), env.clone());
-            { cmd_job.cmd.lock().await.args(&args); }
+            // 容器由BaseProvider通过docker API创建，这里只记录容器的配置
+            let spec = d.container_spec(self.name(), command.to_vec(), env.clone(),
+                                        working_dir.clone(), base_provider_lock.docker_volumes().await);
+            cmd_job = CmdJob::new_container(spec, working_dir.clone(), env.clone());
 
         }else {
             if command.len() == 1{
@@ -269,7 +241,8 @@ impl MirrorProvider for RsyncProvider  {
     
     // 运行rsync命令并等待结果，记录同步文件大小。如果rsync异常，则解析错误并将错误返回
     async fn run(&self, started: Sender<Empty>) -> Result<()> {
-        { *self.data_size.lock().await = String::new(); }
+        { *self.data_size.lock().await = MirrorSize::default(); }
+        { *self.base_provider.read().await.usage.lock().await = None; }
         
         MirrorProvider::start(self).await?;
         
@@ -307,7 +280,7 @@ impl MirrorProvider for RsyncProvider  {
         }
 
         if let Some(size) = extract_size_from_rsync_log(&*self.log_file().await) {
-            *self.data_size.lock().await = size.into()
+            *self.data_size.lock().await = size
         };
         Ok(())
     }
@@ -390,8 +363,16 @@ impl MirrorProvider for RsyncProvider  {
         self.base_provider.read().await.is_master
     }
 
-    async fn data_size(&self) -> String {
-        self.data_size.lock().await.to_string()
+    async fn data_size(&self) -> MirrorSize {
+        self.data_size.lock().await.clone()
+    }
+
+    async fn resource_usage(&self) -> Option<ResourceUsage> {
+        *self.base_provider.read().await.usage.lock().await
+    }
+
+    async fn set_bandwidth(&self, limiter: Option<Arc<TokenBucket>>) {
+        self.base_provider.write().await.bandwidth = limiter;
     }
 
     async fn enter_context(&mut self) -> Arc<Mutex<Option<Context>>> {
@@ -1,13 +1,19 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::env;
+use std::io;
 use std::os::unix::process::ExitStatusExt;
+use std::time::Instant;
 use anyhow::{anyhow, Error, Result};
 use tokio::process::{Child, Command};
 use std::process::Stdio;
+use std::sync::Arc;
+use internal::msg::ResourceUsage;
 use tokio::sync::mpsc::{Sender, Receiver};
 use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use crate::docker::ContainerSpec;
 
 // runner运行操作系统命令，提供命令行，env和log file
 // 它是python-sh或go-sh的替代品
@@ -17,15 +23,27 @@ pub(crate) fn err_process_not_started() -> Error {
 
 // Mutex<CmdJob>
 
+// ContainerStats是读取容器资源统计的任务，以及它统计到的资源消耗
+pub(crate) type ContainerStats = (JoinHandle<()>, Arc<std::sync::Mutex<ResourceUsage>>);
+
 #[derive(Debug)]
 pub(crate) struct CmdJob
 {
     pub(crate) cmd: Mutex<Command>,
     pub(crate) result: Mutex<Option<Child>>,
     pub(crate) pid: Option<i32>,
+    pub(crate) started_at: Option<Instant>,
+    // 进程退出后得到的资源消耗
+    pub(crate) usage: Mutex<Option<ResourceUsage>>,
     pub(crate) working_dir: String,
     pub(crate) env: HashMap<String, String>,
-    pub(crate) log_file: Option<File>,
+    pub(crate) log_file: Mutex<Option<File>>,
+    // 在容器中运行时由BaseProvider通过docker API创建容器，cmd只记录容器中运行的命令
+    pub(crate) container: Option<ContainerSpec>,
+    pub(crate) container_id: Mutex<Option<String>>,
+    // 把容器的输出写入日志文件的任务
+    pub(crate) container_logs: Mutex<Option<JoinHandle<Result<()>>>>,
+    pub(crate) container_stats: Mutex<Option<ContainerStats>>,
     pub(crate) finished_tx: Mutex<Option<Sender<()>>>,
     pub(crate) finished_rx: Mutex<Option<Receiver<()>>>,
     pub(crate) ret_err: Mutex<String>,
@@ -37,16 +55,32 @@ impl CmdJob {
             cmd: Mutex::new(cmd),
             result: Mutex::new(None),
             pid: None,
+            started_at: None,
+            usage: Mutex::new(None),
             working_dir,
             env,
-            log_file: None,
+            log_file: Mutex::new(None),
+            container: None,
+            container_id: Mutex::new(None),
+            container_logs: Mutex::new(None),
+            container_stats: Mutex::new(None),
             finished_tx: Mutex::new(None),
             finished_rx: Mutex::new(None),
             ret_err: Mutex::from(String::new()),
         }
     }
     
+    pub(crate) fn new_container(spec: ContainerSpec, working_dir: String, env: HashMap<String, String>) -> Self {
+        let mut cmd = Command::new(&spec.cmd[0]);
+        cmd.args(&spec.cmd[1..]);
+        CmdJob{
+            container: Some(spec),
+            ..CmdJob::new(cmd, working_dir, env)
+        }
+    }
+
     pub(crate) async fn set_log_file(&self, log_file: Option<File>){
+        *self.log_file.lock().await = log_file.as_ref().map(|log_file| log_file.try_clone().expect("复制文件句柄时失败"));
         let mut cmd_lock = self.cmd.lock().await;
         match log_file {
             Some(log_file) => {
@@ -75,6 +109,11 @@ impl CmdJob {
                 // 命令正在运行。这个分支只能进入一次，分支内将drop发送端
                 TryRecvError::Empty => {
                     if let Some(child) = self.result.lock().await.as_mut() {
+                        // 先在不回收进程的情况下得到它的资源消耗，再由tokio回收进程
+                        let usage = match self.pid {
+                            Some(pid) => wait_usage(pid, self.started_at).await,
+                            None => None,
+                        };
                         let result = child.wait().await;
                         drop(self.finished_tx.lock().await.take().unwrap());
                         match result {
@@ -84,6 +123,7 @@ impl CmdJob {
                             }
                             // 命令正确，运行时是否出错都会进入这个分支，通过返回的状态码来判断是否正常退出
                             Ok(exit_status) => {
+                                *self.usage.lock().await = usage;
                                 match exit_status.code() {
                                     // 正常退出
                                     Some(0) => {},
@@ -109,6 +149,43 @@ impl CmdJob {
     }
 }
 
+// wait_usage等待进程退出但不回收它（WNOWAIT），得到这个进程和它回收过的子进程消耗的资源，
+// 进程之后仍由tokio的Child::wait回收。glibc的waitid不返回rusage，所以直接使用系统调用
+async fn wait_usage(pid: i32, started_at: Option<Instant>) -> Option<ResourceUsage> {
+    tokio::task::spawn_blocking(move || {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+        loop {
+            let ret = unsafe {
+                libc::syscall(libc::SYS_waitid, libc::P_PID, pid, &mut info as *mut libc::siginfo_t,
+                              libc::WEXITED | libc::WNOWAIT, &mut rusage as *mut libc::rusage)
+            };
+            if ret == 0 {
+                return Some(rusage_to_usage(&rusage, started_at))
+            }
+            if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                return None
+            }
+        }
+    }).await.ok().flatten()
+}
+
+fn timeval_ms(tv: &libc::timeval) -> u64 {
+    tv.tv_sec as u64 * 1000 + tv.tv_usec as u64 / 1000
+}
+
+pub(crate) fn rusage_to_usage(rusage: &libc::rusage, started_at: Option<Instant>) -> ResourceUsage {
+    ResourceUsage {
+        user_time_ms: timeval_ms(&rusage.ru_utime),
+        system_time_ms: timeval_ms(&rusage.ru_stime),
+        // Linux上ru_maxrss的单位是KiB，ru_inblock和ru_oublock的单位是512字节的块
+        max_rss_bytes: rusage.ru_maxrss as u64 * 1024,
+        read_bytes: rusage.ru_inblock as u64 * 512,
+        write_bytes: rusage.ru_oublock as u64 * 512,
+        elapsed_ms: started_at.map(|t| t.elapsed().as_millis() as u64).unwrap_or_default(),
+    }
+}
+
 pub(crate) fn new_environ(env: HashMap<String, String>, inherit: bool) -> HashMap<String, String> {
     let mut environ = HashMap::with_capacity(env.len());
 
@@ -132,3 +209,30 @@ pub(crate) fn new_environ(env: HashMap<String, String>, inherit: bool) -> HashMa
     environ
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wait_usage(){
+        // 同时运行的两个进程各自得到自己的资源消耗
+        let mut busy = Command::new("sh")
+            .args(["-c", "i=0; while [ $i -lt 200000 ]; do i=$((i+1)); done"])
+            .spawn().unwrap();
+        let mut idle = Command::new("sleep").arg("0.1").spawn().unwrap();
+        let started_at = Some(Instant::now());
+        let (busy_usage, idle_usage) = tokio::join!(
+            wait_usage(busy.id().unwrap() as i32, started_at),
+            wait_usage(idle.id().unwrap() as i32, started_at),
+        );
+        // 进程仍由tokio回收
+        assert!(busy.wait().await.unwrap().success());
+        assert!(idle.wait().await.unwrap().success());
+
+        let (busy_usage, idle_usage) = (busy_usage.unwrap(), idle_usage.unwrap());
+        assert!(busy_usage.user_time_ms + busy_usage.system_time_ms > 50, "{:?}", busy_usage);
+        assert!(idle_usage.user_time_ms + idle_usage.system_time_ms < 50, "{:?}", idle_usage);
+        assert!(busy_usage.max_rss_bytes > 0 && idle_usage.max_rss_bytes > 0);
+    }
+}
@@ -0,0 +1,677 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Component, Path};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use anyhow::{anyhow, Result};
+use anymap::AnyMap;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use regex::Regex;
+use reqwest::{Client, RequestBuilder, Response, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{oneshot, Mutex, RwLock, Semaphore};
+use tokio::task::JoinSet;
+use internal::util::dir_size;
+use internal::size::MirrorSize;
+use internal::msg::ResourceUsage;
+use crate::base_provider::BaseProvider;
+use crate::common::{Empty, DEFAULT_MAX_RETRY};
+use crate::config::ProviderEnum;
+use crate::context::Context;
+use crate::docker::DockerHook;
+use crate::hooks::{HookType, JobHook};
+use crate::provider::{MirrorProvider, _LOG_DIR_KEY, _LOG_FILE_KEY, _WORKING_DIR_KEY};
+use crate::rate_limit::TokenBucket;
+use crate::staged_sync::{write_log, StagedSync};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+const DEFAULT_ENDPOINT: &str = "https://s3.amazonaws.com";
+const DEFAULT_REGION: &str = "us-east-1";
+const DEFAULT_PROFILE: &str = "default";
+// 空请求体的sha256
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+lazy_static! {
+    static ref CONTENTS_REGEX: Regex = Regex::new(r"(?s)<Contents>(.*?)</Contents>").unwrap();
+    static ref XML_TAG_REGEX: Regex = Regex::new(r"<(\w+)>([^<]*)</\w+>").unwrap();
+}
+
+#[derive(Clone, Default, Debug)]
+pub(crate) struct S3Config {
+    pub(crate) name: String,
+    // s3://<bucket>/<prefix>/
+    pub(crate) upstream_url: String,
+    pub(crate) endpoint: String,
+    pub(crate) region: String,
+    // AWS格式的credentials文件，为空时从环境变量中读取，都没有时匿名访问
+    pub(crate) credentials_file: String,
+    pub(crate) profile: String,
+    pub(crate) env: HashMap<String, String>,
+
+    pub(crate) concurrency: usize,
+    // 每秒下载的字节数，0表示不限制
+    pub(crate) bandwidth_limit: u64,
+    // 不删除上游已经不存在的文件
+    pub(crate) no_delete: bool,
+
+    pub(crate) working_dir: String,
+    pub(crate) log_dir: String,
+    pub(crate) log_file: String,
+
+    pub(crate) interval: Duration,
+    pub(crate) retry: i64,
+    pub(crate) timeout: Duration,
+}
+
+#[derive(Clone, Default, PartialEq)]
+pub(crate) struct Credentials {
+    pub(crate) access_key_id: String,
+    pub(crate) secret_access_key: String,
+    pub(crate) session_token: String,
+}
+
+// 不在日志中输出密钥
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials").field("access_key_id", &self.access_key_id).finish_non_exhaustive()
+    }
+}
+
+// S3Object是ListObjectsV2返回的对象信息
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct S3Object {
+    pub(crate) size: u64,
+    pub(crate) etag: String,
+    pub(crate) mtime: String,
+}
+
+impl S3Object {
+    // modified解析LastModified，格式如 2009-10-12T17:50:30.000Z
+    pub(crate) fn modified(&self) -> Option<SystemTime> {
+        chrono::DateTime::parse_from_rfc3339(&self.mtime).ok().map(SystemTime::from)
+    }
+}
+
+// parse_s3_url把 s3://bucket/prefix 拆分成bucket和以/结尾的prefix
+pub(crate) fn parse_s3_url(url: &str) -> Result<(String, String)> {
+    let rest = url.strip_prefix("s3://")
+        .ok_or_else(|| anyhow!("s3 provider的upstream应该以s3://开头：{}", url))?;
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        return Err(anyhow!("upstream {} 中没有bucket", url))
+    }
+    let mut prefix = prefix.trim_start_matches('/').to_string();
+    if !prefix.is_empty() && !prefix.ends_with('/') {
+        prefix.push('/');
+    }
+    Ok((bucket.to_string(), prefix))
+}
+
+// load_credentials_file从AWS格式的credentials文件中读取profile对应的密钥
+pub(crate) fn load_credentials_file(path: &str, profile: &str) -> Result<Credentials> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("读取credentials文件 {} 失败：{}", path, e))?;
+    let mut section = String::new();
+    let mut values = HashMap::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().trim_start_matches("profile ").trim().to_string();
+            continue
+        }
+        if section != profile {
+            continue
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            values.insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    let get = |key: &str| values.get(key).cloned().unwrap_or_default();
+    let creds = Credentials {
+        access_key_id: get("aws_access_key_id"),
+        secret_access_key: get("aws_secret_access_key"),
+        session_token: get("aws_session_token"),
+    };
+    if creds.access_key_id.is_empty() || creds.secret_access_key.is_empty() {
+        return Err(anyhow!("credentials文件 {} 中没有profile {} 的密钥", path, profile))
+    }
+    Ok(creds)
+}
+
+// uri_encode按照SigV4的要求编码，只保留非保留字符
+pub(crate) fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC可以使用任意长度的密钥");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// sign_request按照AWS Signature Version 4为没有请求体的请求签名，返回需要添加的请求头；
+// url的路径和参数需要已经用uri_encode编码过
+pub(crate) fn sign_request(creds: &Credentials, region: &str, method: &str, url: &Url, amz_date: &str) -> Vec<(String, String)> {
+    let date = &amz_date[..8];
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let mut query: Vec<String> = url.query().unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| if pair.contains('=') { pair.to_string() } else { format!("{}=", pair) })
+        .collect();
+    query.sort();
+
+    let mut headers = vec![
+        ("host".to_string(), host),
+        ("x-amz-content-sha256".to_string(), EMPTY_SHA256.to_string()),
+        ("x-amz-date".to_string(), amz_date.to_string()),
+    ];
+    if !creds.session_token.is_empty() {
+        headers.push(("x-amz-security-token".to_string(), creds.session_token.clone()));
+    }
+    let canonical_headers: String = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v.trim())).collect();
+    let signed_headers = headers.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>().join(";");
+    let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}",
+        method, url.path(), query.join("&"), canonical_headers, signed_headers, EMPTY_SHA256);
+
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date, scope, hex::encode(Sha256::digest(canonical_request.as_bytes())));
+    let mut key = hmac_sha256(format!("AWS4{}", creds.secret_access_key).as_bytes(), date);
+    for part in [region, "s3", "aws4_request"] {
+        key = hmac_sha256(&key, part);
+    }
+    let signature = hex::encode(hmac_sha256(&key, &string_to_sign));
+
+    headers.remove(0);
+    headers.push(("authorization".to_string(), format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        creds.access_key_id, scope, signed_headers, signature)));
+    headers
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&#34;", "\"")
+        .replace("&amp;", "&")
+}
+
+// xml_fields返回一段xml中所有不包含子元素的元素的值
+fn xml_fields(xml: &str) -> HashMap<String, String> {
+    XML_TAG_REGEX.captures_iter(xml)
+        .map(|cap| (cap[1].to_string(), xml_unescape(&cap[2])))
+        .collect()
+}
+
+// 对象的key和对象信息
+type ObjectList = Vec<(String, S3Object)>;
+
+// parse_list_objects解析ListObjectsV2的结果，返回对象列表和下一页的continuation token
+pub(crate) fn parse_list_objects(xml: &str) -> Result<(ObjectList, Option<String>)> {
+    let mut objects = vec![];
+    for cap in CONTENTS_REGEX.captures_iter(xml) {
+        let fields = xml_fields(&cap[1]);
+        let key = fields.get("Key").cloned().ok_or_else(|| anyhow!("对象信息中没有Key"))?;
+        let size = fields.get("Size").and_then(|s| s.parse().ok())
+            .ok_or_else(|| anyhow!("对象 {} 的Size无效", key))?;
+        objects.push((key, S3Object {
+            size,
+            etag: fields.get("ETag").cloned().unwrap_or_default(),
+            mtime: fields.get("LastModified").cloned().unwrap_or_default(),
+        }));
+    }
+    let rest = CONTENTS_REGEX.replace_all(xml, "");
+    let fields = xml_fields(&rest);
+    let token = match fields.get("IsTruncated").map(String::as_str) {
+        Some("true") => Some(fields.get("NextContinuationToken").cloned()
+            .ok_or_else(|| anyhow!("结果被截断但没有NextContinuationToken"))?),
+        _ => None,
+    };
+    Ok((objects, token))
+}
+
+// relative_key返回对象相对于prefix的本地路径，路径不合法时返回None
+fn relative_key(prefix: &str, key: &str) -> Option<String> {
+    let path = key.strip_prefix(prefix)?;
+    let valid = !path.is_empty()
+        && !path.ends_with('/')
+        && Path::new(path).components().all(|c| matches!(c, Component::Normal(_)));
+    if valid { Some(path.to_string()) } else { None }
+}
+
+// local_is_up_to_date判断本地文件是否和上游的对象一致，没有上一次的记录时比较本地文件的大小和修改时间
+fn local_is_up_to_date(local: &Path, old: Option<&S3Object>, remote: &S3Object) -> bool {
+    match fs::metadata(local) {
+        Ok(meta) if meta.is_file() && meta.len() == remote.size => match old {
+            Some(old) => old.etag == remote.etag && old.size == remote.size,
+            None => remote.modified().is_some_and(|t| meta.modified().ok() == Some(t)),
+        },
+        _ => false,
+    }
+}
+
+// check_response把S3的错误响应转换为错误信息，如 403 Forbidden AccessDenied: Access Denied
+async fn check_response(resp: Response) -> Result<Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp)
+    }
+    let fields = xml_fields(&resp.text().await.unwrap_or_default());
+    match (fields.get("Code"), fields.get("Message")) {
+        (Some(code), Some(msg)) => Err(anyhow!("{} {}: {}", status, code, msg)),
+        (Some(code), None) => Err(anyhow!("{} {}", status, code)),
+        _ => Err(anyhow!("{}", status)),
+    }
+}
+
+// S3Client是一个只支持ListObjectsV2和GetObject的S3客户端，使用path-style的地址
+#[derive(Clone, Default, Debug)]
+struct S3Client {
+    client: Client,
+    endpoint: String,
+    region: String,
+    creds: Option<Credentials>,
+}
+
+impl S3Client {
+    fn get(&self, url: Url) -> RequestBuilder {
+        let mut req = self.client.get(url.clone());
+        if let Some(creds) = self.creds.as_ref() {
+            let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+            for (key, value) in sign_request(creds, &self.region, "GET", &url, &amz_date) {
+                req = req.header(key, value);
+            }
+        }
+        req
+    }
+
+    fn object_url(&self, bucket: &str, key: &str) -> Result<Url> {
+        Ok(Url::parse(&format!("{}/{}/{}", self.endpoint, uri_encode(bucket, true), uri_encode(key, false)))?)
+    }
+
+    async fn list_objects(&self, bucket: &str, prefix: &str) -> Result<ObjectList> {
+        let mut objects = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = format!("list-type=2&prefix={}", uri_encode(prefix, true));
+            if let Some(token) = token.as_ref() {
+                query.push_str(&format!("&continuation-token={}", uri_encode(token, true)));
+            }
+            let url = Url::parse(&format!("{}/{}?{}", self.endpoint, uri_encode(bucket, true), query))?;
+            debug!("列出对象：{}", url);
+            let xml = check_response(self.get(url).send().await?).await?.text().await?;
+            let (page, next) = parse_list_objects(&xml)?;
+            objects.extend(page);
+            match next {
+                Some(next) => token = Some(next),
+                None => return Ok(objects),
+            }
+        }
+    }
+
+    // fetch_object下载对象到staged，下载速度受limiter限制
+    async fn fetch_object(&self, url: Url, remote: &S3Object, staged: &Path, limiter: Option<Arc<TokenBucket>>) -> Result<()> {
+        let mut resp = check_response(self.get(url).send().await?).await?;
+        if let Some(parent) = staged.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = tokio::fs::File::create(staged).await?;
+        let mut written = 0u64;
+        while let Some(chunk) = resp.chunk().await? {
+            if let Some(limiter) = limiter.as_ref() {
+                limiter.acquire(chunk.len() as u64).await;
+            }
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        file.flush().await?;
+        if written != remote.size {
+            return Err(anyhow!("文件不完整，应为 {} 字节，实际收到 {} 字节", remote.size, written))
+        }
+        if let Some(mtime) = remote.modified() {
+            file.into_std().await.set_modified(mtime)?;
+        }
+        Ok(())
+    }
+}
+
+// S3Provider把S3兼容的对象存储中的一个bucket或prefix同步到镜像目录，在worker进程内完成同步
+#[derive(Clone, Default, Debug)]
+pub(crate) struct S3Provider {
+    pub(crate) base_provider: Arc<RwLock<BaseProvider>>,
+    pub(crate) s3_config: Arc<S3Config>,
+    bucket: String,
+    prefix: String,
+    client: S3Client,
+    data_size: Arc<Mutex<MirrorSize>>,
+    // 终止同步时通过这个channel通知run
+    cancel: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+}
+
+unsafe impl Send for S3Provider{}
+unsafe impl Sync for S3Provider{}
+
+impl S3Provider {
+    pub(crate) async fn new(mut c: S3Config) -> Result<Self>{
+        if c.retry == 0{
+            c.retry = DEFAULT_MAX_RETRY;
+        }
+        if c.concurrency == 0{
+            c.concurrency = DEFAULT_CONCURRENCY;
+        }
+        if c.endpoint.is_empty() {
+            c.endpoint = DEFAULT_ENDPOINT.to_string();
+        }
+        c.endpoint = c.endpoint.trim_end_matches('/').to_string();
+        if c.region.is_empty() {
+            c.region = DEFAULT_REGION.to_string();
+        }
+        if c.profile.is_empty() {
+            c.profile = DEFAULT_PROFILE.to_string();
+        }
+        let (bucket, prefix) = parse_s3_url(&c.upstream_url)?;
+        let endpoint = Url::parse(&c.endpoint)
+            .map_err(|e| anyhow!("s3_endpoint {} 不是合法的地址：{}", c.endpoint, e))?;
+        if endpoint.scheme() != "http" && endpoint.scheme() != "https" {
+            return Err(anyhow!("s3_endpoint只支持http和https：{}", c.endpoint))
+        }
+
+        // 优先使用credentials文件，其次是mirror的env和worker的环境变量
+        let creds = if !c.credentials_file.is_empty() {
+            Some(load_credentials_file(&c.credentials_file, &c.profile)?)
+        } else {
+            let var = |key: &str| c.env.get(key).cloned()
+                .or_else(|| std::env::var(key).ok())
+                .unwrap_or_default();
+            let creds = Credentials {
+                access_key_id: var("AWS_ACCESS_KEY_ID"),
+                secret_access_key: var("AWS_SECRET_ACCESS_KEY"),
+                session_token: var("AWS_SESSION_TOKEN"),
+            };
+            if creds.access_key_id.is_empty() || creds.secret_access_key.is_empty() { None } else { Some(creds) }
+        };
+
+        let provider = S3Provider{
+            base_provider: Arc::new(RwLock::new(BaseProvider::new(c.name.clone(), c.interval, c.retry, c.timeout))),
+            s3_config: Arc::from(c.clone()),
+            bucket,
+            prefix,
+            client: S3Client {
+                client: Client::builder()
+                    .connect_timeout(std::time::Duration::from_secs(30))
+                    .user_agent(format!("rtsync/{}", env!("CARGO_PKG_VERSION")))
+                    .build()?,
+                endpoint: c.endpoint.clone(),
+                region: c.region.clone(),
+                creds,
+            },
+            ..S3Provider::default()
+        };
+        let base_provider_lock = provider.base_provider.write().await;
+        if let Some(ctx) = base_provider_lock.ctx.lock().await.as_mut(){
+            let mut value = AnyMap::new();
+            value.insert(c.working_dir);
+            ctx.set(_WORKING_DIR_KEY.to_string(), value);
+
+            let mut value = AnyMap::new();
+            value.insert(c.log_dir);
+            ctx.set(_LOG_DIR_KEY.to_string(), value);
+
+            let mut value = AnyMap::new();
+            value.insert(c.log_file);
+            ctx.set(_LOG_FILE_KEY.to_string(), value);
+        }
+        drop(base_provider_lock);
+        Ok(provider)
+    }
+
+    pub(crate) fn has_credentials(&self) -> bool {
+        self.client.creds.is_some()
+    }
+
+    // list_objects返回prefix下所有对象的本地路径和对象信息，跳过目录和路径不合法的对象
+    pub(crate) async fn list_objects(&self, log: &mut Option<File>) -> Result<BTreeMap<String, (String, S3Object)>> {
+        let mut objects = BTreeMap::new();
+        for (key, object) in self.client.list_objects(&self.bucket, &self.prefix).await? {
+            match relative_key(&self.prefix, &key) {
+                Some(path) => { objects.insert(path, (key, object)); }
+                None if key.ends_with('/') => {}
+                None => write_log(log, &format!("跳过路径不合法的对象 {}", key)),
+            }
+        }
+        Ok(objects)
+    }
+
+    async fn open_log_file(&self) -> Result<Option<File>> {
+        let log_file = self.log_file().await;
+        if log_file.eq("/dev/null") {
+            return Ok(None)
+        }
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(&log_file)
+            .map_err(|e| anyhow!("无法打开log文件：{} : {}", log_file, e))?;
+        Ok(Some(file))
+    }
+
+    // sync下载有变化的对象到临时目录，全部成功后先替换镜像目录中的文件，再删除上游已经不存在的文件
+    async fn sync(&self, log: &mut Option<File>) -> Result<()> {
+        let staged_sync = StagedSync::new(&self.working_dir().await, &self.log_dir().await, &self.name(), "s3")?;
+        let old_state: BTreeMap<String, S3Object> = staged_sync.load_state();
+
+        if !self.has_credentials() {
+            write_log(log, "没有配置密钥，匿名访问");
+        }
+        let objects = self.list_objects(log).await?;
+        if objects.is_empty() {
+            return Err(anyhow!("上游 {} 中没有任何对象", self.s3_config.upstream_url))
+        }
+        write_log(log, &format!("上游共有 {} 个对象", objects.len()));
+
+        // 由worker分配带宽时使用分到的带宽，否则使用s3_bandwidth_limit
+        let limiter = match (self.base_provider.read().await.bandwidth.clone(), self.s3_config.bandwidth_limit) {
+            (Some(limiter), _) => Some(limiter),
+            (None, 0) => None,
+            (None, limit) => Some(Arc::new(TokenBucket::new(limit))),
+        };
+        let semaphore = Arc::new(Semaphore::new(self.s3_config.concurrency));
+        let mut tasks = JoinSet::new();
+        for (path, (key, remote)) in objects.iter() {
+            if local_is_up_to_date(&staged_sync.local(path), old_state.get(path), remote) {
+                continue
+            }
+            let url = self.client.object_url(&self.bucket, key)?;
+            let (client, semaphore, limiter) = (self.client.clone(), semaphore.clone(), limiter.clone());
+            let (path, remote, staged) = (path.clone(), remote.clone(), staged_sync.staged(path));
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let result = client.fetch_object(url, &remote, &staged, limiter).await;
+                (path, remote, result)
+            });
+        }
+
+        let mut downloaded = vec![];
+        let mut failed = 0;
+        while let Some(joined) = tasks.join_next().await {
+            let (path, remote, result) = joined?;
+            match result {
+                Ok(()) => {
+                    write_log(log, &format!("下载 {} ({} 字节)", path, remote.size));
+                    downloaded.push(path);
+                }
+                Err(e) => {
+                    write_log(log, &format!("下载 {} 失败：{}", path, e));
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            staged_sync.abort();
+            return Err(anyhow!("{}/{} 个对象下载失败，镜像未更新", failed, failed + downloaded.len()))
+        }
+
+        staged_sync.install(&downloaded)?;
+        let removed = match self.s3_config.no_delete {
+            true => 0,
+            false => staged_sync.remove_stale(|path| objects.contains_key(path), log)?,
+        };
+        let new_state: BTreeMap<&String, &S3Object> = objects.iter().map(|(path, (_, object))| (path, object)).collect();
+        staged_sync.save_state(&new_state)?;
+        write_log(log, &format!("下载了 {} 个对象，删除了 {} 个文件", downloaded.len(), removed));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MirrorProvider for S3Provider {
+    fn name(&self) -> String {
+        self.s3_config.name.clone()
+    }
+
+    fn upstream(&self) -> String {
+        self.s3_config.upstream_url.clone()
+    }
+
+    fn r#type(&self) -> ProviderEnum {
+        ProviderEnum::S3
+    }
+
+    async fn run(&self, started: Sender<Empty>) -> Result<()> {
+        if self.is_running().await {
+            return Err(anyhow!("provider现在正在运行"))
+        }
+        { *self.data_size.lock().await = MirrorSize::default(); }
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        { *self.cancel.lock().await = Some(cancel_tx); }
+
+        let mut log = self.open_log_file().await?;
+        self.base_provider.read().await.is_running.store(true, Ordering::Release);
+        debug!("将is_running字段设置为true :{}", self.name());
+        started.send(()).await.expect("无法发送");
+
+        let result = tokio::select! {
+            result = self.sync(&mut log) => result,
+            _ = cancel_rx => Err(anyhow!("同步被终止")),
+        };
+        if let Err(e) = result.as_ref() {
+            write_log(&mut log, &format!("同步失败：{}", e));
+        }
+        match dir_size(Path::new(&self.working_dir().await)) {
+            Ok(size) => *self.data_size.lock().await = MirrorSize::from_bytes(size),
+            Err(e) => warn!("统计 {} 的大小失败：{}", self.working_dir().await, e),
+        }
+
+        self.cancel.lock().await.take();
+        self.base_provider.read().await.is_running.store(false, Ordering::Release);
+        debug!("将is_running字段设置为false，调用者：{}", self.name());
+        result
+    }
+
+    async fn terminate(&self) -> Result<()> {
+        debug!("正在终止provider：{}", self.name());
+        if let Some(cancel) = self.cancel.lock().await.take() {
+            let _ = cancel.send(());
+        }
+        Ok(())
+    }
+
+    async fn is_running(&self) -> bool {
+        self.base_provider.read().await.is_running()
+    }
+
+    async fn docker(&self) -> Arc<Option<DockerHook>> {
+        self.base_provider.read().await.docker_ref()
+    }
+
+    async fn add_hook(&mut self, hook: HookType) {
+        self.base_provider.write().await.add_hook(hook).await;
+    }
+
+    async fn hooks(&self) -> Arc<Mutex<Vec<Box<dyn JobHook>>>> {
+        self.base_provider.read().await
+            .hooks()
+    }
+
+    fn interval(&self) -> Duration {
+        self.s3_config.interval
+    }
+
+    fn retry(&self) -> i64 {
+        self.s3_config.retry
+    }
+
+    fn timeout(&self) -> Duration {
+        self.s3_config.timeout
+    }
+
+    async fn working_dir(&self) -> String {
+        self.base_provider.read().await.working_dir().await
+    }
+
+    async fn log_dir(&self) -> String {
+        self.base_provider.read().await.log_dir().await
+    }
+
+    async fn log_file(&self) -> String {
+        self.base_provider.read().await.log_file().await
+    }
+
+    async fn is_master(&self) -> bool {
+        self.base_provider.read().await.is_master
+    }
+
+    async fn data_size(&self) -> MirrorSize {
+        self.data_size.lock().await.clone()
+    }
+
+    // 同步在worker进程内完成，没有单独统计资源消耗
+    async fn resource_usage(&self) -> Option<ResourceUsage> {
+        *self.base_provider.read().await.usage.lock().await
+    }
+
+    async fn set_bandwidth(&self, limiter: Option<Arc<TokenBucket>>) {
+        self.base_provider.write().await.bandwidth = limiter;
+    }
+
+    fn bandwidth_adjustable(&self) -> bool {
+        true
+    }
+
+    async fn enter_context(&mut self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .enter_context().await
+    }
+
+    async fn exit_context(&mut self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .exit_context().await
+    }
+
+    async fn context(&self) -> Arc<Mutex<Option<Context>>> {
+        self.base_provider.write().await
+            .context()
+    }
+}
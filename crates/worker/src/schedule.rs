@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{Mutex, MutexGuard};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use skiplist::skipmap::SkipMap;
-use log::{debug, warn};
+use log::{debug, info, warn};
 use crate::job::MirrorJob;
+use crate::concurrency::effective_priority;
 // jobs的调度队列
 
 #[derive(Debug, Clone)]
@@ -55,26 +56,52 @@ impl ScheduleQueue {
         }
 
         jobs.insert(job_name.clone(), true);
-        list.insert(sched_time, job);
+        Self::insert(&mut list, sched_time, job);
 
         debug!("添加了 job {} @ {:?}", job_name, sched_time);
     }
 
-    // 如果第一个任务的同步时间比现在早，将其弹出
+    // insert以sched_time为键插入job，已经有任务使用这个时间时往后错开一纳秒，避免覆盖其他任务
+    fn insert(list: &mut SkipMap<DateTime<Utc>, MirrorJob>, mut sched_time: DateTime<Utc>, job: MirrorJob) {
+        while list.contains_key(&sched_time) {
+            sched_time += Duration::nanoseconds(1);
+        }
+        list.insert(sched_time, job);
+    }
+
+    // 在同步时间比现在早的任务中弹出优先级最高的一个，优先级相同时弹出同步时间最早的
+    // 任务的优先级随着超过同步时间的时长增加，避免低优先级的任务一直得不到同步
+    // 不在允许同步的时间段内的任务被推迟到下一个允许同步的时间，留在队列中
     pub async fn pop(&self) -> Option<MirrorJob> {
         let mut list = self.list.lock().await;
-        
-        if let Some((sched_time, job)) = list.front() {
-            let sched_time = *sched_time;
-            let job_name = job.name();
-            if sched_time < Utc::now() {
-                let ret = list.pop_front().unwrap();
-                self.jobs.lock().await.remove(&job_name);
-                debug!("移出 job {} @ {:?}", job_name, sched_time);
-                return Some(ret.1);
+        let now = Utc::now();
+
+        let mut deferred = vec![];
+        let mut best: Option<(i64, DateTime<Utc>)> = None;
+        for (sched_time, job) in list.iter() {
+            if *sched_time >= now {
+                break;
             }
+            if let Some(next) = job.deferred_until(now) {
+                deferred.push((*sched_time, next));
+                continue;
+            }
+            let priority = effective_priority(job.priority, (now - *sched_time).to_std().unwrap_or_default());
+            if best.is_none_or(|(p, _)| priority > p) {
+                best = Some((priority, *sched_time));
+            }
+        }
+        for (sched_time, next) in deferred {
+            let job = list.remove(&sched_time).unwrap();
+            info!("job {} 不在允许同步的时间段内，推迟到 {:?}", job.name(), next);
+            Self::insert(&mut list, next, job);
         }
-        None
+
+        let (_, sched_time) = best?;
+        let job = list.remove(&sched_time).unwrap();
+        self.jobs.lock().await.remove(&job.name());
+        debug!("移出 job {} @ {:?}", job.name(), sched_time);
+        Some(job)
     }
 
     pub fn remove(&self, 
@@ -105,6 +132,8 @@ mod tests {
     use chrono::Duration;
     use internal::logger::init_logger;
     use crate::cmd_provider::{CmdConfig, CmdProvider};
+    use crate::sync_window::SyncWindow;
+    use crate::concurrency::CRITICAL_PRIORITY;
 
     #[tokio::test]
     async fn test_popping_on_empty_schedule() {
@@ -153,6 +182,73 @@ mod tests {
         
     }
     
+    #[tokio::test]
+    async fn test_jobs_at_same_time(){
+        let schedule = ScheduleQueue::new();
+        let sched = Utc::now() - Duration::seconds(1);
+        for name in ["schedule_test1", "schedule_test2"] {
+            let c = CmdConfig{
+                name: name.to_string(),
+                ..CmdConfig::default()
+            };
+            let provider = CmdProvider::new(c).await.unwrap();
+            schedule.add_job(sched, MirrorJob::new(Box::new(provider))).await;
+        }
+        assert_eq!(schedule.get_jobs().await.len(), 2);
+        assert_eq!(schedule.pop().await.unwrap().name(), "schedule_test1");
+        assert_eq!(schedule.pop().await.unwrap().name(), "schedule_test2");
+    }
+
+    #[tokio::test]
+    async fn test_deferring_jobs_outside_window(){
+        let schedule = ScheduleQueue::new();
+        let c = CmdConfig{
+            name: "schedule_test".to_string(),
+            ..CmdConfig::default()
+        };
+        let provider = CmdProvider::new(c).await.unwrap();
+        let mut job = MirrorJob::new(Box::new(provider));
+        // 只允许在两小时后同步
+        let start = Utc::now() + Duration::hours(2);
+        let end = start + Duration::hours(1);
+        let window = format!("{}-{}", start.format("%H:%M"), end.format("%H:%M"));
+        job.window = Some(SyncWindow::parse(&[window], &[], Some("UTC"), false).unwrap());
+
+        schedule.add_job(Utc::now() - Duration::seconds(1), job.clone()).await;
+        assert!(schedule.pop().await.is_none());
+        let jobs = schedule.get_jobs().await;
+        assert_eq!(jobs.len(), 1);
+        assert!(jobs[0].next_scheduled > start - Duration::minutes(1));
+        assert!(jobs[0].next_scheduled <= start);
+    }
+
+    #[tokio::test]
+    async fn test_popping_jobs_by_priority(){
+        let schedule = ScheduleQueue::new();
+        let now = Utc::now();
+        for (name, priority, sched) in [
+            ("low", -40, now - Duration::minutes(30)),
+            ("normal", 0, now - Duration::seconds(1)),
+            ("critical", CRITICAL_PRIORITY, now - Duration::seconds(2)),
+            // 已经等待了10分钟，优先级从-5变为5
+            ("aged", -5, now - Duration::minutes(10)),
+            ("later", CRITICAL_PRIORITY, now + Duration::hours(1)),
+        ] {
+            let c = CmdConfig{
+                name: name.to_string(),
+                ..CmdConfig::default()
+            };
+            let provider = CmdProvider::new(c).await.unwrap();
+            let mut job = MirrorJob::new(Box::new(provider));
+            job.priority = priority;
+            schedule.add_job(sched, job).await;
+        }
+        for name in ["critical", "aged", "normal", "low"] {
+            assert_eq!(schedule.pop().await.unwrap().name(), name);
+        }
+        assert!(schedule.pop().await.is_none());
+    }
+
     #[tokio::test]
     async fn test_removing_jobs(){
         let schedule = ScheduleQueue::new();
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+// StagedSync是http和s3 provider共用的同步流程：有变化的文件先下载到临时目录，全部成功后先移动到镜像目录，
+// 再删除上游已经不存在的文件，最后把上游文件的元信息写入状态文件，供下一次同步判断文件是否有变化。
+// 临时目录和状态文件都在日志目录中，不会被镜像的用户看到。每个文件的替换是原子的，但文件是逐个替换的，
+// 替换过程中用户可能同时看到新旧两个版本的文件，需要整体切换时使用 publish_mode = "atomic"
+pub(crate) struct StagedSync {
+    working_dir: PathBuf,
+    staging: PathBuf,
+    log_dir: PathBuf,
+    state_path: PathBuf,
+}
+
+impl StagedSync {
+    // new创建镜像目录并删除上一次同步残留的临时目录，kind区分不同的provider，例如 http、s3
+    pub(crate) fn new(working_dir: &str, log_dir: &str, name: &str, kind: &str) -> Result<Self> {
+        let working_dir = PathBuf::from(working_dir);
+        fs::create_dir_all(&working_dir)?;
+        let log_dir = PathBuf::from(log_dir);
+        let staging = log_dir.join(format!(".{}-tmp-{}", kind, name));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        let state_path = log_dir.join(format!(".{}-state-{}.json", kind, name));
+        Ok(StagedSync { working_dir, staging, log_dir, state_path })
+    }
+
+    // local返回文件在镜像目录中的路径
+    pub(crate) fn local(&self, path: &str) -> PathBuf {
+        self.working_dir.join(path)
+    }
+
+    // staged返回文件在临时目录中的路径
+    pub(crate) fn staged(&self, path: &str) -> PathBuf {
+        self.staging.join(path)
+    }
+
+    // load_state读取上一次同步记录的状态，读取失败时返回空的状态，所有文件都会被重新检查
+    pub(crate) fn load_state<T: DeserializeOwned>(&self) -> BTreeMap<String, T> {
+        match fs::read(&self.state_path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!("读取 {} 失败：{}，将重新检查所有文件", self.state_path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        }
+    }
+
+    // abort在下载失败时删除临时目录，镜像目录保持不变
+    pub(crate) fn abort(&self) {
+        let _ = fs::remove_dir_all(&self.staging);
+    }
+
+    // install把下载好的文件移动到镜像目录中，然后删除临时目录
+    pub(crate) fn install(&self, downloaded: &[String]) -> Result<()> {
+        for path in downloaded {
+            install_file(&self.working_dir, &self.staged(path), &self.local(path))?;
+        }
+        fs::remove_dir_all(&self.staging).or_else(|e| if self.staging.exists() { Err(e) } else { Ok(()) })?;
+        Ok(())
+    }
+
+    // remove_stale删除镜像目录中is_upstream返回false的文件以及因此变空的目录，返回删除的文件数
+    pub(crate) fn remove_stale(&self, is_upstream: impl Fn(&str) -> bool, log: &mut Option<File>) -> Result<usize> {
+        let mut existing = vec![];
+        local_files(&self.working_dir, &self.working_dir, &mut existing)?;
+        let mut removed = 0;
+        for path in existing.iter().filter(|p| !is_upstream(p)) {
+            write_log(log, &format!("删除 {}", path));
+            fs::remove_file(self.local(path))?;
+            removed += 1;
+        }
+        remove_empty_dirs(&self.working_dir)?;
+        Ok(removed)
+    }
+
+    // save_state记录这一次同步后上游文件的状态
+    pub(crate) fn save_state<T: Serialize>(&self, state: &T) -> Result<()> {
+        fs::create_dir_all(&self.log_dir)?;
+        fs::write(&self.state_path, serde_json::to_vec_pretty(state)?)?;
+        Ok(())
+    }
+}
+
+pub(crate) fn write_log(log: &mut Option<File>, msg: &str) {
+    if let Some(file) = log.as_mut() {
+        if let Err(e) = writeln!(file, "{}", msg) {
+            warn!("写入日志文件失败：{}", e);
+        }
+    }
+}
+
+// local_files返回镜像目录中的所有文件
+fn local_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if fs::symlink_metadata(&path)?.is_dir() {
+            local_files(root, &path, files)?;
+        } else {
+            files.push(path.strip_prefix(root)?.display().to_string());
+        }
+    }
+    Ok(())
+}
+
+// remove_empty_dirs删除dir下的空目录，返回dir本身是否为空
+fn remove_empty_dirs(dir: &Path) -> Result<bool> {
+    let mut empty = true;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if fs::symlink_metadata(&path)?.is_dir() && remove_empty_dirs(&path)? {
+            fs::remove_dir(&path)?;
+        } else {
+            empty = false;
+        }
+    }
+    Ok(empty)
+}
+
+// install_file把下载好的文件原子地移动到镜像目录中的dst。上游把文件换成了同名目录（或者相反）时，
+// 先删除挡在路径上的、上游已经不存在的文件或目录；临时目录和镜像目录不在同一个文件系统上时，
+// 先复制到dst旁边的临时文件再改名，用户不会看到复制到一半的文件
+fn install_file(root: &Path, staged: &Path, dst: &Path) -> Result<()> {
+    if let Some(parent) = dst.parent() {
+        for ancestor in parent.ancestors().take_while(|a| *a != root && a.starts_with(root)) {
+            if fs::symlink_metadata(ancestor).is_ok_and(|m| !m.is_dir()) {
+                fs::remove_file(ancestor)?;
+            }
+        }
+        fs::create_dir_all(parent)?;
+    }
+    if fs::symlink_metadata(dst).is_ok_and(|m| m.is_dir()) {
+        fs::remove_dir_all(dst)?;
+    }
+    match fs::rename(staged, dst) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            let tmp = dst.with_file_name(format!(".{}.tmp", dst.file_name().unwrap_or_default().to_string_lossy()));
+            if let Err(e) = fs::copy(staged, &tmp).and_then(|_| fs::rename(&tmp, dst)) {
+                let _ = fs::remove_file(&tmp);
+                return Err(e.into())
+            }
+            fs::remove_file(staged)?;
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn test_staging_in_log_dir(){
+        let tmp_dir = Builder::new().prefix("rtsync").tempdir().unwrap();
+        let working_dir = tmp_dir.path().join("mirrors/debian");
+        let log_dir = tmp_dir.path().join("log");
+        let new = || StagedSync::new(&working_dir.display().to_string(), &log_dir.display().to_string(), "debian", "http").unwrap();
+
+        // 临时目录在日志目录中，不在镜像目录所在的目录中
+        let staged_sync = new();
+        let staged = staged_sync.staged("dists/Release");
+        assert_eq!(staged, log_dir.join(".http-tmp-debian/dists/Release"));
+        fs::create_dir_all(staged.parent().unwrap()).unwrap();
+        fs::write(&staged, "v1").unwrap();
+        assert_eq!(fs::read_dir(tmp_dir.path().join("mirrors")).unwrap().count(), 1);
+
+        staged_sync.install(&["dists/Release".to_string()]).unwrap();
+        assert_eq!(fs::read_to_string(working_dir.join("dists/Release")).unwrap(), "v1");
+        assert!(!log_dir.join(".http-tmp-debian").exists());
+
+        // 上一次同步残留的临时目录被删除
+        fs::create_dir_all(log_dir.join(".http-tmp-debian")).unwrap();
+        new();
+        assert!(!log_dir.join(".http-tmp-debian").exists());
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use crate::cron_schedule::{parse_field, parse_timezone, WEEKDAY_NAMES};
+
+// 查找下一个允许同步的时间时最多向后查找的时间，时间段每周重复，8天足够
+const MAX_SEARCH_MINUTES: i64 = 8 * 24 * 60;
+
+// TimeRange是每周重复的一个时间段，如 "01:00-07:00 weekdays"，结束时间早于开始时间表示跨过午夜
+#[derive(Debug, Clone, PartialEq)]
+struct TimeRange {
+    // 一天中的第几分钟
+    start: u32,
+    end: u32,
+    // 周日为0的位图，跨过午夜时指的是开始的那一天
+    weekdays: u64,
+}
+
+fn parse_minute(s: &str) -> Result<u32> {
+    let (hour, minute) = s.split_once(':')
+        .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)))
+        .filter(|(h, m)| *m < 60 && (*h < 24 || (*h == 24 && *m == 0)))
+        .ok_or_else(|| anyhow!("无效的时间：{}", s))?;
+    Ok(hour * 60 + minute)
+}
+
+impl TimeRange {
+    fn parse(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        let (start, end) = parts.next().and_then(|range| range.split_once('-'))
+            .ok_or_else(|| anyhow!("时间段的格式应该是 HH:MM-HH:MM [星期]：{}", s))?;
+        let (start, end) = (parse_minute(start)?, parse_minute(end)?);
+        if start == end || start == 24 * 60 {
+            return Err(anyhow!("无效的时间段：{}", s))
+        }
+        let weekdays = match parts.next() {
+            None | Some("daily") | Some("everyday") => 0x7f,
+            Some("weekdays") => 0b0111110,
+            Some("weekends") => 0b1000001,
+            Some(days) => {
+                let bits = parse_field(days, 0, 7, &WEEKDAY_NAMES)?;
+                // 7和0都表示周日
+                (bits | (bits >> 7)) & 0x7f
+            }
+        };
+        if parts.next().is_some() {
+            return Err(anyhow!("时间段的格式应该是 HH:MM-HH:MM [星期]：{}", s))
+        }
+        Ok(TimeRange { start, end, weekdays })
+    }
+
+    fn contains(&self, t: NaiveDateTime) -> bool {
+        let weekday = t.weekday().num_days_from_sunday();
+        let minute = t.hour() * 60 + t.minute();
+        let on = |day: u32| self.weekdays & (1 << day) != 0;
+        if self.start < self.end {
+            on(weekday) && self.start <= minute && minute < self.end
+        } else {
+            (on(weekday) && minute >= self.start) || (on((weekday + 6) % 7) && minute < self.end)
+        }
+    }
+}
+
+// SyncWindow限制mirror可以开始同步的时间：配置了allowed_windows时只能在其中的时间段内同步，
+// 并且不能在blackout的时间段内同步；pause为true时窗口关闭后正在运行的同步会被暂停
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SyncWindow {
+    allowed: Vec<TimeRange>,
+    blackout: Vec<TimeRange>,
+    timezone: Option<Tz>,
+    pub(crate) pause: bool,
+}
+
+impl SyncWindow {
+    pub(crate) fn parse(allowed: &[String], blackout: &[String], timezone: Option<&str>, pause: bool) -> Result<Self> {
+        let window = SyncWindow {
+            allowed: allowed.iter().map(|s| TimeRange::parse(s)).collect::<Result<_>>()?,
+            blackout: blackout.iter().map(|s| TimeRange::parse(s)).collect::<Result<_>>()?,
+            timezone: parse_timezone(timezone)?,
+            pause,
+        };
+        if window.next_allowed(Utc::now()).is_none() {
+            return Err(anyhow!("没有允许同步的时间"))
+        }
+        Ok(window)
+    }
+
+    fn local(&self, t: DateTime<Utc>) -> NaiveDateTime {
+        match &self.timezone {
+            Some(tz) => t.with_timezone(tz).naive_local(),
+            None => t.with_timezone(&chrono::Local).naive_local(),
+        }
+    }
+
+    // allows返回t是否在允许同步的时间内
+    pub(crate) fn allows(&self, t: DateTime<Utc>) -> bool {
+        let local = self.local(t);
+        (self.allowed.is_empty() || self.allowed.iter().any(|r| r.contains(local)))
+            && !self.blackout.iter().any(|r| r.contains(local))
+    }
+
+    // next_allowed返回从t开始第一个允许同步的时间，t本身允许时直接返回t
+    pub(crate) fn next_allowed(&self, t: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.allows(t) {
+            return Some(t)
+        }
+        let mut next = t.with_second(0)?.with_nanosecond(0)?;
+        for _ in 0..MAX_SEARCH_MINUTES {
+            next += Duration::minutes(1);
+            if self.allows(next) {
+                return Some(next)
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_sync_window(){
+        // 2024-05-03是周五
+        let w = SyncWindow::parse(&["01:00-07:00 weekdays".to_string(), "22:00-08:00 sat,sun".to_string()],
+                                  &[], Some("UTC"), false).unwrap();
+        assert!(w.allows(utc("2024-05-03T01:00:00Z")));
+        assert!(!w.allows(utc("2024-05-03T07:00:00Z")));
+        assert_eq!(w.next_allowed(utc("2024-05-03T12:34:56Z")), Some(utc("2024-05-04T22:00:00Z")));
+        // 周日22:00开始的时间段延续到周一08:00
+        assert!(w.allows(utc("2024-05-06T07:59:00Z")));
+        assert_eq!(w.next_allowed(utc("2024-05-06T08:00:00Z")), Some(utc("2024-05-07T01:00:00Z")));
+
+        // 只有blackout，教学时间内不同步
+        let w = SyncWindow::parse(&[], &["08:00-12:00 mon-fri".to_string(), "14:00-18:00 mon-fri".to_string()],
+                                  Some("Asia/Shanghai"), true).unwrap();
+        assert!(w.pause);
+        assert!(w.allows(utc("2024-05-04T02:00:00Z")));
+        assert!(!w.allows(utc("2024-05-03T02:00:00Z")));
+        assert_eq!(w.next_allowed(utc("2024-05-03T02:00:00Z")), Some(utc("2024-05-03T04:00:00Z")));
+        assert_eq!(w.next_allowed(utc("2024-05-03T04:30:00Z")), Some(utc("2024-05-03T04:30:00Z")));
+    }
+
+    #[test]
+    fn test_sync_window_invalid(){
+        for invalid in ["", "01:00", "01:00-01:00", "25:00-01:00", "01:00-07:00 someday", "01:00-07:00 mon tue"] {
+            assert!(SyncWindow::parse(&[invalid.to_string()], &[], None, false).is_err(), "{}", invalid);
+        }
+        // 所有时间都被blackout
+        assert!(SyncWindow::parse(&[], &["00:00-24:00".to_string()], None, false).is_err());
+        assert!(SyncWindow::parse(&[], &[], Some("Nowhere"), false).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+#![cfg(test)]
+// 测试中共用的HTTP服务器，http provider、s3 provider和precheck的测试都用它模拟上游
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// Request是服务器收到的请求，请求头的名字都是小写的
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) headers: HashMap<String, String>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Response {
+    pub(crate) fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Response { status, body: body.into(), ..Response::default() }
+    }
+
+    // header添加一个响应头，value为空时不添加
+    pub(crate) fn header(mut self, name: &str, value: &str) -> Self {
+        if !value.is_empty() {
+            self.headers.push((name.to_string(), value.to_string()));
+        }
+        self
+    }
+}
+
+// serve启动一个HTTP服务器，每个请求都交给handler处理，HEAD请求只返回响应头，
+// 返回服务器的地址，例如 http://127.0.0.1:12345
+pub(crate) async fn serve<F>(handler: F) -> String
+where F: Fn(&Request) -> Response + Send + Sync + 'static
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                let mut req = vec![];
+                while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        return
+                    }
+                    req.extend_from_slice(&buf[..n]);
+                }
+                let req = String::from_utf8_lossy(&req).to_string();
+                let mut lines = req.lines();
+                let mut first = lines.next().unwrap().split_whitespace();
+                let request = Request {
+                    method: first.next().unwrap().to_string(),
+                    path: first.next().unwrap().to_string(),
+                    headers: lines.filter_map(|l| l.split_once(": "))
+                        .map(|(k, v)| (k.to_lowercase(), v.to_string()))
+                        .collect(),
+                };
+                let response = handler(&request);
+                let mut resp = format!("HTTP/1.1 {} OK\r\nContent-Length: {}\r\nConnection: close\r\n",
+                                       response.status, response.body.len());
+                for (name, value) in response.headers.iter() {
+                    resp.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                resp.push_str("\r\n");
+                let mut resp = resp.into_bytes();
+                if request.method != "HEAD" {
+                    resp.extend(response.body);
+                }
+                let _ = stream.write_all(&resp).await;
+            });
+        }
+    });
+    format!("http://{}", addr)
+}
@@ -14,9 +14,10 @@ use anymap::AnyMap;
 use chrono::Duration;
 use crate::base_provider::BaseProvider;
 use lazy_static::lazy_static;
-use libc::{getgid, getuid};
 use log::{debug, error};
 use internal::util::extract_size_from_rsync_log;
+use internal::size::MirrorSize;
+use internal::msg::ResourceUsage;
 use crate::common::{Empty, DEFAULT_MAX_RETRY};
 use crate::config::ProviderEnum;
 use crate::context::Context;
@@ -24,6 +25,7 @@ use crate::docker::DockerHook;
 use crate::hooks::{HookType, JobHook};
 use crate::provider::{MirrorProvider, _LOG_DIR_KEY, _LOG_FILE_KEY, _WORKING_DIR_KEY};
 use crate::runner::CmdJob;
+use crate::rate_limit::{rsync_bwlimit, TokenBucket};
 
 #[derive(Clone, Default, Debug)]
 pub(crate) struct TwoStageRsyncConfig {
@@ -60,7 +62,7 @@ pub(crate) struct TwoStageRsyncProvider {
     pub(crate) two_stage_rsync_config: Arc<TwoStageRsyncConfig>,
     stage1_options: Arc<Vec<String>>,
     stage2_options: Arc<Vec<String>>,
-    data_size: Arc<Mutex<String>>,
+    data_size: Arc<Mutex<MirrorSize>>,
 }
 
 unsafe impl Send for TwoStageRsyncProvider{}
@@ -190,43 +192,13 @@ impl TwoStageRsyncProvider {
     async fn cmd(&self, cmd_and_args: Vec<String>, working_dir: String, env: HashMap<String, String>){
         let mut base_provider_lock = self.base_provider.write().await;
         let cmd_job: CmdJob;
-        let mut args: Vec<String> = Vec::new();
         let use_docker = base_provider_lock.docker_ref().is_some();
 
         if let Some(d) = base_provider_lock.docker_ref().as_ref(){
-            let c = "docker";
-            args.extend(vec!["run".to_string(), "--rm".to_string(),
-                             // "-a".to_string(), "STDOUT".to_string(), "-a".to_string(), "STDERR".to_string(),
-                             "--name".to_string(), d.name(self.name().parse().unwrap()),
-                             "-w".to_string(), working_dir.clone()]);
-            // 指定用户
-            unsafe {
-                args.extend(vec!["-u".to_string(),
-                                 format!("{}:{}",getuid().to_string(), getgid().to_string())]);
-            }
-            // 添加卷
-            for vol in base_provider_lock.docker_volumes().await{
-                debug!("数据卷: {}", &vol);
-                args.extend(vec!["-v".to_string(), vol.clone()])
-            }
-            // 设置环境变量
-            for (key, value) in env.iter(){
-                let kv = format!("{}={}", key, value);
-                args.extend(vec!["-e".to_string(), kv.clone()])
-            }
-            // 设置内存限制
-            if d.memory_limit.0 != 0{
-                args.extend(vec!["-m".to_string(), format!("{}b", d.memory_limit.value())])
-            }
-            // 添加选项
-            args.extend(d.options.iter().cloned());
-            // 添加镜像和command
-            args.push(d.image.clone());
-            // 添加command
-            args.extend(cmd_and_args.iter().cloned());
-
-            cmd_job = CmdJob::new(Command::new(c), working_dir.clone(), env.clone());
-            { cmd_job.cmd.lock().await.args(&args); }
+            // 容器由BaseProvider通过docker API创建，这里只记录容器的配置
+            let spec = d.container_spec(self.name(), cmd_and_args.to_vec(), env.clone(),
+                                        working_dir.clone(), base_provider_lock.docker_volumes().await);
+            cmd_job = CmdJob::new_container(spec, working_dir.clone(), env.clone());
 
         }else {
             if cmd_and_args.len() == 1{
@@ -299,7 +271,8 @@ impl MirrorProvider for TwoStageRsyncProvider {
             return Err(anyhow!("provider现在正在运行"))
         }
         let base_provider_lock = self.base_provider.write().await;
-        { *self.data_size.lock().await = String::new(); }
+        { *self.data_size.lock().await = MirrorSize::default(); }
+        { *base_provider_lock.usage.lock().await = None; }
         drop(base_provider_lock);
         
         let stages = vec![1,2];
@@ -311,6 +284,10 @@ impl MirrorProvider for TwoStageRsyncProvider {
                 Err(e) => return Err(e),
                 Ok(options) => {
                     command.extend(options);
+                    // 每个阶段开始时使用当时分到的带宽
+                    if let Some(limiter) = self.base_provider.read().await.bandwidth.as_ref() {
+                        command.push(rsync_bwlimit(limiter));
+                    }
                     command.push(self.two_stage_rsync_config.upstream_url.clone());
                     command.push(self.working_dir().await);
                 }
@@ -353,7 +330,7 @@ impl MirrorProvider for TwoStageRsyncProvider {
         }
         
         if let Some(size) = extract_size_from_rsync_log(&*self.log_file().await) {
-            *self.data_size.lock().await = size.into()
+            *self.data_size.lock().await = size
         };
         Ok(())
     }
@@ -408,8 +385,16 @@ impl MirrorProvider for TwoStageRsyncProvider {
         self.base_provider.read().await.is_master
     }
 
-    async fn data_size(&self) -> String {
-        self.data_size.lock().await.to_string()
+    async fn data_size(&self) -> MirrorSize {
+        self.data_size.lock().await.clone()
+    }
+
+    async fn resource_usage(&self) -> Option<ResourceUsage> {
+        *self.base_provider.read().await.usage.lock().await
+    }
+
+    async fn set_bandwidth(&self, limiter: Option<Arc<TokenBucket>>) {
+        self.base_provider.write().await.bandwidth = limiter;
     }
 
     async fn enter_context(&mut self) -> Arc<Mutex<Option<Context>>> {
@@ -9,7 +9,7 @@ use rocket::serde::json::Json;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::{Mutex, MutexGuard, RwLock, Semaphore};
 use internal::msg::{CmdVerb, MirrorSchedule, MirrorSchedules, MirrorStatus, WorkerCmd, WorkerStatus};
-use internal::util::{create_http_client, get_json, post_json};
+use internal::util::{get_json, http_client_builder, load_client_identity, post_json};
 use libc::getpid;
 use nix::sys::signal::{kill, Signal};
 use rocket::http::Status;
@@ -18,12 +18,17 @@ use scopeguard::defer;
 use skiplist::SkipMap;
 use tokio::time;
 use internal::status::SyncStatus;
+use internal::size::MirrorSize;
 use crate::common::DEFAULT_MAX_RETRY;
 use crate::config::{Config, MirrorConfig};
 use crate::config_diff::{diff_mirror_config, Diff};
+use crate::disk_space_hook::disk_usages;
 use crate::job::{JobCtrlAction, JobMessage, MirrorJob, JobState};
 use crate::provider::new_mirror_provider;
 use crate::schedule::{JobScheduleInfo, ScheduleQueue};
+use crate::concurrency::ConcurrencyGroups;
+use crate::rate_limit::BandwidthBudget;
+use crate::precheck::UP_TO_DATE;
 
 #[derive(Serialize)]
 pub(crate) struct Response {
@@ -36,10 +41,14 @@ struct WorkerManager{
     jobs: Arc<RwLock<HashMap<String, MirrorJob>>>,
     manager_chan: (Sender<JobMessage>, Arc<Mutex<Receiver<JobMessage>>>),
     semaphore: Arc<Semaphore>,
+    // 每个上游主机或concurrency_group的并发限制
+    groups: ConcurrencyGroups,
+    // 正在同步的任务平分的总带宽
+    bandwidth: Arc<BandwidthBudget>,
     schedule: ScheduleQueue,
 }
 impl WorkerManager{
-    fn new(manager_chan_buffer: usize, semaphore_permits: usize)->Self{
+    fn new(manager_chan_buffer: usize, semaphore_permits: usize, groups: ConcurrencyGroups, bandwidth: BandwidthBudget)->Self{
         let (tx, rx) = channel(manager_chan_buffer);
         let rx = Arc::new(Mutex::new(rx));
         WorkerManager{
@@ -47,6 +56,8 @@ impl WorkerManager{
             jobs: Arc::new(RwLock::new(HashMap::new())),
             manager_chan: (tx, rx),
             semaphore: Arc::new(Semaphore::new(semaphore_permits)),
+            groups,
+            bandwidth: Arc::new(bandwidth),
             schedule: ScheduleQueue::new(),
         }
     }
@@ -70,7 +81,8 @@ impl WorkerManager{
 #[derive(Clone)]
 pub struct Worker {
     cfg: Arc<RwLock<Config>>,
-    exit: (Option<Sender<()>>, Arc<Mutex<Receiver<()>>>),
+    // 所有克隆共享同一个发送端，任一克隆调用halt都会使run返回
+    exit: (Arc<Mutex<Option<Sender<()>>>>, Arc<Mutex<Receiver<()>>>),
     http_engine: Arc<Mutex<Option<Rocket<Build>>>>,
     http_client: Client,
     worker_manager: WorkerManager,  // 将要被manage到rocket的数据
@@ -84,12 +96,29 @@ impl Worker {
         }
         let mut http_client = Client::new();
         let concurrent = cfg.global.concurrent.unwrap();
+        let bandwidth_limit = match cfg.global.bandwidth_limit_bytes() {
+            Ok(limit) => limit.unwrap_or_default(),
+            Err(e) => {
+                log::error!("全局的带宽限制无效: {}", e);
+                return None;
+            }
+        };
 
         let ca_cert = cfg.manager.ca_cert.as_deref().filter(|s| !s.is_empty());
-        let client_result = match ca_cert {
-            Some(cert) => create_http_client(Some(cert)),
-            None => create_http_client(None),
+        let identity = match (cfg.manager.client_cert.as_deref(), cfg.manager.client_key.as_deref()) {
+            (Some(cert), Some(key)) if !cert.is_empty() && !key.is_empty() => {
+                match load_client_identity(cert, key) {
+                    Ok(identity) => Some(identity),
+                    Err(e) => {
+                        log::error!("加载客户端证书失败: {}", e);
+                        return None;
+                    }
+                }
+            }
+            _ => None,
         };
+        let client_result = http_client_builder(ca_cert, identity)
+            .and_then(|builder| builder.build());
         match client_result {
             Ok(client) => {
                 http_client = client;
@@ -103,10 +132,11 @@ impl Worker {
         
         let (tx, rx) = channel(1);
         let tx = Some(tx);
-        let worker_manager = WorkerManager::new(32, concurrent as usize);
+        let worker_manager = WorkerManager::new(32, concurrent as usize, ConcurrencyGroups::new(&cfg.global),
+                                              BandwidthBudget::new(bandwidth_limit));
         let w = Worker{
             cfg: Arc::new(RwLock::new(cfg)),
-            exit: (tx, Arc::new(Mutex::new(rx))),
+            exit: (Arc::new(Mutex::new(tx)), Arc::new(Mutex::new(rx))),
             http_engine: Arc::new(Mutex::new(Some(Self::make_http_server(worker_manager.clone())))),
             http_client,
             worker_manager,
@@ -125,7 +155,7 @@ impl Worker {
         self.run_schedule().await;
     }
 
-    pub async fn halt(&mut self) {
+    pub async fn halt(&self) {
         let lock  = self.worker_manager.l.lock().await;
         info!("停止所有镜像任务");
         for job in self.worker_manager.jobs.read().await.values() {
@@ -135,7 +165,7 @@ impl Worker {
         }
         info!("所有镜像任务已停止");
         drop(lock);
-        self.exit.0 = None;
+        self.exit.0.lock().await.take();
     }
 
     // ReloadMirrorConfig 根据新的镜像配置文件刷新 providers 和 jobs
@@ -181,6 +211,7 @@ impl Worker {
                                 error!("设置 job provider {} 失败: {}", name, e);
                                 continue;
                             }
+                            job.set_config(&op.mir_cfg, &self.cfg.read().await.global, &self.worker_manager.groups, &self.worker_manager.bandwidth);
 
                             // re-schedule job according to its previous state
                             match job_state {
@@ -204,7 +235,9 @@ impl Worker {
                                     tokio::spawn(async move {
                                         job_clone.run(manager_chan, semaphore).await.unwrap();
                                     });
-                                    self.worker_manager.schedule.add_job(Utc::now(), job.clone()).await;
+                                    if job.after.is_empty() {
+                                        self.worker_manager.schedule.add_job(Utc::now(), job.clone()).await;
+                                    }
                                 }
                             }
                             info!("重载任务 {}", name);
@@ -220,8 +253,9 @@ impl Worker {
             if op.diff_op != Diff::Add{
                 continue;
             }
-            let provider = new_mirror_provider(op.mir_cfg, self.cfg.read().await.clone()).await;
-            let job = MirrorJob::new(provider);
+            let provider = new_mirror_provider(op.mir_cfg.clone(), self.cfg.read().await.clone()).await;
+            let mut job = MirrorJob::new(provider);
+            job.set_config(&op.mir_cfg, &self.cfg.read().await.global, &self.worker_manager.groups, &self.worker_manager.bandwidth);
             let job_name = job.name();
             { self.worker_manager.jobs.write().await.insert(job_name.clone(), job.clone()); }
 
@@ -232,7 +266,9 @@ impl Worker {
             tokio::spawn(async move {
                 job_clone.run(manager_chan, semaphore).await.unwrap();
             });
-            self.worker_manager.schedule.add_job(Utc::now(), job).await;
+            if job.after.is_empty() {
+                self.worker_manager.schedule.add_job(Utc::now(), job).await;
+            }
             info!("新任务 {}", job_name);
         }
 
@@ -244,7 +280,9 @@ impl Worker {
         let cfg = cfg_lock.clone();
         for mirror in cfg_lock.mirrors.iter(){
             let provider = new_mirror_provider(mirror.clone(), cfg.clone()).await;
-            self.worker_manager.jobs.write().await.insert(provider.name(), MirrorJob::new(provider));
+            let mut job = MirrorJob::new(provider);
+            job.set_config(mirror, &cfg.global, &self.worker_manager.groups, &self.worker_manager.bandwidth);
+            self.worker_manager.jobs.write().await.insert(job.name(), job);
         }
         drop((cfg_lock, cfg));
     }
@@ -281,6 +319,12 @@ impl Worker {
                 figment = figment
                     .merge(("tls.certs", cert))
                     .merge(("tls.key", key));
+                // 双向TLS：使用指定的CA校验manager的客户端证书
+                if let Some(ca) = cfg_lock.server.ssl_client_ca.as_ref().filter(|ca| !ca.is_empty()) {
+                    figment = figment
+                        .merge(("tls.mutual.ca_certs", ca))
+                        .merge(("tls.mutual.mandatory", cfg_lock.server.ssl_client_required.unwrap_or(false)));
+                }
             }
         }
         rocket = rocket.configure(figment);
@@ -325,7 +369,11 @@ impl Worker {
                         tokio::spawn(async move {
                             job_clone.run(manager_chan, semaphore).await.unwrap();
                         });
-                        let stime = m.last_update + job.provider.interval();
+                        if !job.after.is_empty() {
+                            debug!("job {} 在 {:?} 同步成功后开始同步", job.name(), job.after);
+                            continue;
+                        }
+                        let stime = job.next_sync_time(m.last_update);
                         debug!("安排了 job {} @{}", job.name(), stime.format("%d-%m-%Y %H:%M:%S"));
                         self.worker_manager.schedule.add_job(stime, job.clone()).await;
                     }
@@ -344,7 +392,9 @@ impl Worker {
             tokio::spawn(async move {
                 job_clone.run(manager_chan, semaphore).await.unwrap();
             });
-            self.worker_manager.schedule.add_job(Utc::now(), job.clone()).await;
+            if job.after.is_empty() {
+                self.worker_manager.schedule.add_job(Utc::now(), job.clone()).await;
+            }
         }
         drop(lock);
 
@@ -377,10 +427,19 @@ impl Worker {
                     // 任务的同步状态只有在它运行时才有意义。
                     // 如果任务被暂停或禁用，则会发出同步失败信号，需要忽略该信号
                     self.update_status(&job, &job_msg).await;
+                    // 上游没有更新而跳过的同步不会让依赖它的任务同步
+                    if job_msg.status == SyncStatus::Success && job_msg.msg != UP_TO_DATE {
+                        self.start_dependents(&job.name()).await;
+                    }
 
                     // 只有同步任务成功或失败时发送的schedule信号（都为true）才会触发该任务的下一次同步调度
-                    if job_msg.schedule {
-                        let schedule_time = Utc::now() + job.provider.interval();
+                    // 有依赖的任务同步成功后由依赖的任务同步成功时安排，同步失败时和其他任务一样按照失败后的间隔重试
+                    if job_msg.schedule && (job.after.is_empty() || job_msg.status == SyncStatus::Failed) {
+                        let schedule_time = if job_msg.status == SyncStatus::Failed {
+                            job.next_sync_time_after_failure(Utc::now())
+                        } else {
+                            job.next_sync_time(Utc::now())
+                        };
                         info!("{} 的下一次同步时间: {}",
                             job.name(),
                             schedule_time.format("%Y-%m-%d %H:%M:%S"));
@@ -395,6 +454,7 @@ impl Worker {
                     if let Some(job) = self.worker_manager.schedule.pop().await{
                         job.ctrl_chan_tx.send(JobCtrlAction::Start).await.unwrap();
                     }
+                    self.pause_outside_windows().await;
                 },
                 None = exit_lock.recv() => {
 			        // flush status update messages
@@ -421,6 +481,43 @@ impl Worker {
 
     }
 
+    // start_dependents在name同步成功后，安排依赖它的任务立即同步，依赖多个mirror的任务要等到它们都同步成功
+    async fn start_dependents(&self, name: &str) {
+        let jobs: Vec<MirrorJob> = self.worker_manager.jobs.read().await.values().cloned().collect();
+        for job in jobs {
+            if !matches!(job.state(), JobState::None | JobState::Ready) || !job.dependency_finished(name) {
+                continue;
+            }
+            info!("{} 同步成功，开始同步依赖它的 {}", name, job.name());
+            self.worker_manager.schedule.add_job(Utc::now(), job).await;
+        }
+    }
+
+    // pause_outside_windows暂停离开了允许同步的时间段、并且配置了pause_outside_window的同步，
+    // 在下一个允许同步的时间继续，start --force启动的同步不受影响。
+    // 被暂停的同步以paused状态上报，不是一次失败的同步，manager不会把它记入同步历史
+    async fn pause_outside_windows(&self) {
+        let now = Utc::now();
+        let jobs: Vec<MirrorJob> = self.worker_manager.jobs.read().await.values().cloned().collect();
+        for job in jobs {
+            if !job.window.as_ref().is_some_and(|window| window.pause)
+                || job.state() != JobState::Ready || job.forced() || !job.provider.is_running().await {
+                continue
+            }
+            let Some(next) = job.deferred_until(now) else { continue };
+            let msg = format!("不在允许同步的时间段内，同步暂停到 {}", next.format("%Y-%m-%d %H:%M:%S"));
+            info!("{} {}", job.name(), msg);
+            job.ctrl_chan_tx.send(JobCtrlAction::Stop).await.unwrap();
+            self.worker_manager.schedule.add_job(next, job.clone()).await;
+            self.update_status(&job, &JobMessage {
+                status: SyncStatus::Paused,
+                name: job.name(),
+                msg,
+                schedule: false,
+            }).await;
+        }
+    }
+
     async fn name(&self) -> String {
         self.cfg.read().await.global.name.clone().unwrap()
     }
@@ -477,7 +574,7 @@ impl Worker {
             is_master: job.provider.is_master().await,
             status: job_msg.status,
             upstream: job.provider.upstream(),
-            size: "unknown".to_string(),
+            size: MirrorSize::unknown(),
             error_msg: job_msg.msg.clone(),
             ..MirrorStatus::default()
         };
@@ -485,13 +582,19 @@ impl Worker {
         //某些提供商（例如rsync）可能知道镜像的大小
         // 所以我们在这里报告给Manager
         let size_lock = job.size.lock().await;
-        if size_lock.len() != 0 {
+        if !size_lock.is_unknown() {
             smsg.size = size_lock.clone();
         }
         drop(size_lock);
+        smsg.usage = *job.usage.lock().await;
 
         let name = self.name().await;
         let cfg_lock = self.cfg.read().await;
+        if let Some(mirror) = cfg_lock.mirrors.iter().find(|m| m.name.as_deref() == Some(job_msg.name.as_str())){
+            smsg.metadata = mirror.metadata();
+        }
+        let dirs = [cfg_lock.global.mirror_dir.clone().unwrap_or_default(), job.provider.working_dir().await];
+        smsg.disks = disk_usages(&dirs);
         for root in cfg_lock.manager.api_base_list(){
             let url = format!("{}/workers/{}/jobs/{}", root, name, job_msg.name);
             debug!("报告给 manager 服务器: {}", url);
@@ -601,6 +704,11 @@ async fn handle_cmd_from_manager(cmd: Json<WorkerCmd>, w: &State<WorkerManager>)
                 CmdVerb::Start => {
                     if cmd.options.get("force").is_some_and(|force|*force == true){
                         job.ctrl_chan_tx.send(JobCtrlAction::ForceStart).await.unwrap();
+                    }else if let Some(next) = job.deferred_until(Utc::now()) {
+                        // 不在允许同步的时间段内，推迟到下一个允许的时间，--force可以忽略时间段
+                        w.schedule.add_job(next, job.clone()).await;
+                        return Ok(Json(Response {msg: format!("不在允许同步的时间段内，推迟到 {}",
+                                                              next.format("%Y-%m-%d %H:%M:%S"))}));
                     }else {
                         job.ctrl_chan_tx.send(JobCtrlAction::Start).await.unwrap();
                     }